@@ -0,0 +1,110 @@
+//! Benchmarks of the hot paths: the distance kernels, the filter evaluation
+//! and answering a single query with each solver over the dummy dataset.
+//!
+//! ```sh
+//! cargo bench --bench search
+//! ```
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+use glasshouse::config::{Config, SolverKind};
+use glasshouse::constants::K_NEAREST;
+use glasshouse::distance::{QUERY_BLOCK, QueryBlock, l2};
+use glasshouse::solvers;
+use glasshouse::types::{NodesDataset, QueriesDataset};
+
+/// Queries answered by each iteration of the search benchmarks.
+const QUERIES_PER_ITERATION: usize = 16;
+
+fn datasets() -> (NodesDataset, QueriesDataset) {
+    let nodes = NodesDataset::read("tests/dummy-data.bin").expect("dummy nodes are readable");
+    let queries =
+        QueriesDataset::read("tests/dummy-queries.bin").expect("dummy queries are readable");
+    (nodes, queries)
+}
+
+fn distance(c: &mut Criterion) {
+    let (nodes, queries) = datasets();
+    let query = queries.query_vector(0);
+    c.bench_function("l2", |b| {
+        b.iter(|| l2(black_box(query), black_box(nodes.vector(0))))
+    });
+}
+
+/// Distances from a block of queries to every node, computed one query at a
+/// time and with the interleaved kernel of the exact scans.
+fn query_block(c: &mut Criterion) {
+    let (nodes, queries) = datasets();
+    let vectors: Vec<&[f32]> = (0..QUERY_BLOCK).map(|i| queries.query_vector(i)).collect();
+    let block = QueryBlock::new(&vectors);
+    let mut group = c.benchmark_group("query_block");
+    group.bench_function(format!("{}x l2", QUERY_BLOCK), |b| {
+        b.iter(|| {
+            for id in 0..nodes.num_vectors as usize {
+                let node = nodes.vector(id);
+                for query in &vectors {
+                    black_box(l2(black_box(query), node));
+                }
+            }
+        })
+    });
+    group.bench_function("QueryBlock::l2", |b| {
+        b.iter(|| {
+            for id in 0..nodes.num_vectors as usize {
+                black_box(black_box(&block).l2(nodes.vector(id)));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn filters(c: &mut Criterion) {
+    let (nodes, queries) = datasets();
+    let mut group = c.benchmark_group("matches");
+    for i in 0..4 {
+        let query = queries
+            .get(i)
+            .expect("dummy queries hold at least 4 queries");
+        group.bench_function(format!("{:?}", query.query_type), |b| {
+            b.iter(|| {
+                (0..nodes.num_vectors as usize)
+                    .filter(|&id| query.matches(&nodes.get(id).expect("node ids are in range")))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn search(c: &mut Criterion) {
+    let (nodes, queries) = datasets();
+    let mut group = c.benchmark_group("search");
+    for kind in [
+        SolverKind::Baseline,
+        SolverKind::Exact,
+        SolverKind::Ivf,
+        SolverKind::Hnsw,
+    ] {
+        let config = Config {
+            solver: kind,
+            ..Config::default()
+        };
+        let solver = solvers::build(&config, &nodes).expect("dummy nodes can be indexed");
+        group.bench_function(
+            BenchmarkId::new(format!("{:?}", kind), QUERIES_PER_ITERATION),
+            |b| {
+                b.iter(|| {
+                    for i in 0..QUERIES_PER_ITERATION {
+                        let query = queries.get(i).expect("query indices are in range");
+                        black_box(solver.search(&nodes, &query, K_NEAREST));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, distance, query_block, filters, search);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+fn main() {
+    // The gRPC service is generated from its protobuf definition, compiled
+    // with protox so that building does not require a protoc installation.
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/glasshouse.proto");
+        let file_descriptors = protox::compile(["proto/glasshouse.proto"], ["proto"])
+            .expect("Failed to compile proto/glasshouse.proto");
+        tonic_prost_build::configure()
+            .compile_fds(file_descriptors)
+            .expect("Failed to generate the gRPC service");
+    }
+}
@@ -0,0 +1,28 @@
+//! Parses arbitrary bytes as a nodes dataset, the first byte picking the
+//! vector dimensionality. Datasets parsed successfully must be written and
+//! read back identically.
+#![no_main]
+
+use glasshouse::types::NodesDataset;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&dimensions, bytes)) = data.split_first() else {
+        return;
+    };
+    let dimensions = dimensions as usize % 16;
+    let Ok(nodes) = NodesDataset::from_reader(bytes, dimensions) else {
+        return;
+    };
+    let _ = NodesDataset::from_bytes(bytes);
+
+    let mut written = Vec::new();
+    nodes
+        .write_to(&mut written)
+        .expect("writing to memory cannot fail");
+    let copy = NodesDataset::from_reader(&written[..], dimensions).expect("written datasets parse");
+    let mut rewritten = Vec::new();
+    copy.write_to(&mut rewritten)
+        .expect("writing to memory cannot fail");
+    assert_eq!(written, rewritten);
+});
@@ -0,0 +1,62 @@
+/*
+ * C interface of the glasshouse filtered nearest neighbour search library.
+ *
+ * Objects are opaque and owned by the caller, release them with the
+ * matching glasshouse_*_free function. Functions returning a pointer
+ * return NULL on failure and functions returning an int return a negative
+ * value on failure, glasshouse_last_error then describes the failure.
+ * Panics inside the library are reported the same way.
+ */
+#ifndef GLASSHOUSE_H
+#define GLASSHOUSE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct GlasshouseNodes GlasshouseNodes;
+typedef struct GlasshouseQueries GlasshouseQueries;
+typedef struct GlasshouseSolver GlasshouseSolver;
+
+/* Message describing the last failure on the calling thread, or NULL. */
+const char *glasshouse_last_error(void);
+
+/* Number of neighbours returned per query unless the configuration sets k. */
+size_t glasshouse_k_nearest(void);
+
+GlasshouseNodes *glasshouse_nodes_open(const char *path);
+uint32_t glasshouse_nodes_len(const GlasshouseNodes *nodes);
+void glasshouse_nodes_free(GlasshouseNodes *nodes);
+
+GlasshouseQueries *glasshouse_queries_open(const char *path);
+uint32_t glasshouse_queries_len(const GlasshouseQueries *queries);
+void glasshouse_queries_free(GlasshouseQueries *queries);
+
+/*
+ * Builds the solver described by a TOML configuration over the nodes.
+ * Under the cosine metric the solver searches a copy of the nodes scaled
+ * to unit length and scales the queries of every batch alike.
+ */
+GlasshouseSolver *glasshouse_solver_build(const GlasshouseNodes *nodes, const char *config);
+/* Number of neighbours the solver returns per query, 0 if it is null. */
+size_t glasshouse_solver_k(const GlasshouseSolver *solver);
+void glasshouse_solver_free(GlasshouseSolver *solver);
+
+/*
+ * Answers every query and writes glasshouse_solver_k() node IDs per query
+ * to results, which must hold results_len >= queries * k entries. Returns
+ * the number of answered queries, batches of more than INT_MAX queries
+ * are rejected.
+ */
+int glasshouse_search_batch(const GlasshouseSolver *solver, const GlasshouseNodes *nodes,
+                            const GlasshouseQueries *queries, uint32_t *results,
+                            size_t results_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GLASSHOUSE_H */
@@ -0,0 +1,379 @@
+//! Conversion between the datasets and Arrow record batches.
+//!
+//! Nodes batches have the columns `c` and `t`, the categorical and
+//! timestamp attributes, and `vector`. Queries batches have the columns
+//!
+//! - `query_type`: integer, see [`QueryType`].
+//! - `category`: integer, null when the query has no categorical filter.
+//! - `t_lower`, `t_upper`: floats, null when the query has no time filter.
+//! - `vector`: list or fixed size list of floats.
+//!
+//! Integer and float columns of any width are accepted and cast. Batches
+//! produced from a dataset take ownership of its vectors instead of copying
+//! them, as do the node attributes.
+use std::fmt::Display;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, Int32Type, UInt32Type};
+use arrow_array::{
+    Array, ArrayRef, FixedSizeListArray, Float32Array, Int32Array, RecordBatch,
+    RecordBatchIterator, RecordBatchReader, UInt32Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+
+use crate::error::{self, GlasshouseError};
+use crate::io;
+use crate::types::{NodesDataset, ParsedQuery, QueriesDataset, QueryType};
+
+impl NodesDataset {
+    /// Schema of the batches holding nodes of `dimensions` dimensions.
+    pub fn schema(dimensions: usize) -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("c", DataType::Float32, false),
+            Field::new("t", DataType::Float32, false),
+            Field::new("vector", vector_type(dimensions), false),
+        ]))
+    }
+
+    /// Reads every batch of the reader into a single dataset.
+    pub fn from_record_batch_reader<R: RecordBatchReader>(reader: R) -> error::Result<Self> {
+        let mut dimensions = vector_dimensions(&reader.schema());
+        let mut c_attrs = Vec::new();
+        let mut t_attrs = Vec::new();
+        let mut vectors = Vec::new();
+        for batch in reader {
+            let batch = batch.map_err(malformed)?;
+            for &c_attr in floats(&batch, "c")?.values() {
+                c_attrs.push(io::categorical(c_attr)?);
+            }
+            t_attrs.extend_from_slice(floats(&batch, "t")?.values());
+            vectors.extend(vector_values(&batch, &mut dimensions)?);
+        }
+        NodesDataset::from_parts(dimensions.unwrap_or(0), c_attrs, t_attrs, vectors)
+    }
+
+    /// Converts the dataset into a batch without copying its attributes and
+    /// vectors. Deleted nodes are kept, compact the dataset to drop them.
+    pub fn into_record_batch(self) -> error::Result<RecordBatch> {
+        nodes_batch(self.dimensions, self.c_attrs, self.t_attrs, self.vectors)
+    }
+
+    /// Copies the dataset into a batch.
+    pub fn to_record_batch(&self) -> error::Result<RecordBatch> {
+        nodes_batch(
+            self.dimensions,
+            self.c_attrs.clone(),
+            self.t_attrs.clone(),
+            self.vectors.clone(),
+        )
+    }
+}
+
+impl TryFrom<&RecordBatch> for NodesDataset {
+    type Error = GlasshouseError;
+
+    fn try_from(batch: &RecordBatch) -> error::Result<Self> {
+        NodesDataset::from_record_batch_reader(RecordBatchIterator::new(
+            [Ok(batch.clone())],
+            batch.schema(),
+        ))
+    }
+}
+
+impl QueriesDataset {
+    /// Schema of the batches holding queries of `dimensions` dimensions.
+    pub fn schema(dimensions: usize) -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("query_type", DataType::UInt32, false),
+            Field::new("category", DataType::Int32, true),
+            Field::new("t_lower", DataType::Float32, true),
+            Field::new("t_upper", DataType::Float32, true),
+            Field::new("vector", vector_type(dimensions), false),
+        ]))
+    }
+
+    /// Reads every batch of the reader into a single dataset.
+    pub fn from_record_batch_reader<R: RecordBatchReader>(reader: R) -> error::Result<Self> {
+        let mut dimensions = vector_dimensions(&reader.schema());
+        let mut queries_dataset = QueriesDataset {
+            dimensions: dimensions.unwrap_or(0),
+            ..QueriesDataset::default()
+        };
+        for batch in reader {
+            let batch = batch.map_err(malformed)?;
+            let query_types = column(&batch, "query_type", &DataType::UInt32)?;
+            let categories = column(&batch, "category", &DataType::Int32)?;
+            let t_lowers = column(&batch, "t_lower", &DataType::Float32)?;
+            let t_uppers = column(&batch, "t_upper", &DataType::Float32)?;
+            let query_types = query_types.as_primitive::<UInt32Type>();
+            let categories = categories.as_primitive::<Int32Type>();
+            let t_lowers = t_lowers.as_primitive::<Float32Type>();
+            let t_uppers = t_uppers.as_primitive::<Float32Type>();
+            let vectors = vector_values(&batch, &mut dimensions)?;
+            let width = dimensions.unwrap_or(0);
+
+            for row in 0..batch.num_rows() {
+                let index = queries_dataset.num_queries;
+                let invalid = |message: String| {
+                    GlasshouseError::Malformed(format!("Query {}: {}", index, message))
+                };
+                if query_types.is_null(row) {
+                    return Err(invalid("query_type must be set".to_string()));
+                }
+                let query = ParsedQuery {
+                    query_type: QueryType::from_f32(query_types.value(row) as f32)
+                        .map_err(|e| invalid(e.to_string()))?,
+                    v_categorical: categories.is_valid(row).then(|| categories.value(row)),
+                    t_lower_bound: t_lowers.is_valid(row).then(|| t_lowers.value(row)),
+                    t_upper_bound: t_uppers.is_valid(row).then(|| t_uppers.value(row)),
+                    query_vector: &vectors[row * width..(row + 1) * width],
+                };
+                queries_dataset
+                    .push(&query)
+                    .map_err(|e| invalid(e.to_string()))?;
+            }
+        }
+        Ok(queries_dataset)
+    }
+
+    /// Converts the dataset into a batch without copying its vectors.
+    pub fn into_record_batch(mut self) -> error::Result<RecordBatch> {
+        let query_vectors = std::mem::take(&mut self.query_vectors);
+        self.batch(query_vectors)
+    }
+
+    /// Copies the dataset into a batch.
+    pub fn to_record_batch(&self) -> error::Result<RecordBatch> {
+        self.batch(self.query_vectors.clone())
+    }
+
+    fn batch(&self, query_vectors: Vec<f32>) -> error::Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(
+                self.query_types
+                    .iter()
+                    .map(|query_type| query_type.to_f32() as u32)
+                    .collect::<UInt32Array>(),
+            ),
+            Arc::new(
+                self.v_categoricals
+                    .iter()
+                    .map(|v| v.categorical_value())
+                    .collect::<Int32Array>(),
+            ),
+            Arc::new(
+                self.t_lower_bounds
+                    .iter()
+                    .map(|t| t.value())
+                    .collect::<Float32Array>(),
+            ),
+            Arc::new(
+                self.t_upper_bounds
+                    .iter()
+                    .map(|t| t.value())
+                    .collect::<Float32Array>(),
+            ),
+            Arc::new(vector_array(self.dimensions, query_vectors)?),
+        ];
+        RecordBatch::try_new(QueriesDataset::schema(self.dimensions), columns).map_err(malformed)
+    }
+}
+
+impl TryFrom<&RecordBatch> for QueriesDataset {
+    type Error = GlasshouseError;
+
+    fn try_from(batch: &RecordBatch) -> error::Result<Self> {
+        QueriesDataset::from_record_batch_reader(RecordBatchIterator::new(
+            [Ok(batch.clone())],
+            batch.schema(),
+        ))
+    }
+}
+
+fn nodes_batch(
+    dimensions: usize,
+    c_attrs: Vec<i32>,
+    t_attrs: Vec<f32>,
+    vectors: Vec<f32>,
+) -> error::Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            c_attrs
+                .into_iter()
+                .map(|c| c as f32)
+                .collect::<Float32Array>(),
+        ),
+        Arc::new(Float32Array::from(t_attrs)),
+        Arc::new(vector_array(dimensions, vectors)?),
+    ];
+    RecordBatch::try_new(NodesDataset::schema(dimensions), columns).map_err(malformed)
+}
+
+/// Returns the column cast to the given type.
+fn column(batch: &RecordBatch, name: &str, data_type: &DataType) -> error::Result<ArrayRef> {
+    let column = batch
+        .column_by_name(name)
+        .ok_or_else(|| GlasshouseError::Malformed(format!("Missing column {}", name)))?;
+    arrow_cast::cast(column, data_type)
+        .map_err(|e| GlasshouseError::Malformed(format!("Invalid column {}: {}", name, e)))
+}
+
+/// Returns a float column without nulls.
+fn floats(batch: &RecordBatch, name: &str) -> error::Result<Float32Array> {
+    let column = column(batch, name, &DataType::Float32)?;
+    if column.null_count() > 0 {
+        return Err(GlasshouseError::Malformed(format!(
+            "Column {} holds nulls",
+            name
+        )));
+    }
+    Ok(column.as_primitive::<Float32Type>().clone())
+}
+
+/// Number of dimensions of the vectors declared by a fixed size list
+/// `vector` column, other lists declare none.
+fn vector_dimensions(schema: &Schema) -> Option<usize> {
+    match schema.field_with_name("vector").ok()?.data_type() {
+        &DataType::FixedSizeList(_, size) => Some(size as usize),
+        _ => None,
+    }
+}
+
+/// Returns the entries of the `vector` column stored contiguously. Every
+/// vector must have `dimensions` entries, which the first vector sets when
+/// unknown.
+fn vector_values(batch: &RecordBatch, dimensions: &mut Option<usize>) -> error::Result<Vec<f32>> {
+    let vectors = column(
+        batch,
+        "vector",
+        &DataType::List(Arc::new(Field::new_list_field(DataType::Float32, true))),
+    )?;
+    let vectors = vectors.as_list::<i32>();
+    let entries = vectors.values().as_primitive::<Float32Type>();
+    if vectors.null_count() > 0 || entries.null_count() > 0 {
+        return Err(GlasshouseError::Malformed(
+            "Column vector holds nulls".to_string(),
+        ));
+    }
+
+    let mut values = Vec::with_capacity(entries.len());
+    for (row, range) in vectors.value_offsets().windows(2).enumerate() {
+        let vector = &entries.values()[range[0] as usize..range[1] as usize];
+        let dimensions = *dimensions.get_or_insert(vector.len());
+        if vector.len() != dimensions {
+            return Err(GlasshouseError::Malformed(format!(
+                "Row {} holds a vector of dimension {}, expected {}",
+                row,
+                vector.len(),
+                dimensions
+            )));
+        }
+        values.extend_from_slice(vector);
+    }
+    Ok(values)
+}
+
+/// Type of the `vector` column of the batches produced from datasets.
+fn vector_type(dimensions: usize) -> DataType {
+    DataType::FixedSizeList(
+        Arc::new(Field::new_list_field(DataType::Float32, false)),
+        dimensions as i32,
+    )
+}
+
+fn vector_array(dimensions: usize, values: Vec<f32>) -> error::Result<FixedSizeListArray> {
+    FixedSizeListArray::try_new(
+        Arc::new(Field::new_list_field(DataType::Float32, false)),
+        dimensions as i32,
+        Arc::new(Float32Array::from(values)),
+        None,
+    )
+    .map_err(malformed)
+}
+
+fn malformed(error: impl Display) -> GlasshouseError {
+    GlasshouseError::Malformed(format!("Arrow: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Float64Array, Int64Array, ListArray};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+
+    #[test]
+    fn datasets_round_trip() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let nodes = generate::nodes(&mut rng, 100, 8, 4);
+        let queries = generate::queries(&mut rng, 20, 8, 4);
+
+        let read_nodes = NodesDataset::try_from(&nodes.to_record_batch().unwrap()).unwrap();
+        assert_eq!(read_nodes.dimensions, 8);
+        assert_eq!(read_nodes.c_attrs, nodes.c_attrs);
+        assert_eq!(read_nodes.t_attrs, nodes.t_attrs);
+        assert_eq!(read_nodes.vectors, nodes.vectors);
+
+        let batch = queries.to_record_batch().unwrap();
+        let read_queries = QueriesDataset::try_from(&batch).unwrap();
+        assert_eq!(read_queries.num_queries, 20);
+        for index in 0..20 {
+            let (read, query) = (
+                read_queries.get(index).unwrap(),
+                queries.get(index).unwrap(),
+            );
+            assert_eq!(read.query_type, query.query_type);
+            assert_eq!(read.v_categorical, query.v_categorical);
+            assert_eq!(read.t_lower_bound, query.t_lower_bound);
+            assert_eq!(read.t_upper_bound, query.t_upper_bound);
+            assert_eq!(read.query_vector, query.query_vector);
+        }
+    }
+
+    #[test]
+    fn batches_take_the_vectors_without_copying() {
+        let nodes = NodesDataset::from_parts(2, vec![0], vec![0.5], vec![1.0, 2.0]).unwrap();
+        let pointer = nodes.vectors.as_ptr();
+        let batch = nodes.into_record_batch().unwrap();
+        let vectors = batch.column(2).as_fixed_size_list().values();
+        assert_eq!(
+            vectors.as_primitive::<Float32Type>().values().as_ptr(),
+            pointer
+        );
+    }
+
+    #[test]
+    fn queries_accept_lists_of_any_width() {
+        let vectors = ListArray::from_iter_primitive::<Float32Type, _, _>([
+            Some(vec![Some(1.0), Some(2.0)]),
+            Some(vec![Some(3.0), Some(4.0)]),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from(vec![0, 3])),
+            Arc::new(Int64Array::from(vec![None, Some(1)])),
+            Arc::new(Float64Array::from(vec![None, Some(0.0)])),
+            Arc::new(Float64Array::from(vec![None, Some(0.6)])),
+            Arc::new(vectors),
+        ];
+        let fields: Vec<Field> = ["query_type", "category", "t_lower", "t_upper", "vector"]
+            .into_iter()
+            .zip(&columns)
+            .map(|(name, column)| Field::new(name, column.data_type().clone(), true))
+            .collect();
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap();
+
+        let queries = QueriesDataset::try_from(&batch).unwrap();
+        assert_eq!(queries.dimensions, 2);
+        let query = queries.get(1).unwrap();
+        assert_eq!(query.query_type, QueryType::BothConstraints);
+        assert_eq!(query.v_categorical, Some(1));
+        assert_eq!(query.t_upper_bound, Some(0.6));
+        assert_eq!(query.query_vector, &[3.0, 4.0]);
+
+        let without_vectors = batch.project(&[0, 1, 2, 3]).unwrap();
+        assert!(QueriesDataset::try_from(&without_vectors).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Sustained throughput of a solver.
+//!
+//! A run answers each query once, its elapsed time mixes the steady state
+//! with one-shot costs such as page faults, cold caches and the threads
+//! idling at the end of the run. A benchmark instead answers the queries
+//! over and over on every worker thread for a fixed duration, and only
+//! counts the queries completing after a warm-up period.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::error::{self, GlasshouseError};
+use crate::latency::{self, LatencyReport};
+use crate::solvers::Solver;
+use crate::types::{NodesDataset, QueriesDataset};
+
+/// Queries completed by [`bench`] during its measured period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throughput {
+    /// Number of queries completed during the measured period.
+    pub queries: usize,
+    /// Number of queries completed during the warm-up.
+    pub warm_up_queries: usize,
+    /// Length of the measured period, the warm-up excluded.
+    pub elapsed: Duration,
+    /// Latency percentiles of the queries completed during the measured
+    /// period.
+    pub latencies: LatencyReport,
+}
+
+impl Throughput {
+    /// Sustained number of queries completed per second.
+    pub fn qps(&self) -> f64 {
+        self.queries as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Answers the queries in a loop on every worker thread of the current
+/// pool for `warm_up` then `duration`, searching their `k` nearest
+/// neighbours, and measures the queries completed during `duration`.
+pub fn bench(
+    solver: &dyn Solver,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    warm_up: Duration,
+    duration: Duration,
+) -> error::Result<Throughput> {
+    if nodes_dataset.dimensions != queries_dataset.dimensions {
+        return Err(GlasshouseError::DimensionMismatch {
+            nodes: nodes_dataset.dimensions,
+            queries: queries_dataset.dimensions,
+        });
+    }
+    if queries_dataset.num_queries == 0 {
+        return Err(GlasshouseError::Config(
+            "Benchmarks need at least one query".to_string(),
+        ));
+    }
+
+    let num_queries = queries_dataset.num_queries as usize;
+    let next = AtomicUsize::new(0);
+    let start = Instant::now();
+    let (measured, end) = (start + warm_up, start + warm_up + duration);
+    // Each thread records the index and latency of the queries it completes
+    // during the measured period, and counts the others.
+    let samples = rayon::broadcast(|_| {
+        let mut samples = Vec::new();
+        let mut warm_up_queries = 0;
+        loop {
+            let query_start = Instant::now();
+            if query_start >= end {
+                break;
+            }
+            let index = next.fetch_add(1, Ordering::Relaxed) % num_queries;
+            let query = queries_dataset
+                .get(index)
+                .expect("query indices are in range");
+            std::hint::black_box(solver.search_scored(nodes_dataset, &query, k));
+            let completed = Instant::now();
+            if completed < measured {
+                warm_up_queries += 1;
+            } else if completed <= end {
+                samples.push((index, completed - query_start));
+            }
+        }
+        (samples, warm_up_queries)
+    });
+
+    let warm_up_queries = samples.iter().map(|(_, count)| count).sum();
+    let (latencies, query_types): (Vec<_>, Vec<_>) = samples
+        .into_iter()
+        .flat_map(|(samples, _)| samples)
+        .map(|(index, latency)| (Some(latency), queries_dataset.query_types[index]))
+        .unzip();
+    Ok(Throughput {
+        queries: latencies.len(),
+        warm_up_queries,
+        elapsed: duration,
+        latencies: latency::report(&latencies, &query_types),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::solvers::Exact;
+
+    #[test]
+    fn benchmarks_only_measure_after_the_warm_up() {
+        let mut rng = StdRng::seed_from_u64(71);
+        let nodes = generate::nodes(&mut rng, 500, 4, 4);
+        let queries = generate::queries(&mut rng, 10, 4, 4);
+        let throughput = bench(
+            &Exact,
+            &nodes,
+            &queries,
+            10,
+            Duration::from_millis(50),
+            Duration::from_millis(100),
+        )
+        .unwrap();
+
+        assert!(throughput.queries > 0 && throughput.warm_up_queries > 0);
+        assert_eq!(throughput.elapsed, Duration::from_millis(100));
+        assert_eq!(
+            throughput.latencies.overall.map(|p| p.queries),
+            Some(throughput.queries)
+        );
+        assert!(throughput.qps() > 0.0);
+        let no_queries = generate::queries(&mut rng, 0, 4, 4);
+        assert!(matches!(
+            bench(
+                &Exact,
+                &nodes,
+                &no_queries,
+                10,
+                Duration::ZERO,
+                Duration::ZERO
+            ),
+            Err(GlasshouseError::Config(_))
+        ));
+        let narrow = generate::queries(&mut rng, 10, 2, 4);
+        assert!(matches!(
+            bench(&Exact, &nodes, &narrow, 10, Duration::ZERO, Duration::ZERO),
+            Err(GlasshouseError::DimensionMismatch {
+                nodes: 4,
+                queries: 2
+            })
+        ));
+    }
+}
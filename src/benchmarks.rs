@@ -0,0 +1,245 @@
+//! Loaders for standard ANN benchmarks, used to validate the solvers
+//! against well-known recall baselines.
+//!
+//! The files of a benchmark are looked up in a data directory and
+//! downloaded there with `curl` (and unpacked with `tar`) when missing.
+//! The benchmarks carry no attributes: nodes get random categories and
+//! timestamps when asked for, which leaves the vector-only queries and
+//! their ground truth untouched. The vectors of the angular benchmarks are
+//! normalized so that Euclidean distances rank neighbours by cosine
+//! similarity, as their ground truth does.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rand::Rng;
+
+use crate::constants::K_NEAREST;
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, QueriesDataset, QueryResults};
+
+/// A standard ANN benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Benchmark {
+    /// SIFT1M, 1M 128-dimensional SIFT descriptors from the TEXMEX corpus.
+    Sift1m,
+    /// GloVe, 1.2M 100-dimensional word embeddings from ann-benchmarks.
+    /// Requires the `hdf5` feature.
+    Glove100,
+    /// Deep1B, a 10M sample of 96-dimensional image embeddings from
+    /// ann-benchmarks. Requires the `hdf5` feature.
+    Deep10m,
+}
+
+/// Nodes, queries and exact neighbours of a benchmark.
+#[derive(Debug)]
+pub struct LoadedBenchmark {
+    pub nodes: NodesDataset,
+    pub queries: QueriesDataset,
+    pub ground_truth: QueryResults,
+}
+
+impl Benchmark {
+    /// Every benchmark.
+    pub const ALL: [Benchmark; 3] = [Benchmark::Sift1m, Benchmark::Glove100, Benchmark::Deep10m];
+
+    /// Short name of the benchmark.
+    pub fn name(self) -> &'static str {
+        match self {
+            Benchmark::Sift1m => "sift1m",
+            Benchmark::Glove100 => "glove100",
+            Benchmark::Deep10m => "deep10m",
+        }
+    }
+
+    /// Returns true if the ground truth ranks neighbours by cosine
+    /// similarity.
+    pub fn is_angular(self) -> bool {
+        !matches!(self, Benchmark::Sift1m)
+    }
+
+    /// Address the benchmark is downloaded from.
+    pub fn url(self) -> &'static str {
+        match self {
+            Benchmark::Sift1m => "ftp://ftp.irisa.fr/local/texmex/corpus/sift.tar.gz",
+            Benchmark::Glove100 => "http://ann-benchmarks.com/glove-100-angular.hdf5",
+            Benchmark::Deep10m => "http://ann-benchmarks.com/deep-image-96-angular.hdf5",
+        }
+    }
+
+    /// Name of the downloaded file.
+    fn archive(self) -> &'static str {
+        self.url()
+            .rsplit('/')
+            .next()
+            .expect("urls hold a file name")
+    }
+
+    /// Files the benchmark is read from, relative to the data directory.
+    pub fn files(self) -> Vec<PathBuf> {
+        match self {
+            Benchmark::Sift1m => [
+                "sift_base.fvecs",
+                "sift_query.fvecs",
+                "sift_groundtruth.ivecs",
+            ]
+            .iter()
+            .map(|file| Path::new("sift").join(file))
+            .collect(),
+            Benchmark::Glove100 | Benchmark::Deep10m => vec![PathBuf::from(self.archive())],
+        }
+    }
+
+    /// Downloads the benchmark to the data directory unless its files are
+    /// already there.
+    pub fn fetch<P: AsRef<Path>>(self, data_dir: P) -> error::Result<()> {
+        let data_dir = data_dir.as_ref();
+        if self.files().iter().all(|file| data_dir.join(file).exists()) {
+            return Ok(());
+        }
+        std::fs::create_dir_all(data_dir)?;
+        let archive = data_dir.join(self.archive());
+        run(Command::new("curl")
+            .args(["--fail", "--location", "--output"])
+            .arg(&archive)
+            .arg(self.url()))?;
+        if archive.extension().is_some_and(|e| e == "gz") {
+            run(Command::new("tar")
+                .arg("-xzf")
+                .arg(&archive)
+                .arg("-C")
+                .arg(data_dir))?;
+            std::fs::remove_file(archive)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the benchmark from the data directory, downloading it first
+    /// when missing.
+    pub fn load<P: AsRef<Path>>(self, data_dir: P) -> error::Result<LoadedBenchmark> {
+        let data_dir = data_dir.as_ref();
+        self.fetch(data_dir)?;
+        let files: Vec<PathBuf> = self
+            .files()
+            .iter()
+            .map(|file| data_dir.join(file))
+            .collect();
+        let mut loaded = match self {
+            Benchmark::Sift1m => LoadedBenchmark {
+                nodes: NodesDataset::read_fvecs(&files[0])?,
+                queries: QueriesDataset::read_fvecs(&files[1])?,
+                ground_truth: crate::io::vecs::read_results_ivecs(&files[2], K_NEAREST)?,
+            },
+            Benchmark::Glove100 | Benchmark::Deep10m => read_ann_benchmarks(&files[0])?,
+        };
+        if self.is_angular() {
+            normalize(loaded.nodes.dimensions, &mut loaded.nodes.vectors);
+            normalize(loaded.queries.dimensions, &mut loaded.queries.query_vectors);
+        }
+        Ok(loaded)
+    }
+}
+
+impl fmt::Display for Benchmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Gives the nodes uniformly distributed categories in
+/// `0..num_categories` and timestamps in `[0, 1]`.
+pub fn synthesize_attributes<R: Rng>(
+    rng: &mut R,
+    nodes_dataset: &mut NodesDataset,
+    num_categories: u32,
+) {
+    let num_categories = num_categories.max(1);
+    for c_attr in &mut nodes_dataset.c_attrs {
+        *c_attr = rng.random_range(0..num_categories) as i32;
+    }
+    for t_attr in &mut nodes_dataset.t_attrs {
+        *t_attr = rng.random();
+    }
+    nodes_dataset.index_attributes();
+}
+
+/// Scales the vectors, stored contiguously, to unit length.
+fn normalize(dimensions: usize, vectors: &mut [f32]) {
+    for vector in vectors.chunks_exact_mut(dimensions.max(1)) {
+        let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|value| *value /= norm);
+        }
+    }
+}
+
+#[cfg(feature = "hdf5")]
+fn read_ann_benchmarks(path: &Path) -> error::Result<LoadedBenchmark> {
+    let file = crate::io::hdf5::AnnBenchmarks::read(path)?;
+    Ok(LoadedBenchmark {
+        nodes: file.nodes,
+        queries: file.queries,
+        ground_truth: file.neighbors,
+    })
+}
+
+#[cfg(not(feature = "hdf5"))]
+fn read_ann_benchmarks(path: &Path) -> error::Result<LoadedBenchmark> {
+    Err(GlasshouseError::Malformed(format!(
+        "Reading {} requires the hdf5 feature",
+        path.display()
+    )))
+}
+
+/// Runs a command, failing if it cannot be started or exits unsuccessfully.
+fn run(command: &mut Command) -> error::Result<()> {
+    let status = command.status()?;
+    if !status.success() {
+        return Err(GlasshouseError::Malformed(format!(
+            "{:?} failed with {}",
+            command, status
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::io::vecs;
+
+    #[test]
+    fn local_benchmarks_are_read_without_downloading() {
+        let data_dir = crate::testing::temp_path("benchmarks");
+        let sift_dir = data_dir.join("sift");
+        std::fs::create_dir_all(&sift_dir).unwrap();
+        let write =
+            |file: &str, bytes: Vec<u8>| std::fs::write(sift_dir.join(file), bytes).unwrap();
+        let mut base = Vec::new();
+        vecs::write_fvecs(&mut base, 2, &[3.0, 4.0, 1.0, 0.0]).unwrap();
+        write("sift_base.fvecs", base);
+        let mut query = Vec::new();
+        vecs::write_fvecs(&mut query, 2, &[0.0, 0.0]).unwrap();
+        write("sift_query.fvecs", query);
+        let mut truth = Vec::new();
+        let ids: Vec<i32> = (0..K_NEAREST as i32).collect();
+        vecs::write_ivecs(&mut truth, ids.len(), &ids).unwrap();
+        write("sift_groundtruth.ivecs", truth);
+
+        let mut loaded = Benchmark::Sift1m.load(&data_dir).unwrap();
+        std::fs::remove_dir_all(data_dir).unwrap();
+        assert_eq!(loaded.nodes.vector(0), &[3.0, 4.0]);
+        assert_eq!(loaded.queries.num_queries, 1);
+        assert_eq!(loaded.ground_truth[0][1], 1);
+
+        synthesize_attributes(&mut StdRng::seed_from_u64(1), &mut loaded.nodes, 3);
+        assert!(loaded.nodes.c_attrs.iter().all(|&c| (0..3).contains(&c)));
+
+        let mut vectors = vec![3.0, 4.0, 0.0, 0.0];
+        normalize(2, &mut vectors);
+        assert_eq!(vectors, vec![0.6, 0.8, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Wall-clock budget shared by the phases of a run.
+//!
+//! The budget starts with the program, loading and building consume it and
+//! the search gets what is left minus a reserve for writing the results.
+//! Before searching, the solver effort is lowered until the estimated search
+//! time fits, cancelling the search at [`Budget::search_deadline`] then pads
+//! whatever is still unanswered once the estimate proves too optimistic.
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::error::{self, GlasshouseError};
+use crate::solvers::Solver;
+use crate::types::{NodesDataset, QueriesDataset};
+
+/// Queries timed to estimate the duration of the search.
+const PROBE_QUERIES: usize = 256;
+
+/// Wall-clock budget of a run.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    start: Instant,
+    limit: Duration,
+}
+
+impl Budget {
+    /// Returns a budget of `limit` counted from `start`.
+    pub fn new(start: Instant, limit: Duration) -> Self {
+        Budget { start, limit }
+    }
+
+    /// Time kept for writing the results, a twentieth of the budget.
+    pub fn reserve(&self) -> Duration {
+        self.limit / 20
+    }
+
+    /// Instant by which the search must stop for the results to be written
+    /// before the budget expires.
+    pub fn search_deadline(&self) -> Instant {
+        self.start + self.limit - self.reserve()
+    }
+
+    /// Time left for searching.
+    pub fn search_time_left(&self) -> Duration {
+        self.search_deadline()
+            .saturating_duration_since(Instant::now())
+    }
+}
+
+/// Outcome of fitting the search of a solver into a budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fit {
+    /// Number of times the search effort was halved.
+    pub degradations: u32,
+    /// Estimated duration of the search with the final effort.
+    pub estimate: Duration,
+}
+
+/// Halves the search effort of the solver until answering every query for
+/// its `k` nearest neighbours is estimated to take less than `available`,
+/// or the effort is minimal. The
+/// estimate extrapolates the time taken by up to `PROBE_QUERIES` queries
+/// spread over the dataset.
+pub fn fit(
+    solver: &mut dyn Solver,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    available: Duration,
+) -> error::Result<Fit> {
+    if nodes_dataset.dimensions != queries_dataset.dimensions {
+        return Err(GlasshouseError::DimensionMismatch {
+            nodes: nodes_dataset.dimensions,
+            queries: queries_dataset.dimensions,
+        });
+    }
+
+    let num_queries = queries_dataset.num_queries as usize;
+    let step = num_queries.div_ceil(PROBE_QUERIES).max(1);
+    let probes: Vec<usize> = (0..num_queries).step_by(step).collect();
+    let mut degradations = 0;
+    loop {
+        let estimate = estimate(&*solver, nodes_dataset, queries_dataset, k, &probes);
+        if estimate <= available || !solver.degrade(k) {
+            return Ok(Fit {
+                degradations,
+                estimate,
+            });
+        }
+        degradations += 1;
+    }
+}
+
+/// Times the probe queries and extrapolates to the whole dataset.
+fn estimate(
+    solver: &dyn Solver,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    probes: &[usize],
+) -> Duration {
+    if probes.is_empty() {
+        return Duration::ZERO;
+    }
+    let start = Instant::now();
+    probes.par_iter().for_each(|&i| {
+        let query = queries_dataset.get(i).expect("probe indices are in range");
+        solver.search(nodes_dataset, &query, k);
+    });
+    start
+        .elapsed()
+        .mul_f64(queries_dataset.num_queries as f64 / probes.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::constants::K_NEAREST;
+    use crate::generate;
+    use crate::solvers::IvfBuilder;
+
+    #[test]
+    fn exhausted_budgets_degrade_to_the_minimal_effort() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let nodes = generate::nodes(&mut rng, 1_000, 8, 4);
+        let queries = generate::queries(&mut rng, 100, 8, 4);
+        let mut ivf = IvfBuilder::new()
+            .nlist(16)
+            .nprobe(16)
+            .build(&nodes)
+            .unwrap();
+
+        let generous = fit(
+            &mut ivf,
+            &nodes,
+            &queries,
+            K_NEAREST,
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        assert_eq!(generous.degradations, 0);
+        assert_eq!(ivf.nprobe(), 16);
+
+        let exhausted = fit(&mut ivf, &nodes, &queries, K_NEAREST, Duration::ZERO).unwrap();
+        assert_eq!(exhausted.degradations, 4);
+        assert_eq!(ivf.nprobe(), 1);
+    }
+
+    #[test]
+    fn fits_reject_mismatched_dimensions() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let nodes = generate::nodes(&mut rng, 100, 8, 4);
+        let queries = generate::queries(&mut rng, 10, 4, 4);
+        let mut ivf = IvfBuilder::new().nlist(4).nprobe(4).build(&nodes).unwrap();
+
+        assert!(matches!(
+            fit(&mut ivf, &nodes, &queries, K_NEAREST, Duration::ZERO),
+            Err(GlasshouseError::DimensionMismatch {
+                nodes: 8,
+                queries: 4
+            })
+        ));
+        // The solver is left as it was.
+        assert_eq!(ivf.nprobe(), 4);
+    }
+}
@@ -0,0 +1,439 @@
+//! Run configuration loaded from a TOML file.
+//!
+//! Every field is optional and falls back to the defaults used by the
+//! contest runs, a minimal configuration selecting a solver looks like:
+//!
+//! ```toml
+//! solver = "hnsw"
+//! threads = 8
+//! build_threads = 4
+//!
+//! [paths]
+//! nodes = "./tests/dummy-data.bin"
+//! queries = "./tests/dummy-queries.bin"
+//! output = "./tests/output.bin"
+//!
+//! [hnsw]
+//! m = 16
+//! ef_construction = 200
+//! ef_search = 128
+//! ```
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::K_NEAREST;
+use crate::error::{self, GlasshouseError};
+use crate::solvers::{
+    Baseline, BaselineBuilder, DEFAULT_PAD_ID, Hnsw, HnswBuilder, Ivf, IvfBuilder, Reduced,
+};
+use crate::types::ScoredNeighbor;
+
+/// Solvers that can be selected from the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SolverKind {
+    #[default]
+    Baseline,
+    Exact,
+    Ivf,
+    Hnsw,
+}
+
+/// Similarity the nearest neighbours are ranked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    /// Euclidean distance between the vectors as they are.
+    #[default]
+    L2,
+    /// Cosine similarity, the vectors are scaled to unit length when the
+    /// datasets are loaded and the reported distances are cosine distances,
+    /// `1 - cos`.
+    Cosine,
+}
+
+impl Metric {
+    /// Converts the squared Euclidean distance the solvers rank neighbours
+    /// by to the distance reported under the metric: unchanged under `L2`,
+    /// and halved to the cosine distance `1 - cos` under `Cosine` since the
+    /// vectors then have unit length.
+    pub fn distance(self, squared_l2: f32) -> f32 {
+        match self {
+            Metric::L2 => squared_l2,
+            Metric::Cosine => squared_l2 / 2.0,
+        }
+    }
+
+    /// Converts the distances of the neighbours found for a query, see
+    /// [`Metric::distance`].
+    pub fn report(self, neighbors: &mut [ScoredNeighbor]) {
+        for neighbor in neighbors {
+            neighbor.distance = self.distance(neighbor.distance);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Solver used to answer the queries.
+    pub solver: SolverKind,
+    /// Number of worker threads, `0` uses one thread per core.
+    pub threads: usize,
+    /// Number of worker threads building indexes in a pool of their own,
+    /// so that builds overlapping searches do not take their threads. `0`
+    /// builds on the worker threads. Only the k-means of IVF builds run in
+    /// parallel, HNSW builds insert their nodes one at a time on a single
+    /// thread of the pool.
+    pub build_threads: usize,
+    /// Seed used by the randomized parts of index construction.
+    pub seed: u64,
+    /// Vector dimensionality, inferred from the file sizes when not set.
+    pub dimensions: Option<usize>,
+    /// Number of neighbours returned per query, `K_NEAREST` when not set.
+    pub k: Option<usize>,
+    /// Identifier padding results with fewer than `k` neighbours,
+    /// `u32::MAX` when not set.
+    pub pad_id: Option<u32>,
+    /// Reject datasets holding NaN or infinite values when loading them.
+    pub check_finite: bool,
+    /// Similarity the neighbours are ranked by.
+    pub metric: Metric,
+    pub paths: PathsConfig,
+    pub baseline: BaselineConfig,
+    pub exact: ExactConfig,
+    pub ivf: IvfConfig,
+    pub hnsw: HnswConfig,
+    pub hybrid: HybridConfig,
+    pub pca: PcaConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Nodes dataset to search.
+    pub nodes: PathBuf,
+    /// Shards of the nodes dataset, concatenated in order. When set they
+    /// are loaded instead of `nodes`.
+    pub node_shards: Vec<PathBuf>,
+    /// Queries dataset to answer.
+    pub queries: PathBuf,
+    /// File the results are written to.
+    pub output: PathBuf,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            nodes: PathBuf::from("./tests/dummy-data.bin"),
+            node_shards: Vec::new(),
+            queries: PathBuf::from("./tests/dummy-queries.bin"),
+            output: PathBuf::from("./tests/output.bin"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BaselineConfig {
+    /// Proportion of the nodes scanned for each query.
+    pub sample_proportion: f32,
+}
+
+impl Default for BaselineConfig {
+    fn default() -> Self {
+        BaselineConfig {
+            sample_proportion: Baseline::DEFAULT_SAMPLE_PROPORTION,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExactConfig {
+    /// Number of candidates of a query kept in memory, the others are
+    /// spilled to disk in sorted runs merged once the scan completes. `0`
+    /// keeps every candidate in memory.
+    pub spill_batch: usize,
+    /// Directory the candidates are spilled to, the temporary directory
+    /// when not set.
+    pub spill_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IvfConfig {
+    /// Number of inverted lists.
+    pub nlist: usize,
+    /// Number of lists scanned for each query.
+    pub nprobe: usize,
+    /// Relative slack on the distance of the last of the `nprobe` closest
+    /// centroids within which further lists are scanned, `0.0` disables it.
+    pub multi_probe: f32,
+    /// Probe more lists for queries with selective filters.
+    pub adaptive: bool,
+}
+
+impl Default for IvfConfig {
+    fn default() -> Self {
+        IvfConfig {
+            nlist: Ivf::DEFAULT_NLIST,
+            nprobe: Ivf::DEFAULT_NPROBE,
+            multi_probe: 0.0,
+            adaptive: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HnswConfig {
+    /// Maximum number of neighbours per node on the upper layers.
+    pub m: usize,
+    /// Width of the candidate list used while building the graph.
+    pub ef_construction: usize,
+    /// Width of the candidate list used while searching the graph.
+    pub ef_search: usize,
+    /// Widen the candidate list for queries with selective filters.
+    pub adaptive: bool,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfig {
+            m: Hnsw::DEFAULT_M,
+            ef_construction: Hnsw::DEFAULT_EF_CONSTRUCTION,
+            ef_search: Hnsw::DEFAULT_EF_SEARCH,
+            adaptive: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HybridConfig {
+    /// Weight of the distance between a node timestamp and the middle of
+    /// the query range added to the vector distance, replacing the hard
+    /// timestamp filter. `0.0` keeps the filter.
+    pub weight: f32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PcaConfig {
+    /// Dimensionality the vectors are projected to before building the
+    /// solver, which searches the projected vectors and reranks its
+    /// candidates with the full ones. `0` searches the full vectors.
+    pub dimensions: usize,
+    /// Number of nodes sampled to train the projection.
+    pub sample_size: usize,
+    /// Number of candidates found in the projected space per neighbour
+    /// returned.
+    pub oversampling: usize,
+}
+
+impl Default for PcaConfig {
+    fn default() -> Self {
+        PcaConfig {
+            dimensions: 0,
+            sample_size: Reduced::DEFAULT_SAMPLE_SIZE,
+            oversampling: Reduced::DEFAULT_OVERSAMPLING,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration from a TOML file.
+    #[cfg(feature = "fs")]
+    pub fn read<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        let contents = fs::read_to_string(file_path)?;
+        Self::parse(&contents)
+    }
+
+    /// Parses the configuration from a TOML string.
+    pub fn parse(contents: &str) -> error::Result<Self> {
+        toml::from_str(contents).map_err(|e| GlasshouseError::Config(e.to_string()))
+    }
+
+    /// Number of neighbours returned per query.
+    pub fn k(&self) -> usize {
+        self.k.unwrap_or(K_NEAREST)
+    }
+
+    /// Identifier padding results with fewer than `k` neighbours.
+    pub fn pad_id(&self) -> u32 {
+        self.pad_id.unwrap_or(DEFAULT_PAD_ID)
+    }
+
+    /// Runs `build` on a pool of `build_threads` worker threads, on the
+    /// current pool when `build_threads` is 0.
+    pub fn on_build_threads<T: Send>(
+        &self,
+        build: impl FnOnce() -> error::Result<T> + Send,
+    ) -> error::Result<T> {
+        if self.build_threads == 0 {
+            return build();
+        }
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.build_threads)
+            .thread_name(|index| format!("build-{}", index))
+            .build()
+            .map_err(|e| GlasshouseError::Config(format!("Cannot start the build threads: {}", e)))?
+            .install(build)
+    }
+
+    /// Sets a solver parameter from its dotted name, such as
+    /// `hnsw.ef_search`, and its textual value.
+    pub fn set(&mut self, name: &str, value: &str) -> error::Result<()> {
+        match name {
+            "seed" => self.seed = parse_value(name, value)?,
+            "baseline.sample_proportion" => {
+                self.baseline.sample_proportion = parse_value(name, value)?
+            }
+            "exact.spill_batch" => self.exact.spill_batch = parse_value(name, value)?,
+            "ivf.nlist" => self.ivf.nlist = parse_value(name, value)?,
+            "ivf.nprobe" => self.ivf.nprobe = parse_value(name, value)?,
+            "ivf.multi_probe" => self.ivf.multi_probe = parse_value(name, value)?,
+            "ivf.adaptive" => self.ivf.adaptive = parse_value(name, value)?,
+            "hnsw.m" => self.hnsw.m = parse_value(name, value)?,
+            "hnsw.ef_construction" => self.hnsw.ef_construction = parse_value(name, value)?,
+            "hnsw.ef_search" => self.hnsw.ef_search = parse_value(name, value)?,
+            "hnsw.adaptive" => self.hnsw.adaptive = parse_value(name, value)?,
+            "hybrid.weight" => self.hybrid.weight = parse_value(name, value)?,
+            _ => {
+                return Err(GlasshouseError::Config(format!(
+                    "Unknown parameter {}",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a builder for the configured baseline.
+    pub fn baseline_builder(&self) -> BaselineBuilder {
+        BaselineBuilder::new()
+            .sample_proportion(self.baseline.sample_proportion)
+            .seed(self.seed)
+    }
+
+    /// Returns a builder for the configured IVF index.
+    pub fn ivf_builder(&self) -> IvfBuilder {
+        IvfBuilder::new()
+            .nlist(self.ivf.nlist)
+            .nprobe(self.ivf.nprobe)
+            .multi_probe(self.ivf.multi_probe)
+            .adaptive(self.ivf.adaptive)
+            .seed(self.seed)
+    }
+
+    /// Returns a builder for the configured HNSW graph.
+    pub fn hnsw_builder(&self) -> HnswBuilder {
+        HnswBuilder::new()
+            .m(self.hnsw.m)
+            .ef_construction(self.hnsw.ef_construction)
+            .ef_search(self.hnsw.ef_search)
+            .adaptive(self.hnsw.adaptive)
+            .seed(self.seed)
+    }
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> error::Result<T>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| GlasshouseError::Config(format!("Invalid {} {:?}: {}", name, value, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_run_on_their_own_pool() {
+        let mut config = Config::default();
+        let on_pool = |config: &Config| {
+            config
+                .on_build_threads(|| {
+                    Ok((
+                        rayon::current_num_threads(),
+                        std::thread::current().name().map(str::to_string),
+                    ))
+                })
+                .unwrap()
+        };
+        assert_eq!(on_pool(&config).0, rayon::current_num_threads());
+
+        config.build_threads = 3;
+        let (threads, name) = on_pool(&config);
+        assert_eq!(threads, 3);
+        assert!(name.unwrap().starts_with("build-"));
+    }
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        let config = Config::parse("").unwrap();
+
+        assert_eq!(config.solver, SolverKind::Baseline);
+        assert_eq!(config.hnsw.m, Hnsw::DEFAULT_M);
+        assert_eq!(config.paths.nodes, PathBuf::from("./tests/dummy-data.bin"));
+    }
+
+    #[test]
+    fn can_parse_solver_parameters() {
+        let config = Config::parse(
+            r#"
+            solver = "ivf"
+            threads = 4
+            build_threads = 2
+
+            [paths]
+            output = "out.bin"
+
+            [ivf]
+            nprobe = 32
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.solver, SolverKind::Ivf);
+        assert_eq!(config.threads, 4);
+        assert_eq!(config.build_threads, 2);
+        assert_eq!(
+            config
+                .on_build_threads(|| Ok(rayon::current_num_threads()))
+                .unwrap(),
+            2
+        );
+        assert_eq!(config.ivf.nprobe, 32);
+        assert_eq!(config.ivf.nlist, Ivf::DEFAULT_NLIST);
+        assert_eq!(config.k(), K_NEAREST);
+        assert_eq!(config.paths.output, PathBuf::from("out.bin"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(Config::parse("solver = \"hnsw\"\nefs = 10").is_err());
+    }
+
+    #[test]
+    fn can_set_parameters_by_name() {
+        let mut config = Config::default();
+        config.set("hnsw.ef_search", "256").unwrap();
+        config.set("baseline.sample_proportion", "0.5").unwrap();
+
+        assert_eq!(config.hnsw.ef_search, 256);
+        assert_eq!(config.baseline.sample_proportion, 0.5);
+        assert!(config.set("hnsw.ef_search", "wide").is_err());
+        assert!(config.set("hnsw.efs", "10").is_err());
+    }
+}
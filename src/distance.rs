@@ -0,0 +1,89 @@
+//! Distance kernels used by the solvers.
+
+/// Calculates squared Euclidean distance between two vectors of equal dimensions.
+pub fn l2(vec1: &[f32], vec2: &[f32]) -> f32 {
+    debug_assert_eq!(vec1.len(), vec2.len());
+    vec1.iter().zip(vec2.iter()).fold(0.0, |acc, (a, b)| {
+        let diff = a - b;
+        acc + diff * diff
+    })
+}
+
+/// Number of queries compared at once by [`QueryBlock::l2`].
+pub const QUERY_BLOCK: usize = 8;
+
+/// Vectors of up to [`QUERY_BLOCK`] queries interleaved dimension by
+/// dimension, so that each value of a node is compared with every query of
+/// the block at once. The loop over the queries of a dimension is
+/// vectorized with their distances kept in registers, scanning the nodes
+/// once per block instead of once per query.
+#[derive(Debug, Clone, Default)]
+pub struct QueryBlock {
+    len: usize,
+    values: Vec<f32>,
+}
+
+impl QueryBlock {
+    /// Interleaves the vectors of the queries, which have equal dimensions.
+    ///
+    /// # Panics
+    ///
+    /// Panics with more than [`QUERY_BLOCK`] queries.
+    pub fn new(queries: &[&[f32]]) -> Self {
+        assert!(queries.len() <= QUERY_BLOCK, "too many queries in a block");
+        let dimensions = queries.first().map_or(0, |query| query.len());
+        let mut values = vec![0.0; dimensions * QUERY_BLOCK];
+        for (lane, query) in queries.iter().enumerate() {
+            debug_assert_eq!(query.len(), dimensions);
+            for (dimension, &value) in query.iter().enumerate() {
+                values[dimension * QUERY_BLOCK + lane] = value;
+            }
+        }
+        QueryBlock {
+            len: queries.len(),
+            values,
+        }
+    }
+
+    /// Number of queries in the block.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the block holds no query.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Squared Euclidean distances between the node vector and every query
+    /// of the block, in the order the queries were given. Entries past
+    /// [`QueryBlock::len`] are meaningless. Each distance is summed in the
+    /// same order as [`l2`], so both return the same value.
+    pub fn l2(&self, node: &[f32]) -> [f32; QUERY_BLOCK] {
+        debug_assert_eq!(node.len() * QUERY_BLOCK, self.values.len());
+        let mut sums = [0.0; QUERY_BLOCK];
+        for (&value, lanes) in node.iter().zip(self.values.chunks_exact(QUERY_BLOCK)) {
+            for (sum, &lane) in sums.iter_mut().zip(lanes) {
+                let diff = value - lane;
+                *sum += diff * diff;
+            }
+        }
+        sums
+    }
+}
+
+/// Scales the vector to unit length for the cosine metric: the squared
+/// distance between unit vectors is `2 - 2 cos`, so [`l2`] ranks them by
+/// decreasing cosine similarity, as a dot product kernel would. Returns
+/// false and leaves the vector unchanged when its norm is zero, as it has
+/// no direction.
+pub fn normalize(vector: &mut [f32]) -> bool {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return false;
+    }
+    for value in vector {
+        *value /= norm;
+    }
+    true
+}
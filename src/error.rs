@@ -0,0 +1,29 @@
+//! Error type shared by the library.
+use std::io;
+
+use thiserror::Error;
+
+/// Errors raised while loading data, configuring solvers and answering
+/// queries.
+#[derive(Debug, Error)]
+pub enum GlasshouseError {
+    /// Reading or writing a file failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A dataset, results or index, read from a file or built in memory,
+    /// does not follow its format.
+    #[error("Malformed data: {0}")]
+    Malformed(String),
+    /// A query carries a type outside of the four supported ones.
+    #[error("Invalid query type value: {0}")]
+    InvalidQueryType(f32),
+    /// The configuration or the solver parameters are invalid.
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    /// The nodes and queries hold vectors of different dimensionality.
+    #[error("Nodes have {nodes} dimensions but queries have {queries}")]
+    DimensionMismatch { nodes: usize, queries: usize },
+}
+
+/// Result type of the fallible library functions.
+pub type Result<T> = std::result::Result<T, GlasshouseError>;
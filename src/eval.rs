@@ -0,0 +1,628 @@
+//! Evaluation of search results against exact ground truth.
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::error::{self, GlasshouseError};
+use crate::types::{QueryResult, QueryType};
+
+/// Differences between the top-K sets of a query in two results files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryDiff {
+    /// Index of the query.
+    pub query: usize,
+    /// Size of the intersection of both top-K sets over their union.
+    pub jaccard: f64,
+    /// Neighbours only found in the first results, in rank order.
+    pub only_left: Vec<u32>,
+    /// Neighbours only found in the second results, in rank order.
+    pub only_right: Vec<u32>,
+}
+
+/// Comparison of two results files answering the same queries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultsDiff {
+    pub queries: usize,
+    /// Jaccard overlap of the top-K sets averaged over all queries.
+    pub mean_jaccard: f64,
+    /// Queries whose top-K sets differ, in query order.
+    pub differing: Vec<QueryDiff>,
+}
+
+/// Recall@K over a group of queries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recall {
+    pub queries: usize,
+    pub recall: f64,
+    /// Number of queries whose result is padded, holding fewer than K
+    /// neighbours.
+    pub under_filled: usize,
+}
+
+/// Recall@K of a run estimated from the exact answers of a random sample
+/// of its queries, with a 95% confidence interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecallEstimate {
+    /// Number of sampled queries.
+    pub queries: usize,
+    pub recall: f64,
+    /// Bounds of the confidence interval, within `[0, 1]`.
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Recall@K over all queries and over the queries of each type, types
+/// without any query are omitted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecallReport {
+    pub overall: Recall,
+    pub by_type: Vec<(QueryType, Recall)>,
+}
+
+/// Returns the recall@K of the results, the fraction of ground truth
+/// neighbours present in the results over all queries. K is the number of
+/// neighbours of each result, ground truth holding more is cut to its K
+/// closest. Entries equal to `pad_id` fill results with fewer than K
+/// neighbours, they are neither counted as ground truth neighbours nor as
+/// found ones.
+pub fn recall(
+    results: &[QueryResult],
+    ground_truth: &[QueryResult],
+    pad_id: u32,
+) -> error::Result<f64> {
+    if results.len() != ground_truth.len() {
+        return Err(GlasshouseError::Malformed(format!(
+            "Results hold {} queries but ground truth holds {}",
+            results.len(),
+            ground_truth.len()
+        )));
+    }
+
+    let (mut found, mut expected) = (0, 0);
+    for (result, truth) in results.iter().zip(ground_truth) {
+        let (query_found, query_expected) = shared(result, truth, pad_id);
+        found += query_found;
+        expected += query_expected;
+    }
+    if expected == 0 {
+        return Ok(1.0);
+    }
+    Ok(found as f64 / expected as f64)
+}
+
+/// Estimates the recall@K of a run of `population` queries from the
+/// results and exact answers of a uniform random sample of them, see
+/// [`recall`]. The recall is a ratio of sums over the sampled queries, its
+/// interval follows from the linearized variance of that ratio with a
+/// finite population correction.
+pub fn estimate_recall(
+    results: &[QueryResult],
+    ground_truth: &[QueryResult],
+    pad_id: u32,
+    population: usize,
+) -> error::Result<RecallEstimate> {
+    let recall = recall(results, ground_truth, pad_id)?;
+    let counts: Vec<(f64, f64)> = results
+        .iter()
+        .zip(ground_truth)
+        .map(|(result, truth)| {
+            let (found, expected) = shared(result, truth, pad_id);
+            (found as f64, expected as f64)
+        })
+        .collect();
+    let queries = counts.len();
+    let mean_expected = counts.iter().map(|&(_, expected)| expected).sum::<f64>() / queries as f64;
+    if queries < 2 || mean_expected == 0.0 {
+        return Ok(RecallEstimate {
+            queries,
+            recall,
+            lower: if queries == population { recall } else { 0.0 },
+            upper: if queries == population { recall } else { 1.0 },
+        });
+    }
+
+    let residuals = counts
+        .iter()
+        .map(|&(found, expected)| (found - recall * expected).powi(2))
+        .sum::<f64>()
+        / (queries - 1) as f64;
+    let correction = 1.0 - queries as f64 / population.max(queries) as f64;
+    let standard_error = (correction * residuals / queries as f64).sqrt() / mean_expected;
+    // Two-sided 95% quantile of the normal distribution.
+    let margin = 1.96 * standard_error;
+    Ok(RecallEstimate {
+        queries,
+        recall,
+        lower: (recall - margin).max(0.0),
+        upper: (recall + margin).min(1.0),
+    })
+}
+
+/// Returns the number of results padded with `pad_id`, holding fewer than
+/// K neighbours.
+pub fn under_filled(results: &[QueryResult], pad_id: u32) -> usize {
+    results
+        .iter()
+        .filter(|result| result.contains(&pad_id))
+        .count()
+}
+
+/// Returns the number of ground truth neighbours of a query present in its
+/// result, and the number of ground truth neighbours.
+fn shared(result: &QueryResult, truth: &QueryResult, pad_id: u32) -> (usize, usize) {
+    let truth: HashSet<&u32> = truth
+        .iter()
+        .take(result.len())
+        .filter(|&&id| id != pad_id)
+        .collect();
+    let result: HashSet<&u32> = result.iter().filter(|&&id| id != pad_id).collect();
+    (result.intersection(&truth).count(), truth.len())
+}
+
+/// Returns the recall@K and the number of under-filled results overall and
+/// per query type, `query_types` holds the type of each query in the
+/// results order.
+pub fn recall_by_type(
+    results: &[QueryResult],
+    ground_truth: &[QueryResult],
+    query_types: &[QueryType],
+    pad_id: u32,
+) -> error::Result<RecallReport> {
+    if query_types.len() != results.len() {
+        return Err(GlasshouseError::Malformed(format!(
+            "Results hold {} queries but the queries dataset holds {}",
+            results.len(),
+            query_types.len()
+        )));
+    }
+    let overall = Recall {
+        queries: results.len(),
+        recall: recall(results, ground_truth, pad_id)?,
+        under_filled: under_filled(results, pad_id),
+    };
+
+    let mut by_type = Vec::new();
+    for query_type in QueryType::ALL {
+        let (results, ground_truth): (Vec<_>, Vec<_>) = results
+            .iter()
+            .zip(ground_truth)
+            .zip(query_types)
+            .filter(|(_, t)| **t == query_type)
+            .map(|((result, truth), _)| (result.clone(), truth.clone()))
+            .unzip();
+        if !results.is_empty() {
+            let recall = recall(&results, &ground_truth, pad_id)?;
+            by_type.push((
+                query_type,
+                Recall {
+                    queries: results.len(),
+                    recall,
+                    under_filled: under_filled(&results, pad_id),
+                },
+            ));
+        }
+    }
+    Ok(RecallReport { overall, by_type })
+}
+
+/// Recall@K of the queries of a run answered so far, updated from the
+/// worker threads as queries complete.
+///
+/// Runs answer the queries grouped by type, the recall of each type is
+/// meaningful early on while the overall recall only is once most queries
+/// are answered.
+#[derive(Debug)]
+pub struct LiveRecall<'a> {
+    ground_truth: &'a [QueryResult],
+    query_types: &'a [QueryType],
+    pad_id: u32,
+    /// Rows of the ground truth answered by each recorded query when the
+    /// run answers deduplicated queries, empty when it answers every row.
+    duplicates: Vec<Vec<usize>>,
+    /// Answered queries, found and expected ground truth neighbours, and
+    /// under-filled results of each query type.
+    counts: Mutex<[[usize; 4]; 4]>,
+}
+
+impl<'a> LiveRecall<'a> {
+    /// Starts tracking the recall of a run of the queries of the given
+    /// types, before any query is answered.
+    pub fn new(
+        ground_truth: &'a [QueryResult],
+        query_types: &'a [QueryType],
+        pad_id: u32,
+    ) -> error::Result<Self> {
+        if query_types.len() != ground_truth.len() {
+            return Err(GlasshouseError::Malformed(format!(
+                "Ground truth holds {} queries but the queries dataset holds {}",
+                ground_truth.len(),
+                query_types.len()
+            )));
+        }
+        Ok(LiveRecall {
+            ground_truth,
+            query_types,
+            pad_id,
+            duplicates: Vec::new(),
+            counts: Mutex::new([[0; 4]; 4]),
+        })
+    }
+
+    /// Tracks a run answering the distinct queries of the dataset, `rows`
+    /// maps each query of the dataset to its distinct query as returned by
+    /// `QueriesDataset::dedup`. The result recorded for a distinct query
+    /// counts for every query of the dataset it stands for.
+    pub fn with_rows(mut self, rows: &[u32]) -> error::Result<Self> {
+        if rows.len() != self.ground_truth.len() {
+            return Err(GlasshouseError::Malformed(format!(
+                "Ground truth holds {} queries but {} rows were deduplicated",
+                self.ground_truth.len(),
+                rows.len()
+            )));
+        }
+        let mut duplicates =
+            vec![Vec::new(); rows.iter().map(|&row| row as usize + 1).max().unwrap_or(0)];
+        for (query, &row) in rows.iter().enumerate() {
+            duplicates[row as usize].push(query);
+        }
+        self.duplicates = duplicates;
+        Ok(self)
+    }
+
+    /// Records the result of a query, padded to K with `pad_id`.
+    pub fn record(&self, query: usize, result: &QueryResult) {
+        if self.duplicates.is_empty() {
+            self.record_row(query, result);
+        } else {
+            for &row in &self.duplicates[query] {
+                self.record_row(row, result);
+            }
+        }
+    }
+
+    fn record_row(&self, query: usize, result: &QueryResult) {
+        let (found, expected) = shared(result, &self.ground_truth[query], self.pad_id);
+        let under_filled = result.contains(&self.pad_id) as usize;
+        let query_type = self.query_types[query].to_f32() as usize;
+        let mut counts = self.counts.lock().unwrap();
+        for (count, added) in counts[query_type]
+            .iter_mut()
+            .zip([1, found, expected, under_filled])
+        {
+            *count += added;
+        }
+    }
+
+    /// Returns the recall of the queries answered so far, overall and for
+    /// each type with answered queries.
+    pub fn report(&self) -> RecallReport {
+        let counts = *self.counts.lock().unwrap();
+        let recall = |[queries, found, expected, under_filled]: [usize; 4]| Recall {
+            queries,
+            recall: if expected == 0 {
+                1.0
+            } else {
+                found as f64 / expected as f64
+            },
+            under_filled,
+        };
+        let mut total = [0; 4];
+        for type_counts in &counts {
+            for (sum, count) in total.iter_mut().zip(type_counts) {
+                *sum += count;
+            }
+        }
+        RecallReport {
+            overall: recall(total),
+            by_type: QueryType::ALL
+                .into_iter()
+                .zip(counts)
+                .filter(|(_, type_counts)| type_counts[0] > 0)
+                .map(|(query_type, type_counts)| (query_type, recall(type_counts)))
+                .collect(),
+        }
+    }
+}
+
+/// Compares the top-K sets of two results, ignoring the order of the
+/// neighbours within each set.
+pub fn diff(left: &[QueryResult], right: &[QueryResult]) -> error::Result<ResultsDiff> {
+    if left.len() != right.len() {
+        return Err(GlasshouseError::Malformed(format!(
+            "First results hold {} queries but second results hold {}",
+            left.len(),
+            right.len()
+        )));
+    }
+
+    let mut jaccard_sum = 0.0;
+    let mut differing = Vec::new();
+    for (query, (left, right)) in left.iter().zip(right).enumerate() {
+        let left_set: HashSet<&u32> = left.iter().collect();
+        let right_set: HashSet<&u32> = right.iter().collect();
+        let shared = left_set.intersection(&right_set).count();
+        let jaccard = shared as f64 / left_set.union(&right_set).count() as f64;
+        jaccard_sum += jaccard;
+        if shared != left_set.len() || shared != right_set.len() {
+            differing.push(QueryDiff {
+                query,
+                jaccard,
+                only_left: missing_from(left, &right_set),
+                only_right: missing_from(right, &left_set),
+            });
+        }
+    }
+    Ok(ResultsDiff {
+        queries: left.len(),
+        mean_jaccard: if left.is_empty() {
+            1.0
+        } else {
+            jaccard_sum / left.len() as f64
+        },
+        differing,
+    })
+}
+
+/// Returns the distinct neighbours of the result missing from the set, in
+/// rank order.
+fn missing_from(result: &QueryResult, set: &HashSet<&u32>) -> Vec<u32> {
+    let mut seen = HashSet::new();
+    result
+        .iter()
+        .filter(|id| !set.contains(id) && seen.insert(**id))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::K_NEAREST;
+
+    #[test]
+    fn recall_counts_shared_neighbours() {
+        let truth: QueryResult = (0..K_NEAREST as u32).collect();
+        let half: QueryResult = (0..K_NEAREST as u32)
+            .map(|i| i + K_NEAREST as u32 / 2)
+            .collect();
+
+        let truth = [truth];
+        assert_eq!(recall(&truth, &truth, u32::MAX).unwrap(), 1.0);
+        assert_eq!(recall(&[half], &truth, u32::MAX).unwrap(), 0.5);
+        assert!(matches!(
+            recall(&[truth[0].clone(), truth[0].clone()], &truth, u32::MAX),
+            Err(GlasshouseError::Malformed(_))
+        ));
+
+        // Results of fewer neighbours are compared to the closest ones.
+        let top_10: QueryResult = truth[0][..10].into();
+        assert_eq!(recall(&[top_10], &truth, u32::MAX).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn recall_estimates_bound_the_sampled_recall() {
+        let truth: QueryResult = (0..K_NEAREST as u32).collect();
+        let half: QueryResult = (0..K_NEAREST as u32)
+            .map(|i| i + K_NEAREST as u32 / 2)
+            .collect();
+        let results: Vec<QueryResult> = (0..20)
+            .map(|i| {
+                if i % 4 == 0 {
+                    half.clone()
+                } else {
+                    truth.clone()
+                }
+            })
+            .collect();
+        let ground_truth = vec![truth; 20];
+
+        let estimate = estimate_recall(&results, &ground_truth, u32::MAX, 1000).unwrap();
+        assert_eq!(estimate.queries, 20);
+        assert!((estimate.recall - 0.875).abs() < 1e-9);
+        assert!(estimate.lower < 0.875 && 0.875 < estimate.upper);
+        assert!(estimate.lower > 0.75 && estimate.upper < 1.0);
+
+        // Sampling every query leaves no uncertainty.
+        let census = estimate_recall(&results, &ground_truth, u32::MAX, 20).unwrap();
+        assert_eq!((census.lower, census.upper), (census.recall, census.recall));
+        assert!(matches!(
+            estimate_recall(&results[..2], &ground_truth, u32::MAX, 20),
+            Err(GlasshouseError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn padding_is_not_a_neighbour() {
+        // Two matching nodes, 0 and 5, the rest is padding.
+        let pad = |pad_id: u32, ids: &[u32]| -> QueryResult {
+            (0..K_NEAREST)
+                .map(|i| ids.get(i).copied().unwrap_or(pad_id))
+                .collect()
+        };
+        let truth = [pad(u32::MAX, &[5, 0])];
+        assert_eq!(
+            recall(&[pad(u32::MAX, &[0, 5])], &truth, u32::MAX).unwrap(),
+            1.0
+        );
+        assert_eq!(
+            recall(&[pad(u32::MAX, &[5])], &truth, u32::MAX).unwrap(),
+            0.5
+        );
+        // Padding with a node ID reports node 0 as found.
+        assert_eq!(recall(&[pad(0, &[5])], &truth, u32::MAX).unwrap(), 1.0);
+        assert_eq!(
+            recall(&[pad(u32::MAX, &[])], &[pad(u32::MAX, &[])], u32::MAX).unwrap(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn under_filled_results_hold_padding() {
+        let full: QueryResult = (0..K_NEAREST as u32).collect();
+        let mut short = full.clone();
+        short[K_NEAREST - 1] = u32::MAX;
+
+        assert_eq!(under_filled(&[], u32::MAX), 0);
+        assert_eq!(under_filled(&[full.clone(), short.clone()], u32::MAX), 1);
+        // Results holding the pad ID as a node are counted as padded.
+        assert_eq!(under_filled(&[full, short], 0), 2);
+    }
+
+    #[test]
+    fn recall_is_split_by_query_type() {
+        let truth: QueryResult = (0..K_NEAREST as u32).collect();
+        let half: QueryResult = (0..K_NEAREST as u32)
+            .map(|i| i + K_NEAREST as u32 / 2)
+            .collect();
+        let types = [
+            QueryType::VectorOnly,
+            QueryType::TimestampConstraint,
+            QueryType::VectorOnly,
+        ];
+
+        let mut padded = half.clone();
+        padded[K_NEAREST - 1] = u32::MAX;
+        let results = [truth.clone(), padded, half];
+        let ground_truth = vec![truth.clone(); 3];
+        let report = recall_by_type(&results, &ground_truth, &types, u32::MAX).unwrap();
+        assert_eq!(report.overall.queries, 3);
+        assert!((report.overall.recall - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(report.overall.under_filled, 1);
+        assert_eq!(
+            report.by_type,
+            vec![
+                (
+                    QueryType::VectorOnly,
+                    Recall {
+                        queries: 2,
+                        recall: 0.75,
+                        under_filled: 0,
+                    }
+                ),
+                (
+                    QueryType::TimestampConstraint,
+                    Recall {
+                        queries: 1,
+                        recall: 0.5,
+                        under_filled: 1,
+                    }
+                ),
+            ]
+        );
+        assert!(matches!(
+            recall_by_type(
+                std::slice::from_ref(&truth),
+                std::slice::from_ref(&truth),
+                &types,
+                u32::MAX
+            ),
+            Err(GlasshouseError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn live_recall_matches_the_final_recall() {
+        let truth: QueryResult = (0..K_NEAREST as u32).collect();
+        let half: QueryResult = (0..K_NEAREST as u32)
+            .map(|i| i + K_NEAREST as u32 / 2)
+            .collect();
+        let mut padded = half.clone();
+        padded[K_NEAREST - 1] = u32::MAX;
+        let results = [truth.clone(), padded, half];
+        let types = [
+            QueryType::VectorOnly,
+            QueryType::TimestampConstraint,
+            QueryType::VectorOnly,
+        ];
+        let ground_truth = vec![truth; 3];
+
+        let live = LiveRecall::new(&ground_truth, &types, u32::MAX).unwrap();
+        assert_eq!(live.report().overall.queries, 0);
+        live.record(1, &results[1]);
+        let report = live.report();
+        assert_eq!(report.overall.recall, 0.5);
+        assert_eq!(report.by_type.len(), 1);
+        live.record(0, &results[0]);
+        live.record(2, &results[2]);
+        assert_eq!(
+            live.report(),
+            recall_by_type(&results, &ground_truth, &types, u32::MAX).unwrap()
+        );
+        assert!(matches!(
+            LiveRecall::new(&ground_truth, &types[..2], u32::MAX),
+            Err(GlasshouseError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn live_recall_counts_deduplicated_queries_for_every_row() {
+        let truth: QueryResult = (0..K_NEAREST as u32).collect();
+        let half: QueryResult = (0..K_NEAREST as u32)
+            .map(|i| i + K_NEAREST as u32 / 2)
+            .collect();
+        // Queries 0 and 2 are identical, the run answers queries 0 and 1.
+        let rows = [0, 1, 0];
+        let distinct = [half.clone(), truth.clone()];
+        let types = [
+            QueryType::VectorOnly,
+            QueryType::TimestampConstraint,
+            QueryType::VectorOnly,
+        ];
+        let ground_truth = vec![truth; 3];
+
+        let live = LiveRecall::new(&ground_truth, &types, u32::MAX)
+            .unwrap()
+            .with_rows(&rows)
+            .unwrap();
+        live.record(1, &distinct[1]);
+        live.record(0, &distinct[0]);
+        let results: Vec<QueryResult> = rows
+            .iter()
+            .map(|&row| distinct[row as usize].clone())
+            .collect();
+        let report = live.report();
+        assert_eq!(report.overall.queries, 3);
+        assert_eq!(
+            report,
+            recall_by_type(&results, &ground_truth, &types, u32::MAX).unwrap()
+        );
+
+        let live = LiveRecall::new(&ground_truth, &types, u32::MAX).unwrap();
+        assert!(matches!(
+            live.with_rows(&rows[..2]),
+            Err(GlasshouseError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn diffs_report_differing_queries() {
+        let truth: QueryResult = (0..K_NEAREST as u32).collect();
+        let mut reversed = truth.clone();
+        reversed.reverse();
+        let mut changed = truth.clone();
+        changed[0] = 1000;
+
+        let diff = diff(&[truth.clone(), truth], &[reversed, changed]).unwrap();
+        assert_eq!(diff.queries, 2);
+        assert_eq!(diff.differing.len(), 1);
+        let query = &diff.differing[0];
+        assert_eq!(query.query, 1);
+        assert_eq!(
+            (query.only_left.clone(), query.only_right.clone()),
+            (vec![0], vec![1000])
+        );
+        let jaccard = (K_NEAREST - 1) as f64 / (K_NEAREST + 1) as f64;
+        assert_eq!(query.jaccard, jaccard);
+        assert_eq!(diff.mean_jaccard, (1.0 + jaccard) / 2.0);
+    }
+
+    #[test]
+    fn diffs_answer_the_same_queries() {
+        let truth: QueryResult = (0..K_NEAREST as u32).collect();
+        assert!(matches!(
+            diff(std::slice::from_ref(&truth), &[]),
+            Err(GlasshouseError::Malformed(_))
+        ));
+        let empty = diff(&[], &[]).unwrap();
+        assert_eq!((empty.queries, empty.mean_jaccard), (0, 1.0));
+    }
+}
@@ -0,0 +1,415 @@
+//! C interface to load datasets, build solvers and answer query batches.
+//!
+//! All objects are returned as opaque pointers owned by the caller, which
+//! must release them with the matching `glasshouse_*_free` function.
+//! Functions returning a pointer return null on failure and functions
+//! returning an `int` return a negative value on failure, in both cases
+//! `glasshouse_last_error` describes the failure. Panics are caught at
+//! the boundary and reported the same way instead of unwinding into the
+//! caller. See `include/glasshouse.h` for the C declarations.
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::config::{Config, Metric};
+use crate::constants::K_NEAREST;
+use crate::solvers::{self, Solver};
+use crate::types::{NodesDataset, QueriesDataset};
+
+/// Nodes dataset, declared as the opaque `GlasshouseNodes` in C.
+pub type GlasshouseNodes = NodesDataset;
+
+/// Queries dataset, declared as the opaque `GlasshouseQueries` in C.
+pub type GlasshouseQueries = QueriesDataset;
+
+/// Solver built over a nodes dataset with the number of neighbours it
+/// returns per query, opaque to C callers.
+pub struct GlasshouseSolver {
+    solver: Box<dyn Solver>,
+    k: usize,
+    metric: Metric,
+    /// Copy of the nodes scaled to unit length under the cosine metric,
+    /// which the solver was built over in place of the caller's nodes.
+    normalized: Option<NodesDataset>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Describes the payload of a caught panic.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Runs the body of an exported function, a panic is recorded as the last
+/// error and `on_panic` returned in place of unwinding into the C caller.
+fn guard<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        set_last_error(format!("Panicked: {}", panic_message(payload.as_ref())));
+        on_panic
+    })
+}
+
+/// Converts a C string argument, recording an error if it is invalid.
+unsafe fn to_str<'a>(value: *const c_char, name: &str) -> Option<&'a str> {
+    if value.is_null() {
+        set_last_error(format!("{} is null", name));
+        return None;
+    }
+    match unsafe { CStr::from_ptr(value) }.to_str() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            set_last_error(format!("{} is not valid UTF-8: {}", name, e));
+            None
+        }
+    }
+}
+
+/// Returns the message describing the last failure on the calling thread,
+/// or null if no failure happened. The message is valid until the next
+/// failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn glasshouse_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|last_error| {
+            last_error
+                .borrow()
+                .as_ref()
+                .map_or(ptr::null(), |message| message.as_ptr())
+        })
+    })
+}
+
+/// Number of neighbours returned per query by solvers whose configuration
+/// does not set `k`.
+#[unsafe(no_mangle)]
+pub extern "C" fn glasshouse_k_nearest() -> usize {
+    K_NEAREST
+}
+
+/// Reads a nodes dataset from a binary file.
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_nodes_open(path: *const c_char) -> *mut GlasshouseNodes {
+    guard(ptr::null_mut(), || {
+        let Some(path) = (unsafe { to_str(path, "path") }) else {
+            return ptr::null_mut();
+        };
+        match NodesDataset::read(path) {
+            Ok(nodes_dataset) => Box::into_raw(Box::new(nodes_dataset)),
+            Err(e) => {
+                set_last_error(format!("Failed to load nodes dataset: {}", e));
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Number of nodes in the dataset.
+///
+/// # Safety
+///
+/// `nodes` must be a dataset returned by `glasshouse_nodes_open`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_nodes_len(nodes: *const GlasshouseNodes) -> u32 {
+    guard(0, || {
+        unsafe { nodes.as_ref() }.map_or(0, |nodes_dataset| nodes_dataset.num_vectors)
+    })
+}
+
+/// Releases a nodes dataset, passing null is a no-op.
+///
+/// # Safety
+///
+/// `nodes` must be null or a dataset returned by `glasshouse_nodes_open`
+/// that was not released yet and is not used by a live solver.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_nodes_free(nodes: *mut GlasshouseNodes) {
+    guard((), || {
+        if !nodes.is_null() {
+            drop(unsafe { Box::from_raw(nodes) });
+        }
+    })
+}
+
+/// Reads a queries dataset from a binary file.
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_queries_open(path: *const c_char) -> *mut GlasshouseQueries {
+    guard(ptr::null_mut(), || {
+        let Some(path) = (unsafe { to_str(path, "path") }) else {
+            return ptr::null_mut();
+        };
+        match QueriesDataset::read(path) {
+            Ok(queries_dataset) => Box::into_raw(Box::new(queries_dataset)),
+            Err(e) => {
+                set_last_error(format!("Failed to load queries dataset: {}", e));
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Number of queries in the dataset.
+///
+/// # Safety
+///
+/// `queries` must be a dataset returned by `glasshouse_queries_open`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_queries_len(queries: *const GlasshouseQueries) -> u32 {
+    guard(0, || {
+        unsafe { queries.as_ref() }.map_or(0, |queries_dataset| queries_dataset.num_queries)
+    })
+}
+
+/// Releases a queries dataset, passing null is a no-op.
+///
+/// # Safety
+///
+/// `queries` must be null or a dataset returned by `glasshouse_queries_open`
+/// that was not released yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_queries_free(queries: *mut GlasshouseQueries) {
+    guard((), || {
+        if !queries.is_null() {
+            drop(unsafe { Box::from_raw(queries) });
+        }
+    })
+}
+
+/// Builds the solver described by a TOML configuration, in the format of
+/// the `--config` file, over the nodes dataset. Paths in the configuration
+/// are ignored. Under the cosine metric the solver is built over a copy of
+/// the nodes scaled to unit length.
+///
+/// # Safety
+///
+/// `nodes` must be a dataset returned by `glasshouse_nodes_open` and
+/// `config` a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_solver_build(
+    nodes: *const GlasshouseNodes,
+    config: *const c_char,
+) -> *mut GlasshouseSolver {
+    guard(ptr::null_mut(), || {
+        let Some(nodes_dataset) = (unsafe { nodes.as_ref() }) else {
+            set_last_error("nodes is null".to_string());
+            return ptr::null_mut();
+        };
+        let Some(config) = (unsafe { to_str(config, "config") }) else {
+            return ptr::null_mut();
+        };
+
+        let solver = Config::parse(config).and_then(|config| {
+            let normalized = (config.metric == Metric::Cosine).then(|| {
+                let mut normalized = nodes_dataset.clone();
+                normalized.normalize_vectors();
+                normalized
+            });
+            let solver = solvers::build(&config, normalized.as_ref().unwrap_or(nodes_dataset))?;
+            Ok(GlasshouseSolver {
+                solver,
+                k: config.k(),
+                metric: config.metric,
+                normalized,
+            })
+        });
+        match solver {
+            Ok(solver) => Box::into_raw(Box::new(solver)),
+            Err(e) => {
+                set_last_error(e.to_string());
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Number of neighbours returned per query by a solver, 0 if it is null.
+///
+/// # Safety
+///
+/// `solver` must be null or a solver returned by `glasshouse_solver_build`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_solver_k(solver: *const GlasshouseSolver) -> usize {
+    guard(0, || {
+        unsafe { solver.as_ref() }.map_or(0, |solver| solver.k)
+    })
+}
+
+/// Releases a solver, passing null is a no-op.
+///
+/// # Safety
+///
+/// `solver` must be null or a solver returned by `glasshouse_solver_build`
+/// that was not released yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_solver_free(solver: *mut GlasshouseSolver) {
+    guard((), || {
+        if !solver.is_null() {
+            drop(unsafe { Box::from_raw(solver) });
+        }
+    })
+}
+
+/// Answers every query of the dataset in parallel and writes the results
+/// to `results`, `glasshouse_solver_k` node IDs per query in query order. Returns the
+/// number of answered queries, datasets holding more queries than an `int`
+/// can count are rejected. Under the cosine metric a copy of the queries
+/// scaled to unit length is searched.
+///
+/// # Safety
+///
+/// `solver` must have been built over `nodes`, and `results` must point to
+/// `results_len` writable `uint32_t`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn glasshouse_search_batch(
+    solver: *const GlasshouseSolver,
+    nodes: *const GlasshouseNodes,
+    queries: *const GlasshouseQueries,
+    results: *mut u32,
+    results_len: usize,
+) -> c_int {
+    guard(-1, || {
+        let (Some(solver), Some(nodes_dataset), Some(queries_dataset)) =
+            (unsafe { (solver.as_ref(), nodes.as_ref(), queries.as_ref()) })
+        else {
+            set_last_error("solver, nodes and queries must not be null".to_string());
+            return -1;
+        };
+        let Ok(answered) = c_int::try_from(queries_dataset.num_queries) else {
+            set_last_error(format!(
+                "{} queries do not fit the returned count",
+                queries_dataset.num_queries
+            ));
+            return -1;
+        };
+        let Some(required_len) = (queries_dataset.num_queries as usize).checked_mul(solver.k)
+        else {
+            set_last_error("results do not fit in memory".to_string());
+            return -1;
+        };
+        if results.is_null() || results_len < required_len {
+            set_last_error(format!(
+                "results must hold {} IDs, got {}",
+                required_len, results_len
+            ));
+            return -1;
+        }
+
+        let normalized = match solver.metric {
+            Metric::Cosine => {
+                let mut normalized = queries_dataset.clone();
+                if let Err(e) = normalized.normalize_vectors() {
+                    set_last_error(e.to_string());
+                    return -1;
+                }
+                Some(normalized)
+            }
+            Metric::L2 => None,
+        };
+        match solvers::run(
+            solver.solver.as_ref(),
+            solver.normalized.as_ref().unwrap_or(nodes_dataset),
+            normalized.as_ref().unwrap_or(queries_dataset),
+            solver.k,
+        ) {
+            Ok(answers) => {
+                let results = unsafe { std::slice::from_raw_parts_mut(results, required_len) };
+                for (chunk, answer) in results.chunks_exact_mut(solver.k).zip(&answers) {
+                    chunk.copy_from_slice(answer);
+                }
+                answered
+            }
+            Err(e) => {
+                set_last_error(e.to_string());
+                -1
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::solvers::Exact;
+
+    #[test]
+    fn can_search_through_the_c_interface() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let nodes_path = crate::testing::temp_path("ffi-nodes.bin");
+        let queries_path = crate::testing::temp_path("ffi-queries.bin");
+        let nodes_dataset = generate::nodes(&mut rng, 200, 8, 4);
+        let queries_dataset = generate::queries(&mut rng, 10, 8, 4);
+        nodes_dataset.write(&nodes_path).unwrap();
+        queries_dataset.write(&queries_path).unwrap();
+        let expected = solvers::run(&Exact, &nodes_dataset, &queries_dataset, K_NEAREST).unwrap();
+
+        let c_path = |path: &std::path::Path| CString::new(path.to_str().unwrap()).unwrap();
+        let config = CString::new("solver = \"exact\"").unwrap();
+        let bad_config = CString::new("solver = \"unknown\"").unwrap();
+        let mut results = vec![0u32; 10 * K_NEAREST];
+        unsafe {
+            let nodes = glasshouse_nodes_open(c_path(&nodes_path).as_ptr());
+            let queries = glasshouse_queries_open(c_path(&queries_path).as_ptr());
+            assert_eq!(glasshouse_nodes_len(nodes), 200);
+            assert_eq!(glasshouse_queries_len(queries), 10);
+
+            assert!(glasshouse_solver_build(nodes, bad_config.as_ptr()).is_null());
+            assert!(!glasshouse_last_error().is_null());
+
+            let solver = glasshouse_solver_build(nodes, config.as_ptr());
+            assert!(!solver.is_null());
+            assert_eq!(glasshouse_solver_k(solver), K_NEAREST);
+            let answered = glasshouse_search_batch(
+                solver,
+                nodes,
+                queries,
+                results.as_mut_ptr(),
+                results.len(),
+            );
+            assert_eq!(answered, 10);
+            assert_eq!(
+                glasshouse_search_batch(solver, nodes, queries, results.as_mut_ptr(), 1),
+                -1
+            );
+
+            glasshouse_solver_free(solver);
+            glasshouse_queries_free(queries);
+            glasshouse_nodes_free(nodes);
+        }
+        std::fs::remove_file(&nodes_path).unwrap();
+        std::fs::remove_file(&queries_path).unwrap();
+
+        assert_eq!(results, expected.concat());
+    }
+
+    #[test]
+    fn panics_are_reported_as_errors() {
+        assert_eq!(guard(-1, || panic!("solver failed")), -1);
+
+        let message = unsafe { CStr::from_ptr(glasshouse_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "Panicked: solver failed");
+    }
+}
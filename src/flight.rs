@@ -0,0 +1,341 @@
+//! Arrow Flight service for bulk query submission.
+//!
+//! Clients drive the engine with a `DoExchange` call: they send record
+//! batches of queries and receive, for each of them, a record batch holding
+//! the neighbours of every query. Query batches have the columns described
+//! in [`crate::arrow`]. Result batches have the columns `query_id`
+//! (position of the query among all the queries of the exchange), `rank`,
+//! `node_id` and `distance`, one row per neighbour, with at most `k`
+//! neighbours per query. Under the cosine metric query vectors are scaled
+//! to unit length as the nodes are, zero vectors are rejected, and
+//! distances are cosine distances, `1 - cos`.
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock};
+
+use arrow_array::{ArrayRef, Float32Array, RecordBatch, UInt32Array, UInt64Array};
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::config::Metric;
+use crate::constants::K_NEAREST;
+use crate::solvers::{self, Solver};
+use crate::types::{NodesDataset, QueriesDataset};
+
+/// Schema of the result batches.
+pub static RESULTS_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    Arc::new(Schema::new(vec![
+        Field::new("query_id", DataType::UInt64, false),
+        Field::new("rank", DataType::UInt32, false),
+        Field::new("node_id", DataType::UInt32, false),
+        Field::new("distance", DataType::Float32, false),
+    ]))
+});
+
+struct FlightState {
+    nodes_dataset: NodesDataset,
+    solver: Box<dyn Solver>,
+}
+
+/// Implementation of the Arrow Flight service.
+#[derive(Clone)]
+pub struct SearchFlightService {
+    state: Arc<FlightState>,
+    k: usize,
+    metric: Metric,
+}
+
+impl SearchFlightService {
+    pub fn new(nodes_dataset: NodesDataset, solver: Box<dyn Solver>) -> Self {
+        SearchFlightService {
+            state: Arc::new(FlightState {
+                nodes_dataset,
+                solver,
+            }),
+            k: K_NEAREST,
+            metric: Metric::L2,
+        }
+    }
+
+    /// Sets the number of neighbours returned per query.
+    pub fn with_k(mut self, k: usize) -> Self {
+        self.k = k;
+        self
+    }
+
+    /// Sets the metric the nodes were loaded under, query vectors are
+    /// scaled to unit length under the cosine metric.
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Wraps the service into a server that can be added to a tonic router.
+    pub fn into_server(self) -> FlightServiceServer<Self> {
+        FlightServiceServer::new(self)
+    }
+
+    /// Answers a batch of queries, numbering them from `first_query_id`.
+    pub fn search_batch(
+        &self,
+        batch: &RecordBatch,
+        first_query_id: u64,
+    ) -> Result<RecordBatch, Status> {
+        let mut queries_dataset =
+            QueriesDataset::try_from(batch).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        if self.metric == Metric::Cosine {
+            queries_dataset
+                .normalize_vectors()
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+        let results = solvers::run_scored(
+            self.state.solver.as_ref(),
+            &self.state.nodes_dataset,
+            &queries_dataset,
+            self.k,
+        )
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let num_rows = results.iter().map(|result| result.len()).sum();
+        let mut query_ids = Vec::with_capacity(num_rows);
+        let mut ranks = Vec::with_capacity(num_rows);
+        let mut node_ids = Vec::with_capacity(num_rows);
+        let mut distances = Vec::with_capacity(num_rows);
+        for (index, result) in results.iter().enumerate() {
+            for (rank, neighbor) in result.iter().enumerate() {
+                query_ids.push(first_query_id + index as u64);
+                ranks.push(rank as u32);
+                node_ids.push(neighbor.id);
+                distances.push(self.metric.distance(neighbor.distance));
+            }
+        }
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(query_ids)),
+            Arc::new(UInt32Array::from(ranks)),
+            Arc::new(UInt32Array::from(node_ids)),
+            Arc::new(Float32Array::from(distances)),
+        ];
+        RecordBatch::try_new(RESULTS_SCHEMA.clone(), columns)
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+/// Serves the Flight service on the given address until the process exits.
+pub async fn serve(
+    addr: SocketAddr,
+    service: SearchFlightService,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(service.into_server())
+        .serve(addr)
+        .await
+}
+
+#[tonic::async_trait]
+impl FlightService for SearchFlightService {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented(
+            "Queries are submitted with DoExchange",
+        ))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented(
+            "Queries are submitted with DoExchange",
+        ))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(Status::unimplemented(
+            "Queries are submitted with DoExchange",
+        ))
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented(
+            "Queries are submitted with DoExchange",
+        ))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented(
+            "Queries are submitted with DoExchange",
+        ))
+    }
+
+    async fn do_get(
+        &self,
+        _request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        Err(Status::unimplemented(
+            "Queries are submitted with DoExchange",
+        ))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented(
+            "Queries are submitted with DoExchange",
+        ))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented(
+            "Queries are submitted with DoExchange",
+        ))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Ok(Response::new(stream::empty().boxed()))
+    }
+
+    async fn do_exchange(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        let batches: Vec<RecordBatch> = FlightRecordBatchStream::new_from_flight_data(
+            request.into_inner().map_err(FlightError::from),
+        )
+        .try_collect()
+        .await?;
+
+        let service = self.clone();
+        let results = tokio::task::spawn_blocking(move || {
+            let mut first_query_id = 0;
+            let mut results = Vec::with_capacity(batches.len());
+            for batch in &batches {
+                results.push(Ok(service.search_batch(batch, first_query_id)?));
+                first_query_id += batch.num_rows() as u64;
+            }
+            Ok::<_, Status>(results)
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))??;
+
+        let flight_data = FlightDataEncoderBuilder::new()
+            .with_schema(RESULTS_SCHEMA.clone())
+            .build(stream::iter(results))
+            .map_err(Status::from);
+        Ok(Response::new(flight_data.boxed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::builder::{FixedSizeListBuilder, Float32Builder};
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, UInt32Type};
+    use arrow_array::{Float64Array, Int64Array};
+
+    use super::*;
+    use crate::solvers::Exact;
+
+    fn service() -> SearchFlightService {
+        let nodes_dataset = NodesDataset::from_parts(
+            2,
+            vec![0, 1, 1],
+            vec![0.1, 0.5, 0.9],
+            vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0],
+        )
+        .unwrap();
+        SearchFlightService::new(nodes_dataset, Box::new(Exact))
+    }
+
+    /// Builds a batch with the column types pandas produces by default.
+    fn queries(vector: &[f32]) -> RecordBatch {
+        let mut vectors = FixedSizeListBuilder::new(Float32Builder::new(), vector.len() as i32);
+        for _ in 0..2 {
+            vectors.values().append_slice(vector);
+            vectors.append(true);
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from(vec![0, 3])),
+            Arc::new(Int64Array::from(vec![None, Some(1)])),
+            Arc::new(Float64Array::from(vec![None, Some(0.0)])),
+            Arc::new(Float64Array::from(vec![None, Some(0.6)])),
+            Arc::new(vectors.finish()),
+        ];
+        let fields: Vec<Field> = ["query_type", "category", "t_lower", "t_upper", "vector"]
+            .into_iter()
+            .zip(&columns)
+            .map(|(name, column)| Field::new(name, column.data_type().clone(), true))
+            .collect();
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap()
+    }
+
+    #[test]
+    fn answers_query_batches() {
+        let results = service().search_batch(&queries(&[2.0, 2.0]), 10).unwrap();
+        let query_ids = results
+            .column(0)
+            .as_primitive::<arrow_array::types::UInt64Type>();
+        let ranks = results.column(1).as_primitive::<UInt32Type>();
+        let node_ids = results.column(2).as_primitive::<UInt32Type>();
+        let distances = results.column(3).as_primitive::<Float32Type>();
+
+        // Three matches for the unconstrained query, one for the filtered one.
+        assert_eq!(results.num_rows(), 4);
+        assert_eq!(
+            (query_ids.value(0), ranks.value(0), node_ids.value(0)),
+            (10, 0, 2)
+        );
+        assert_eq!(query_ids.value(3), 11);
+        assert_eq!((node_ids.value(3), distances.value(3)), (1, 2.0));
+
+        let results = service()
+            .with_k(1)
+            .search_batch(&queries(&[2.0, 2.0]), 0)
+            .unwrap();
+        assert_eq!(results.num_rows(), 2);
+    }
+
+    #[test]
+    fn rejects_malformed_batches() {
+        let status = service().search_batch(&queries(&[2.0]), 0).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let batch = queries(&[2.0, 2.0]).project(&[0, 1, 2, 4]).unwrap();
+        let status = service().search_batch(&batch, 0).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
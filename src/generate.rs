@@ -0,0 +1,246 @@
+//! Generation of synthetic datasets following the layout of the contest
+//! datasets, useful to exercise the solvers at arbitrary sizes, and of
+//! query workloads derived from existing nodes to stress the solvers on
+//! filters of a controlled selectivity.
+use std::collections::HashMap;
+
+use rand::Rng;
+use rand::distr::Distribution;
+use rand::distr::weighted::WeightedIndex;
+
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, OptionalFilterValue, QueriesDataset, QueryType};
+
+/// Shape of a query workload derived from a nodes dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    /// Relative frequency of each query type, in the order of
+    /// [`QueryType::ALL`].
+    pub type_weights: [f64; 4],
+    /// Exponent of the Zipf distribution of the queried categories ranked
+    /// from the most to the least frequent among the nodes. `0` picks every
+    /// category equally often, negative values favour rare categories.
+    pub category_skew: f64,
+    /// Smallest and largest width of the timestamp windows, as fractions of
+    /// the timestamp range of the nodes.
+    pub window: (f64, f64),
+    /// Largest absolute offset added to each entry of the node vector a
+    /// query vector is drawn from.
+    pub noise: f32,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            type_weights: [1.0; 4],
+            category_skew: 0.0,
+            window: (0.0, 1.0),
+            noise: 0.0,
+        }
+    }
+}
+
+/// Generates nodes with uniformly distributed vector entries in `[-1, 1]`,
+/// categories in `0..num_categories` and timestamps in `[0, 1]`.
+pub fn nodes<R: Rng>(
+    rng: &mut R,
+    num_vectors: u32,
+    dimensions: usize,
+    num_categories: u32,
+) -> NodesDataset {
+    let num_categories = num_categories.max(1);
+    let c_attrs = (0..num_vectors)
+        .map(|_| rng.random_range(0..num_categories) as i32)
+        .collect();
+    let t_attrs = (0..num_vectors).map(|_| rng.random::<f32>()).collect();
+    let vectors = (0..num_vectors as usize * dimensions)
+        .map(|_| rng.random_range(-1.0..=1.0))
+        .collect();
+
+    NodesDataset::from_parts(dimensions, c_attrs, t_attrs, vectors)
+        .expect("generated attributes and vectors have matching lengths")
+}
+
+/// Generates queries with uniformly distributed types, categories in
+/// `0..num_categories` and timestamp ranges within `[0, 1]`.
+pub fn queries<R: Rng>(
+    rng: &mut R,
+    num_queries: u32,
+    dimensions: usize,
+    num_categories: u32,
+) -> QueriesDataset {
+    let num_categories = num_categories.max(1);
+    let unset = OptionalFilterValue::new(-1.0);
+    let mut dataset = QueriesDataset {
+        num_queries,
+        dimensions,
+        ..Default::default()
+    };
+
+    for _ in 0..num_queries {
+        let query_type =
+            QueryType::from_f32(rng.random_range(0..4) as f32).expect("query types are in 0..4");
+        let category = OptionalFilterValue::new(rng.random_range(0..num_categories) as f32);
+        let (lower, upper) = {
+            let a = rng.random::<f32>();
+            let b = rng.random::<f32>();
+            (
+                OptionalFilterValue::new(a.min(b)),
+                OptionalFilterValue::new(a.max(b)),
+            )
+        };
+
+        let (v_categorical, t_lower_bound, t_upper_bound) = match query_type {
+            QueryType::VectorOnly => (unset, unset, unset),
+            QueryType::CategoricalConstraint => (category, unset, unset),
+            QueryType::TimestampConstraint => (unset, lower, upper),
+            QueryType::BothConstraints => (category, lower, upper),
+        };
+        dataset.query_types.push(query_type);
+        dataset.v_categoricals.push(v_categorical);
+        dataset.t_lower_bounds.push(t_lower_bound);
+        dataset.t_upper_bounds.push(t_upper_bound);
+    }
+    dataset.query_vectors = (0..num_queries as usize * dimensions)
+        .map(|_| rng.random_range(-1.0..=1.0))
+        .collect();
+
+    dataset
+}
+
+/// Generates queries whose vectors are drawn from random nodes and whose
+/// filters follow the attribute distributions of the nodes as shaped by
+/// the workload.
+pub fn workload<R: Rng>(
+    rng: &mut R,
+    nodes_dataset: &NodesDataset,
+    num_queries: u32,
+    workload: &Workload,
+) -> error::Result<QueriesDataset> {
+    let live: Vec<usize> = (0..nodes_dataset.num_vectors as usize)
+        .filter(|&id| !nodes_dataset.is_deleted(id as u32))
+        .collect();
+    if live.is_empty() {
+        return Err(GlasshouseError::Config(
+            "Cannot derive queries from an empty nodes dataset".to_string(),
+        ));
+    }
+    let query_types = WeightedIndex::new(workload.type_weights)
+        .map_err(|e| GlasshouseError::Config(format!("Invalid query type weights: {}", e)))?;
+
+    let mut counts: HashMap<i32, u32> = HashMap::new();
+    let (mut t_min, mut t_max) = (f32::INFINITY, f32::NEG_INFINITY);
+    for &id in &live {
+        *counts.entry(nodes_dataset.c_attrs[id]).or_default() += 1;
+        t_min = t_min.min(nodes_dataset.t_attrs[id]);
+        t_max = t_max.max(nodes_dataset.t_attrs[id]);
+    }
+    let mut categories: Vec<(i32, u32)> = counts.into_iter().collect();
+    categories.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let category_ranks = WeightedIndex::new(
+        (1..=categories.len()).map(|rank| (rank as f64).powf(-workload.category_skew)),
+    )
+    .map_err(|e| GlasshouseError::Config(format!("Invalid category skew: {}", e)))?;
+
+    let (min_width, max_width) = workload.window;
+    if !(0.0..=max_width).contains(&min_width) || max_width > 1.0 {
+        return Err(GlasshouseError::Config(format!(
+            "Invalid window widths [{}, {}], expected 0 <= min <= max <= 1",
+            min_width, max_width
+        )));
+    }
+
+    let unset = OptionalFilterValue::new(-1.0);
+    let mut dataset = QueriesDataset {
+        num_queries,
+        dimensions: nodes_dataset.dimensions,
+        ..Default::default()
+    };
+    for _ in 0..num_queries {
+        let query_type = QueryType::ALL[query_types.sample(rng)];
+        let category = OptionalFilterValue::new(categories[category_ranks.sample(rng)].0 as f32);
+        let (lower, upper) = {
+            let width = rng.random_range(min_width..=max_width) as f32 * (t_max - t_min);
+            let lower = t_min + rng.random::<f32>() * (t_max - t_min - width);
+            (
+                OptionalFilterValue::new(lower),
+                OptionalFilterValue::new(lower + width),
+            )
+        };
+
+        let (v_categorical, t_lower_bound, t_upper_bound) = match query_type {
+            QueryType::VectorOnly => (unset, unset, unset),
+            QueryType::CategoricalConstraint => (category, unset, unset),
+            QueryType::TimestampConstraint => (unset, lower, upper),
+            QueryType::BothConstraints => (category, lower, upper),
+        };
+        dataset.query_types.push(query_type);
+        dataset.v_categoricals.push(v_categorical);
+        dataset.t_lower_bounds.push(t_lower_bound);
+        dataset.t_upper_bounds.push(t_upper_bound);
+
+        let node = live[rng.random_range(0..live.len())];
+        for &value in nodes_dataset.vector(node) {
+            let offset = if workload.noise > 0.0 {
+                rng.random_range(-workload.noise..=workload.noise)
+            } else {
+                0.0
+            };
+            dataset.query_vectors.push(value + offset);
+        }
+    }
+
+    Ok(dataset)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+
+    #[test]
+    fn workloads_follow_their_shape() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let nodes_dataset = nodes(&mut rng, 1_000, 4, 20);
+        let shape = Workload {
+            type_weights: [0.0, 0.0, 0.0, 1.0],
+            category_skew: -2.0,
+            window: (0.1, 0.1),
+            ..Default::default()
+        };
+        let queries_dataset = workload(&mut rng, &nodes_dataset, 100, &shape).unwrap();
+
+        assert_eq!(queries_dataset.num_queries, 100);
+        assert_eq!(queries_dataset.query_vectors.len(), 400);
+        for index in 0..100 {
+            let query = queries_dataset.get(index).unwrap();
+            assert_eq!(query.query_type, QueryType::BothConstraints);
+            let width = query.t_upper_bound.unwrap() - query.t_lower_bound.unwrap();
+            assert!((0.09..0.11).contains(&width));
+            assert!((0..20).contains(&query.v_categorical.unwrap()));
+        }
+
+        let invalid = Workload {
+            window: (0.5, 0.2),
+            ..Default::default()
+        };
+        assert!(matches!(
+            workload(&mut rng, &nodes_dataset, 1, &invalid),
+            Err(GlasshouseError::Config(_))
+        ));
+        let no_type = Workload {
+            type_weights: [0.0; 4],
+            ..Default::default()
+        };
+        assert!(matches!(
+            workload(&mut rng, &nodes_dataset, 1, &no_type),
+            Err(GlasshouseError::Config(_))
+        ));
+        assert!(matches!(
+            workload(&mut rng, &NodesDataset::default(), 1, &shape),
+            Err(GlasshouseError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+//! gRPC search service, see `proto/glasshouse.proto` for its definition.
+//!
+//! Queries mirror the rows of the binary queries format. `BatchSearch`
+//! answers a batch in a single response while `StreamSearch` streams each
+//! result as soon as it completes. Both return the `k` nearest neighbours
+//! of each query, where `k` is set by the request or else by the server.
+//! Under the cosine metric query vectors are scaled to unit length as the
+//! nodes are, zero vectors are rejected, and distances are cosine
+//! distances, `1 - cos`.
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::config::Metric;
+use crate::constants::K_NEAREST;
+use crate::distance;
+use crate::solvers::{self, Delivery, Solver};
+use crate::types::{NodesDataset, OptionalFilterValue, ParsedQuery, QueryType, ScoredNeighbor};
+
+/// Messages and service traits generated from `proto/glasshouse.proto`.
+pub mod proto {
+    tonic::include_proto!("glasshouse");
+}
+
+use proto::search_server::{Search, SearchServer};
+use proto::{BatchSearchRequest, BatchSearchResponse, Query, QueryResult};
+
+/// Number of streamed results buffered before the search waits for the client.
+const STREAM_BUFFER: usize = 1024;
+
+struct SearchState {
+    nodes_dataset: NodesDataset,
+    solver: Box<dyn Solver>,
+}
+
+/// Implementation of the `Search` gRPC service.
+#[derive(Clone)]
+pub struct SearchService {
+    state: Arc<SearchState>,
+    k: usize,
+    metric: Metric,
+}
+
+impl SearchService {
+    pub fn new(nodes_dataset: NodesDataset, solver: Box<dyn Solver>) -> Self {
+        SearchService {
+            state: Arc::new(SearchState {
+                nodes_dataset,
+                solver,
+            }),
+            k: K_NEAREST,
+            metric: Metric::L2,
+        }
+    }
+
+    /// Sets the number of neighbours returned for requests that do not set
+    /// `k`.
+    pub fn with_k(mut self, k: usize) -> Self {
+        self.k = k;
+        self
+    }
+
+    /// Sets the metric the nodes were loaded under, query vectors are
+    /// scaled to unit length under the cosine metric.
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Scales the query vectors to unit length under the cosine metric,
+    /// rejecting zero vectors which have no cosine similarity.
+    fn normalize(&self, queries: &mut [Query]) -> Result<(), Status> {
+        if self.metric == Metric::Cosine {
+            for (index, query) in queries.iter_mut().enumerate() {
+                if !distance::normalize(&mut query.vector) {
+                    return Err(Status::invalid_argument(format!(
+                        "Query {} has a zero vector, which has no cosine similarity",
+                        index
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of neighbours to return for a request, at most the number of
+    /// nodes.
+    fn k(&self, request: &BatchSearchRequest) -> usize {
+        let k = match request.k {
+            0 => self.k,
+            k => k as usize,
+        };
+        k.min(self.state.nodes_dataset.num_vectors as usize)
+    }
+
+    /// Wraps the service into a server that can be added to a tonic router.
+    pub fn into_server(self) -> SearchServer<Self> {
+        SearchServer::new(self)
+    }
+}
+
+/// Serves the search service on the given address until the process exits.
+pub async fn serve(
+    addr: SocketAddr,
+    service: SearchService,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(service.into_server())
+        .serve(addr)
+        .await
+}
+
+impl SearchState {
+    /// Checks that every query is well formed for the loaded dataset.
+    fn validate(&self, queries: &[Query]) -> Result<(), Status> {
+        for (index, query) in queries.iter().enumerate() {
+            if query.vector.len() != self.nodes_dataset.dimensions {
+                return Err(Status::invalid_argument(format!(
+                    "Query {} has dimension {}, expected {}",
+                    index,
+                    query.vector.len(),
+                    self.nodes_dataset.dimensions
+                )));
+            }
+            QueryType::from_f32(query.query_type as f32)
+                .map_err(|e| Status::invalid_argument(format!("Query {}: {}", index, e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts the neighbours found for a query to its message, with the
+/// distances reported under the metric.
+fn query_result(query_index: usize, result: &[ScoredNeighbor], metric: Metric) -> QueryResult {
+    QueryResult {
+        query_index: query_index as u32,
+        ids: result.iter().map(|neighbor| neighbor.id).collect(),
+        distances: result
+            .iter()
+            .map(|neighbor| metric.distance(neighbor.distance))
+            .collect(),
+    }
+}
+
+/// Interprets a validated query the same way as a row of the binary format.
+fn parse(query: &Query) -> ParsedQuery<'_> {
+    ParsedQuery {
+        query_type: QueryType::from_f32(query.query_type as f32).expect("validated query"),
+        v_categorical: OptionalFilterValue::new(query.category as f32).categorical_value(),
+        t_lower_bound: OptionalFilterValue::new(query.t_lower).value(),
+        t_upper_bound: OptionalFilterValue::new(query.t_upper).value(),
+        query_vector: &query.vector,
+    }
+}
+
+#[tonic::async_trait]
+impl Search for SearchService {
+    async fn batch_search(
+        &self,
+        request: Request<BatchSearchRequest>,
+    ) -> Result<Response<BatchSearchResponse>, Status> {
+        let request = request.into_inner();
+        let k = self.k(&request);
+        let mut queries = request.queries;
+        self.state.validate(&queries)?;
+        self.normalize(&mut queries)?;
+
+        let state = self.state.clone();
+        let metric = self.metric;
+        let results = tokio::task::spawn_blocking(move || {
+            queries
+                .par_iter()
+                .enumerate()
+                .map(|(index, query)| {
+                    let result = state
+                        .solver
+                        .search_scored(&state.nodes_dataset, &parse(query), k);
+                    query_result(index, &result, metric)
+                })
+                .collect()
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?;
+
+        Ok(Response::new(BatchSearchResponse { results }))
+    }
+
+    type StreamSearchStream = ReceiverStream<Result<QueryResult, Status>>;
+
+    async fn stream_search(
+        &self,
+        request: Request<BatchSearchRequest>,
+    ) -> Result<Response<Self::StreamSearchStream>, Status> {
+        let request = request.into_inner();
+        let k = self.k(&request);
+        let mut queries = request.queries;
+        self.state.validate(&queries)?;
+        self.normalize(&mut queries)?;
+
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let state = self.state.clone();
+        let metric = self.metric;
+        tokio::task::spawn_blocking(move || {
+            solvers::stream_scored(
+                state.solver.as_ref(),
+                &state.nodes_dataset,
+                queries.iter().map(parse),
+                k,
+                Delivery::Unordered,
+                |index, result| {
+                    // Sending only fails once the client went away, the
+                    // remaining results are then dropped.
+                    let _ = sender.blocking_send(Ok(query_result(index, &result, metric)));
+                },
+            );
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::solvers::{DEFAULT_PAD_ID, Exact};
+
+    fn service() -> SearchService {
+        let nodes_dataset = NodesDataset::from_parts(
+            2,
+            vec![0, 1, 1],
+            vec![0.1, 0.5, 0.9],
+            vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0],
+        )
+        .unwrap();
+        SearchService::new(nodes_dataset, Box::new(Exact))
+    }
+
+    fn queries() -> BatchSearchRequest {
+        BatchSearchRequest {
+            queries: vec![
+                Query {
+                    query_type: 0,
+                    category: -1,
+                    t_lower: -1.0,
+                    t_upper: -1.0,
+                    vector: vec![2.0, 2.0],
+                },
+                Query {
+                    query_type: 3,
+                    category: 1,
+                    t_lower: 0.0,
+                    t_upper: 0.6,
+                    vector: vec![2.0, 2.0],
+                },
+            ],
+            k: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_and_stream_answers_agree() {
+        let service = service();
+        let batch = service
+            .batch_search(Request::new(queries()))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert_eq!(batch[0].ids[0], 2);
+        assert_eq!(batch[1].ids[0], 1);
+        assert_eq!(batch[1].distances[0], 2.0);
+
+        let mut streamed: Vec<QueryResult> = service
+            .stream_search(Request::new(queries()))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        streamed.sort_by_key(|result| result.query_index);
+        assert_eq!(streamed, batch);
+    }
+
+    #[tokio::test]
+    async fn under_filled_results_are_not_padded() {
+        let service = service();
+        let batch = service
+            .batch_search(Request::new(queries()))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        // Only node 1 passes the filters of the second query.
+        assert_eq!(batch[1].ids, [1]);
+        assert_eq!(batch[1].distances, [2.0]);
+
+        let streamed: Vec<QueryResult> = service
+            .stream_search(Request::new(queries()))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert!(
+            streamed
+                .iter()
+                .all(|result| result.ids.len() == result.distances.len()
+                    && !result.ids.contains(&DEFAULT_PAD_ID))
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_can_set_k() {
+        let mut request = queries();
+        request.k = 1;
+        let results = service()
+            .batch_search(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert!(results.iter().all(|result| result.ids.len() == 1));
+
+        let results = service()
+            .with_k(2)
+            .batch_search(Request::new(queries()))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert_eq!(results[0].ids, [2, 1]);
+
+        let mut request = queries();
+        request.k = u32::MAX;
+        let results = service()
+            .batch_search(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert_eq!(results[0].ids, [2, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_queries() {
+        let mut request = queries();
+        request.queries[0].vector.pop();
+        let status = service()
+            .batch_search(Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let mut request = queries();
+        request.queries[1].query_type = 7;
+        let status = service()
+            .stream_search(Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,180 @@
+//! CRC32 checksums guarding results files.
+//!
+//! A checksummed results file holds the results in the contest format
+//! followed by a 16-byte trailer: the magic `GHCK`, then the CRC32 of the
+//! results, of the nodes dataset and of the queries dataset as
+//! little-endian `uint32`. The dataset checksums are those of the datasets
+//! saved in the contest format, so they match the CRC32 of the `.bin` files.
+//! Readers tell both layouts apart from the file length and the magic, as
+//! the trailer is not a multiple of a results row unless `k` is at most 4.
+#[cfg(feature = "fs")]
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crc32fast::Hasher;
+
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, QueriesDataset, QueryResult};
+
+/// Marks the checksum trailer of a results file.
+const MAGIC: &[u8; 4] = b"GHCK";
+
+/// Length of the checksum trailer in bytes.
+pub const TRAILER_LEN: usize = 16;
+
+/// Checksums of a results file and of the datasets it answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksums {
+    pub results: u32,
+    pub nodes: u32,
+    pub queries: u32,
+}
+
+impl Checksums {
+    /// Computes the checksums of results answering the queries over the
+    /// nodes.
+    pub fn new(
+        results: &[QueryResult],
+        nodes_dataset: &NodesDataset,
+        queries_dataset: &QueriesDataset,
+    ) -> Self {
+        let mut hasher = Hasher::new();
+        for result in results {
+            for id in result {
+                hasher.update(&id.to_le_bytes());
+            }
+        }
+        Checksums {
+            results: hasher.finalize(),
+            nodes: nodes_dataset.checksum(),
+            queries: queries_dataset.checksum(),
+        }
+    }
+
+    #[cfg(any(feature = "fs", test))]
+    fn to_bytes(self) -> [u8; TRAILER_LEN] {
+        let mut bytes = [0; TRAILER_LEN];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4..8].copy_from_slice(&self.results.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.nodes.to_le_bytes());
+        bytes[12..].copy_from_slice(&self.queries.to_le_bytes());
+        bytes
+    }
+}
+
+impl NodesDataset {
+    /// CRC32 of the dataset saved in the contest format.
+    pub fn checksum(&self) -> u32 {
+        let mut writer = CrcWriter(Hasher::new());
+        self.write_to(&mut writer)
+            .expect("writing to a hasher cannot fail");
+        writer.0.finalize()
+    }
+}
+
+impl QueriesDataset {
+    /// CRC32 of the dataset saved in the contest format.
+    pub fn checksum(&self) -> u32 {
+        let mut writer = CrcWriter(Hasher::new());
+        self.write_to(&mut writer)
+            .expect("writing to a hasher cannot fail");
+        writer.0.finalize()
+    }
+}
+
+/// Splits the contents of a results file into the results and their
+/// checksums, if any, after checking the checksum of the results.
+pub fn split(bytes: &[u8], row_len: usize) -> error::Result<(&[u8], Option<Checksums>)> {
+    if bytes.len() < TRAILER_LEN || !(bytes.len() - TRAILER_LEN).is_multiple_of(row_len) {
+        return Ok((bytes, None));
+    }
+    let (payload, trailer) = bytes.split_at(bytes.len() - TRAILER_LEN);
+    if &trailer[..4] != MAGIC {
+        return Ok((bytes, None));
+    }
+    let word = |offset: usize| {
+        u32::from_le_bytes(
+            trailer[offset..offset + 4]
+                .try_into()
+                .expect("trailer words hold 4 bytes"),
+        )
+    };
+    let checksums = Checksums {
+        results: word(4),
+        nodes: word(8),
+        queries: word(12),
+    };
+    let actual = crc32fast::hash(payload);
+    if actual != checksums.results {
+        return Err(GlasshouseError::Malformed(format!(
+            "Results checksum mismatch: expected {:08x}, computed {:08x}",
+            checksums.results, actual
+        )));
+    }
+    Ok((payload, Some(checksums)))
+}
+
+/// Appends the checksum trailer to a results file.
+#[cfg(feature = "fs")]
+pub fn append<P: AsRef<Path>>(file_path: P, checksums: Checksums) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).open(file_path)?;
+    file.write_all(&checksums.to_bytes())
+}
+
+/// Reads the checksums of a results file in the contest format holding `k`
+/// neighbours per query, `None` if it has none.
+#[cfg(feature = "fs")]
+pub fn read<P: AsRef<Path>>(file_path: P, k: usize) -> error::Result<Option<Checksums>> {
+    let bytes = std::fs::read(file_path)?;
+    Ok(split(&bytes, k * 4)?.1)
+}
+
+/// Feeds everything written to it to a CRC32 hasher.
+struct CrcWriter(Hasher);
+
+impl Write for CrcWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::K_NEAREST;
+
+    #[test]
+    fn trailers_are_checked_and_stripped() {
+        let results: Vec<QueryResult> = vec![vec![3; K_NEAREST].into(), vec![5; K_NEAREST].into()];
+        let nodes_dataset = NodesDataset::from_parts(1, vec![1], vec![0.5], vec![2.0]).unwrap();
+        let queries_dataset = QueriesDataset::default();
+        let checksums = Checksums::new(&results, &nodes_dataset, &queries_dataset);
+
+        let mut nodes_bytes = Vec::new();
+        nodes_dataset.write_to(&mut nodes_bytes).unwrap();
+        assert_eq!(checksums.nodes, crc32fast::hash(&nodes_bytes));
+
+        let mut bytes: Vec<u8> = results
+            .iter()
+            .flatten()
+            .flat_map(|id| id.to_le_bytes())
+            .collect();
+        let payload_len = bytes.len();
+        bytes.extend_from_slice(&checksums.to_bytes());
+        let row_len = K_NEAREST * 4;
+        let (payload, read) = split(&bytes, row_len).unwrap();
+        assert_eq!(payload.len(), payload_len);
+        assert_eq!(read, Some(checksums));
+        assert_eq!(split(&bytes[..payload_len], row_len).unwrap().1, None);
+
+        bytes[0] ^= 1;
+        assert!(split(&bytes, row_len).is_err());
+    }
+}
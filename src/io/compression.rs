@@ -0,0 +1,169 @@
+//! Transparent decompression of dataset files.
+//!
+//! With the `zstd` feature, files ending in `.zst` are decompressed while
+//! they are read. Inferring the dimensionality of a compressed dataset
+//! relies on the uncompressed size recorded in the zstd frame header, which
+//! the `zstd` tool writes when compressing files but not streams, the
+//! dimensions must be given otherwise.
+//!
+//! With the `gzip` feature, files ending in `.gz` are decompressed as well.
+//! gzip only records the uncompressed size modulo 2^32, the dimensions are
+//! inferred when a single size bounded by the best deflate compression
+//! ratio matches the number of rows.
+use std::fs::File;
+use std::io::{BufReader, Read};
+#[cfg(any(feature = "zstd", feature = "gzip"))]
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::{self, GlasshouseError};
+
+/// Largest zstd frame header, enough to find the content size.
+#[cfg(feature = "zstd")]
+const ZSTD_FRAME_HEADER_MAX: u64 = 18;
+
+/// Best compression ratio of deflate, bounding the uncompressed size of a
+/// gzip file.
+#[cfg(feature = "gzip")]
+const DEFLATE_MAX_RATIO: u64 = 1032;
+
+/// Length of the uncompressed contents of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContentLength {
+    Exact(u64),
+    /// Known modulo 2^32 and bounded by `max`.
+    #[cfg(feature = "gzip")]
+    Wrapped {
+        low: u32,
+        max: u64,
+    },
+    #[cfg(feature = "zstd")]
+    Unknown,
+}
+
+impl ContentLength {
+    /// Returns every length consistent with what is known, `None` when
+    /// nothing is.
+    pub(crate) fn candidates(self) -> Option<impl Iterator<Item = u64>> {
+        let (first, step, max) = match self {
+            ContentLength::Exact(len) => (len, 1, len),
+            #[cfg(feature = "gzip")]
+            ContentLength::Wrapped { low, max } => (low as u64, 1 << 32, max),
+            #[cfg(feature = "zstd")]
+            ContentLength::Unknown => return None,
+        };
+        Some((first..=max.max(first)).step_by(step))
+    }
+}
+
+/// Opens a file for reading, decompressing it on the fly when compressed.
+/// Returns the reader and the length of the uncompressed contents.
+pub(crate) fn open(file_path: &Path) -> error::Result<(Box<dyn Read>, ContentLength)> {
+    let file = File::open(file_path)?;
+    match file_path.extension().and_then(|e| e.to_str()) {
+        Some("zst") => open_zstd(file),
+        Some("gz") => open_gzip(file),
+        _ => {
+            let len = file.metadata()?.len();
+            Ok((Box::new(BufReader::new(file)), ContentLength::Exact(len)))
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn open_zstd(mut file: File) -> error::Result<(Box<dyn Read>, ContentLength)> {
+    let mut header = Vec::new();
+    (&mut file)
+        .take(ZSTD_FRAME_HEADER_MAX)
+        .read_to_end(&mut header)?;
+    file.seek(SeekFrom::Start(0))?;
+    let len = zstd::zstd_safe::get_frame_content_size(&header)
+        .map_err(|_| GlasshouseError::Malformed("Invalid zstd frame header".to_string()))?;
+    let len = len.map_or(ContentLength::Unknown, ContentLength::Exact);
+    Ok((Box::new(zstd::Decoder::new(file)?), len))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn open_zstd(_file: File) -> error::Result<(Box<dyn Read>, ContentLength)> {
+    Err(GlasshouseError::Malformed(
+        "Reading .zst files requires the zstd feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "gzip")]
+fn open_gzip(mut file: File) -> error::Result<(Box<dyn Read>, ContentLength)> {
+    // The last 4 bytes of a gzip member hold the uncompressed size modulo 2^32.
+    let compressed_len = file.metadata()?.len();
+    let mut size = [0u8; 4];
+    file.seek(SeekFrom::End(-4))?;
+    file.read_exact(&mut size)?;
+    file.seek(SeekFrom::Start(0))?;
+    let len = ContentLength::Wrapped {
+        low: u32::from_le_bytes(size),
+        max: compressed_len * DEFLATE_MAX_RATIO,
+    };
+    let decoder = flate2::read::MultiGzDecoder::new(BufReader::new(file));
+    Ok((Box::new(decoder), len))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn open_gzip(_file: File) -> error::Result<(Box<dyn Read>, ContentLength)> {
+    Err(GlasshouseError::Malformed(
+        "Reading .gz files requires the gzip feature".to_string(),
+    ))
+}
+
+#[cfg(all(test, any(feature = "zstd", feature = "gzip")))]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use crate::generate;
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn compressed_datasets_are_read_transparently() {
+        use crate::types::NodesDataset;
+
+        let mut rng = StdRng::seed_from_u64(4);
+        let nodes = generate::nodes(&mut rng, 50, 8, 4);
+        let mut bytes = Vec::new();
+        nodes.write_to(&mut bytes).unwrap();
+        let path = crate::testing::temp_path("compressed-nodes.bin.zst");
+
+        // Single-pass compression records the uncompressed length.
+        std::fs::write(&path, zstd::bulk::compress(&bytes, 3).unwrap()).unwrap();
+        let read = NodesDataset::read(&path).unwrap();
+        assert_eq!(read.dimensions, 8);
+        assert_eq!(read.vectors, nodes.vectors);
+
+        // Streaming compression does not.
+        std::fs::write(&path, zstd::encode_all(bytes.as_slice(), 3).unwrap()).unwrap();
+        assert!(NodesDataset::read(&path).is_err());
+        let read = NodesDataset::read_with_dimensions(&path, 8).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(read.vectors, nodes.vectors);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip_datasets_are_read_transparently() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        use crate::types::QueriesDataset;
+
+        let mut rng = StdRng::seed_from_u64(6);
+        let queries = generate::queries(&mut rng, 40, 6, 4);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        queries.write_to(&mut encoder).unwrap();
+        let path = crate::testing::temp_path("compressed-queries.bin.gz");
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let read = QueriesDataset::read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(read.dimensions, 6);
+        assert_eq!(read.query_vectors, queries.query_vectors);
+        assert_eq!(read.query_types, queries.query_types);
+    }
+}
@@ -0,0 +1,71 @@
+//! CSV results for spreadsheets and dataframes.
+//!
+//! The file starts with the header `query_id,rank,node_id,distance` and
+//! holds one row per neighbour, closest first, with its squared Euclidean
+//! distance. Queries with fewer than `k` neighbours have fewer rows.
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufWriter;
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::types::ScoredResults;
+
+/// Writes the header and one row per neighbour of the scored results.
+pub fn write_results_to<W: Write>(writer: &mut W, results: &ScoredResults) -> io::Result<()> {
+    writeln!(writer, "query_id,rank,node_id,distance")?;
+    for (query_id, neighbors) in results.iter().enumerate() {
+        for (rank, neighbor) in neighbors.iter().enumerate() {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                query_id, rank, neighbor.id, neighbor.distance
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves scored results to a CSV file.
+#[cfg(feature = "fs")]
+pub fn write_results<P: AsRef<Path>>(results: &ScoredResults, file_path: P) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_results_to(&mut writer, results)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ScoredNeighbor;
+
+    #[test]
+    fn writes_one_row_per_neighbor() {
+        let results = vec![
+            vec![
+                ScoredNeighbor {
+                    id: 12,
+                    distance: 0.25,
+                },
+                ScoredNeighbor {
+                    id: 3,
+                    distance: 1.0,
+                },
+            ],
+            vec![],
+            vec![ScoredNeighbor {
+                id: 7,
+                distance: 2.5,
+            }],
+        ];
+
+        let mut bytes = Vec::new();
+        write_results_to(&mut bytes, &results).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "query_id,rank,node_id,distance\n0,0,12,0.25\n0,1,3,1\n2,0,7,2.5\n"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Export of graph indexes for visualization in Graphviz or Gephi.
+//!
+//! Every node carries its top level, its attributes and whether it is the
+//! entry point or deleted. Every edge of every layer is written as a
+//! directed edge labelled with its level, so a layer is inspected by
+//! filtering the edges on it.
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufWriter;
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::solvers::Hnsw;
+use crate::types::NodesDataset;
+
+impl Hnsw {
+    /// Writes the graph in the Graphviz DOT language.
+    pub fn write_dot_to<W: Write>(
+        &self,
+        nodes_dataset: &NodesDataset,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        writeln!(writer, "digraph hnsw {{")?;
+        for node_id in 0..self.len() as u32 {
+            let (c_attr, t_attr) = attributes(nodes_dataset, node_id);
+            write!(
+                writer,
+                "  {} [level={}, c={}, t={}",
+                node_id,
+                self.levels(node_id).saturating_sub(1),
+                c_attr,
+                t_attr
+            )?;
+            if self.entry_point() == Some(node_id) {
+                write!(writer, ", shape=doublecircle")?;
+            }
+            if nodes_dataset.is_deleted(node_id) {
+                write!(writer, ", style=dashed")?;
+            }
+            writeln!(writer, "];")?;
+        }
+        for (node_id, level, neighbor) in self.edges() {
+            writeln!(writer, "  {} -> {} [level={}];", node_id, neighbor, level)?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    /// Writes the graph in the GraphML format.
+    pub fn write_graphml_to<W: Write>(
+        &self,
+        nodes_dataset: &NodesDataset,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        let keys = [
+            ("level", "node", "int"),
+            ("c", "node", "int"),
+            ("t", "node", "double"),
+            ("entry", "node", "boolean"),
+            ("deleted", "node", "boolean"),
+            ("edge_level", "edge", "int"),
+        ];
+        for (id, domain, kind) in keys {
+            let name = id.trim_start_matches("edge_");
+            writeln!(
+                writer,
+                r#"  <key id="{}" for="{}" attr.name="{}" attr.type="{}"/>"#,
+                id, domain, name, kind
+            )?;
+        }
+        writeln!(writer, r#"  <graph id="hnsw" edgedefault="directed">"#)?;
+        for node_id in 0..self.len() as u32 {
+            let (c_attr, t_attr) = attributes(nodes_dataset, node_id);
+            writeln!(
+                writer,
+                r#"    <node id="n{}"><data key="level">{}</data><data key="c">{}</data><data key="t">{}</data><data key="entry">{}</data><data key="deleted">{}</data></node>"#,
+                node_id,
+                self.levels(node_id).saturating_sub(1),
+                c_attr,
+                t_attr,
+                self.entry_point() == Some(node_id),
+                nodes_dataset.is_deleted(node_id)
+            )?;
+        }
+        for (node_id, level, neighbor) in self.edges() {
+            writeln!(
+                writer,
+                r#"    <edge source="n{}" target="n{}"><data key="edge_level">{}</data></edge>"#,
+                node_id, neighbor, level
+            )?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+
+    /// Saves the graph to a file, in GraphML for the `.graphml` extension
+    /// and in DOT otherwise.
+    #[cfg(feature = "fs")]
+    pub fn write_graph<P: AsRef<Path>>(
+        &self,
+        nodes_dataset: &NodesDataset,
+        file_path: P,
+    ) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        if file_path.extension().is_some_and(|e| e == "graphml") {
+            self.write_graphml_to(nodes_dataset, &mut writer)?;
+        } else {
+            self.write_dot_to(nodes_dataset, &mut writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Every edge of every layer as (node, level, neighbour).
+    fn edges(&self) -> impl Iterator<Item = (u32, usize, u32)> + '_ {
+        (0..self.len() as u32).flat_map(move |node_id| {
+            (0..self.levels(node_id)).flat_map(move |level| {
+                self.neighbors(node_id, level)
+                    .iter()
+                    .map(move |&neighbor| (node_id, level, neighbor))
+            })
+        })
+    }
+}
+
+/// Categorical and timestamp attributes of a node, zero if the dataset does
+/// not hold it.
+fn attributes(nodes_dataset: &NodesDataset, node_id: u32) -> (i32, f32) {
+    let index = node_id as usize;
+    (
+        nodes_dataset.c_attrs.get(index).copied().unwrap_or(0),
+        nodes_dataset.t_attrs.get(index).copied().unwrap_or(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use crate::generate;
+    use crate::solvers::HnswBuilder;
+
+    #[test]
+    fn graphs_are_exported_with_every_edge() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let nodes = generate::nodes(&mut rng, 30, 4, 3);
+        let hnsw = HnswBuilder::new().m(4).build(&nodes).unwrap();
+        let num_edges: usize = (0..30)
+            .map(|id| {
+                (0..hnsw.levels(id))
+                    .map(|l| hnsw.neighbors(id, l).len())
+                    .sum::<usize>()
+            })
+            .sum();
+
+        let mut dot = Vec::new();
+        hnsw.write_dot_to(&nodes, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph hnsw {"));
+        assert_eq!(dot.matches(" -> ").count(), num_edges);
+        assert!(dot.contains("shape=doublecircle"));
+
+        let mut graphml = Vec::new();
+        hnsw.write_graphml_to(&nodes, &mut graphml).unwrap();
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert_eq!(graphml.matches("<node ").count(), 30);
+        assert_eq!(graphml.matches("<edge ").count(), num_edges);
+    }
+}
@@ -0,0 +1,100 @@
+//! The `.hdf5` files of [ann-benchmarks](https://github.com/erikbern/ann-benchmarks).
+//!
+//! Each file holds the base vectors in a `train` dataset, the query vectors
+//! in `test` and the IDs of their exact nearest neighbours in `neighbors`,
+//! all as two-dimensional arrays with one row per vector. The vectors carry
+//! no attributes, nodes have a categorical and timestamp attribute of `0`
+//! and queries are vector-only.
+//!
+//! The solvers rank nodes by Euclidean distance, only the `euclidean`
+//! datasets of ann-benchmarks are comparable with the published results.
+use std::path::Path;
+
+use hdf5_metno::{File, H5Type};
+
+use crate::constants::K_NEAREST;
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, QueriesDataset, QueryResults};
+
+use super::vecs::results_from_rows;
+
+/// Datasets of an ann-benchmarks file.
+#[derive(Debug)]
+pub struct AnnBenchmarks {
+    /// Base vectors, read from `train`.
+    pub nodes: NodesDataset,
+    /// Query vectors, read from `test`.
+    pub queries: QueriesDataset,
+    /// Exact neighbours of each query, read from `neighbors` and truncated
+    /// to `K_NEAREST`.
+    pub neighbors: QueryResults,
+}
+
+impl AnnBenchmarks {
+    /// Reads the base vectors, query vectors and ground truth of a file.
+    pub fn read<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        let file = open(file_path)?;
+        Ok(AnnBenchmarks {
+            nodes: nodes(&file)?,
+            queries: queries(&file)?,
+            neighbors: neighbors(&file, K_NEAREST)?,
+        })
+    }
+}
+
+impl NodesDataset {
+    /// Reads the base vectors of an ann-benchmarks file.
+    pub fn read_hdf5<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        nodes(&open(file_path)?)
+    }
+}
+
+impl QueriesDataset {
+    /// Reads the query vectors of an ann-benchmarks file.
+    pub fn read_hdf5<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        queries(&open(file_path)?)
+    }
+}
+
+/// Reads the exact neighbours of an ann-benchmarks file, truncated to `k`.
+pub fn read_neighbors<P: AsRef<Path>>(file_path: P, k: usize) -> error::Result<QueryResults> {
+    neighbors(&open(file_path)?, k)
+}
+
+fn open<P: AsRef<Path>>(file_path: P) -> error::Result<File> {
+    File::open(file_path).map_err(malformed)
+}
+
+fn nodes(file: &File) -> error::Result<NodesDataset> {
+    let (dimensions, vectors) = read_rows::<f32>(file, "train")?;
+    NodesDataset::from_vectors(dimensions, vectors)
+}
+
+fn queries(file: &File) -> error::Result<QueriesDataset> {
+    let (dimensions, query_vectors) = read_rows::<f32>(file, "test")?;
+    Ok(QueriesDataset::from_vectors(dimensions, query_vectors))
+}
+
+fn neighbors(file: &File, k: usize) -> error::Result<QueryResults> {
+    let (width, ids) = read_rows::<i32>(file, "neighbors")?;
+    results_from_rows(width, &ids, k)
+}
+
+/// Reads a two-dimensional dataset, returning its row width and its values
+/// in row-major order.
+fn read_rows<T: H5Type>(file: &File, name: &str) -> error::Result<(usize, Vec<T>)> {
+    let dataset = file.dataset(name).map_err(malformed)?;
+    let shape = dataset.shape();
+    let [_, width] = shape[..] else {
+        return Err(GlasshouseError::Malformed(format!(
+            "Dataset {} has shape {:?}, expected two dimensions",
+            name, shape
+        )));
+    };
+    let values = dataset.read_raw::<T>().map_err(malformed)?;
+    Ok((width, values))
+}
+
+fn malformed(error: hdf5_metno::Error) -> GlasshouseError {
+    GlasshouseError::Malformed(format!("HDF5: {}", error))
+}
@@ -0,0 +1,103 @@
+//! JSON Lines results, written for inspection and post-processing rather
+//! than evaluation.
+//!
+//! Each line holds one query: its index, type and filters, unset filters
+//! being `null`, followed by its neighbours closest first with their
+//! squared Euclidean distance:
+//!
+//! ```json
+//! {"query":0,"query_type":"CategoricalConstraint","category":3,"t_lower":null,"t_upper":null,"neighbors":[{"id":12,"distance":0.25}]}
+//! ```
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufWriter;
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::types::{QueriesDataset, QueryType, ScoredNeighbor, ScoredResults};
+
+/// Line of a results file.
+#[derive(Debug, Serialize)]
+struct QueryRecord<'a> {
+    query: usize,
+    query_type: QueryType,
+    category: Option<i32>,
+    t_lower: Option<f32>,
+    t_upper: Option<f32>,
+    neighbors: &'a [ScoredNeighbor],
+}
+
+/// Writes one line per query of the dataset with its scored results.
+pub fn write_results_to<W: Write>(
+    writer: &mut W,
+    results: &ScoredResults,
+    queries_dataset: &QueriesDataset,
+) -> io::Result<()> {
+    for (index, neighbors) in results.iter().enumerate() {
+        let query = queries_dataset
+            .get(index)
+            .ok_or_else(|| io::Error::other(format!("Query {} is out of range", index)))?;
+        let record = QueryRecord {
+            query: index,
+            query_type: query.query_type,
+            category: query.v_categorical,
+            t_lower: query.t_lower_bound,
+            t_upper: query.t_upper_bound,
+            neighbors,
+        };
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Saves scored results to a JSON Lines file.
+#[cfg(feature = "fs")]
+pub fn write_results<P: AsRef<Path>>(
+    results: &ScoredResults,
+    queries_dataset: &QueriesDataset,
+    file_path: P,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_results_to(&mut writer, results, queries_dataset)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ParsedQuery;
+
+    #[test]
+    fn writes_one_line_per_query() {
+        let mut queries = QueriesDataset::default();
+        queries
+            .push(&ParsedQuery {
+                query_type: QueryType::CategoricalConstraint,
+                v_categorical: Some(3),
+                t_lower_bound: None,
+                t_upper_bound: None,
+                query_vector: &[0.0, 1.0],
+            })
+            .unwrap();
+        let results = vec![vec![ScoredNeighbor {
+            id: 12,
+            distance: 0.25,
+        }]];
+
+        let mut bytes = Vec::new();
+        write_results_to(&mut bytes, &results, &queries).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "{\"query\":0,\"query_type\":\"CategoricalConstraint\",\"category\":3,\
+             \"t_lower\":null,\"t_upper\":null,\"neighbors\":[{\"id\":12,\"distance\":0.25}]}\n"
+        );
+        assert!(
+            write_results_to(&mut Vec::new(), &[results, vec![vec![]]].concat(), &queries).is_err()
+        );
+    }
+}
@@ -0,0 +1,1874 @@
+//! Utilities for reading and writing the binary format used
+//! by the SIGMOD 2024 datasets.
+//!
+//! The binary format used by the SIGMOD 2024 datasets is a custom format
+//! that stores data points as vectors of floats. Each data point is stored
+//! as a sequence of 4-byte floats in little-endian order.
+//!
+//! We differentiate between two types of files, nodes and queries.
+//!
+//! Nodes are represented as vectors of dimension `102` where the first
+//! two entries in the vector are the categorical attribute and timestamp
+//! attribute respectively. The remaining `100` entries are the actual vector
+//! entries.
+//!
+//! Queries are represented as vectors of dimension `104` where the first
+//! entry is the query type; which distinguishes between non-constrained
+//! queries, equality queries, range queries and equality and range queries.
+//!
+//! The contest datasets always use `100`-dimensional vectors but the header
+//! only stores the number of rows, so for other datasets the dimensionality
+//! is either given explicitly or inferred from the size of the file.
+//!
+//! The [`vecs`] module reads the formats of the classic ANN datasets and,
+//! with the `hdf5` feature, the `hdf5` module reads ann-benchmarks files.
+//! The `npy` and `parquet` features add the NumPy and Parquet formats, the
+//! `sqlite` feature reads nodes from database tables, the
+//! [`json`] and [`csv`] modules write results for inspection and the
+//! [`text`] module holds human-readable datasets for small fixtures, the
+//! [`graph`] module exports graph indexes for visualization. Dataset files
+//! ending in `.zst` and `.gz` are decompressed on the fly with the `zstd`
+//! and `gzip` features.
+pub mod checksum;
+#[cfg(feature = "fs")]
+pub(crate) mod compression;
+pub mod csv;
+pub mod graph;
+#[cfg(feature = "hdf5")]
+pub mod hdf5;
+pub mod json;
+#[cfg(feature = "npy")]
+pub mod npy;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod text;
+pub mod vecs;
+
+use crate::constants::*;
+use crate::distance;
+use crate::error::{self, GlasshouseError};
+use crate::progress::{NoProgress, Phase, Progress, Tracker};
+#[cfg(feature = "fs")]
+use crate::shard;
+use crate::types::*; // Or specific types like NodesDataset, QueriesDataset, etc.
+#[cfg(any(feature = "fs", test))]
+use rand::Rng;
+#[cfg(feature = "fs")]
+use rand::SeedableRng;
+#[cfg(feature = "fs")]
+use rand::rngs::StdRng;
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufWriter;
+use std::io::{self, Read, Write};
+use std::mem;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::warn;
+
+impl NodesDataset {
+    /// Returns a parsed node at the given index, or `None` if the node was deleted.
+    pub fn get(&self, index: usize) -> Option<ParsedNode<'_>> {
+        if index >= self.num_vectors as usize || self.is_deleted(index as u32) {
+            return None;
+        }
+        Some(ParsedNode {
+            c_attr: self.c_attrs[index],
+            t_attr: self.t_attrs[index],
+            vector: self.vector(index),
+        })
+    }
+
+    /// Returns the vector of the node at the given index.
+    pub fn vector(&self, index: usize) -> &[f32] {
+        &self.vectors[index * self.dimensions..(index + 1) * self.dimensions]
+    }
+
+    /// Appends a node to the dataset and returns its ID, the first node
+    /// pushed to an empty dataset sets its dimensionality.
+    pub fn push(&mut self, node: ParsedNodeOwned) -> error::Result<u32> {
+        if self.num_vectors == 0 && self.vectors.is_empty() {
+            self.dimensions = node.vector.len();
+        }
+        if node.vector.len() != self.dimensions {
+            return Err(GlasshouseError::Malformed(format!(
+                "Cannot push a vector of dimension {} to a dataset of dimension {}",
+                node.vector.len(),
+                self.dimensions
+            )));
+        }
+
+        let node_id = self.num_vectors;
+        if !self.tombstones.is_empty() && node_id.is_multiple_of(64) {
+            self.tombstones.push(0);
+        }
+        self.c_attrs.push(node.c_attr);
+        self.t_attrs.push(node.t_attr);
+        self.vectors.extend_from_slice(&node.vector);
+        self.num_vectors += 1;
+        // The node has the largest ID so it goes after equal attributes, and
+        // the indexes stay sorted while pushed attributes do not decrease.
+        // Otherwise they are dropped and sorted again on the next filtered
+        // query, rather than shifted on every push.
+        if !node.t_attr.is_nan() {
+            let sorted = self.t_index.get().is_some_and(|t_index| {
+                t_index
+                    .last()
+                    .is_none_or(|&(t, _)| t.total_cmp(&node.t_attr).is_le())
+            });
+            match self.t_index.get_mut() {
+                Some(t_index) if sorted => t_index.push((node.t_attr, node_id)),
+                _ => drop(self.t_index.take()),
+            }
+        }
+        let sorted = self
+            .c_index
+            .get()
+            .is_some_and(|c_index| c_index.last().is_none_or(|&(c, _)| c <= node.c_attr));
+        match self.c_index.get_mut() {
+            Some(c_index) if sorted => c_index.push((node.c_attr, node_id)),
+            _ => drop(self.c_index.take()),
+        }
+        self.category_stats_mut(node.c_attr)
+            .add(node.t_attr, &node.vector);
+        Ok(node_id)
+    }
+
+    /// Appends the nodes of another dataset, keeping their order, and
+    /// returns the ID of its first node in this dataset. Appending to an
+    /// empty dataset sets its dimensionality.
+    pub fn append(&mut self, other: NodesDataset) -> error::Result<u32> {
+        if self.num_vectors == 0 && self.vectors.is_empty() {
+            self.dimensions = other.dimensions;
+        }
+        if other.num_vectors > 0 && other.dimensions != self.dimensions {
+            return Err(GlasshouseError::Malformed(format!(
+                "Cannot append vectors of dimension {} to a dataset of dimension {}",
+                other.dimensions, self.dimensions
+            )));
+        }
+
+        let first_id = self.num_vectors;
+        self.c_attrs.extend_from_slice(&other.c_attrs);
+        self.t_attrs.extend_from_slice(&other.t_attrs);
+        self.vectors.extend_from_slice(&other.vectors);
+        self.num_vectors += other.num_vectors;
+        if !self.tombstones.is_empty() {
+            self.tombstones
+                .resize((self.num_vectors as usize).div_ceil(64), 0);
+        }
+        for node_id in (0..other.num_vectors).filter(|&id| other.is_deleted(id)) {
+            self.delete(first_id + node_id);
+        }
+        self.index_attributes();
+        Ok(first_id)
+    }
+
+    /// Creates a nodes dataset from in-memory attributes and vectors, stored
+    /// contiguously with `dimensions` entries per node.
+    pub fn from_parts(
+        dimensions: usize,
+        c_attrs: Vec<i32>,
+        t_attrs: Vec<f32>,
+        vectors: Vec<f32>,
+    ) -> error::Result<Self> {
+        let num_vectors = c_attrs.len();
+        if t_attrs.len() != num_vectors || vectors.len() != num_vectors * dimensions {
+            return Err(GlasshouseError::Malformed(format!(
+                "Expected {} timestamps and {} vector entries for {} nodes of dimension {}, got {} and {}",
+                num_vectors,
+                num_vectors * dimensions,
+                num_vectors,
+                dimensions,
+                t_attrs.len(),
+                vectors.len()
+            )));
+        }
+        let mut nodes_dataset = NodesDataset {
+            num_vectors: num_vectors as u32,
+            dimensions,
+            c_attrs,
+            t_attrs,
+            vectors,
+            ..Default::default()
+        };
+        nodes_dataset.index_attributes();
+        Ok(nodes_dataset)
+    }
+
+    /// Parses a nodes dataset from the contents of a binary file, inferring
+    /// the vector dimensionality from its length.
+    pub fn from_bytes(bytes: &[u8]) -> error::Result<Self> {
+        let mut reader = bytes;
+        let num_vectors = read_header(&mut reader)?;
+        let dimensions =
+            infer_dimensions(bytes.len() as u64, num_vectors, NODE_VECTOR_START_INDEX)?;
+        Self::read_rows(reader, num_vectors, dimensions, &NoProgress)
+    }
+
+    /// Reads a nodes dataset in the binary format from a reader, holding
+    /// vectors with the given number of dimensions.
+    pub fn from_reader<R: Read>(mut reader: R, dimensions: usize) -> error::Result<Self> {
+        let num_vectors = read_header(&mut reader)?;
+        Self::read_rows(reader, num_vectors, dimensions, &NoProgress)
+    }
+
+    /// Marks a node as deleted, returns false if it does not exist or was
+    /// already deleted. Deleted nodes are skipped by the solvers but keep
+    /// their ID until the dataset is compacted.
+    pub fn delete(&mut self, node_id: u32) -> bool {
+        if node_id >= self.num_vectors || self.is_deleted(node_id) {
+            return false;
+        }
+        if self.tombstones.is_empty() {
+            self.tombstones = vec![0; (self.num_vectors as usize).div_ceil(64)];
+        }
+        self.tombstones[node_id as usize / 64] |= 1 << (node_id % 64);
+        true
+    }
+
+    /// Returns true if the node was deleted.
+    pub fn is_deleted(&self, node_id: u32) -> bool {
+        self.tombstones
+            .get(node_id as usize / 64)
+            .is_some_and(|word| word & (1 << (node_id % 64)) != 0)
+    }
+
+    /// Number of deleted nodes awaiting compaction.
+    pub fn num_deleted(&self) -> u32 {
+        self.tombstones.iter().map(|word| word.count_ones()).sum()
+    }
+
+    /// Sorts the nodes by timestamp for [`NodesDataset::timestamp_range`]
+    /// and by category for [`NodesDataset::category_nodes`], must be called
+    /// again after assigning `t_attrs` or `c_attrs` directly.
+    pub fn index_attributes(&mut self) {
+        self.t_index = OnceLock::from(sorted_t_index(&self.t_attrs));
+        self.c_index = OnceLock::from(sorted_c_index(&self.c_attrs));
+        self.category_stats.clear();
+        let c_index = self.c_index.get().expect("set above");
+        for &(c_attr, node_id) in c_index {
+            if self
+                .category_stats
+                .last()
+                .is_none_or(|stats| stats.category != c_attr)
+            {
+                self.category_stats
+                    .push(CategoryStats::new(c_attr, self.dimensions));
+            }
+            let index = node_id as usize;
+            let vector = &self.vectors[index * self.dimensions..(index + 1) * self.dimensions];
+            let stats = self.category_stats.last_mut().expect("pushed above");
+            stats.add(self.t_attrs[index], vector);
+        }
+    }
+
+    /// Returns the statistics of a category, `None` if it has no node.
+    pub fn category_stats(&self, category: i32) -> Option<&CategoryStats> {
+        self.category_stats
+            .binary_search_by_key(&category, |stats| stats.category)
+            .ok()
+            .map(|position| &self.category_stats[position])
+    }
+
+    /// Returns the statistics of a category, created empty if needed.
+    fn category_stats_mut(&mut self, category: i32) -> &mut CategoryStats {
+        let position = match self
+            .category_stats
+            .binary_search_by_key(&category, |stats| stats.category)
+        {
+            Ok(position) => position,
+            Err(position) => {
+                self.category_stats
+                    .insert(position, CategoryStats::new(category, self.dimensions));
+                position
+            }
+        };
+        &mut self.category_stats[position]
+    }
+
+    /// Returns the IDs of the nodes with a timestamp in `[lower, upper]`,
+    /// deleted nodes included, in increasing timestamp order.
+    pub fn timestamp_range(&self, lower: f32, upper: f32) -> impl Iterator<Item = u32> + '_ {
+        self.timestamp_entries(lower, upper)
+            .iter()
+            .map(|&(_, node_id)| node_id)
+    }
+
+    /// Number of nodes returned by [`NodesDataset::timestamp_range`].
+    pub fn timestamp_range_len(&self, lower: f32, upper: f32) -> usize {
+        self.timestamp_entries(lower, upper).len()
+    }
+
+    fn timestamp_entries(&self, lower: f32, upper: f32) -> &[(f32, u32)] {
+        let t_index = self.t_index.get_or_init(|| sorted_t_index(&self.t_attrs));
+        let start = t_index.partition_point(|&(t, _)| t < lower);
+        let end = t_index.partition_point(|&(t, _)| t <= upper).max(start);
+        &t_index[start..end]
+    }
+
+    /// Returns the IDs of the nodes of a category, deleted nodes included,
+    /// in increasing ID order.
+    pub fn category_nodes(&self, category: i32) -> impl Iterator<Item = u32> + '_ {
+        self.category_entries(category)
+            .iter()
+            .map(|&(_, node_id)| node_id)
+    }
+
+    /// Number of nodes returned by [`NodesDataset::category_nodes`].
+    pub fn category_len(&self, category: i32) -> usize {
+        self.category_entries(category).len()
+    }
+
+    fn category_entries(&self, category: i32) -> &[(i32, u32)] {
+        let c_index = self.c_index.get_or_init(|| sorted_c_index(&self.c_attrs));
+        let start = c_index.partition_point(|&(c, _)| c < category);
+        let end = c_index.partition_point(|&(c, _)| c <= category);
+        &c_index[start..end]
+    }
+
+    /// Scales every vector to unit length for the cosine metric, see
+    /// [`distance::normalize`], and recomputes the category centroids from
+    /// the scaled vectors. Nodes whose vector is zero have no cosine
+    /// similarity to any query and are deleted with a warning, so they are
+    /// never returned, returns their IDs.
+    pub fn normalize_vectors(&mut self) -> Vec<u32> {
+        let mut deleted = Vec::new();
+        if self.dimensions == 0 {
+            return deleted;
+        }
+        for index in 0..self.num_vectors as usize {
+            let vector = &mut self.vectors[index * self.dimensions..(index + 1) * self.dimensions];
+            if !distance::normalize(vector) && self.delete(index as u32) {
+                deleted.push(index as u32);
+            }
+        }
+        self.index_attributes();
+        if let Some(&first) = deleted.first() {
+            warn!(
+                deleted = deleted.len(),
+                first, "deleted nodes with a zero vector, which have no cosine similarity"
+            );
+        }
+        deleted
+    }
+
+    /// Fails on the first node holding a NaN or infinite attribute or vector
+    /// entry, which would otherwise silently corrupt distances.
+    pub fn check_finite(&self) -> error::Result<()> {
+        for index in 0..self.num_vectors as usize {
+            let t_attr = self.t_attrs[index];
+            if !std::iter::once(&t_attr)
+                .chain(self.vector(index))
+                .all(|v| v.is_finite())
+            {
+                return Err(GlasshouseError::Malformed(format!(
+                    "Node {} holds a non-finite value",
+                    index
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes deleted nodes from the dataset, returns the new ID of every
+    /// node indexed by its previous ID or `None` for deleted nodes.
+    pub fn compact(&mut self) -> Vec<Option<u32>> {
+        let mut remap = Vec::with_capacity(self.num_vectors as usize);
+        let mut next_id = 0;
+        for node_id in 0..self.num_vectors {
+            if self.is_deleted(node_id) {
+                remap.push(None);
+                continue;
+            }
+            let (from, to) = (node_id as usize, next_id as usize);
+            self.c_attrs[to] = self.c_attrs[from];
+            self.t_attrs[to] = self.t_attrs[from];
+            self.vectors.copy_within(
+                from * self.dimensions..(from + 1) * self.dimensions,
+                to * self.dimensions,
+            );
+            remap.push(Some(next_id));
+            next_id += 1;
+        }
+
+        self.num_vectors = next_id;
+        self.c_attrs.truncate(next_id as usize);
+        self.t_attrs.truncate(next_id as usize);
+        self.vectors.truncate(next_id as usize * self.dimensions);
+        self.tombstones.clear();
+        self.index_attributes();
+        remap
+    }
+
+    /// Reads the nodes dataset from a binary file, inferring the vector
+    /// dimensionality from the size of the file.
+    #[cfg(feature = "fs")]
+    pub fn read<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        Self::read_with_progress(file_path, None, &NoProgress)
+    }
+
+    /// Reads the nodes dataset from a binary file holding vectors with
+    /// the given number of dimensions.
+    #[cfg(feature = "fs")]
+    pub fn read_with_dimensions<P: AsRef<Path>>(
+        file_path: P,
+        dimensions: usize,
+    ) -> error::Result<Self> {
+        Self::read_with_progress(file_path, Some(dimensions), &NoProgress)
+    }
+
+    /// Reads the nodes dataset from a binary file, reporting the rows read
+    /// to the progress. The dimensionality is inferred from the size of the
+    /// file when not given. `.zst` files are decompressed while read.
+    #[cfg(feature = "fs")]
+    pub fn read_with_progress<P: AsRef<Path>>(
+        file_path: P,
+        dimensions: Option<usize>,
+        progress: &dyn Progress,
+    ) -> error::Result<Self> {
+        let (mut reader, file_len) = compression::open(file_path.as_ref())?;
+
+        let num_vectors = read_header(&mut reader)?;
+        let dimensions =
+            content_dimensions(file_len, dimensions, num_vectors, NODE_VECTOR_START_INDEX)?;
+        Self::read_rows(reader, num_vectors, dimensions, progress)
+    }
+
+    /// Reads the nodes of one of `parts` contiguous partitions of a binary
+    /// file, see [`crate::shard::partition`], skipping the rows of the other
+    /// partitions. Returns them with the ID of their first node in the file.
+    #[cfg(feature = "fs")]
+    pub fn read_partition<P: AsRef<Path>>(
+        file_path: P,
+        dimensions: Option<usize>,
+        part: usize,
+        parts: usize,
+        progress: &dyn Progress,
+    ) -> error::Result<(Self, u32)> {
+        let (mut reader, file_len) = compression::open(file_path.as_ref())?;
+
+        let num_vectors = read_header(&mut reader)?;
+        let dimensions =
+            content_dimensions(file_len, dimensions, num_vectors, NODE_VECTOR_START_INDEX)?;
+        let range = shard::partition(num_vectors, part, parts);
+        let row_bytes = ((NODE_VECTOR_START_INDEX + dimensions) * mem::size_of::<f32>()) as u64;
+        let skip = range.start as u64 * row_bytes;
+        if io::copy(&mut reader.by_ref().take(skip), &mut io::sink())? < skip {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let nodes_dataset = Self::read_rows(reader, range.end - range.start, dimensions, progress)?;
+        Ok((nodes_dataset, range.start))
+    }
+
+    /// Reads a nodes dataset split across several binary files, the nodes of
+    /// each shard follow those of the previous ones so IDs are global over
+    /// the concatenation. All shards must hold vectors of the same
+    /// dimensionality, inferred from each file when not given.
+    #[cfg(feature = "fs")]
+    pub fn read_shards<P: AsRef<Path>>(
+        file_paths: &[P],
+        dimensions: Option<usize>,
+        progress: &dyn Progress,
+    ) -> error::Result<Self> {
+        let mut nodes_dataset = NodesDataset::default();
+        for file_path in file_paths {
+            let shard = Self::read_with_progress(file_path, dimensions, progress)?;
+            nodes_dataset.append(shard).map_err(|e| {
+                GlasshouseError::Malformed(format!("Shard {}: {}", file_path.as_ref().display(), e))
+            })?;
+        }
+        Ok(nodes_dataset)
+    }
+
+    /// Reads a uniform random sample of at most `capacity` nodes from a
+    /// binary file in a single pass, never holding more than the sample in
+    /// memory. Returns the sampled nodes in file order along with the ID of
+    /// each of them in the file.
+    #[cfg(feature = "fs")]
+    pub fn read_reservoir<P: AsRef<Path>>(
+        file_path: P,
+        dimensions: Option<usize>,
+        capacity: usize,
+        seed: u64,
+        progress: &dyn Progress,
+    ) -> error::Result<(Self, Vec<u32>)> {
+        let (mut reader, file_len) = compression::open(file_path.as_ref())?;
+
+        let num_vectors = read_header(&mut reader)?;
+        let dimensions =
+            content_dimensions(file_len, dimensions, num_vectors, NODE_VECTOR_START_INDEX)?;
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self::read_reservoir_rows(
+            reader,
+            num_vectors,
+            dimensions,
+            capacity,
+            &mut rng,
+            progress,
+        )
+    }
+
+    /// Samples the rows with reservoir sampling: the first `capacity` rows
+    /// fill the reservoir, each later row replaces a random slot with a
+    /// probability of `capacity` over the number of rows read so far.
+    #[cfg(any(feature = "fs", test))]
+    fn read_reservoir_rows<R: Read, G: Rng>(
+        mut reader: R,
+        num_vectors: u32,
+        dimensions: usize,
+        capacity: usize,
+        rng: &mut G,
+        progress: &dyn Progress,
+    ) -> error::Result<(Self, Vec<u32>)> {
+        let tracker = Tracker::new(progress, Phase::Load, num_vectors as u64);
+        let capacity = capacity.min(num_vectors as usize);
+        let mut ids = Vec::with_capacity(capacity);
+        let mut c_attrs = Vec::with_capacity(capacity);
+        let mut t_attrs = Vec::with_capacity(capacity);
+        let mut vectors = Vec::with_capacity(capacity * dimensions);
+
+        let mut buffer = vec![0.0f32; NODE_VECTOR_START_INDEX + dimensions];
+        for node_id in 0..num_vectors {
+            read_f32s(&mut reader, &mut buffer)?;
+            tracker.advance(1);
+            let c_attr = categorical(buffer[NODE_C_ATTR_INDEX])?;
+            if ids.len() < capacity {
+                ids.push(node_id);
+                c_attrs.push(c_attr);
+                t_attrs.push(buffer[NODE_T_ATTR_INDEX]);
+                vectors.extend_from_slice(&buffer[NODE_VECTOR_START_INDEX..]);
+                continue;
+            }
+            let slot = rng.random_range(0..=node_id as usize);
+            if slot < capacity {
+                ids[slot] = node_id;
+                c_attrs[slot] = c_attr;
+                t_attrs[slot] = buffer[NODE_T_ATTR_INDEX];
+                vectors[slot * dimensions..(slot + 1) * dimensions]
+                    .copy_from_slice(&buffer[NODE_VECTOR_START_INDEX..]);
+            }
+        }
+
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.sort_unstable_by_key(|&slot| ids[slot]);
+        let mut sample = NodesDataset {
+            num_vectors: ids.len() as u32,
+            dimensions,
+            ..Default::default()
+        };
+        for &slot in &order {
+            sample.c_attrs.push(c_attrs[slot]);
+            sample.t_attrs.push(t_attrs[slot]);
+            sample
+                .vectors
+                .extend_from_slice(&vectors[slot * dimensions..(slot + 1) * dimensions]);
+        }
+        sample.index_attributes();
+        Ok((sample, order.into_iter().map(|slot| ids[slot]).collect()))
+    }
+
+    fn read_rows<R: Read>(
+        mut reader: R,
+        num_vectors: u32,
+        dimensions: usize,
+        progress: &dyn Progress,
+    ) -> error::Result<Self> {
+        let tracker = Tracker::new(progress, Phase::Load, num_vectors as u64);
+        let capacity = preallocated_rows(num_vectors, NODE_VECTOR_START_INDEX + dimensions);
+        let mut c_attrs = Vec::with_capacity(capacity);
+        let mut t_attrs = Vec::with_capacity(capacity);
+        let mut vectors = Vec::with_capacity(capacity * dimensions);
+
+        // Re-use a buffer for each item to avoid reallocations.
+        let mut buffer = vec![0.0f32; NODE_VECTOR_START_INDEX + dimensions];
+
+        for _ in 0..num_vectors {
+            read_f32s(&mut reader, &mut buffer)?;
+
+            c_attrs.push(categorical(buffer[NODE_C_ATTR_INDEX])?);
+            t_attrs.push(buffer[NODE_T_ATTR_INDEX]);
+            vectors.extend_from_slice(&buffer[NODE_VECTOR_START_INDEX..]);
+            tracker.advance(1);
+        }
+
+        let mut nodes_dataset = NodesDataset {
+            num_vectors,
+            dimensions,
+            c_attrs,
+            t_attrs,
+            vectors,
+            ..Default::default()
+        };
+        nodes_dataset.index_attributes();
+        Ok(nodes_dataset)
+    }
+
+    /// Writes the nodes dataset to a binary file.
+    #[cfg(feature = "fs")]
+    pub fn write<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let file = File::create(file_path)?;
+        let mut writer = BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Serializes the nodes dataset in the binary format.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.num_vectors.to_le_bytes())?;
+        for index in 0..self.num_vectors as usize {
+            write_row(
+                writer,
+                &[self.c_attrs[index] as f32, self.t_attrs[index]],
+                self.vector(index),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Timestamp and ID of every node with a timestamp other than NaN, sorted
+/// by timestamp then ID.
+fn sorted_t_index(t_attrs: &[f32]) -> Vec<(f32, u32)> {
+    let mut t_index: Vec<_> = t_attrs
+        .iter()
+        .enumerate()
+        .filter(|(_, t_attr)| !t_attr.is_nan())
+        .map(|(node_id, &t_attr)| (t_attr, node_id as u32))
+        .collect();
+    t_index.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    t_index
+}
+
+/// Category and ID of every node sorted by category then ID.
+fn sorted_c_index(c_attrs: &[i32]) -> Vec<(i32, u32)> {
+    let mut c_index: Vec<_> = c_attrs
+        .iter()
+        .enumerate()
+        .map(|(node_id, &c_attr)| (c_attr, node_id as u32))
+        .collect();
+    c_index.sort_unstable();
+    c_index
+}
+
+impl QueriesDataset {
+    /// Returns a parsed query at the given index.
+    pub fn get(&self, index: usize) -> Option<ParsedQuery<'_>> {
+        if index >= self.num_queries as usize {
+            return None;
+        }
+        Some(ParsedQuery {
+            query_type: self.query_types[index],
+            v_categorical: self.v_categoricals[index].categorical_value(),
+            t_lower_bound: self.t_lower_bounds[index].value(),
+            t_upper_bound: self.t_upper_bounds[index].value(),
+            query_vector: self.query_vector(index),
+        })
+    }
+
+    /// Returns the vector of the query at the given index.
+    pub fn query_vector(&self, index: usize) -> &[f32] {
+        &self.query_vectors[index * self.dimensions..(index + 1) * self.dimensions]
+    }
+
+    /// Scales every query vector to unit length for the cosine metric, see
+    /// [`distance::normalize`]. Fails on the first query whose vector is
+    /// zero, as it has no cosine similarity to any node, leaving the queries
+    /// before it scaled.
+    pub fn normalize_vectors(&mut self) -> error::Result<()> {
+        if self.dimensions == 0 {
+            return Ok(());
+        }
+        for (index, vector) in self
+            .query_vectors
+            .chunks_exact_mut(self.dimensions)
+            .enumerate()
+        {
+            if !distance::normalize(vector) {
+                return Err(GlasshouseError::Malformed(format!(
+                    "Query {} has a zero vector, which has no cosine similarity",
+                    index
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails on the first query holding a NaN or infinite filter or vector
+    /// entry.
+    pub fn check_finite(&self) -> error::Result<()> {
+        for index in 0..self.num_queries as usize {
+            let filters = [
+                self.v_categoricals[index].raw(),
+                self.t_lower_bounds[index].raw(),
+                self.t_upper_bounds[index].raw(),
+            ];
+            if !filters
+                .iter()
+                .chain(self.query_vector(index))
+                .all(|v| v.is_finite())
+            {
+                return Err(GlasshouseError::Malformed(format!(
+                    "Query {} holds a non-finite value",
+                    index
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the distinct queries, in the order of their first
+    /// occurrence, and for each query the index of its copy in them. The
+    /// results of query `i` are those of the distinct query `rows[i]`.
+    pub fn dedup(&self) -> (QueriesDataset, Vec<u32>) {
+        let first = crate::solvers::batch::first_duplicates(self);
+        let mut distinct = Vec::new();
+        let mut rows = Vec::with_capacity(first.len());
+        for (index, &first) in first.iter().enumerate() {
+            if first == index {
+                rows.push(distinct.len() as u32);
+                distinct.push(index);
+            } else {
+                rows.push(rows[first]);
+            }
+        }
+        (crate::sample::select_queries(self, &distinct), rows)
+    }
+
+    /// Rewrites degenerate queries into the simpler type they effectively
+    /// have, as the solvers match no node for a constraint missing its
+    /// value. A categorical constraint without a category is dropped, a
+    /// timestamp constraint missing one bound is open on that side and one
+    /// missing both is dropped. Returns the number of rewritten queries.
+    pub fn normalize(&mut self) -> u32 {
+        let mut rewritten = 0;
+        for index in 0..self.num_queries as usize {
+            let query_type = self.query_types[index];
+            let (categorical, timestamp) = match query_type {
+                QueryType::VectorOnly => (false, false),
+                QueryType::CategoricalConstraint => (true, false),
+                QueryType::TimestampConstraint => (false, true),
+                QueryType::BothConstraints => (true, true),
+            };
+            let categorical = categorical && self.v_categoricals[index].value().is_some();
+            let lower = self.t_lower_bounds[index].value();
+            let upper = self.t_upper_bounds[index].value();
+            let timestamp = timestamp && (lower.is_some() || upper.is_some());
+
+            let normalized = match (categorical, timestamp) {
+                (false, false) => QueryType::VectorOnly,
+                (true, false) => QueryType::CategoricalConstraint,
+                (false, true) => QueryType::TimestampConstraint,
+                (true, true) => QueryType::BothConstraints,
+            };
+            let open = timestamp && (lower.is_none() || upper.is_none());
+            if open {
+                let lower = lower.unwrap_or(f32::NEG_INFINITY);
+                let upper = upper.unwrap_or(f32::INFINITY);
+                self.t_lower_bounds[index] = OptionalFilterValue::new(lower);
+                self.t_upper_bounds[index] = OptionalFilterValue::new(upper);
+            }
+            if open || normalized != query_type {
+                self.query_types[index] = normalized;
+                rewritten += 1;
+            }
+        }
+        rewritten
+    }
+
+    /// Appends a query to the dataset and returns its index, the first query
+    /// pushed to an empty dataset sets its dimensionality.
+    pub fn push(&mut self, query: &ParsedQuery) -> error::Result<u32> {
+        if self.num_queries == 0 && self.query_vectors.is_empty() {
+            self.dimensions = query.query_vector.len();
+        }
+        if query.query_vector.len() != self.dimensions {
+            return Err(GlasshouseError::Malformed(format!(
+                "Cannot push a query vector of dimension {} to a dataset of dimension {}",
+                query.query_vector.len(),
+                self.dimensions
+            )));
+        }
+
+        let unset = OptionalFilterValue::new(-1.0);
+        let index = self.num_queries;
+        self.query_types.push(query.query_type);
+        self.v_categoricals.push(
+            query
+                .v_categorical
+                .map_or(unset, |v| OptionalFilterValue::new(v as f32)),
+        );
+        self.t_lower_bounds
+            .push(query.t_lower_bound.map_or(unset, OptionalFilterValue::new));
+        self.t_upper_bounds
+            .push(query.t_upper_bound.map_or(unset, OptionalFilterValue::new));
+        self.query_vectors.extend_from_slice(query.query_vector);
+        self.num_queries += 1;
+        Ok(index)
+    }
+
+    /// Parses a queries dataset from the contents of a binary file,
+    /// inferring the vector dimensionality from its length.
+    pub fn from_bytes(bytes: &[u8]) -> error::Result<Self> {
+        let mut reader = bytes;
+        let num_queries = read_header(&mut reader)?;
+        let dimensions =
+            infer_dimensions(bytes.len() as u64, num_queries, QUERY_VECTOR_START_INDEX)?;
+        Self::read_rows(reader, num_queries, dimensions, &NoProgress)
+    }
+
+    /// Reads a queries dataset in the binary format from a reader, holding
+    /// query vectors with the given number of dimensions.
+    pub fn from_reader<R: Read>(mut reader: R, dimensions: usize) -> error::Result<Self> {
+        let num_queries = read_header(&mut reader)?;
+        Self::read_rows(reader, num_queries, dimensions, &NoProgress)
+    }
+
+    /// Reads the queries dataset from a binary file, inferring the vector
+    /// dimensionality from the size of the file.
+    #[cfg(feature = "fs")]
+    pub fn read<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        Self::read_with_progress(file_path, None, &NoProgress)
+    }
+
+    /// Reads the queries dataset from a binary file holding query vectors
+    /// with the given number of dimensions.
+    #[cfg(feature = "fs")]
+    pub fn read_with_dimensions<P: AsRef<Path>>(
+        file_path: P,
+        dimensions: usize,
+    ) -> error::Result<Self> {
+        Self::read_with_progress(file_path, Some(dimensions), &NoProgress)
+    }
+
+    /// Reads the queries dataset from a binary file, reporting the rows read
+    /// to the progress. The dimensionality is inferred from the size of the
+    /// file when not given. `.zst` files are decompressed while read.
+    #[cfg(feature = "fs")]
+    pub fn read_with_progress<P: AsRef<Path>>(
+        file_path: P,
+        dimensions: Option<usize>,
+        progress: &dyn Progress,
+    ) -> error::Result<Self> {
+        let (mut reader, file_len) = compression::open(file_path.as_ref())?;
+
+        let num_queries = read_header(&mut reader)?;
+        let dimensions =
+            content_dimensions(file_len, dimensions, num_queries, QUERY_VECTOR_START_INDEX)?;
+        Self::read_rows(reader, num_queries, dimensions, progress)
+    }
+
+    fn read_rows<R: Read>(
+        mut reader: R,
+        num_queries: u32,
+        dimensions: usize,
+        progress: &dyn Progress,
+    ) -> error::Result<Self> {
+        let tracker = Tracker::new(progress, Phase::Load, num_queries as u64);
+        let capacity = preallocated_rows(num_queries, QUERY_VECTOR_START_INDEX + dimensions);
+        let mut query_types_vec = Vec::with_capacity(capacity);
+        let mut v_categoricals_vec = Vec::with_capacity(capacity);
+        let mut t_lower_bounds_vec = Vec::with_capacity(capacity);
+        let mut t_upper_bounds_vec = Vec::with_capacity(capacity);
+        let mut query_vectors_vec = Vec::with_capacity(capacity * dimensions);
+
+        let mut buffer = vec![0.0f32; QUERY_VECTOR_START_INDEX + dimensions];
+
+        for _ in 0..num_queries {
+            read_f32s(&mut reader, &mut buffer)?;
+
+            query_types_vec.push(QueryType::from_f32(buffer[QUERY_TYPE_INDEX])?);
+            v_categoricals_vec.push(OptionalFilterValue::new(buffer[QUERY_V_CAT_INDEX]));
+            t_lower_bounds_vec.push(OptionalFilterValue::new(buffer[QUERY_T_LOWER_INDEX]));
+            t_upper_bounds_vec.push(OptionalFilterValue::new(buffer[QUERY_T_UPPER_INDEX]));
+            query_vectors_vec.extend_from_slice(&buffer[QUERY_VECTOR_START_INDEX..]);
+            tracker.advance(1);
+        }
+
+        Ok(QueriesDataset {
+            num_queries,
+            dimensions,
+            query_types: query_types_vec,
+            v_categoricals: v_categoricals_vec,
+            t_lower_bounds: t_lower_bounds_vec,
+            t_upper_bounds: t_upper_bounds_vec,
+            query_vectors: query_vectors_vec,
+        })
+    }
+
+    /// Writes the queries dataset to a binary file.
+    #[cfg(feature = "fs")]
+    pub fn write<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let file = File::create(file_path)?;
+        let mut writer = BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Serializes the queries dataset in the binary format.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.num_queries.to_le_bytes())?;
+        for index in 0..self.num_queries as usize {
+            write_row(
+                writer,
+                &[
+                    self.query_types[index].to_f32(),
+                    self.v_categoricals[index].raw(),
+                    self.t_lower_bounds[index].raw(),
+                    self.t_upper_bounds[index].raw(),
+                ],
+                self.query_vector(index),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a single dataset row made of its attributes followed by its vector.
+fn write_row<W: Write>(writer: &mut W, attrs: &[f32], vector: &[f32]) -> io::Result<()> {
+    for value in attrs.iter().chain(vector) {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Converts a categorical attribute, stored as a float in the dataset
+/// files, to an integer. Fails on values without an exact integer
+/// counterpart.
+pub fn categorical(value: f32) -> error::Result<i32> {
+    // Casts saturate, so 2^31 would otherwise be read as `i32::MAX`.
+    let in_range = (i32::MIN as f32..-(i32::MIN as f32)).contains(&value);
+    if !in_range || value.fract() != 0.0 {
+        return Err(GlasshouseError::Malformed(format!(
+            "Categorical attribute {} is not a 32-bit integer",
+            value
+        )));
+    }
+    Ok(value as i32)
+}
+
+/// Reads little-endian floats filling the buffer. The bytes are read in
+/// place and only swapped on big-endian hosts.
+fn read_f32s<R: Read>(reader: &mut R, buffer: &mut [f32]) -> io::Result<()> {
+    // Unsafe block for doing a zero-copy read into a float buffer.
+    unsafe {
+        let byte_buffer = std::slice::from_raw_parts_mut(
+            buffer.as_mut_ptr() as *mut u8,
+            mem::size_of_val(buffer),
+        );
+        reader.read_exact(byte_buffer)?;
+    }
+    if cfg!(target_endian = "big") {
+        for value in buffer.iter_mut() {
+            *value = f32::from_bits(u32::from_le(value.to_bits()));
+        }
+    }
+    Ok(())
+}
+
+/// Writes integers in little-endian order, copying their bytes as they are
+/// on little-endian hosts.
+fn write_u32s<W: Write>(writer: &mut W, values: &[u32]) -> io::Result<()> {
+    if cfg!(target_endian = "little") {
+        let byte_slice = unsafe {
+            std::slice::from_raw_parts(values.as_ptr() as *const u8, mem::size_of_val(values))
+        };
+        writer.write_all(byte_slice)
+    } else {
+        values
+            .iter()
+            .try_for_each(|value| writer.write_all(&value.to_le_bytes()))
+    }
+}
+
+/// Largest number of bytes reserved for the rows of a dataset before
+/// reading them.
+const MAX_PREALLOCATED_BYTES: usize = 1 << 30;
+
+/// Number of rows of `row_width` floats to reserve memory for before
+/// reading them. The row count of the header is only trusted up to
+/// [`MAX_PREALLOCATED_BYTES`], so a corrupt header fails on the first
+/// missing row instead of on a huge allocation.
+fn preallocated_rows(num_rows: u32, row_width: usize) -> usize {
+    let row_bytes = row_width.saturating_mul(mem::size_of::<f32>()).max(1);
+    (num_rows as usize).min(MAX_PREALLOCATED_BYTES / row_bytes)
+}
+
+/// Reads the row count stored in the header of a dataset file.
+fn read_header<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Error raised when inferring the dimensionality of a compressed file
+/// whose uncompressed length is unknown or ambiguous.
+#[cfg(feature = "fs")]
+fn unknown_length() -> GlasshouseError {
+    GlasshouseError::Malformed(
+        "The uncompressed length is unknown, give the vector dimensions".to_string(),
+    )
+}
+
+/// Returns the vector dimensionality of a possibly compressed dataset file,
+/// inferred from its uncompressed length when not given. Given
+/// dimensionalities must be positive and, when the length is known, account
+/// for it exactly.
+#[cfg(feature = "fs")]
+fn content_dimensions(
+    length: compression::ContentLength,
+    dimensions: Option<usize>,
+    num_rows: u32,
+    num_attrs: usize,
+) -> error::Result<usize> {
+    let Some(dimensions) = dimensions else {
+        return infer_content_dimensions(length, num_rows, num_attrs);
+    };
+    if dimensions == 0 {
+        return Err(GlasshouseError::Malformed(
+            "Vectors must have at least one dimension".to_string(),
+        ));
+    }
+    let Some(mut candidates) = length.candidates() else {
+        return Ok(dimensions);
+    };
+    let expected = (num_attrs as u64 + dimensions as u64)
+        .checked_mul(num_rows as u64 * mem::size_of::<f32>() as u64)
+        .and_then(|payload_len| payload_len.checked_add(mem::size_of::<u32>() as u64));
+    if !candidates.any(|file_len| Some(file_len) == expected) {
+        return Err(GlasshouseError::Malformed(format!(
+            "File length is not consistent with {} rows of {} attributes and {} dimensions",
+            num_rows, num_attrs, dimensions
+        )));
+    }
+    Ok(dimensions)
+}
+
+/// Infers the vector dimensionality of a possibly compressed dataset file
+/// from its uncompressed length, which must match a single dimensionality.
+#[cfg(feature = "fs")]
+fn infer_content_dimensions(
+    length: compression::ContentLength,
+    num_rows: u32,
+    num_attrs: usize,
+) -> error::Result<usize> {
+    let candidates = length.candidates().ok_or_else(unknown_length)?;
+    let mut dimensions = None;
+    let mut last_error = None;
+    for file_len in candidates {
+        match infer_dimensions(file_len, num_rows, num_attrs) {
+            Ok(inferred) if dimensions.is_some_and(|d| d != inferred) => {
+                return Err(unknown_length());
+            }
+            Ok(inferred) => dimensions = Some(inferred),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    dimensions.ok_or_else(|| last_error.unwrap_or_else(unknown_length))
+}
+
+/// Infers the vector dimensionality of a dataset file from its length, the
+/// number of rows in its header and the number of attributes preceding the
+/// vector in each row.
+///
+/// Empty datasets carry no information about their width and are assumed
+/// to use the contest dimensionality.
+fn infer_dimensions(file_len: u64, num_rows: u32, num_attrs: usize) -> error::Result<usize> {
+    if num_rows == 0 {
+        return Ok(VECTOR_DIMENSIONS);
+    }
+    let payload_len = file_len.saturating_sub(mem::size_of::<u32>() as u64);
+    let row_len = mem::size_of::<f32>() as u64 * num_rows as u64;
+    if !payload_len.is_multiple_of(row_len) {
+        return Err(GlasshouseError::Malformed(format!(
+            "File length {} is not consistent with {} rows of 32-bit floats",
+            file_len, num_rows
+        )));
+    }
+    let row_width = (payload_len / row_len) as usize;
+    if row_width <= num_attrs {
+        return Err(GlasshouseError::Malformed(format!(
+            "Rows of width {} are too narrow to hold {} attributes and a vector",
+            row_width, num_attrs
+        )));
+    }
+    Ok(row_width - num_attrs)
+}
+
+/// Saves the KNN results to a binary file.
+/// The format is |Q| x k x id (uint32_t).
+#[cfg(feature = "fs")]
+pub fn write<P: AsRef<Path>>(results: &QueryResults, file_path: P) -> io::Result<()> {
+    let mut writer = ResultsWriter::create(file_path)?;
+    for single_query_results in results {
+        writer.push(single_query_results)?;
+    }
+    writer.finish()?; // Ensure all buffered data is written
+    Ok(())
+}
+
+/// Writes KNN results in the format of [`write`] one query at a time, so
+/// results can be flushed while the remaining queries are searched.
+#[derive(Debug)]
+pub struct ResultsWriter<W: Write> {
+    writer: W,
+    written: u32,
+}
+
+#[cfg(feature = "fs")]
+impl ResultsWriter<BufWriter<File>> {
+    /// Creates the results file.
+    pub fn create<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(file_path)?)))
+    }
+}
+
+impl<W: Write> ResultsWriter<W> {
+    pub fn new(writer: W) -> Self {
+        ResultsWriter { writer, written: 0 }
+    }
+
+    /// Appends the results of the next query.
+    pub fn push(&mut self, result: &[u32]) -> io::Result<()> {
+        write_u32s(&mut self.writer, result)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Number of query results written so far.
+    pub fn written(&self) -> u32 {
+        self.written
+    }
+
+    /// Flushes the results and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads KNN results of `k` neighbours per query previously saved with
+/// [`write`], checking their checksum trailer if they have one.
+#[cfg(feature = "fs")]
+pub fn read_results<P: AsRef<Path>>(file_path: P, k: usize) -> error::Result<QueryResults> {
+    check_k(k)?;
+    let bytes = std::fs::read(file_path)?;
+    let row_len = k * mem::size_of::<u32>();
+    let (bytes, _) = checksum::split(&bytes, row_len)?;
+    if !bytes.len().is_multiple_of(row_len) {
+        return Err(GlasshouseError::Malformed(format!(
+            "Results file length {} is not a multiple of {} neighbours",
+            bytes.len(),
+            k
+        )));
+    }
+
+    let results = bytes
+        .chunks_exact(row_len)
+        .map(|row| {
+            row.chunks_exact(4)
+                .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect()
+        })
+        .collect();
+    Ok(results)
+}
+
+/// Saves the distances of scored results next to their ids, the format is
+/// |Q| x k x distance (float32) in the order of the ids written by
+/// [`write`]. Missing neighbours have an infinite distance.
+#[cfg(feature = "fs")]
+pub fn write_distances<P: AsRef<Path>>(
+    results: &ScoredResults,
+    file_path: P,
+    k: usize,
+) -> io::Result<()> {
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+
+    for result in results {
+        for rank in 0..k {
+            let distance = result.get(rank).map_or(f32::INFINITY, |n| n.distance);
+            writer.write_all(&distance.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the distances of `k` neighbours per query previously saved with
+/// [`write_distances`].
+#[cfg(feature = "fs")]
+pub fn read_distances<P: AsRef<Path>>(file_path: P, k: usize) -> error::Result<Vec<Box<[f32]>>> {
+    check_k(k)?;
+    let bytes = std::fs::read(file_path)?;
+    let row_len = k * mem::size_of::<f32>();
+    if !bytes.len().is_multiple_of(row_len) {
+        return Err(GlasshouseError::Malformed(format!(
+            "Distances file length {} is not a multiple of {} neighbours",
+            bytes.len(),
+            k
+        )));
+    }
+
+    let distances = bytes
+        .chunks_exact(row_len)
+        .map(|row| {
+            row.chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect()
+        })
+        .collect();
+    Ok(distances)
+}
+
+/// Saves scored results as |Q| x k x (id (uint32_t), distance (float32))
+/// pairs, missing neighbours have the ID `pad_id` and an infinite distance.
+#[cfg(feature = "fs")]
+pub fn write_scored<P: AsRef<Path>>(
+    results: &ScoredResults,
+    file_path: P,
+    k: usize,
+    pad_id: u32,
+) -> io::Result<()> {
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+
+    for result in results {
+        for rank in 0..k {
+            let (id, distance) = result
+                .get(rank)
+                .map_or((pad_id, f32::INFINITY), |n| (n.id, n.distance));
+            writer.write_all(&id.to_le_bytes())?;
+            writer.write_all(&distance.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the scored results of `k` neighbours per query previously saved
+/// with [`write_scored`], dropping the neighbours padded with `pad_id`.
+#[cfg(feature = "fs")]
+pub fn read_scored<P: AsRef<Path>>(
+    file_path: P,
+    k: usize,
+    pad_id: u32,
+) -> error::Result<ScoredResults> {
+    check_k(k)?;
+    let bytes = std::fs::read(file_path)?;
+    let pair_len = mem::size_of::<u32>() + mem::size_of::<f32>();
+    let row_len = k * pair_len;
+    if !bytes.len().is_multiple_of(row_len) {
+        return Err(GlasshouseError::Malformed(format!(
+            "Scored results file length {} is not a multiple of {} neighbours",
+            bytes.len(),
+            k
+        )));
+    }
+
+    let results = bytes
+        .chunks_exact(row_len)
+        .map(|row| {
+            row.chunks_exact(pair_len)
+                .map(|pair| ScoredNeighbor {
+                    id: u32::from_le_bytes([pair[0], pair[1], pair[2], pair[3]]),
+                    distance: f32::from_le_bytes([pair[4], pair[5], pair[6], pair[7]]),
+                })
+                .filter(|neighbor| neighbor.id != pad_id)
+                .collect()
+        })
+        .collect();
+    Ok(results)
+}
+
+/// Fails unless results files hold at least one neighbour per query, their
+/// rows would be empty otherwise.
+#[cfg(feature = "fs")]
+fn check_k(k: usize) -> error::Result<()> {
+    if k == 0 {
+        return Err(GlasshouseError::Config(
+            "The number of neighbours k must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod in_memory_tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+
+    #[test]
+    fn dedup_keeps_the_first_of_identical_queries() {
+        let mut rng = StdRng::seed_from_u64(43);
+        let queries = generate::queries(&mut rng, 4, 3, 2);
+        let repeated = crate::sample::select_queries(&queries, &[0, 1, 0, 2, 1, 3, 3]);
+
+        let (distinct, rows) = repeated.dedup();
+        assert_eq!(distinct.num_queries, 4);
+        assert_eq!(rows, [0, 1, 0, 2, 1, 3, 3]);
+        for (index, &row) in rows.iter().enumerate() {
+            assert_eq!(
+                distinct.query_vector(row as usize),
+                repeated.query_vector(index)
+            );
+        }
+    }
+
+    #[test]
+    fn reservoir_keeps_a_sample_of_the_rows() {
+        let mut rng = StdRng::seed_from_u64(41);
+        let nodes = generate::nodes(&mut rng, 200, 3, 4);
+        let mut bytes = Vec::new();
+        nodes.write_to(&mut bytes).unwrap();
+        let rows = &bytes[mem::size_of::<u32>()..];
+
+        let (sample, ids) =
+            NodesDataset::read_reservoir_rows(rows, 200, 3, 20, &mut rng, &NoProgress).unwrap();
+        assert_eq!(sample.num_vectors, 20);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(ids[19] >= 100);
+        for (index, &id) in ids.iter().enumerate() {
+            assert_eq!(sample.vector(index), nodes.vector(id as usize));
+            assert_eq!(sample.t_attrs[index], nodes.t_attrs[id as usize]);
+        }
+        assert!(sample.category_len(nodes.c_attrs[ids[0] as usize]) > 0);
+
+        let (all, ids) =
+            NodesDataset::read_reservoir_rows(rows, 200, 3, 500, &mut rng, &NoProgress).unwrap();
+        assert_eq!(all.vectors, nodes.vectors);
+        assert_eq!(ids, (0..200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn categories_must_be_integers() {
+        assert_eq!(categorical(7.0).unwrap(), 7);
+        assert_eq!(categorical(-1.0).unwrap(), -1);
+        assert!(categorical(1.5).is_err());
+        assert!(categorical(f32::NAN).is_err());
+        assert!(categorical(2_147_483_648.0).is_err());
+        assert_eq!(categorical(-2_147_483_648.0).unwrap(), i32::MIN);
+    }
+
+    #[test]
+    fn cosine_normalization_scales_vectors_to_unit_length() {
+        let mut nodes =
+            NodesDataset::from_vectors(2, vec![3.0, 4.0, 0.0, 0.0, 10.0, 0.0, -1.0, 1.0]).unwrap();
+        assert_eq!(nodes.normalize_vectors(), [1]);
+        assert!(nodes.is_deleted(1));
+        assert_eq!(nodes.vector(0), [0.6, 0.8]);
+        assert_eq!(nodes.vector(2), [1.0, 0.0]);
+        // The centroid is the mean of the scaled vectors, deleted included.
+        let centroid = &nodes.category_stats(0).unwrap().centroid;
+        let expected = [
+            (1.6 - std::f32::consts::FRAC_1_SQRT_2) / 4.0,
+            (0.8 + std::f32::consts::FRAC_1_SQRT_2) / 4.0,
+        ];
+        for (value, expected) in centroid.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+
+        let mut queries = QueriesDataset::from_vectors(2, vec![1.0, 0.1, 0.0, 0.0]);
+        assert!(matches!(
+            queries.normalize_vectors(),
+            Err(GlasshouseError::Malformed(message)) if message.starts_with("Query 1 ")
+        ));
+
+        // The closest node by angle is the closest once normalized, the
+        // longer node pointing the same way as the query included.
+        let mut queries = QueriesDataset::from_vectors(2, vec![1.0, 0.1]);
+        queries.normalize_vectors().unwrap();
+        let results = crate::solvers::run(&crate::solvers::Exact, &nodes, &queries, 3).unwrap();
+        assert_eq!(results[0][..], [2, 0, 3]);
+    }
+
+    #[test]
+    fn non_finite_values_are_reported() {
+        let mut nodes =
+            NodesDataset::from_parts(2, vec![1, 2], vec![0.5, 0.25], vec![1.0, 2.0, 3.0, 4.0])
+                .unwrap();
+        assert!(nodes.check_finite().is_ok());
+        nodes.vectors[3] = f32::NAN;
+        let error = nodes.check_finite().unwrap_err().to_string();
+        assert!(error.contains("Node 1"), "{}", error);
+
+        let mut queries = QueriesDataset::from_vectors(2, vec![0.0, 0.0]);
+        assert!(queries.check_finite().is_ok());
+        queries.t_upper_bounds[0] = OptionalFilterValue::new(f32::INFINITY);
+        assert!(queries.check_finite().is_err());
+    }
+
+    #[test]
+    fn in_memory_datasets_match_serialized_ones() {
+        let nodes =
+            NodesDataset::from_parts(2, vec![1, 2], vec![0.5, 0.25], vec![1.0, 2.0, 3.0, 4.0])
+                .unwrap();
+        let mut queries = QueriesDataset::default();
+        queries
+            .push(&ParsedQuery {
+                query_type: QueryType::BothConstraints,
+                v_categorical: Some(2),
+                t_lower_bound: Some(0.0),
+                t_upper_bound: Some(0.3),
+                query_vector: &[3.0, 4.0],
+            })
+            .unwrap();
+
+        let mut nodes_bytes = Vec::new();
+        let mut queries_bytes = Vec::new();
+        nodes.write_to(&mut nodes_bytes).unwrap();
+        queries.write_to(&mut queries_bytes).unwrap();
+        let nodes_copy = NodesDataset::from_bytes(&nodes_bytes).unwrap();
+        let queries_copy = QueriesDataset::from_bytes(&queries_bytes).unwrap();
+
+        assert_eq!(nodes_copy.dimensions, 2);
+        assert_eq!(nodes_copy.vectors, nodes.vectors);
+        assert_eq!(queries_copy.get(0).unwrap().v_categorical, Some(2));
+        assert_eq!(queries_copy.get(0).unwrap().query_vector, &[3.0, 4.0]);
+        assert!(queries_copy.get(0).unwrap().matches(&nodes.get(1).unwrap()));
+        assert!(NodesDataset::from_parts(2, vec![1], vec![], vec![]).is_err());
+    }
+
+    #[test]
+    fn degenerate_queries_are_normalized() {
+        let mut queries = QueriesDataset::default();
+        let cases = [
+            (QueryType::CategoricalConstraint, None, None, None),
+            (QueryType::TimestampConstraint, None, Some(0.5), None),
+            (QueryType::BothConstraints, Some(2), None, None),
+            (QueryType::BothConstraints, Some(2), Some(0.1), Some(0.2)),
+        ];
+        for (query_type, v_categorical, t_lower_bound, t_upper_bound) in cases {
+            queries
+                .push(&ParsedQuery {
+                    query_type,
+                    v_categorical,
+                    t_lower_bound,
+                    t_upper_bound,
+                    query_vector: &[0.0],
+                })
+                .unwrap();
+        }
+
+        assert_eq!(queries.normalize(), 3);
+        assert_eq!(
+            queries.query_types,
+            vec![
+                QueryType::VectorOnly,
+                QueryType::TimestampConstraint,
+                QueryType::CategoricalConstraint,
+                QueryType::BothConstraints,
+            ]
+        );
+        let open = queries.get(1).unwrap();
+        assert_eq!(open.t_upper_bound, Some(f32::INFINITY));
+        assert!(open.matches(&ParsedNode {
+            c_attr: 0,
+            t_attr: 7.0,
+            vector: &[0.0],
+        }));
+        assert_eq!(queries.normalize(), 0);
+    }
+
+    #[test]
+    fn timestamp_ranges_follow_updates() {
+        let mut nodes = NodesDataset::from_parts(
+            1,
+            vec![0; 5],
+            vec![0.5, 0.1, f32::NAN, 0.5, 0.9],
+            vec![0.0; 5],
+        )
+        .unwrap();
+        let range = |nodes: &NodesDataset, lower, upper| {
+            nodes.timestamp_range(lower, upper).collect::<Vec<_>>()
+        };
+        assert_eq!(range(&nodes, 0.1, 0.5), vec![1, 0, 3]);
+        assert_eq!(range(&nodes, 0.6, 0.2), Vec::<u32>::new());
+
+        nodes
+            .push(ParsedNodeOwned {
+                c_attr: 0,
+                t_attr: 0.5,
+                vector: vec![0.0],
+            })
+            .unwrap();
+        assert_eq!(range(&nodes, 0.5, 1.0), vec![0, 3, 5, 4]);
+
+        assert_eq!(nodes.category_len(0), 6);
+
+        nodes.delete(0);
+        nodes.compact();
+        assert_eq!(range(&nodes, 0.5, 0.5), vec![2, 4]);
+        assert_eq!(nodes.category_nodes(0).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert_eq!(nodes.category_len(1), 0);
+    }
+
+    #[test]
+    fn pushed_nodes_are_indexed_lazily() {
+        let mut nodes =
+            NodesDataset::from_parts(1, vec![1, 2], vec![0.1, 0.2], vec![0.0; 2]).unwrap();
+        let node = |c_attr, t_attr| ParsedNodeOwned {
+            c_attr,
+            t_attr,
+            vector: vec![0.0],
+        };
+        nodes.push(node(2, 0.2)).unwrap();
+        nodes.push(node(3, f32::NAN)).unwrap();
+        assert_eq!(nodes.t_index.get().unwrap().len(), 3);
+        assert_eq!(nodes.c_index.get().unwrap().len(), 4);
+
+        nodes.push(node(0, 0.0)).unwrap();
+        nodes.push(node(1, 0.15)).unwrap();
+        assert!(nodes.t_index.get().is_none());
+        assert!(nodes.c_index.get().is_none());
+        assert_eq!(
+            nodes.timestamp_range(0.0, 1.0).collect::<Vec<_>>(),
+            [4, 0, 5, 1, 2]
+        );
+        assert_eq!(nodes.category_nodes(1).collect::<Vec<_>>(), [0, 5]);
+        assert_eq!(nodes.category_len(3), 1);
+    }
+
+    #[test]
+    fn category_stats_follow_pushes() {
+        let mut nodes = NodesDataset::from_parts(
+            2,
+            vec![1, 0, 1],
+            vec![0.4, 0.2, f32::NAN],
+            vec![1.0, 2.0, 0.0, 0.0, 3.0, 4.0],
+        )
+        .unwrap();
+        let stats = |nodes: &NodesDataset, category| nodes.category_stats(category).cloned();
+        assert_eq!(
+            stats(&nodes, 1),
+            Some(CategoryStats {
+                category: 1,
+                count: 2,
+                timestamps: Some((0.4, 0.4)),
+                centroid: vec![2.0, 3.0],
+            })
+        );
+        assert_eq!(stats(&nodes, 2), None);
+
+        let node = |c_attr, t_attr, vector: [f32; 2]| ParsedNodeOwned {
+            c_attr,
+            t_attr,
+            vector: vector.to_vec(),
+        };
+        nodes.push(node(1, 0.1, [5.0, 5.0])).unwrap();
+        nodes.push(node(2, f32::NAN, [1.0, 1.0])).unwrap();
+        assert_eq!(stats(&nodes, 1).unwrap().count, 3);
+        assert_eq!(stats(&nodes, 1).unwrap().timestamps, Some((0.1, 0.4)));
+        assert_eq!(stats(&nodes, 1).unwrap().centroid, [3.0, 11.0 / 3.0]);
+        assert_eq!(stats(&nodes, 2).unwrap().timestamps, None);
+        let pushed: Vec<CategoryStats> = nodes.category_stats.clone();
+        nodes.index_attributes();
+        assert_eq!(nodes.category_stats.len(), pushed.len());
+        for (indexed, pushed) in nodes.category_stats.iter().zip(&pushed) {
+            assert_eq!(indexed.count, pushed.count);
+            assert_eq!(indexed.timestamps, pushed.timestamps);
+        }
+    }
+
+    #[test]
+    fn appended_datasets_keep_global_ids() {
+        let mut nodes =
+            NodesDataset::from_parts(1, vec![1, 2], vec![0.1, 0.2], vec![1.0, 2.0]).unwrap();
+        nodes.delete(1);
+        let mut shard = NodesDataset::from_parts(1, vec![3], vec![0.3], vec![3.0]).unwrap();
+        shard.delete(0);
+
+        assert_eq!(nodes.append(shard).unwrap(), 2);
+        assert_eq!(nodes.num_vectors, 3);
+        assert_eq!(nodes.vector(2), &[3.0]);
+        assert!(nodes.is_deleted(1) && nodes.is_deleted(2));
+
+        let wide = NodesDataset::from_parts(2, vec![0], vec![0.0], vec![0.0, 0.0]).unwrap();
+        assert!(matches!(
+            nodes.append(wide),
+            Err(GlasshouseError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn datasets_built_in_memory_reject_inconsistent_shapes() {
+        assert!(matches!(
+            NodesDataset::from_parts(2, vec![0, 1], vec![0.0], vec![0.0; 4]),
+            Err(GlasshouseError::Malformed(_))
+        ));
+        assert!(matches!(
+            NodesDataset::from_parts(2, vec![0], vec![0.0], vec![0.0; 3]),
+            Err(GlasshouseError::Malformed(_))
+        ));
+
+        let mut nodes = NodesDataset::from_parts(2, vec![0], vec![0.0], vec![0.0; 2]).unwrap();
+        let node = ParsedNodeOwned {
+            c_attr: 0,
+            t_attr: 0.0,
+            vector: vec![0.0; 3],
+        };
+        assert!(matches!(
+            nodes.push(node),
+            Err(GlasshouseError::Malformed(_))
+        ));
+        assert_eq!(nodes.num_vectors, 1);
+
+        let mut queries = QueriesDataset::default();
+        let query = |query_vector| ParsedQuery {
+            query_type: QueryType::VectorOnly,
+            v_categorical: None,
+            t_lower_bound: None,
+            t_upper_bound: None,
+            query_vector,
+        };
+        assert_eq!(queries.push(&query(&[1.0, 2.0])).unwrap(), 0);
+        assert!(matches!(
+            queries.push(&query(&[1.0])),
+            Err(GlasshouseError::Malformed(_))
+        ));
+        assert_eq!(queries.num_queries, 1);
+    }
+
+    #[test]
+    fn readers_stop_at_the_last_row() {
+        let nodes = NodesDataset::from_parts(2, vec![1, 2], vec![0.5, 0.25], vec![1.0; 4]).unwrap();
+        let mut bytes = Vec::new();
+        nodes.write_to(&mut bytes).unwrap();
+        let copy = NodesDataset::from_reader(&bytes[..], 2).unwrap();
+        assert_eq!(copy.c_attrs, nodes.c_attrs);
+        assert_eq!(copy.vectors, nodes.vectors);
+
+        // A corrupt row count fails on the first missing row.
+        bytes[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            NodesDataset::from_reader(&bytes[..], 2),
+            Err(GlasshouseError::Io(_))
+        ));
+        assert!(matches!(
+            QueriesDataset::from_reader(&bytes[..], 0),
+            Err(GlasshouseError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_datasets_are_reported() {
+        let mut bytes = 1u32.to_le_bytes().to_vec();
+        for value in [7.0f32, -1.0, -1.0, -1.0, 1.0, 2.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        assert!(matches!(
+            QueriesDataset::from_bytes(&bytes),
+            Err(GlasshouseError::InvalidQueryType(7.0))
+        ));
+        assert!(matches!(
+            QueriesDataset::from_bytes(&bytes[..bytes.len() - 2]),
+            Err(GlasshouseError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn results_writer_matches_batch_layout() {
+        let mut writer = ResultsWriter::new(Vec::new());
+        writer.push(&[7; K_NEAREST]).unwrap();
+        writer.push(&[9; K_NEAREST]).unwrap();
+        assert_eq!(writer.written(), 2);
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes.len(), 2 * K_NEAREST * 4);
+        assert_eq!(bytes[..4], 7u32.to_le_bytes());
+        assert_eq!(bytes[K_NEAREST * 4..K_NEAREST * 4 + 4], 9u32.to_le_bytes());
+    }
+
+    #[test]
+    fn rows_are_decoded_as_little_endian() {
+        let values = [1.5f32, -0.25, f32::MAX];
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut buffer = [0.0f32; 3];
+        read_f32s(&mut bytes.as_slice(), &mut buffer).unwrap();
+        assert_eq!(buffer, values);
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_read_dummy_datasets() {
+        let nodes_file = "tests/dummy-data.bin";
+        let queries_file = "tests/dummy-queries.bin";
+        // Read the file and check that it contains the expected data.
+        let nodes = NodesDataset::read(nodes_file);
+        let queries = QueriesDataset::read(queries_file);
+
+        assert!(nodes.is_ok());
+        assert!(queries.is_ok());
+
+        assert!(nodes.unwrap().num_vectors == 10000);
+        assert!(queries.unwrap().num_queries == 10000);
+    }
+
+    #[test]
+    fn partitions_read_their_rows_only() {
+        let nodes = NodesDataset::read("tests/dummy-data.bin").unwrap();
+        let (part, first_id) =
+            NodesDataset::read_partition("tests/dummy-data.bin", None, 2, 3, &NoProgress).unwrap();
+
+        assert_eq!((first_id, part.num_vectors), (6667, 3333));
+        assert_eq!(part.dimensions, nodes.dimensions);
+        assert_eq!(part.c_attrs[..], nodes.c_attrs[6667..]);
+        assert_eq!(part.vector(0), nodes.vector(6667));
+    }
+
+    #[test]
+    fn can_query_individual_nodes_and_queries() {
+        let nodes_file = "tests/dummy-data.bin";
+        let queries_file = "tests/dummy-queries.bin";
+
+        let nodes = NodesDataset::read(nodes_file).unwrap();
+        let queries = QueriesDataset::read(queries_file).unwrap();
+
+        assert!(nodes.get(0).is_some());
+        assert!(queries.get(0).is_some());
+    }
+
+    #[test]
+    fn infers_contest_dimensions_from_file_size() {
+        let nodes = NodesDataset::read("tests/dummy-data.bin").unwrap();
+        let queries = QueriesDataset::read("tests/dummy-queries.bin").unwrap();
+
+        assert_eq!(nodes.dimensions, VECTOR_DIMENSIONS);
+        assert_eq!(queries.dimensions, VECTOR_DIMENSIONS);
+        assert_eq!(nodes.get(0).unwrap().vector.len(), VECTOR_DIMENSIONS);
+    }
+
+    #[test]
+    fn can_read_datasets_with_other_dimensions() {
+        let path = crate::testing::temp_path("nodes-dim3.bin");
+        let rows: [[f32; 5]; 2] = [[1.0, 0.5, 1.0, 2.0, 3.0], [2.0, 0.25, 4.0, 5.0, 6.0]];
+        let mut bytes = 2u32.to_le_bytes().to_vec();
+        for value in rows.iter().flatten() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        std::fs::write(&path, bytes).unwrap();
+
+        let inferred = NodesDataset::read(&path).unwrap();
+        let explicit = NodesDataset::read_with_dimensions(&path, 3).unwrap();
+        let too_wide = NodesDataset::read_with_dimensions(&path, 4);
+        let empty = NodesDataset::read_with_dimensions(&path, 0);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(too_wide.is_err());
+        assert!(empty.is_err());
+
+        assert_eq!(inferred.dimensions, 3);
+        assert_eq!(explicit.dimensions, 3);
+        assert_eq!(inferred.get(1).unwrap().vector, &[4.0, 5.0, 6.0]);
+        assert_eq!(inferred.get(1).unwrap().t_attr, 0.25);
+    }
+
+    #[test]
+    fn written_datasets_read_back_identically() {
+        let nodes = NodesDataset::read("tests/dummy-data.bin").unwrap();
+        let queries = QueriesDataset::read("tests/dummy-queries.bin").unwrap();
+        let nodes_path = crate::testing::temp_path("roundtrip-nodes.bin");
+        let queries_path = crate::testing::temp_path("roundtrip-queries.bin");
+
+        nodes.write(&nodes_path).unwrap();
+        queries.write(&queries_path).unwrap();
+        let nodes_copy = NodesDataset::read(&nodes_path).unwrap();
+        let queries_copy = QueriesDataset::read(&queries_path).unwrap();
+        std::fs::remove_file(&nodes_path).unwrap();
+        std::fs::remove_file(&queries_path).unwrap();
+
+        assert_eq!(nodes_copy.c_attrs, nodes.c_attrs);
+        assert_eq!(nodes_copy.vectors, nodes.vectors);
+        assert_eq!(queries_copy.query_types, queries.query_types);
+        assert_eq!(queries_copy.t_upper_bounds, queries.t_upper_bounds);
+        assert_eq!(queries_copy.query_vectors, queries.query_vectors);
+    }
+
+    #[test]
+    fn written_results_read_back_identically() {
+        let path = crate::testing::temp_path("roundtrip-results.bin");
+        let mut result = vec![0; K_NEAREST];
+        result[0] = 42;
+        result[K_NEAREST - 1] = 7;
+        let expected: QueryResults = vec![result.into(), vec![1; K_NEAREST].into()];
+
+        write(&expected, &path).unwrap();
+        let results = read_results(&path, K_NEAREST).unwrap();
+        let too_wide = read_results(&path, 3 * K_NEAREST);
+        let empty_rows = read_results(&path, 0);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(results, expected);
+        assert!(matches!(too_wide, Err(GlasshouseError::Malformed(_))));
+        assert!(matches!(empty_rows, Err(GlasshouseError::Config(_))));
+    }
+
+    #[test]
+    fn written_distances_read_back_padded() {
+        let path = crate::testing::temp_path("roundtrip-distances.bin");
+        let result = vec![
+            ScoredNeighbor {
+                id: 3,
+                distance: 0.5,
+            },
+            ScoredNeighbor {
+                id: 1,
+                distance: 2.0,
+            },
+        ];
+
+        write_distances(&vec![result], &path, K_NEAREST).unwrap();
+        let distances = read_distances(&path, K_NEAREST).unwrap();
+        let empty_rows = read_distances(&path, 0);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(empty_rows, Err(GlasshouseError::Config(_))));
+
+        assert_eq!(distances.len(), 1);
+        assert_eq!(distances[0][..2], [0.5, 2.0]);
+        assert!(distances[0][2..].iter().all(|d| d.is_infinite()));
+    }
+
+    #[test]
+    fn written_scored_results_read_back_unpadded() {
+        let path = crate::testing::temp_path("roundtrip-results.scored");
+        let results = vec![
+            vec![
+                ScoredNeighbor {
+                    id: 3,
+                    distance: 0.5,
+                },
+                // Only the pad ID marks missing neighbours, whatever the
+                // distance of the found ones.
+                ScoredNeighbor {
+                    id: 0,
+                    distance: f32::INFINITY,
+                },
+            ],
+            Vec::new(),
+        ];
+
+        write_scored(&results, &path, K_NEAREST, 7).unwrap();
+        let bytes = std::fs::metadata(&path).unwrap().len();
+        let scored = read_scored(&path, K_NEAREST, 7).unwrap();
+        let padded = read_scored(&path, K_NEAREST, 0).unwrap();
+        let empty_rows = read_scored(&path, 0, 7);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(bytes, (2 * K_NEAREST * 8) as u64);
+        assert_eq!(scored, results);
+        assert_eq!(padded[0].len(), K_NEAREST - 1);
+        assert!(padded[0].iter().skip(1).all(|neighbor| neighbor.id == 7));
+        assert!(matches!(empty_rows, Err(GlasshouseError::Config(_))));
+    }
+}
@@ -0,0 +1,537 @@
+//! NumPy `.npy` arrays and `.npz` archives.
+//!
+//! A two-dimensional `.npy` array holds vectors without attributes, read as
+//! nodes with a categorical and timestamp attribute of `0` or as
+//! vector-only queries. An `.npz` archive holds the `vectors` array and,
+//! optionally, one-dimensional attribute arrays:
+//!
+//! - nodes: `c` and `t`, the categorical and timestamp attributes.
+//! - queries: `query_type`, `category`, `t_lower` and `t_upper`, `-1`
+//!   marking unset filters as in the contest format.
+//!
+//! Arrays of little-endian floats and integers of any width are accepted
+//! and converted to `f32`. Results are saved as an `.npz` archive holding
+//! the `ids` (`uint32`) and `distances` (`float32`) arrays of shape
+//! `(|Q|, k)`, missing neighbours have the ID `DEFAULT_PAD_ID` and
+//! an infinite distance.
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::error::{self, GlasshouseError};
+use crate::solvers::DEFAULT_PAD_ID;
+use crate::types::{NodesDataset, OptionalFilterValue, QueriesDataset, QueryType, ScoredResults};
+
+/// Magic string opening every `.npy` stream.
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// Array read from a `.npy` stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NpyArray {
+    /// Length of each axis.
+    pub shape: Vec<usize>,
+    /// Values in row-major order, converted to `f32`.
+    pub values: Vec<f32>,
+}
+
+impl NpyArray {
+    /// Returns the number of rows and columns of a two-dimensional array.
+    fn matrix(&self, name: &str) -> error::Result<(usize, usize)> {
+        match self.shape[..] {
+            [rows, columns] => Ok((rows, columns)),
+            _ => Err(GlasshouseError::Malformed(format!(
+                "Array {} has shape {:?}, expected two dimensions",
+                name, self.shape
+            ))),
+        }
+    }
+}
+
+/// Element types written to `.npy` streams.
+pub trait Element: Copy {
+    /// NumPy type description, such as `<f4`.
+    const DESCR: &'static str;
+
+    fn to_le_bytes(self) -> [u8; 4];
+}
+
+impl Element for f32 {
+    const DESCR: &'static str = "<f4";
+
+    fn to_le_bytes(self) -> [u8; 4] {
+        f32::to_le_bytes(self)
+    }
+}
+
+impl Element for u32 {
+    const DESCR: &'static str = "<u4";
+
+    fn to_le_bytes(self) -> [u8; 4] {
+        u32::to_le_bytes(self)
+    }
+}
+
+impl Element for i32 {
+    const DESCR: &'static str = "<i4";
+
+    fn to_le_bytes(self) -> [u8; 4] {
+        i32::to_le_bytes(self)
+    }
+}
+
+impl NodesDataset {
+    /// Reads nodes without attributes from a two-dimensional `.npy` file.
+    pub fn read_npy<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        let vectors = read_npy(BufReader::new(File::open(file_path)?))?;
+        let (_, dimensions) = vectors.matrix("vectors")?;
+        NodesDataset::from_vectors(dimensions, vectors.values)
+    }
+
+    /// Reads nodes from the `vectors`, `c` and `t` arrays of an `.npz`
+    /// file, missing attributes are `0`.
+    pub fn read_npz<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        let mut archive = open(file_path)?;
+        let vectors = entry(&mut archive, "vectors")?.ok_or_else(|| missing("vectors"))?;
+        let (num_vectors, dimensions) = vectors.matrix("vectors")?;
+        let mut nodes_dataset = NodesDataset::from_vectors(dimensions, vectors.values)?;
+        if let Some(c_attrs) = column(&mut archive, "c", num_vectors)? {
+            nodes_dataset.c_attrs = c_attrs
+                .into_iter()
+                .map(crate::io::categorical)
+                .collect::<error::Result<_>>()?;
+        }
+        if let Some(t_attrs) = column(&mut archive, "t", num_vectors)? {
+            nodes_dataset.t_attrs = t_attrs;
+            nodes_dataset.index_attributes();
+        }
+        Ok(nodes_dataset)
+    }
+
+    /// Saves the node vectors, without attributes, to a `.npy` file.
+    pub fn write_npy<P: AsRef<Path>>(&self, file_path: P) -> error::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        write_npy(&mut writer, &self.shape(), &self.vectors)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Saves the nodes to an `.npz` file holding the `vectors`, `c` and `t`
+    /// arrays.
+    pub fn write_npz<P: AsRef<Path>>(&self, file_path: P) -> error::Result<()> {
+        let rows = [self.num_vectors as usize];
+        let mut archive = create(file_path)?;
+        add(&mut archive, "vectors", &self.shape(), &self.vectors)?;
+        add(&mut archive, "c", &rows, &self.c_attrs)?;
+        add(&mut archive, "t", &rows, &self.t_attrs)?;
+        archive.finish().map_err(zip_error)?.flush()?;
+        Ok(())
+    }
+
+    fn shape(&self) -> [usize; 2] {
+        [self.num_vectors as usize, self.dimensions]
+    }
+}
+
+impl QueriesDataset {
+    /// Reads vector-only queries from a two-dimensional `.npy` file.
+    pub fn read_npy<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        let query_vectors = read_npy(BufReader::new(File::open(file_path)?))?;
+        let (_, dimensions) = query_vectors.matrix("vectors")?;
+        Ok(QueriesDataset::from_vectors(
+            dimensions,
+            query_vectors.values,
+        ))
+    }
+
+    /// Reads queries from the `vectors`, `query_type`, `category`, `t_lower`
+    /// and `t_upper` arrays of an `.npz` file, missing arrays leave the
+    /// queries vector-only.
+    pub fn read_npz<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        let mut archive = open(file_path)?;
+        let vectors = entry(&mut archive, "vectors")?.ok_or_else(|| missing("vectors"))?;
+        let (num_queries, dimensions) = vectors.matrix("vectors")?;
+        let mut queries_dataset = QueriesDataset::from_vectors(dimensions, vectors.values);
+        if let Some(query_types) = column(&mut archive, "query_type", num_queries)? {
+            queries_dataset.query_types = query_types
+                .into_iter()
+                .map(QueryType::from_f32)
+                .collect::<error::Result<_>>()?;
+        }
+        let filters = [
+            ("category", &mut queries_dataset.v_categoricals),
+            ("t_lower", &mut queries_dataset.t_lower_bounds),
+            ("t_upper", &mut queries_dataset.t_upper_bounds),
+        ];
+        for (name, filter) in filters {
+            if let Some(values) = column(&mut archive, name, num_queries)? {
+                *filter = values.into_iter().map(OptionalFilterValue::new).collect();
+            }
+        }
+        Ok(queries_dataset)
+    }
+
+    /// Saves the query vectors, without filters, to a `.npy` file.
+    pub fn write_npy<P: AsRef<Path>>(&self, file_path: P) -> error::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        write_npy(&mut writer, &self.shape(), &self.query_vectors)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Saves the queries to an `.npz` file holding the `vectors`,
+    /// `query_type`, `category`, `t_lower` and `t_upper` arrays.
+    pub fn write_npz<P: AsRef<Path>>(&self, file_path: P) -> error::Result<()> {
+        let rows = [self.num_queries as usize];
+        let query_types: Vec<i32> = self
+            .query_types
+            .iter()
+            .map(|query_type| query_type.to_f32() as i32)
+            .collect();
+        let mut archive = create(file_path)?;
+        add(&mut archive, "vectors", &self.shape(), &self.query_vectors)?;
+        add(&mut archive, "query_type", &rows, &query_types)?;
+        let filters = [
+            ("category", &self.v_categoricals),
+            ("t_lower", &self.t_lower_bounds),
+            ("t_upper", &self.t_upper_bounds),
+        ];
+        for (name, filter) in filters {
+            let values: Vec<f32> = filter.iter().map(OptionalFilterValue::raw).collect();
+            add(&mut archive, name, &rows, &values)?;
+        }
+        archive.finish().map_err(zip_error)?.flush()?;
+        Ok(())
+    }
+
+    fn shape(&self) -> [usize; 2] {
+        [self.num_queries as usize, self.dimensions]
+    }
+}
+
+/// Saves the IDs and distances of `k` neighbours per query to an `.npz`
+/// file.
+pub fn write_results_npz<P: AsRef<Path>>(
+    results: &ScoredResults,
+    file_path: P,
+    k: usize,
+) -> error::Result<()> {
+    let mut ids = Vec::with_capacity(results.len() * k);
+    let mut distances = Vec::with_capacity(results.len() * k);
+    for result in results {
+        for rank in 0..k {
+            let neighbor = result.get(rank);
+            ids.push(neighbor.map_or(DEFAULT_PAD_ID, |n| n.id));
+            distances.push(neighbor.map_or(f32::INFINITY, |n| n.distance));
+        }
+    }
+
+    let shape = [results.len(), k];
+    let mut archive = create(file_path)?;
+    add(&mut archive, "ids", &shape, &ids)?;
+    add(&mut archive, "distances", &shape, &distances)?;
+    archive.finish().map_err(zip_error)?.flush()?;
+    Ok(())
+}
+
+/// Reads a `.npy` stream, converting its values to `f32`.
+pub fn read_npy<R: Read>(mut reader: R) -> error::Result<NpyArray> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(GlasshouseError::Malformed(
+            "Missing the .npy magic string".to_string(),
+        ));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => {
+            return Err(GlasshouseError::Malformed(format!(
+                "Unsupported .npy version {}",
+                version
+            )));
+        }
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let descr = header_value(&header, "descr")?;
+    let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+    if header_value(&header, "fortran_order")? != "False" {
+        return Err(GlasshouseError::Malformed(
+            "Fortran ordered .npy arrays are not supported".to_string(),
+        ));
+    }
+    let shape = header_value(&header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|axis| !axis.is_empty())
+        .map(|axis| {
+            axis.parse::<usize>().map_err(|_| {
+                GlasshouseError::Malformed(format!("Invalid .npy shape axis {}", axis))
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+
+    let len: usize = shape.iter().product();
+    let values = match descr {
+        "<f4" => read_values(reader, len, f32::from_le_bytes)?,
+        "<f8" => read_values(reader, len, |b| f64::from_le_bytes(b) as f32)?,
+        "|i1" => read_values(reader, len, |b| i8::from_le_bytes(b) as f32)?,
+        "|u1" => read_values(reader, len, |b| u8::from_le_bytes(b) as f32)?,
+        "<i2" => read_values(reader, len, |b| i16::from_le_bytes(b) as f32)?,
+        "<u2" => read_values(reader, len, |b| u16::from_le_bytes(b) as f32)?,
+        "<i4" => read_values(reader, len, |b| i32::from_le_bytes(b) as f32)?,
+        "<u4" => read_values(reader, len, |b| u32::from_le_bytes(b) as f32)?,
+        "<i8" => read_values(reader, len, |b| i64::from_le_bytes(b) as f32)?,
+        "<u8" => read_values(reader, len, |b| u64::from_le_bytes(b) as f32)?,
+        _ => {
+            return Err(GlasshouseError::Malformed(format!(
+                "Unsupported .npy type {}",
+                descr
+            )));
+        }
+    };
+    Ok(NpyArray { shape, values })
+}
+
+/// Writes values in row-major order as a `.npy` stream of the given shape.
+pub fn write_npy<W: Write, T: Element>(
+    writer: &mut W,
+    shape: &[usize],
+    values: &[T],
+) -> io::Result<()> {
+    let axes: Vec<String> = shape.iter().map(usize::to_string).collect();
+    let shape = match axes.len() {
+        1 => format!("({},)", axes[0]),
+        _ => format!("({})", axes.join(", ")),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        T::DESCR,
+        shape
+    );
+    // The header ends with a newline and the data starts 64-byte aligned.
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.extend(std::iter::repeat_n(
+        ' ',
+        unpadded.next_multiple_of(64) - unpadded,
+    ));
+    header.push('\n');
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for &value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Returns the text following `'key':` in a header dictionary, up to the
+/// next key.
+fn header_value<'a>(header: &'a str, key: &str) -> error::Result<&'a str> {
+    let pattern = format!("'{}':", key);
+    let start = header
+        .find(&pattern)
+        .ok_or_else(|| GlasshouseError::Malformed(format!("Missing .npy header key {}", key)))?
+        + pattern.len();
+    let rest = header[start..].trim_start();
+    // Tuples hold commas, the value ends after the closing parenthesis.
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|end| end + 1)
+    } else {
+        rest.find([',', '}'])
+    };
+    Ok(rest[..end.unwrap_or(rest.len())].trim())
+}
+
+fn read_values<R: Read, const N: usize>(
+    mut reader: R,
+    len: usize,
+    decode: impl Fn([u8; N]) -> f32,
+) -> io::Result<Vec<f32>> {
+    let mut bytes = vec![0u8; len * N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(N)
+        .map(|chunk| decode(chunk.try_into().expect("chunks have N bytes")))
+        .collect())
+}
+
+fn open<P: AsRef<Path>>(file_path: P) -> error::Result<ZipArchive<BufReader<File>>> {
+    ZipArchive::new(BufReader::new(File::open(file_path)?)).map_err(zip_error)
+}
+
+fn create<P: AsRef<Path>>(file_path: P) -> io::Result<ZipWriter<BufWriter<File>>> {
+    Ok(ZipWriter::new(BufWriter::new(File::create(file_path)?)))
+}
+
+/// Adds the array `name` to an archive.
+fn add<T: Element>(
+    archive: &mut ZipWriter<BufWriter<File>>,
+    name: &str,
+    shape: &[usize],
+    values: &[T],
+) -> error::Result<()> {
+    archive
+        .start_file(format!("{}.npy", name), SimpleFileOptions::default())
+        .map_err(zip_error)?;
+    write_npy(archive, shape, values)?;
+    Ok(())
+}
+
+/// Reads the array `name` of an archive, `None` if it holds no such array.
+fn entry(archive: &mut ZipArchive<BufReader<File>>, name: &str) -> error::Result<Option<NpyArray>> {
+    match archive.by_name(&format!("{}.npy", name)) {
+        Ok(file) => read_npy(file).map(Some),
+        Err(ZipError::FileNotFound) => Ok(None),
+        Err(e) => Err(zip_error(e)),
+    }
+}
+
+/// Reads the one-dimensional array `name` of an archive, which must hold
+/// one value per row.
+fn column(
+    archive: &mut ZipArchive<BufReader<File>>,
+    name: &str,
+    rows: usize,
+) -> error::Result<Option<Vec<f32>>> {
+    let Some(array) = entry(archive, name)? else {
+        return Ok(None);
+    };
+    if array.shape != [rows] {
+        return Err(GlasshouseError::Malformed(format!(
+            "Array {} has shape {:?}, expected ({},)",
+            name, array.shape, rows
+        )));
+    }
+    Ok(Some(array.values))
+}
+
+fn missing(name: &str) -> GlasshouseError {
+    GlasshouseError::Malformed(format!("Missing array {}", name))
+}
+
+fn zip_error(error: ZipError) -> GlasshouseError {
+    match error {
+        ZipError::Io(error) => GlasshouseError::Io(error),
+        error => GlasshouseError::Malformed(format!("npz: {}", error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ScoredNeighbor;
+
+    #[test]
+    fn arrays_round_trip() {
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[2, 3], &[1u32, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(&bytes[..6], MAGIC);
+        assert!((bytes.len() - 6 * 4).is_multiple_of(64));
+
+        let array = read_npy(bytes.as_slice()).unwrap();
+        assert_eq!(array.shape, vec![2, 3]);
+        assert_eq!(array.values, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn reads_arrays_written_by_numpy() {
+        // np.save(f, np.array([[1.5, -2.0]], dtype="<f8"))
+        let mut bytes = b"\x93NUMPY\x01\x00\x76\x00".to_vec();
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (1, 2), }";
+        bytes.extend(format!("{:<117}\n", header).as_bytes());
+        bytes.extend(1.5f64.to_le_bytes());
+        bytes.extend((-2.0f64).to_le_bytes());
+
+        let array = read_npy(bytes.as_slice()).unwrap();
+        assert_eq!(array.shape, vec![1, 2]);
+        assert_eq!(array.values, vec![1.5, -2.0]);
+        assert!(read_npy(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let read = |version: u8, descr: &str, fortran_order: &str, shape: &str| {
+            let header = format!(
+                "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+                descr, fortran_order, shape
+            );
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend([version, 0]);
+            bytes.extend((header.len() as u16).to_le_bytes());
+            bytes.extend(header.as_bytes());
+            bytes.extend(1.0f32.to_le_bytes());
+            read_npy(bytes.as_slice())
+        };
+        let malformed =
+            |read: error::Result<NpyArray>| matches!(read, Err(GlasshouseError::Malformed(_)));
+
+        assert_eq!(read(1, "<f4", "False", "(1,)").unwrap().values, [1.0]);
+        assert!(malformed(read(4, "<f4", "False", "(1,)")));
+        assert!(malformed(read(1, "<c8", "False", "(1,)")));
+        assert!(malformed(read(1, "<f4", "True", "(1,)")));
+        assert!(malformed(read(1, "<f4", "False", "(x,)")));
+        assert!(malformed(read_npy(&b"\x93NUMPZ\x01\x00"[..])));
+    }
+
+    #[test]
+    fn datasets_round_trip() {
+        let path = crate::testing::temp_path("roundtrip-queries.npz");
+        let mut queries = QueriesDataset::from_vectors(2, vec![1.0, 2.0, 3.0, 4.0]);
+        queries.query_types[1] = QueryType::CategoricalConstraint;
+        queries.v_categoricals[1] = OptionalFilterValue::new(5.0);
+        queries.write_npz(&path).unwrap();
+        let read = QueriesDataset::read_npz(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(read.query_vectors, queries.query_vectors);
+        assert_eq!(read.query_types, queries.query_types);
+        assert_eq!(read.get(1).unwrap().v_categorical, Some(5));
+        assert_eq!(read.get(1).unwrap().t_lower_bound, None);
+    }
+
+    #[test]
+    fn results_round_trip() {
+        let path = crate::testing::temp_path("roundtrip-results.npz");
+        let results = vec![vec![
+            ScoredNeighbor {
+                id: 7,
+                distance: 0.5,
+            },
+            ScoredNeighbor {
+                id: 3,
+                distance: 1.0,
+            },
+        ]];
+        write_results_npz(&results, &path, 4).unwrap();
+
+        let mut archive = open(&path).unwrap();
+        let ids = entry(&mut archive, "ids").unwrap().unwrap();
+        let distances = entry(&mut archive, "distances").unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(ids.shape, vec![1, 4]);
+        assert_eq!(
+            ids.values,
+            [7.0, 3.0, DEFAULT_PAD_ID as f32, DEFAULT_PAD_ID as f32]
+        );
+        assert_eq!(distances.values, [0.5, 1.0, f32::INFINITY, f32::INFINITY]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Parquet datasets and results.
+//!
+//! Datasets are stored with the columns of the record batches described in
+//! [`crate::arrow`]. Results are written with one row per neighbour and the
+//! columns `query_id`, `rank` and `node_id`.
+use std::fmt::Display;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, RecordBatch, UInt32Array};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, QueriesDataset, QueryResults};
+
+impl NodesDataset {
+    /// Reads a nodes dataset from a Parquet file.
+    pub fn read_parquet<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        NodesDataset::from_record_batch_reader(reader(file_path)?)
+    }
+
+    /// Saves the nodes dataset to a Parquet file.
+    pub fn write_parquet<P: AsRef<Path>>(&self, file_path: P) -> error::Result<()> {
+        write_batch(file_path, &self.to_record_batch()?)
+    }
+}
+
+impl QueriesDataset {
+    /// Reads a queries dataset from a Parquet file.
+    pub fn read_parquet<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        QueriesDataset::from_record_batch_reader(reader(file_path)?)
+    }
+
+    /// Saves the queries dataset to a Parquet file.
+    pub fn write_parquet<P: AsRef<Path>>(&self, file_path: P) -> error::Result<()> {
+        write_batch(file_path, &self.to_record_batch()?)
+    }
+}
+
+/// Saves KNN results to a Parquet file, one row per neighbour.
+pub fn write_results<P: AsRef<Path>>(results: &QueryResults, file_path: P) -> error::Result<()> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("query_id", DataType::UInt32, false),
+        Field::new("rank", DataType::UInt32, false),
+        Field::new("node_id", DataType::UInt32, false),
+    ]));
+    let rows = results.iter().enumerate().flat_map(|(query_id, result)| {
+        result
+            .iter()
+            .enumerate()
+            .map(move |(rank, &node_id)| (query_id as u32, (rank as u32, node_id)))
+    });
+    let (query_ids, (ranks, node_ids)): (Vec<u32>, (Vec<u32>, Vec<u32>)) = rows.unzip();
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from(query_ids)),
+        Arc::new(UInt32Array::from(ranks)),
+        Arc::new(UInt32Array::from(node_ids)),
+    ];
+    let batch = RecordBatch::try_new(schema, columns).map_err(malformed)?;
+    write_batch(file_path, &batch)
+}
+
+fn reader<P: AsRef<Path>>(file_path: P) -> error::Result<ParquetRecordBatchReader> {
+    ParquetRecordBatchReaderBuilder::try_new(File::open(file_path)?)
+        .and_then(|builder| builder.build())
+        .map_err(malformed)
+}
+
+fn write_batch<P: AsRef<Path>>(file_path: P, batch: &RecordBatch) -> error::Result<()> {
+    let mut writer =
+        ArrowWriter::try_new(File::create(file_path)?, batch.schema(), None).map_err(malformed)?;
+    writer.write(batch).map_err(malformed)?;
+    writer.close().map_err(malformed)?;
+    Ok(())
+}
+
+fn malformed(error: impl Display) -> GlasshouseError {
+    GlasshouseError::Malformed(format!("Parquet: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+
+    #[test]
+    fn datasets_round_trip() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let nodes = generate::nodes(&mut rng, 100, 8, 4);
+        let queries = generate::queries(&mut rng, 20, 8, 4);
+        let nodes_path = crate::testing::temp_path("roundtrip-nodes.parquet");
+        let queries_path = crate::testing::temp_path("roundtrip-queries.parquet");
+
+        nodes.write_parquet(&nodes_path).unwrap();
+        queries.write_parquet(&queries_path).unwrap();
+        let read_nodes = NodesDataset::read_parquet(&nodes_path).unwrap();
+        let read_queries = QueriesDataset::read_parquet(&queries_path).unwrap();
+        std::fs::remove_file(nodes_path).unwrap();
+        std::fs::remove_file(queries_path).unwrap();
+
+        assert_eq!(read_nodes.dimensions, 8);
+        assert_eq!(read_nodes.c_attrs, nodes.c_attrs);
+        assert_eq!(read_nodes.t_attrs, nodes.t_attrs);
+        assert_eq!(read_nodes.vectors, nodes.vectors);
+        assert_eq!(read_queries.num_queries, 20);
+        for index in 0..20 {
+            let (read, query) = (
+                read_queries.get(index).unwrap(),
+                queries.get(index).unwrap(),
+            );
+            assert_eq!(read.query_type, query.query_type);
+            assert_eq!(read.v_categorical, query.v_categorical);
+            assert_eq!(read.t_lower_bound, query.t_lower_bound);
+            assert_eq!(read.t_upper_bound, query.t_upper_bound);
+            assert_eq!(read.query_vector, query.query_vector);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Nodes datasets stored in an SQLite table.
+//!
+//! The table holds one row per node with the columns `id`, `c`, `t` and
+//! `embedding`. Node IDs are taken from `id`: rows are ordered by it and
+//! missing IDs become deleted nodes, so results refer to the rows of the
+//! table. Embeddings are either blobs of little-endian `f32` values or text
+//! lists such as `[0.1, 0.2]`, which is how DuckDB stores `FLOAT[]` columns
+//! in an attached SQLite database.
+use std::path::Path;
+
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::error::{self, GlasshouseError};
+use crate::types::NodesDataset;
+
+impl NodesDataset {
+    /// Reads a nodes dataset from a table of an SQLite database.
+    pub fn read_sqlite<P: AsRef<Path>>(file_path: P, table: &str) -> error::Result<Self> {
+        let connection = Connection::open_with_flags(file_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(sql_error)?;
+        NodesDataset::from_sqlite(&connection, table)
+    }
+
+    /// Reads a nodes dataset from a table of an open SQLite database.
+    pub fn from_sqlite(connection: &Connection, table: &str) -> error::Result<Self> {
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT id, c, t, embedding FROM \"{}\" ORDER BY id",
+                table.replace('"', "\"\"")
+            ))
+            .map_err(sql_error)?;
+        let mut rows = statement.query([]).map_err(sql_error)?;
+
+        let mut dimensions = None;
+        let (mut c_attrs, mut t_attrs, mut vectors) = (Vec::new(), Vec::new(), Vec::new());
+        let mut missing = Vec::new();
+        while let Some(row) = rows.next().map_err(sql_error)? {
+            let id: i64 = row.get(0).map_err(sql_error)?;
+            let vector = embedding(row.get_ref(3).map_err(sql_error)?)?;
+            let dimensions = *dimensions.get_or_insert(vector.len());
+            if vector.len() != dimensions {
+                return Err(GlasshouseError::Malformed(format!(
+                    "Node {} has {} dimensions, expected {}",
+                    id,
+                    vector.len(),
+                    dimensions
+                )));
+            }
+            let next_id = c_attrs.len() as i64;
+            if id < next_id || id > u32::MAX as i64 {
+                return Err(GlasshouseError::Malformed(format!(
+                    "Node ID {} is duplicated or out of range",
+                    id
+                )));
+            }
+            for missing_id in next_id..id {
+                missing.push(missing_id as u32);
+                c_attrs.push(0);
+                t_attrs.push(0.0);
+                vectors.resize(vectors.len() + dimensions, 0.0);
+            }
+            c_attrs.push(super::categorical(
+                row.get::<_, f64>(1).map_err(sql_error)? as f32,
+            )?);
+            t_attrs.push(row.get::<_, f64>(2).map_err(sql_error)? as f32);
+            vectors.extend_from_slice(&vector);
+        }
+
+        let mut nodes_dataset =
+            NodesDataset::from_parts(dimensions.unwrap_or(0), c_attrs, t_attrs, vectors)?;
+        for node_id in missing {
+            nodes_dataset.delete(node_id);
+        }
+        Ok(nodes_dataset)
+    }
+}
+
+/// Decodes an embedding stored as a blob of little-endian `f32` values or
+/// as a text list.
+fn embedding(value: ValueRef<'_>) -> error::Result<Vec<f32>> {
+    match value {
+        ValueRef::Blob(bytes) if bytes.len() % 4 == 0 => Ok(bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().expect("chunks of 4 bytes")))
+            .collect()),
+        ValueRef::Text(text) => std::str::from_utf8(text)
+            .ok()
+            .and_then(|text| {
+                text.trim()
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.parse::<f32>().ok())
+                    .collect()
+            })
+            .ok_or_else(|| GlasshouseError::Malformed("Invalid embedding list".to_string())),
+        _ => Err(GlasshouseError::Malformed(format!(
+            "Expected an embedding blob or list, got {:?}",
+            value.data_type()
+        ))),
+    }
+}
+
+fn sql_error(e: rusqlite::Error) -> GlasshouseError {
+    GlasshouseError::Malformed(format!("SQLite: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_read_with_their_ids() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE nodes (id INTEGER, c INTEGER, t REAL, embedding);
+                 INSERT INTO nodes VALUES (3, 2, 0.75, '[3.0, 4.0]');
+                 INSERT INTO nodes VALUES (0, 1, 0.5, X'0000803F00000040');",
+            )
+            .unwrap();
+
+        let nodes = NodesDataset::from_sqlite(&connection, "nodes").unwrap();
+        assert_eq!(nodes.num_vectors, 4);
+        assert_eq!(nodes.dimensions, 2);
+        assert_eq!(nodes.vector(0), &[1.0, 2.0]);
+        assert_eq!(nodes.vector(3), &[3.0, 4.0]);
+        assert_eq!(nodes.c_attrs[3], 2);
+        assert!(nodes.is_deleted(1) && nodes.is_deleted(2));
+        assert!(!nodes.is_deleted(3));
+
+        connection
+            .execute("INSERT INTO nodes VALUES (3, 0, 0.0, '[1, 2]')", [])
+            .unwrap();
+        assert!(NodesDataset::from_sqlite(&connection, "nodes").is_err());
+        assert!(NodesDataset::from_sqlite(&connection, "missing").is_err());
+    }
+}
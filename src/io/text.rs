@@ -0,0 +1,227 @@
+//! Plain-text datasets for small hand-written fixtures.
+//!
+//! Every line holds one row with the layout of the binary format, values
+//! separated by whitespace or commas: `c t v...` for nodes and
+//! `query_type category t_lower t_upper v...` for queries, `-1` marking
+//! unset filters. Blank lines and lines starting with `#` are ignored and
+//! the number of rows is not stored.
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+#[cfg(feature = "fs")]
+use std::io::{BufReader, BufWriter};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::constants::*;
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, OptionalFilterValue, QueriesDataset, QueryType};
+
+impl NodesDataset {
+    /// Parses a nodes dataset from its text representation.
+    pub fn from_text<R: BufRead>(reader: R) -> error::Result<Self> {
+        let (dimensions, rows) = read_rows(reader, NODE_VECTOR_START_INDEX)?;
+        let mut c_attrs = Vec::new();
+        let mut t_attrs = Vec::new();
+        let mut vectors = Vec::new();
+        for row in rows.chunks_exact(NODE_VECTOR_START_INDEX + dimensions) {
+            c_attrs.push(super::categorical(row[NODE_C_ATTR_INDEX])?);
+            t_attrs.push(row[NODE_T_ATTR_INDEX]);
+            vectors.extend_from_slice(&row[NODE_VECTOR_START_INDEX..]);
+        }
+        NodesDataset::from_parts(dimensions, c_attrs, t_attrs, vectors)
+    }
+
+    /// Reads a nodes dataset from a text file.
+    #[cfg(feature = "fs")]
+    pub fn read_text<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        Self::from_text(BufReader::new(File::open(file_path)?))
+    }
+
+    /// Writes the text representation of the nodes dataset.
+    pub fn write_text_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for index in 0..self.num_vectors as usize {
+            write_row(
+                writer,
+                &[self.c_attrs[index] as f32, self.t_attrs[index]],
+                self.vector(index),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Saves the nodes dataset to a text file.
+    #[cfg(feature = "fs")]
+    pub fn write_text<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        self.write_text_to(&mut writer)?;
+        writer.flush()
+    }
+}
+
+impl QueriesDataset {
+    /// Parses a queries dataset from its text representation.
+    pub fn from_text<R: BufRead>(reader: R) -> error::Result<Self> {
+        let (dimensions, rows) = read_rows(reader, QUERY_VECTOR_START_INDEX)?;
+        let mut queries_dataset = QueriesDataset {
+            dimensions,
+            ..Default::default()
+        };
+        for row in rows.chunks_exact(QUERY_VECTOR_START_INDEX + dimensions) {
+            queries_dataset
+                .query_types
+                .push(QueryType::from_f32(row[QUERY_TYPE_INDEX])?);
+            queries_dataset
+                .v_categoricals
+                .push(OptionalFilterValue::new(row[QUERY_V_CAT_INDEX]));
+            queries_dataset
+                .t_lower_bounds
+                .push(OptionalFilterValue::new(row[QUERY_T_LOWER_INDEX]));
+            queries_dataset
+                .t_upper_bounds
+                .push(OptionalFilterValue::new(row[QUERY_T_UPPER_INDEX]));
+            queries_dataset
+                .query_vectors
+                .extend_from_slice(&row[QUERY_VECTOR_START_INDEX..]);
+            queries_dataset.num_queries += 1;
+        }
+        Ok(queries_dataset)
+    }
+
+    /// Reads a queries dataset from a text file.
+    #[cfg(feature = "fs")]
+    pub fn read_text<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        Self::from_text(BufReader::new(File::open(file_path)?))
+    }
+
+    /// Writes the text representation of the queries dataset.
+    pub fn write_text_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for index in 0..self.num_queries as usize {
+            let attrs = [
+                self.query_types[index].to_f32(),
+                self.v_categoricals[index].raw(),
+                self.t_lower_bounds[index].raw(),
+                self.t_upper_bounds[index].raw(),
+            ];
+            write_row(writer, &attrs, self.query_vector(index))?;
+        }
+        Ok(())
+    }
+
+    /// Saves the queries dataset to a text file.
+    #[cfg(feature = "fs")]
+    pub fn write_text<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        self.write_text_to(&mut writer)?;
+        writer.flush()
+    }
+}
+
+/// Reads every row, returning their common vector dimensionality and their
+/// values stored contiguously.
+fn read_rows<R: BufRead>(reader: R, num_attrs: usize) -> error::Result<(usize, Vec<f32>)> {
+    let mut width: Option<usize> = None;
+    let mut values = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let len = values.len();
+        for value in line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|value| !value.is_empty())
+        {
+            values.push(value.parse::<f32>().map_err(|_| {
+                GlasshouseError::Malformed(format!("Line {}: invalid value {}", index + 1, value))
+            })?);
+        }
+        let row_width = values.len() - len;
+        if row_width <= num_attrs || width.is_some_and(|width| width != row_width) {
+            return Err(GlasshouseError::Malformed(format!(
+                "Line {}: expected {} values, got {}",
+                index + 1,
+                width.map_or_else(|| format!("more than {}", num_attrs), |w| w.to_string()),
+                row_width
+            )));
+        }
+        width = Some(row_width);
+    }
+    Ok((width.map_or(0, |width| width - num_attrs), values))
+}
+
+fn write_row<W: Write>(writer: &mut W, attrs: &[f32], vector: &[f32]) -> io::Result<()> {
+    let mut values = attrs.iter().chain(vector);
+    if let Some(value) = values.next() {
+        write!(writer, "{}", value)?;
+    }
+    for value in values {
+        write!(writer, " {}", value)?;
+    }
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datasets_round_trip() {
+        let text = "# c t vector\n1 0.5 1.0 2.0\n\n2, 0.25, 3.0, 4.0\n";
+        let nodes = NodesDataset::from_text(text.as_bytes()).unwrap();
+        assert_eq!(nodes.num_vectors, 2);
+        assert_eq!(nodes.dimensions, 2);
+        assert_eq!(nodes.c_attrs, vec![1, 2]);
+        assert_eq!(nodes.vector(1), &[3.0, 4.0]);
+
+        let mut written = Vec::new();
+        nodes.write_text_to(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "1 0.5 1 2\n2 0.25 3 4\n"
+        );
+
+        let queries =
+            QueriesDataset::from_text("3 2 0 0.3 3 4\n0 -1 -1 -1 1 2\n".as_bytes()).unwrap();
+        let query = queries.get(0).unwrap();
+        assert_eq!(query.query_type, QueryType::BothConstraints);
+        assert!(query.matches(&nodes.get(1).unwrap()));
+        assert_eq!(queries.get(1).unwrap().v_categorical, None);
+
+        let mut written = Vec::new();
+        queries.write_text_to(&mut written).unwrap();
+        let read = QueriesDataset::from_text(written.as_slice()).unwrap();
+        assert_eq!(read.query_vectors, queries.query_vectors);
+    }
+
+    #[test]
+    fn malformed_rows_are_reported() {
+        assert!(NodesDataset::from_text("1 0.5 1.0\n1 0.5 1.0 2.0\n".as_bytes()).is_err());
+        assert!(NodesDataset::from_text("1 0.5\n".as_bytes()).is_err());
+        assert!(NodesDataset::from_text("1 0.5 x\n".as_bytes()).is_err());
+        assert!(QueriesDataset::from_text("7 -1 -1 -1 1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn text_fixtures_are_searchable() {
+        use crate::constants::K_NEAREST;
+        use crate::solvers::{Exact, Solver};
+
+        let nodes = NodesDataset::read_text("tests/tiny-nodes.txt").unwrap();
+        let queries = QueriesDataset::read_text("tests/tiny-queries.txt").unwrap();
+        let ids = |index: usize| -> Vec<u32> {
+            Exact
+                .search_scored(&nodes, &queries.get(index).unwrap(), K_NEAREST)
+                .iter()
+                .map(|neighbor| neighbor.id)
+                .collect()
+        };
+
+        assert_eq!(ids(0), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(ids(1), vec![1, 3, 5]);
+        assert_eq!(ids(2), vec![3, 2, 4]);
+        assert_eq!(ids(3), vec![3]);
+    }
+}
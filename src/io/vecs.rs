@@ -0,0 +1,333 @@
+//! The `.fvecs`, `.bvecs` and `.ivecs` formats of the classic ANN datasets
+//! (SIFT, SIFT1B, GIST, Deep).
+//!
+//! Every row starts with its number of components as a little-endian
+//! `int32`, followed by the components as little-endian `float32` in
+//! `.fvecs` files, `uint8` in `.bvecs` files and `int32` in `.ivecs` files.
+//! Base and query vectors are stored in `.fvecs` or `.bvecs` files and
+//! ground truth neighbours in `.ivecs` files. Byte vectors are converted to
+//! `f32` on load.
+//!
+//! These datasets carry no attributes, nodes read from them have a
+//! categorical and timestamp attribute of `0` and queries are vector-only.
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::{self, Read, Write};
+#[cfg(feature = "fs")]
+use std::io::{BufReader, BufWriter};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, OptionalFilterValue, QueriesDataset, QueryResults, QueryType};
+
+impl NodesDataset {
+    /// Parses a nodes dataset from an `.fvecs` stream.
+    pub fn from_fvecs<R: Read>(reader: R) -> error::Result<Self> {
+        let (dimensions, vectors) = read_fvecs(reader)?;
+        Self::from_vectors(dimensions, vectors)
+    }
+
+    /// Parses a nodes dataset from a `.bvecs` stream.
+    pub fn from_bvecs<R: Read>(reader: R) -> error::Result<Self> {
+        let (dimensions, vectors) = read_bvecs(reader)?;
+        Self::from_vectors(dimensions, vectors)
+    }
+
+    /// Reads a nodes dataset from an `.fvecs` file.
+    #[cfg(feature = "fs")]
+    pub fn read_fvecs<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        Self::from_fvecs(BufReader::new(File::open(file_path)?))
+    }
+
+    /// Reads a nodes dataset from a `.bvecs` file.
+    #[cfg(feature = "fs")]
+    pub fn read_bvecs<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        Self::from_bvecs(BufReader::new(File::open(file_path)?))
+    }
+
+    /// Saves the node vectors, without attributes, to an `.fvecs` file.
+    #[cfg(feature = "fs")]
+    pub fn write_fvecs<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        write_fvecs(&mut writer, self.dimensions, &self.vectors)?;
+        writer.flush()
+    }
+
+    /// Creates nodes without attributes from their vectors.
+    pub(crate) fn from_vectors(dimensions: usize, vectors: Vec<f32>) -> error::Result<Self> {
+        let num_vectors = vectors.len().checked_div(dimensions).unwrap_or(0);
+        NodesDataset::from_parts(
+            dimensions,
+            vec![0; num_vectors],
+            vec![0.0; num_vectors],
+            vectors,
+        )
+    }
+}
+
+impl QueriesDataset {
+    /// Parses vector-only queries from an `.fvecs` stream.
+    pub fn from_fvecs<R: Read>(reader: R) -> error::Result<Self> {
+        let (dimensions, query_vectors) = read_fvecs(reader)?;
+        Ok(Self::from_vectors(dimensions, query_vectors))
+    }
+
+    /// Parses vector-only queries from a `.bvecs` stream.
+    pub fn from_bvecs<R: Read>(reader: R) -> error::Result<Self> {
+        let (dimensions, query_vectors) = read_bvecs(reader)?;
+        Ok(Self::from_vectors(dimensions, query_vectors))
+    }
+
+    /// Reads vector-only queries from an `.fvecs` file.
+    #[cfg(feature = "fs")]
+    pub fn read_fvecs<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        Self::from_fvecs(BufReader::new(File::open(file_path)?))
+    }
+
+    /// Reads vector-only queries from a `.bvecs` file.
+    #[cfg(feature = "fs")]
+    pub fn read_bvecs<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        Self::from_bvecs(BufReader::new(File::open(file_path)?))
+    }
+
+    /// Saves the query vectors, without filters, to an `.fvecs` file.
+    #[cfg(feature = "fs")]
+    pub fn write_fvecs<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        write_fvecs(&mut writer, self.dimensions, &self.query_vectors)?;
+        writer.flush()
+    }
+
+    /// Creates vector-only queries from their vectors.
+    pub(crate) fn from_vectors(dimensions: usize, query_vectors: Vec<f32>) -> Self {
+        let num_queries = query_vectors.len().checked_div(dimensions).unwrap_or(0);
+        let unset = OptionalFilterValue::new(-1.0);
+        QueriesDataset {
+            num_queries: num_queries as u32,
+            dimensions,
+            query_types: vec![QueryType::VectorOnly; num_queries],
+            v_categoricals: vec![unset; num_queries],
+            t_lower_bounds: vec![unset; num_queries],
+            t_upper_bounds: vec![unset; num_queries],
+            query_vectors,
+        }
+    }
+}
+
+/// Reads every row of an `.fvecs` stream, returning their common number of
+/// dimensions and their components stored contiguously.
+pub fn read_fvecs<R: Read>(reader: R) -> error::Result<(usize, Vec<f32>)> {
+    read_vecs(reader, f32::from_le_bytes)
+}
+
+/// Reads every row of a `.bvecs` stream, returning their common number of
+/// dimensions and their components converted to `f32` stored contiguously.
+pub fn read_bvecs<R: Read>(reader: R) -> error::Result<(usize, Vec<f32>)> {
+    read_vecs(reader, |[byte]: [u8; 1]| byte as f32)
+}
+
+/// Reads every row of an `.ivecs` stream, returning their common number of
+/// components and the components stored contiguously.
+pub fn read_ivecs<R: Read>(reader: R) -> error::Result<(usize, Vec<i32>)> {
+    read_vecs(reader, i32::from_le_bytes)
+}
+
+/// Writes rows of `dimensions` components, stored contiguously, as an
+/// `.fvecs` stream.
+pub fn write_fvecs<W: Write>(writer: &mut W, dimensions: usize, values: &[f32]) -> io::Result<()> {
+    write_vecs(writer, dimensions, values, |value| value.to_le_bytes())
+}
+
+/// Writes rows of `dimensions` components, stored contiguously, as a
+/// `.bvecs` stream.
+pub fn write_bvecs<W: Write>(writer: &mut W, dimensions: usize, values: &[u8]) -> io::Result<()> {
+    write_vecs(writer, dimensions, values, |value| [value])
+}
+
+/// Writes rows of `dimensions` components, stored contiguously, as an
+/// `.ivecs` stream.
+pub fn write_ivecs<W: Write>(writer: &mut W, dimensions: usize, values: &[i32]) -> io::Result<()> {
+    write_vecs(writer, dimensions, values, |value| value.to_le_bytes())
+}
+
+/// Parses KNN results from an `.ivecs` stream, rows holding more than `k`
+/// neighbours are truncated.
+pub fn results_from_ivecs<R: Read>(reader: R, k: usize) -> error::Result<QueryResults> {
+    let (width, ids) = read_ivecs(reader)?;
+    results_from_rows(width, &ids, k)
+}
+
+/// Converts rows of `width` neighbour IDs, stored contiguously, to KNN
+/// results keeping the first `k` neighbours of each row.
+pub(crate) fn results_from_rows(
+    width: usize,
+    ids: &[i32],
+    k: usize,
+) -> error::Result<QueryResults> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    if width < k {
+        return Err(GlasshouseError::Malformed(format!(
+            "Rows of {} neighbours are too short to hold {} neighbours",
+            width, k
+        )));
+    }
+    Ok(ids
+        .chunks_exact(width)
+        .map(|row| row[..k].iter().map(|&value| value as u32).collect())
+        .collect())
+}
+
+/// Saves KNN results to an `.ivecs` file, the format expected by the
+/// evaluation scripts of the classic ANN datasets.
+#[cfg(feature = "fs")]
+pub fn write_results_ivecs<P: AsRef<Path>>(results: &QueryResults, file_path: P) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    let width = results.first().map_or(0, |result| result.len());
+    let ids: Vec<i32> = results.iter().flatten().map(|&id| id as i32).collect();
+    write_ivecs(&mut writer, width, &ids)?;
+    writer.flush()
+}
+
+/// Reads the first `k` neighbours of KNN results from an `.ivecs` file.
+#[cfg(feature = "fs")]
+pub fn read_results_ivecs<P: AsRef<Path>>(file_path: P, k: usize) -> error::Result<QueryResults> {
+    results_from_ivecs(BufReader::new(File::open(file_path)?), k)
+}
+
+/// Reads rows of `N`-byte components until the end of the stream, every
+/// row must have the same number of components.
+fn read_vecs<R: Read, T, const N: usize>(
+    mut reader: R,
+    decode: impl Fn([u8; N]) -> T,
+) -> error::Result<(usize, Vec<T>)> {
+    let mut dimensions = None;
+    let mut values = Vec::new();
+    let mut row = Vec::new();
+    let mut header = [0u8; 4];
+    while read_header(&mut reader, &mut header)? {
+        let row_dimensions = i32::from_le_bytes(header);
+        if row_dimensions <= 0 || dimensions.is_some_and(|d| d != row_dimensions as usize) {
+            return Err(GlasshouseError::Malformed(format!(
+                "Row {} has {} components, expected {}",
+                values.len() / dimensions.unwrap_or(1),
+                row_dimensions,
+                dimensions.map_or("a positive count".to_string(), |d| d.to_string())
+            )));
+        }
+        let row_dimensions = row_dimensions as usize;
+        dimensions = Some(row_dimensions);
+
+        // The component count is untrusted, the row grows with the bytes
+        // actually read so a corrupt count fails at the end of the stream
+        // instead of on a huge allocation.
+        let row_bytes = row_dimensions * N;
+        row.clear();
+        (&mut reader).take(row_bytes as u64).read_to_end(&mut row)?;
+        if row.len() < row_bytes {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        values.extend(
+            row.chunks_exact(N)
+                .map(|chunk| decode(chunk.try_into().expect("chunks hold exactly one component"))),
+        );
+    }
+    Ok((dimensions.unwrap_or(0), values))
+}
+
+/// Reads the component count of the next row, returns false at the end of
+/// the stream.
+fn read_header<R: Read>(reader: &mut R, header: &mut [u8; 4]) -> error::Result<bool> {
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+fn write_vecs<W: Write, T: Copy, const N: usize>(
+    writer: &mut W,
+    dimensions: usize,
+    values: &[T],
+    encode: impl Fn(T) -> [u8; N],
+) -> io::Result<()> {
+    if dimensions == 0 {
+        return Ok(());
+    }
+    for row in values.chunks_exact(dimensions) {
+        writer.write_all(&(dimensions as i32).to_le_bytes())?;
+        for &value in row {
+            writer.write_all(&encode(value))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::K_NEAREST;
+
+    #[test]
+    fn fvecs_read_back_identically() {
+        let mut bytes = Vec::new();
+        write_fvecs(&mut bytes, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert_eq!(bytes.len(), 2 * (4 + 3 * 4));
+
+        let nodes = NodesDataset::from_fvecs(bytes.as_slice()).unwrap();
+        assert_eq!((nodes.num_vectors, nodes.dimensions), (2, 3));
+        assert_eq!(nodes.get(1).unwrap().vector, &[4.0, 5.0, 6.0]);
+        let queries = QueriesDataset::from_fvecs(bytes.as_slice()).unwrap();
+        assert_eq!(queries.num_queries, 2);
+        assert_eq!(queries.get(0).unwrap().query_type, QueryType::VectorOnly);
+    }
+
+    #[test]
+    fn bvecs_are_converted_to_floats() {
+        let mut bytes = Vec::new();
+        write_bvecs(&mut bytes, 4, &[0, 1, 128, 255, 7, 7, 7, 7]).unwrap();
+        assert_eq!(bytes.len(), 2 * (4 + 4));
+
+        let nodes = NodesDataset::from_bvecs(bytes.as_slice()).unwrap();
+        assert_eq!((nodes.num_vectors, nodes.dimensions), (2, 4));
+        assert_eq!(nodes.get(0).unwrap().vector, &[0.0, 1.0, 128.0, 255.0]);
+        let queries = QueriesDataset::from_bvecs(bytes.as_slice()).unwrap();
+        assert_eq!(queries.query_vector(1), &[7.0; 4]);
+    }
+
+    #[test]
+    fn ivecs_results_are_truncated_to_k() {
+        let ids: Vec<i32> = (0..2 * (K_NEAREST as i32 + 1)).collect();
+        let mut bytes = Vec::new();
+        write_ivecs(&mut bytes, K_NEAREST + 1, &ids).unwrap();
+
+        let results = results_from_ivecs(bytes.as_slice(), K_NEAREST).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1][0], K_NEAREST as u32 + 1);
+        assert_eq!(results[1][K_NEAREST - 1], 2 * K_NEAREST as u32);
+        let top_10 = results_from_ivecs(bytes.as_slice(), 10).unwrap();
+        assert_eq!(top_10[1][..], results[1][..10]);
+    }
+
+    #[test]
+    fn ragged_and_truncated_vecs_are_rejected() {
+        let mut bytes = Vec::new();
+        write_fvecs(&mut bytes, 2, &[1.0, 2.0]).unwrap();
+        write_fvecs(&mut bytes, 3, &[1.0, 2.0, 3.0]).unwrap();
+        assert!(read_fvecs(bytes.as_slice()).is_err());
+        assert!(read_fvecs(&bytes[..6]).is_err());
+        assert!(results_from_ivecs(&bytes[..12], K_NEAREST).is_err());
+
+        let mut huge = i32::MAX.to_le_bytes().to_vec();
+        huge.extend_from_slice(&[0; 16]);
+        assert!(read_fvecs(huge.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! Percentiles of the per-query search latencies.
+use std::time::Duration;
+
+use crate::types::QueryType;
+
+/// Latency percentiles over a group of queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Percentiles {
+    pub queries: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Percentiles {
+    /// Returns the nearest-rank percentiles of the latencies, `None` when
+    /// there are none.
+    pub fn of(latencies: &[Duration]) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = latencies.to_vec();
+        sorted.sort_unstable();
+        let rank = |percentile: usize| sorted[(sorted.len() * percentile).div_ceil(100) - 1];
+        Some(Percentiles {
+            queries: sorted.len(),
+            p50: rank(50),
+            p95: rank(95),
+            p99: rank(99),
+            max: rank(100),
+        })
+    }
+}
+
+/// Latency percentiles over all answered queries and over the answered
+/// queries of each type, types without any answered query are omitted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyReport {
+    pub overall: Option<Percentiles>,
+    pub by_type: Vec<(QueryType, Percentiles)>,
+}
+
+/// Summarizes the latency of each query, `None` for unanswered queries,
+/// `query_types` holds the type of each query in the same order.
+pub fn report(latencies: &[Option<Duration>], query_types: &[QueryType]) -> LatencyReport {
+    let answered = |query_type: Option<QueryType>| -> Vec<Duration> {
+        latencies
+            .iter()
+            .zip(query_types)
+            .filter(|(_, t)| query_type.is_none_or(|query_type| **t == query_type))
+            .filter_map(|(latency, _)| *latency)
+            .collect()
+    };
+    LatencyReport {
+        overall: Percentiles::of(&answered(None)),
+        by_type: QueryType::ALL
+            .into_iter()
+            .filter_map(|query_type| {
+                Percentiles::of(&answered(Some(query_type))).map(|p| (query_type, p))
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let latencies: Vec<Duration> = (1..=200).rev().map(Duration::from_millis).collect();
+        let percentiles = Percentiles::of(&latencies).unwrap();
+
+        assert_eq!(percentiles.queries, 200);
+        assert_eq!(percentiles.p50, Duration::from_millis(100));
+        assert_eq!(percentiles.p95, Duration::from_millis(190));
+        assert_eq!(percentiles.p99, Duration::from_millis(198));
+        assert_eq!(percentiles.max, Duration::from_millis(200));
+        assert_eq!(Percentiles::of(&[]), None);
+    }
+
+    #[test]
+    fn reports_skip_unanswered_queries() {
+        let latencies = [
+            Some(Duration::from_millis(3)),
+            None,
+            Some(Duration::from_millis(1)),
+        ];
+        let types = [
+            QueryType::VectorOnly,
+            QueryType::TimestampConstraint,
+            QueryType::VectorOnly,
+        ];
+
+        let report = report(&latencies, &types);
+        assert_eq!(report.overall.unwrap().queries, 2);
+        assert_eq!(report.overall.unwrap().p50, Duration::from_millis(1));
+        assert_eq!(report.by_type.len(), 1);
+        assert_eq!(report.by_type[0].0, QueryType::VectorOnly);
+    }
+}
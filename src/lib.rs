@@ -0,0 +1,47 @@
+//! Filtered approximate nearest neighbour search for the SIGMOD 2024
+//! programming contest.
+//!
+//! Reading and writing files is gated behind the default `fs` feature,
+//! without it the crate compiles to `wasm32-unknown-unknown` and datasets
+//! are built in memory with `NodesDataset::from_parts`, `from_bytes` and
+//! `push`.
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bench;
+#[cfg(feature = "fs")]
+pub mod benchmarks;
+pub mod budget;
+pub mod cancel;
+pub mod config;
+pub mod constants;
+pub mod distance;
+pub mod error;
+pub mod eval;
+#[cfg(feature = "fs")]
+pub mod ffi;
+#[cfg(feature = "flight")]
+pub mod flight;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod io;
+pub mod latency;
+pub mod memory;
+pub mod pca;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+pub mod progress;
+pub mod remote;
+pub mod report;
+pub mod sample;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod shard;
+pub mod solvers;
+pub mod stats;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tune;
+pub mod types;
+pub mod validate;
+pub mod warmup;
@@ -1,162 +1,3835 @@
-mod constants;
-mod io;
-mod types;
+use std::{
+    borrow::Cow,
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, IsTerminal, Write},
+    mem,
+    path::{Path, PathBuf},
+    process,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
 
-use std::{error::Error, time::Instant};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use tracing::field::Empty;
+use tracing::{Level, debug, error, info, info_span, trace, warn};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
 
-use constants::{K_NEAREST, VECTOR_DIMENSIONS};
-use types::{NodesDataset, QueriesDataset, QueryResult, QueryResults, QueryType};
+use glasshouse::benchmarks::{self, Benchmark};
+use glasshouse::budget::{self, Budget};
+use glasshouse::cancel::CancellationToken;
+use glasshouse::config::{Config, Metric, SolverKind};
+use glasshouse::constants::VECTOR_DIMENSIONS;
+#[cfg(feature = "profiling")]
+use glasshouse::profiling;
+use glasshouse::progress::{NoProgress, Phase, Progress};
+use glasshouse::remote::{RemoteShard, ShardServer};
+use glasshouse::report::{self, RunReport};
+use glasshouse::solvers::{
+    self, Checkpoint, Delivery, DiskIndex, Index, Reservoir, Solver, Strategy,
+};
+use glasshouse::types::{NodesDataset, QueriesDataset, QueryResults, QueryType, ScoredResults};
+use glasshouse::validate::{self, Anomaly};
+use glasshouse::{
+    bench, eval, generate, io, latency, memory, remote, sample, shard, stats, tune, warmup,
+};
 
-/// Baseline solution.
-struct Baseline;
+#[derive(Debug, Parser)]
+#[command(
+    name = "glasshouse",
+    version,
+    about = "Filtered approximate nearest neighbour search"
+)]
+struct Cli {
+    /// TOML run configuration, command line flags take precedence over it.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Vector dimensionality, inferred from the file sizes when not set.
+    #[arg(long, global = true)]
+    dimensions: Option<usize>,
+    /// Number of neighbours returned per query, 100 by default as in the
+    /// contest.
+    #[arg(long, global = true)]
+    k: Option<usize>,
+    /// Candidate list width of HNSW searches, overriding `hnsw.ef_search`.
+    /// Wider lists raise recall at the cost of latency.
+    #[arg(long, global = true)]
+    ef_search: Option<usize>,
+    /// Proportion of the nodes the baseline scans for each query, drawn at
+    /// random, overriding `baseline.sample_proportion`.
+    #[arg(long, global = true)]
+    sample_proportion: Option<f32>,
+    /// Node ID padding results with fewer than K neighbours, written by
+    /// `search` and `gen-gt` and ignored by `eval`. Defaults to 4294967295,
+    /// results padded by older versions need `--pad-id 0`.
+    #[arg(long, global = true)]
+    pad_id: Option<u32>,
+    /// Reject datasets holding NaN or infinite values when loading them,
+    /// instead of leaving the affected nodes out of the results.
+    #[arg(long, global = true)]
+    check_finite: bool,
+    /// Similarity the neighbours are ranked by, overriding `metric`. Under
+    /// `cosine` the vectors are scaled to unit length once loaded.
+    #[arg(long, global = true, value_enum)]
+    metric: Option<Metric>,
+    /// Number of worker threads, 0 uses one thread per core.
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+    /// Number of worker threads building indexes in a pool of their own,
+    /// 0 builds on the worker threads. Only IVF builds use more than one.
+    #[arg(long, global = true)]
+    build_threads: Option<usize>,
+    /// Time budget of the run in seconds, once exceeded the search stops and
+    /// the results computed so far are written.
+    #[arg(long, global = true)]
+    deadline: Option<f64>,
+    /// Wall-clock budget of the whole run, such as `90s`, `20m` or `1h`.
+    /// The search effort is lowered so that the results are written
+    /// before it expires.
+    #[arg(long, global = true, value_parser = parse_budget)]
+    budget: Option<Duration>,
+    /// Most verbose level of the logs written to stderr.
+    #[arg(long, global = true, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+    /// Log more details, `-v` adds the configuration and a summary of the
+    /// planned strategies, `-vv` the plan of every query.
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "log_level")]
+    verbose: u8,
+    /// Only log warnings and errors, and hide the progress bars.
+    #[arg(short, long, global = true, conflicts_with_all = ["log_level", "verbose"])]
+    quiet: bool,
+    /// Format of the logs, JSON lines are meant for automated runs.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Write the spans of every phase of the run to a Chrome trace, shown
+    /// as a flame chart by Perfetto or `chrome://tracing`.
+    #[arg(long, global = true)]
+    trace_spans: Option<PathBuf>,
+    /// Seconds between two log lines reporting the progress of a long phase
+    /// when stderr is not a terminal, 0 disables them.
+    #[arg(long, global = true, default_value_t = 30)]
+    heartbeat: u64,
+    #[command(subcommand)]
+    command: Command,
+}
 
-/// Calculates squared Euclidean distance between two 100-dim vectors.
-fn l2(vec1: &[f32; VECTOR_DIMENSIONS], vec2: &[f32; VECTOR_DIMENSIONS]) -> f32 {
-    vec1.iter().zip(vec2.iter()).fold(0.0, |acc, (a, b)| {
-        let diff = a - b;
-        acc + diff * diff
-    })
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Build the configured index over a nodes dataset and report its cost.
+    Build(BuildArgs),
+    /// Answer a queries dataset and write the results in the contest format.
+    Search(SearchArgs),
+    /// Compute the recall of a results file against ground truth results.
+    Eval(EvalArgs),
+    /// Find the fastest search parameters reaching a recall target.
+    Tune(TuneArgs),
+    /// Measure recall and throughput over a grid of solver parameters.
+    Sweep(SweepArgs),
+    /// Answer a sample of the queries in a loop for a fixed duration and
+    /// report the sustained throughput and latencies.
+    Bench(BenchArgs),
+    /// Compare the neighbours of two results files.
+    Diff(DiffArgs),
+    /// Convert a dataset between the contest binary format, `.fvecs`,
+    /// `.npy`, `.npz`, `.parquet` and `.txt`, chosen by the file extensions.
+    Convert(ConvertArgs),
+    /// Report the attribute distributions and filter selectivity.
+    Stats(StatsArgs),
+    /// Concatenate nodes datasets into one, in the order given.
+    Merge(MergeArgs),
+    /// Extract a random subset of the nodes and queries datasets.
+    Sample(SampleArgs),
+    /// Shuffle the order of the nodes and save the ID permutation.
+    Shuffle(ShuffleArgs),
+    /// Generate a synthetic nodes and queries dataset.
+    Gen(GenArgs),
+    /// Derive a queries dataset from the nodes with a controlled filter
+    /// selectivity.
+    GenQueries(GenQueriesArgs),
+    /// Compute the exact filtered neighbours and distances of every query.
+    GenGt(GenGtArgs),
+    /// Download a standard ANN benchmark and convert it to the contest
+    /// format, with random node attributes.
+    Fetch(FetchArgs),
+    /// Answer the queries over one partition of the nodes, run by the
+    /// worker processes of `search --workers`.
+    #[command(hide = true)]
+    Shard(ShardArgs),
+    /// Answer query batches over a partition of the nodes for the
+    /// coordinator of `search --remote`.
+    ServeShard(ServeShardArgs),
+    /// Serve JSON search requests over HTTP.
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+    /// Serve batch and streaming search requests over gRPC.
+    #[cfg(feature = "grpc")]
+    ServeGrpc(ServeGrpcArgs),
+    /// Answer bulk query batches over Arrow Flight.
+    #[cfg(feature = "flight")]
+    ServeFlight(ServeFlightArgs),
 }
-impl Baseline {
-    const DEFAULT_PAD_ID: u32 = 0; // Or u32::MAX
-    const SAMPLE_PROPORTION: f32 = 0.001;
-
-    pub fn run(
-        nodes_dataset: &NodesDataset,
-        queries_dataset: &QueriesDataset,
-    ) -> Result<QueryResults, Box<dyn Error>> {
-        let mut all_knn_results: QueryResults =
-            Vec::with_capacity(queries_dataset.num_queries as usize);
-
-        let num_to_sample = ((nodes_dataset.num_vectors as f32 * Self::SAMPLE_PROPORTION) as u32)
-            .max(1)
-            .min(nodes_dataset.num_vectors);
-
-        println!("Baseline Algorithm Parameters:");
-        println!("  K-Nearest: {}", K_NEAREST);
-        println!("  Sample proportion: {}", Self::SAMPLE_PROPORTION);
-        println!("  Actual points to sample per query: {}", num_to_sample);
-
-        for i in 0..(queries_dataset.num_queries as usize) {
-            let query = queries_dataset
-                .get(i)
-                .ok_or_else(|| format!("Failed to get parsed query for index: {}", i))?;
-
-            let mut qualified_candidates: Vec<(f32, u32)> = Vec::new();
-
-            for node_idx in 0..num_to_sample {
-                let node_id = node_idx; // In this sampling strategy, index is ID for the sampled prefix
-                let node = nodes_dataset
-                    .get(node_id as usize)
-                    .ok_or_else(|| format!("Failed to get parsed node for ID: {}", node_id))?;
-
-                let passes_filter = match query.query_type {
-                    QueryType::VectorOnly => true,
-                    QueryType::CategoricalConstraint => query
-                        .v_categorical
-                        .is_some_and(|v_cat| (node.c_attr - v_cat as f32).abs() < f32::EPSILON),
-                    QueryType::TimestampConstraint => {
-                        match (query.t_lower_bound, query.t_upper_bound) {
-                            (Some(l_bound), Some(r_bound)) => {
-                                node.t_attr >= l_bound && node.t_attr <= r_bound
-                            }
-                            _ => false,
-                        }
-                    }
-                    QueryType::BothConstraints => {
-                        let cat_match = query
-                            .v_categorical
-                            .is_some_and(|v_cat| (node.c_attr - v_cat as f32).abs() < f32::EPSILON);
-                        let time_match = match (query.t_lower_bound, query.t_upper_bound) {
-                            (Some(l_bound), Some(r_bound)) => {
-                                node.t_attr >= l_bound && node.t_attr <= r_bound
-                            }
-                            _ => false,
-                        };
-                        cat_match && time_match
-                    }
-                };
 
-                if passes_filter {
-                    let dist = l2(query.query_vector, node.vector);
-                    qualified_candidates.push((dist, node_id));
-                }
-            }
+#[derive(Debug, Args)]
+struct BuildArgs {
+    /// Nodes dataset to index. Several files are loaded as shards of a
+    /// single dataset.
+    #[arg(long, num_args = 1..)]
+    nodes: Vec<PathBuf>,
+    /// Solver to build.
+    #[arg(long, value_enum)]
+    solver: Option<SolverKind>,
+    /// File the built index is saved to.
+    #[arg(long)]
+    save: Option<PathBuf>,
+    /// File the graph of an `hnsw` index is exported to, as GraphML for the
+    /// `.graphml` extension and Graphviz DOT otherwise.
+    #[arg(long)]
+    graph: Option<PathBuf>,
+    /// File the bottom layer of an `hnsw` index is written to along with
+    /// the vectors, searched with `search --disk-index` without loading the
+    /// nodes dataset.
+    #[arg(long)]
+    disk: Option<PathBuf>,
+    /// File every layer of an `hnsw` index is written to along with the
+    /// vectors, laid out to be searched in place with `search
+    /// --mapped-index` by several processes sharing one copy of it.
+    #[cfg(feature = "mmap")]
+    #[arg(long)]
+    mapped: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct SearchArgs {
+    /// Nodes dataset to search. Several files are loaded as shards of a
+    /// single dataset.
+    #[arg(long, num_args = 1..)]
+    nodes: Vec<PathBuf>,
+    /// Queries dataset to answer.
+    #[arg(long)]
+    queries: Option<PathBuf>,
+    /// File the results are written to.
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Format of the results file.
+    #[arg(long, value_enum, default_value = "binary")]
+    output_format: OutputFormat,
+    /// Append CRC32 checksums of the results and of the datasets they
+    /// answer to the results file, checked by `eval`. Only applies to the
+    /// contest results format.
+    #[arg(long)]
+    checksum: bool,
+    /// Write the results of each query as soon as it and the queries before
+    /// it are answered, instead of once every query is. Only applies to the
+    /// contest results format, per-query latencies are not reported.
+    #[arg(long, conflicts_with = "checksum")]
+    stream: bool,
+    /// Solver used to answer the queries.
+    #[arg(long, value_enum)]
+    solver: Option<SolverKind>,
+    /// Previously saved index to search instead of building one.
+    #[arg(long, conflicts_with = "solver")]
+    index: Option<PathBuf>,
+    /// Disk index written by `build --disk` to search instead of the nodes
+    /// dataset. Only the attributes and quantized vectors of the nodes are
+    /// kept in memory, the full vectors are read from the file.
+    #[arg(
+        long,
+        conflicts_with_all = ["solver", "index", "reservoir", "checksum", "estimate_recall", "validate_only"]
+    )]
+    disk_index: Option<PathBuf>,
+    /// Mapped index written by `build --mapped` to search in place instead
+    /// of the nodes dataset. The processes mapping the same file share its
+    /// pages, a file in `/dev/shm` is kept in shared memory.
+    #[cfg(feature = "mmap")]
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "solver", "index", "disk_index", "reservoir", "checksum", "estimate_recall",
+            "validate_only", "workers", "remote"
+        ]
+    )]
+    mapped_index: Option<PathBuf>,
+    /// Search every query, including the duplicates of a query that was
+    /// already answered instead of reusing its results.
+    #[arg(long)]
+    no_query_cache: bool,
+    /// Drop the duplicates of identical queries once loaded and only search
+    /// the distinct ones, their results are copied back to every duplicate
+    /// when writing.
+    #[arg(long, conflicts_with = "stream")]
+    dedup_queries: bool,
+    /// Print how the solver would answer each query, its strategy,
+    /// estimated selectivity and candidate counts, instead of searching.
+    #[arg(long, conflicts_with_all = ["stream", "checksum", "estimate_recall"])]
+    explain: bool,
+    /// Touch every page of the datasets and answer this many throwaway
+    /// queries before the timed search, so that page faults and cold caches
+    /// are not measured.
+    #[arg(long)]
+    warm_up: Option<usize>,
+    /// Fraction of the queries answered exactly once the run completes to
+    /// estimate its recall with a confidence interval, such as `0.01`.
+    #[arg(long, value_parser = parse_fraction)]
+    estimate_recall: Option<f64>,
+    /// Ground truth of the queries, the recall of the run over all queries
+    /// and over each query type is logged and added to the run report.
+    #[arg(long, conflicts_with_all = ["stream", "explain"])]
+    ground_truth: Option<PathBuf>,
+    /// Log the recall of the queries answered so far against the ground
+    /// truth every this many seconds while searching, so that runs with a
+    /// bad parameter choice can be aborted early.
+    #[arg(long, requires = "ground_truth")]
+    live_recall: Option<u64>,
+    /// Parse both datasets, report their anomalies, such as non-finite
+    /// values, malformed filters or filters matching no node, and exit
+    /// without searching. Fails if any anomaly makes the results wrong.
+    #[arg(long, conflicts_with_all = ["stream", "explain", "index", "reservoir"])]
+    validate_only: bool,
+    /// Stream the nodes file once keeping a random sample of this many
+    /// nodes, and answer the queries exactly over the sample. Bounds the
+    /// memory used by the nodes whatever the size of the file.
+    #[arg(
+        long,
+        conflicts_with_all = ["solver", "index", "checksum", "estimate_recall"]
+    )]
+    reservoir: Option<usize>,
+    /// Split the nodes in this many contiguous partitions, each loaded and
+    /// indexed by a worker process of its own, and merge the neighbours
+    /// found by every worker. Bounds the memory of each process when a
+    /// single one cannot hold the whole dataset and its index.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "stream", "index", "disk_index", "reservoir", "checksum", "explain",
+            "estimate_recall", "live_recall", "validate_only", "dedup_queries", "warm_up"
+        ]
+    )]
+    workers: Option<usize>,
+    /// Addresses of `serve-shard` workers holding every partition of the
+    /// nodes, the queries are sent to all of them in batches and their
+    /// neighbours merged. The nodes are not loaded by this process.
+    #[arg(
+        long,
+        num_args = 1..,
+        conflicts_with_all = [
+            "workers", "solver", "stream", "index", "disk_index", "reservoir", "checksum",
+            "explain", "estimate_recall", "live_recall", "validate_only", "dedup_queries",
+            "warm_up"
+        ]
+    )]
+    remote: Vec<String>,
+    /// Number of queries sent to the remote workers at once.
+    #[arg(long, requires = "remote", default_value_t = 1024)]
+    remote_batch: usize,
+    /// Periodically save the results of the queries answered so far to
+    /// this file, removed once the results are written. Only applies to the
+    /// exact solver, whose runs are not timed per query.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "stream", "explain", "live_recall", "validate_only", "reservoir", "workers",
+            "remote", "index", "disk_index"
+        ]
+    )]
+    checkpoint: Option<PathBuf>,
+    /// Time between two checkpoints, such as `90s`, `20m` or `1h`.
+    #[arg(long, value_parser = parse_budget, default_value = "10m", requires = "checkpoint")]
+    checkpoint_interval: Duration,
+    /// Continue the run that saved the checkpoint, skipping the queries it
+    /// answered. Starts a new run when the checkpoint does not exist.
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+}
 
-            qualified_candidates.sort_unstable_by(|a, b| {
-                a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal)
-            });
+#[derive(Debug, Args)]
+struct ServeShardArgs {
+    /// Nodes dataset the partition is read from.
+    #[arg(long)]
+    nodes: Option<PathBuf>,
+    /// Solver indexing the partition.
+    #[arg(long, value_enum)]
+    solver: Option<SolverKind>,
+    /// Partition of the nodes served, from 0.
+    #[arg(long, default_value_t = 0)]
+    part: usize,
+    /// Number of partitions of the nodes, one per worker.
+    #[arg(long, default_value_t = 1)]
+    parts: usize,
+    /// Address the worker listens on.
+    #[arg(long, default_value = "127.0.0.1:7070")]
+    addr: std::net::SocketAddr,
+}
 
-            let mut current_knn_result: QueryResult = [Self::DEFAULT_PAD_ID; K_NEAREST];
-            for k_idx in 0..K_NEAREST {
-                if let Some(candidate) = qualified_candidates.get(k_idx) {
-                    current_knn_result[k_idx] = candidate.1; // Store the ID
-                }
+#[derive(Debug, Args)]
+struct ShardArgs {
+    /// Partition of the nodes answered, from 0.
+    #[arg(long)]
+    part: usize,
+    /// Number of partitions of the nodes.
+    #[arg(long)]
+    parts: usize,
+    /// File the scored results are written to, with IDs in the whole
+    /// nodes dataset.
+    #[arg(long)]
+    output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct EvalArgs {
+    /// Results file to evaluate.
+    results: PathBuf,
+    /// Exact results, as produced by the `exact` solver.
+    ground_truth: PathBuf,
+    /// Queries dataset the results answer, breaks the recall down per
+    /// query type.
+    #[arg(long)]
+    queries: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct TuneArgs {
+    /// Nodes dataset to search. Several files are loaded as shards of a
+    /// single dataset.
+    #[arg(long, num_args = 1..)]
+    nodes: Vec<PathBuf>,
+    /// Sample of queries the parameters are tuned on.
+    #[arg(long)]
+    queries: Option<PathBuf>,
+    /// Exact results of the sample, as produced by `gen-gt`.
+    #[arg(long)]
+    ground_truth: PathBuf,
+    /// Solver whose search parameters are tuned.
+    #[arg(long, value_enum)]
+    solver: Option<SolverKind>,
+    /// Recall@K the tuned parameters must reach on the sample.
+    #[arg(long, default_value_t = 0.95)]
+    target: f64,
+    /// File the tuned configuration is written to, printed when not set.
+    #[arg(long)]
+    save: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct BenchArgs {
+    /// Nodes dataset to search. Several files are loaded as shards of a
+    /// single dataset.
+    #[arg(long, num_args = 1..)]
+    nodes: Vec<PathBuf>,
+    /// Queries dataset the benchmarked queries are drawn from.
+    #[arg(long)]
+    queries: Option<PathBuf>,
+    /// Solver benchmarked.
+    #[arg(long, value_enum)]
+    solver: Option<SolverKind>,
+    /// Previously saved index to search instead of building one.
+    #[arg(long, conflicts_with = "solver")]
+    index: Option<PathBuf>,
+    /// Fraction of the queries drawn at random and answered in a loop.
+    #[arg(long, value_parser = parse_fraction, default_value_t = 1.0)]
+    fraction: f64,
+    /// Seconds the queries are answered before measuring.
+    #[arg(long, default_value_t = 5.0)]
+    warm_up: f64,
+    /// Seconds the queries are measured for, once warmed up.
+    #[arg(long, default_value_t = 30.0)]
+    duration: f64,
+}
+
+#[derive(Debug, Args)]
+struct SweepArgs {
+    /// Nodes dataset to search. Several files are loaded as shards of a
+    /// single dataset.
+    #[arg(long, num_args = 1..)]
+    nodes: Vec<PathBuf>,
+    /// Queries dataset to answer.
+    #[arg(long)]
+    queries: Option<PathBuf>,
+    /// Exact results of the queries, as produced by `gen-gt`.
+    #[arg(long)]
+    ground_truth: PathBuf,
+    /// Solver whose parameters are swept.
+    #[arg(long, value_enum)]
+    solver: Option<SolverKind>,
+    /// Swept parameter and its values, such as `hnsw.ef_search=100,200,400`.
+    /// Repeat to sweep the product of several parameters.
+    #[arg(long = "param", value_parser = parse_axis)]
+    params: Vec<(String, Vec<String>)>,
+    /// CSV file the measurements are written to, printed when not set.
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct DiffArgs {
+    /// First results file.
+    left: PathBuf,
+    /// Second results file.
+    right: PathBuf,
+    /// Most differing queries whose neighbours are listed.
+    #[arg(long, default_value_t = 10)]
+    show: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Neighbour IDs in the format given by the extension: `.ivecs`,
+    /// `.npz`, `.parquet` or the contest binary format otherwise.
+    Binary,
+    /// One JSON object per line holding the filters and the scored
+    /// neighbours of a query.
+    Json,
+    /// One CSV row per neighbour: query_id, rank, node_id and distance.
+    Csv,
+    /// The contest layout with a `(u32 id, f32 distance)` pair per
+    /// neighbour, read back by `eval` and `diff` from `.scored` files.
+    Scored,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum DatasetKind {
+    Nodes,
+    Queries,
+}
+
+#[derive(Debug, Args)]
+struct ConvertArgs {
+    /// Dataset to convert.
+    input: PathBuf,
+    /// Converted dataset.
+    output: PathBuf,
+    /// Whether the dataset holds nodes or queries.
+    #[arg(long, value_enum, default_value = "nodes")]
+    kind: DatasetKind,
+    /// Attributes of the nodes as rows of a categorical and a timestamp
+    /// attribute, in an `.fvecs` or `.npy` file. Written when converting to
+    /// a format holding vectors only and joined to the vectors when
+    /// converting from one.
+    #[arg(long)]
+    attributes: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct MergeArgs {
+    /// Nodes datasets to concatenate.
+    #[arg(required = true, num_args = 1..)]
+    inputs: Vec<PathBuf>,
+    /// File the merged nodes are written to.
+    #[arg(long)]
+    output: PathBuf,
+    /// CSV file recording the ID of the first node of every input in the
+    /// merged dataset and its number of nodes.
+    #[arg(long)]
+    offsets: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct StatsArgs {
+    /// Nodes dataset to describe. Several files are loaded as shards of a
+    /// single dataset.
+    #[arg(long, num_args = 1..)]
+    nodes: Vec<PathBuf>,
+    /// Queries dataset whose filter selectivity is reported.
+    #[arg(long)]
+    queries: Option<PathBuf>,
+    /// Number of buckets of the timestamp histogram.
+    #[arg(long, default_value_t = 10)]
+    buckets: usize,
+    /// Number of most frequent categories listed.
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+}
+
+#[derive(Debug, Args)]
+struct SampleArgs {
+    /// Nodes dataset to sample.
+    #[arg(long, requires = "nodes_output")]
+    nodes: Option<PathBuf>,
+    /// File the sampled nodes are written to.
+    #[arg(long, requires = "nodes")]
+    nodes_output: Option<PathBuf>,
+    /// Queries dataset to sample.
+    #[arg(long, requires = "queries_output")]
+    queries: Option<PathBuf>,
+    /// File the sampled queries are written to.
+    #[arg(long, requires = "queries")]
+    queries_output: Option<PathBuf>,
+    /// Fraction of the rows kept in the samples.
+    #[arg(long, default_value_t = 0.01)]
+    fraction: f64,
+    /// Seed of the random generator.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Debug, Args)]
+struct ShuffleArgs {
+    /// Nodes dataset to shuffle.
+    nodes: PathBuf,
+    /// File the shuffled nodes are written to.
+    output: PathBuf,
+    /// File the previous ID of every shuffled node is written to, as a
+    /// single `.ivecs` row.
+    #[arg(long)]
+    permutation: Option<PathBuf>,
+    /// Seed of the random generator.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Debug, Args)]
+struct GenArgs {
+    /// File the generated nodes are written to.
+    #[arg(long)]
+    nodes: PathBuf,
+    /// File the generated queries are written to.
+    #[arg(long)]
+    queries: PathBuf,
+    /// Number of nodes to generate.
+    #[arg(long, default_value_t = 10_000)]
+    num_nodes: u32,
+    /// Number of queries to generate.
+    #[arg(long, default_value_t = 1_000)]
+    num_queries: u32,
+    /// Number of distinct categorical attribute values.
+    #[arg(long, default_value_t = 10)]
+    categories: u32,
+    /// Seed of the random generator.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Debug, Args)]
+struct FetchArgs {
+    /// Benchmark to fetch.
+    #[arg(value_enum)]
+    benchmark: Benchmark,
+    /// Directory the benchmark is downloaded to and converted in.
+    #[arg(long, default_value = "data")]
+    dir: PathBuf,
+    /// Number of distinct categorical attribute values given to the nodes.
+    #[arg(long, default_value_t = 10)]
+    categories: u32,
+    /// Seed of the random generator.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Debug, Args)]
+struct GenQueriesArgs {
+    /// Nodes dataset the query vectors and filters are drawn from.
+    #[arg(long)]
+    nodes: PathBuf,
+    /// File the generated queries are written to.
+    #[arg(long)]
+    output: PathBuf,
+    /// Number of queries to generate.
+    #[arg(long, default_value_t = 1_000)]
+    num_queries: u32,
+    /// Relative frequencies of the vector-only, categorical, timestamp and
+    /// combined query types.
+    #[arg(long, value_delimiter = ',', default_value = "1,1,1,1")]
+    type_weights: Vec<f64>,
+    /// Zipf exponent of the queried categories ranked by frequency, `0`
+    /// picks every category equally often and negative values favour rare
+    /// categories.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    category_skew: f64,
+    /// Smallest width of the timestamp windows, as a fraction of the
+    /// timestamp range of the nodes.
+    #[arg(long, default_value_t = 0.0)]
+    min_window: f64,
+    /// Largest width of the timestamp windows, as a fraction of the
+    /// timestamp range of the nodes.
+    #[arg(long, default_value_t = 1.0)]
+    max_window: f64,
+    /// Largest offset added to each entry of the node vectors the queries
+    /// are drawn from.
+    #[arg(long, default_value_t = 0.0)]
+    noise: f32,
+    /// Seed of the random generator.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Debug, Args)]
+struct GenGtArgs {
+    /// Nodes dataset to search. Several files are loaded as shards of a
+    /// single dataset.
+    #[arg(long, num_args = 1..)]
+    nodes: Vec<PathBuf>,
+    /// Queries dataset to answer.
+    #[arg(long)]
+    queries: Option<PathBuf>,
+    /// File the exact results are written to.
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Format of the exact results file.
+    #[arg(long, value_enum, default_value = "binary")]
+    output_format: OutputFormat,
+    /// Append CRC32 checksums of the results and of the datasets they
+    /// answer to the results file, checked by `eval`. Only applies to the
+    /// contest results format.
+    #[arg(long)]
+    checksum: bool,
+    /// File the distances of the exact results are written to.
+    #[arg(long)]
+    distances: Option<PathBuf>,
+    /// Periodically save the results of the queries answered so far to
+    /// this file, removed once the results are written.
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// Time between two checkpoints, such as `90s`, `20m` or `1h`.
+    #[arg(long, value_parser = parse_budget, default_value = "10m", requires = "checkpoint")]
+    checkpoint_interval: Duration,
+    /// Continue the run that saved the checkpoint, skipping the queries it
+    /// answered. Starts a new run when the checkpoint does not exist.
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+}
+
+/// Nodes and solver answering the requests of the servers.
+#[cfg(any(feature = "server", feature = "grpc", feature = "flight"))]
+#[derive(Debug, Args)]
+struct ServedIndexArgs {
+    /// Nodes dataset to search. Several files are loaded as shards of a
+    /// single dataset.
+    #[arg(long, num_args = 1..)]
+    nodes: Vec<PathBuf>,
+    /// Solver used to answer the queries.
+    #[arg(long, value_enum)]
+    solver: Option<SolverKind>,
+    /// Previously saved index to search instead of building one.
+    #[arg(long, conflicts_with = "solver")]
+    index: Option<PathBuf>,
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug, Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    served: ServedIndexArgs,
+    /// Address the server listens on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: std::net::SocketAddr,
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Debug, Args)]
+struct ServeGrpcArgs {
+    #[command(flatten)]
+    served: ServedIndexArgs,
+    /// Address the server listens on.
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    addr: std::net::SocketAddr,
+}
+
+#[cfg(feature = "flight")]
+#[derive(Debug, Args)]
+struct ServeFlightArgs {
+    #[command(flatten)]
+    served: ServedIndexArgs,
+    /// Address the server listens on.
+    #[arg(long, default_value = "127.0.0.1:50052")]
+    addr: std::net::SocketAddr,
+}
+
+/// Interval of the heartbeat logs of [`ConsoleProgress`], set once from the
+/// command line.
+static HEARTBEAT: OnceLock<Duration> = OnceLock::new();
+
+/// Time the workers of a sharded run are given past the deadline to write
+/// the results they computed so far before they are stopped.
+const WORKERS_GRACE: Duration = Duration::from_secs(10);
+
+/// Renders the progress of a phase on stderr when it is a terminal, and
+/// otherwise logs it at the heartbeat interval so runs without a terminal
+/// can be followed from their logs.
+struct ConsoleProgress {
+    terminal: bool,
+    heartbeat: Option<Duration>,
+    rendered: Mutex<Rendered>,
+}
+
+/// Progress of the phase last rendered, updates arriving late are skipped.
+struct Rendered {
+    phase: Option<Phase>,
+    done: u64,
+    started: Instant,
+    logged: Instant,
+}
+
+impl ConsoleProgress {
+    fn new() -> Self {
+        let now = Instant::now();
+        ConsoleProgress {
+            terminal: std::io::stderr().is_terminal() && tracing::enabled!(Level::INFO),
+            heartbeat: HEARTBEAT
+                .get()
+                .copied()
+                .filter(|interval| !interval.is_zero()),
+            rendered: Mutex::new(Rendered {
+                phase: None,
+                done: 0,
+                started: now,
+                logged: now,
+            }),
+        }
+    }
+}
+
+impl Progress for ConsoleProgress {
+    fn update(&self, phase: Phase, done: u64, total: u64) {
+        let mut rendered = self.rendered.lock().unwrap();
+        if total == 0 {
+            return;
+        }
+        if done == 0 || rendered.phase != Some(phase) {
+            let now = Instant::now();
+            *rendered = Rendered {
+                phase: Some(phase),
+                done: 0,
+                started: now,
+                logged: now,
+            };
+        } else if done <= rendered.done {
+            return;
+        }
+        rendered.done = done;
+        if self.terminal {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(
+                stderr,
+                "\r[~] {:?}: {}/{} ({}%)",
+                phase,
+                done,
+                total,
+                done * 100 / total
+            );
+            if done == total {
+                let _ = writeln!(stderr);
             }
-            all_knn_results.push(current_knn_result);
+            return;
+        }
+        let Some(heartbeat) = self.heartbeat else {
+            return;
+        };
+        if done == total || rendered.logged.elapsed() < heartbeat {
+            return;
         }
+        rendered.logged = Instant::now();
+        let elapsed = rendered.started.elapsed().as_secs_f64();
+        let rate = done as f64 / elapsed;
+        info!(
+            phase = ?phase,
+            done,
+            total,
+            per_second = rate,
+            eta = ?Duration::from_secs(((total - done) as f64 / rate).ceil() as u64),
+            "progress"
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
 
-        Ok(all_knn_results)
+/// Level of the logs, `--quiet` and `--verbose` take precedence over
+/// `--log-level`.
+fn log_level(cli: &Cli) -> LogLevel {
+    match (cli.quiet, cli.verbose) {
+        (true, _) => LogLevel::Warn,
+        (false, 0) => cli.log_level,
+        (false, 1) => LogLevel::Debug,
+        (false, _) => LogLevel::Trace,
     }
 }
 
-fn main() {
-    let program_start_time = Instant::now();
+/// Installs the subscriber writing the logs to stderr and, when a path is
+/// given, the spans to a Chrome trace flushed once the guard is dropped.
+fn init_logging(
+    level: LogLevel,
+    format: LogFormat,
+    trace_spans: Option<&Path>,
+) -> Option<FlushGuard> {
+    let level = match level {
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    };
+    let logs = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let logs = match format {
+        LogFormat::Text => logs.boxed(),
+        LogFormat::Json => logs.json().boxed(),
+    };
+    let (trace, guard, trace_error) = match trace_spans.map(|path| (path, File::create(path))) {
+        Some((_, Ok(file))) => {
+            let (layer, guard) = ChromeLayerBuilder::new()
+                .writer(file)
+                .include_args(true)
+                .build();
+            (Some(layer), Some(guard), None)
+        }
+        Some((path, Err(e))) => (None, None, Some((path, e))),
+        None => (None, None, None),
+    };
+    tracing_subscriber::registry()
+        .with(logs.with_filter(level))
+        .with(trace.with_filter(level))
+        .init();
+    if let Some((path, e)) = trace_error {
+        warn!(path = %path.display(), "cannot create the trace file, spans are not traced: {}", e);
+    }
+    guard
+}
+
+/// Reads results of `k` neighbours per query in the contest format,
+/// `.scored` results with distances, or `.ivecs` and `.hdf5` ground truth.
+fn read_results(path: &Path, pad_id: u32, k: usize) -> Result<QueryResults, Box<dyn Error>> {
+    Ok(match path.extension().and_then(|e| e.to_str()) {
+        Some("ivecs") => io::vecs::read_results_ivecs(path, k)?,
+        Some("scored") => result_ids(&io::read_scored(path, k, pad_id)?, pad_id, k),
+        #[cfg(feature = "hdf5")]
+        Some("hdf5") => io::hdf5::read_neighbors(path, k)?,
+        _ => io::read_results(path, k)?,
+    })
+}
+
+/// Reads the checksums of a results file in the contest format, `None` for
+/// other formats and files without checksums.
+fn read_checksums(
+    path: &Path,
+    k: usize,
+) -> Result<Option<io::checksum::Checksums>, Box<dyn Error>> {
+    if !in_contest_format(path, OutputFormat::Binary) {
+        return Ok(None);
+    }
+    Ok(io::checksum::read(path, k)?)
+}
+
+/// Writes results in the contest format, as `.ivecs` for the evaluation
+/// scripts of the classic ANN datasets or as `.parquet`.
+fn write_results(results: &QueryResults, path: &Path) -> Result<(), Box<dyn Error>> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("ivecs") => io::vecs::write_results_ivecs(results, path)?,
+        #[cfg(feature = "parquet")]
+        Some("parquet") => io::parquet::write_results(results, path)?,
+        _ => io::write(results, path)?,
+    }
+    Ok(())
+}
+
+/// Returns the IDs of scored results in the contest format, padded to `k`
+/// with `pad_id`.
+fn result_ids(results: &ScoredResults, pad_id: u32, k: usize) -> QueryResults {
+    results
+        .iter()
+        .map(|result| solvers::padded_ids(result, k, pad_id))
+        .collect()
+}
+
+/// Returns the results with the distances reported under the metric, see
+/// [`Metric::distance`], borrowed when they are unchanged.
+fn reported(results: &ScoredResults, metric: Metric) -> Cow<'_, ScoredResults> {
+    match metric {
+        Metric::L2 => Cow::Borrowed(results),
+        Metric::Cosine => Cow::Owned(
+            results
+                .iter()
+                .map(|result| {
+                    let mut result = result.clone();
+                    metric.report(&mut result);
+                    result
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// Writes scored results, keeping their distances in the formats that
+/// store them as reported under the metric, see [`Metric::distance`].
+fn write_scored_results(
+    results: &ScoredResults,
+    queries_dataset: &QueriesDataset,
+    path: &Path,
+    format: OutputFormat,
+    metric: Metric,
+    pad_id: u32,
+    k: usize,
+) -> Result<(), Box<dyn Error>> {
+    let results = reported(results, metric);
+    let results = results.as_ref();
+    match format {
+        OutputFormat::Json => io::json::write_results(results, queries_dataset, path)?,
+        OutputFormat::Csv => io::csv::write_results(results, path)?,
+        OutputFormat::Scored => io::write_scored(results, path, k, pad_id)?,
+        OutputFormat::Binary => match path.extension().and_then(|e| e.to_str()) {
+            #[cfg(feature = "npy")]
+            Some("npz") => io::npy::write_results_npz(results, path, k)?,
+            _ => write_results(&result_ids(results, pad_id, k), path)?,
+        },
+    }
+    Ok(())
+}
+
+/// Returns true if results written to the path in the format are in the
+/// contest format, the only one holding checksums.
+fn in_contest_format(path: &Path, format: OutputFormat) -> bool {
+    format == OutputFormat::Binary
+        && !matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("ivecs" | "npz" | "parquet" | "hdf5" | "scored")
+        )
+}
 
-    let args: Vec<String> = std::env::args().collect();
-    let source_path = args
-        .get(1)
-        .map(String::as_str)
-        .unwrap_or("./tests/dummy-data.bin");
-    let query_path = args
-        .get(2)
-        .map(String::as_str)
-        .unwrap_or("./tests/dummy-queries.bin");
-    let knn_save_path = args
-        .get(3)
-        .map(String::as_str)
-        .unwrap_or("./tests/output.bin");
-
-    // --- Data Loading ---
+/// Fails early when `--checksum` is given for another format than the
+/// contest one.
+fn check_checksum_format(
+    checksum: bool,
+    path: &Path,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    if checksum && !in_contest_format(path, format) {
+        return Err("--checksum only applies to results in the contest format".into());
+    }
+    Ok(())
+}
+
+/// Appends the checksums of the results and datasets to a results file.
+fn append_checksums(
+    results: &ScoredResults,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    path: &Path,
+    pad_id: u32,
+    k: usize,
+) -> Result<(), Box<dyn Error>> {
+    let checksums = io::checksum::Checksums::new(
+        &result_ids(results, pad_id, k),
+        nodes_dataset,
+        queries_dataset,
+    );
+    io::checksum::append(path, checksums)?;
+    info!(
+        results = format_args!("{:08x}", checksums.results),
+        nodes = format_args!("{:08x}", checksums.nodes),
+        queries = format_args!("{:08x}", checksums.queries),
+        "appended checksums"
+    );
+    Ok(())
+}
+
+/// Loads the configuration file if any and applies the global flags.
+fn load_config(cli: &Cli) -> Result<Config, Box<dyn Error>> {
+    let mut config = match &cli.config {
+        Some(config_path) => Config::read(config_path).map_err(|e| {
+            format!(
+                "Failed to load config from {}: {}",
+                config_path.display(),
+                e
+            )
+        })?,
+        None => Config::default(),
+    };
+    if let Some(dimensions) = cli.dimensions {
+        config.dimensions = Some(dimensions);
+    }
+    if config.dimensions == Some(0) {
+        return Err("dimensions must be positive".into());
+    }
+    if let Some(pad_id) = cli.pad_id {
+        config.pad_id = Some(pad_id);
+    }
+    if let Some(k) = cli.k {
+        config.k = Some(k);
+    }
+    if config.k() == 0 {
+        return Err("k must be positive".into());
+    }
+    if let Some(ef_search) = cli.ef_search {
+        config.hnsw.ef_search = ef_search;
+    }
+    if config.hnsw.ef_search == 0 {
+        return Err("ef_search must be positive".into());
+    }
+    if let Some(sample_proportion) = cli.sample_proportion {
+        config.baseline.sample_proportion = sample_proportion;
+    }
+    config.check_finite |= cli.check_finite;
+    if let Some(metric) = cli.metric {
+        config.metric = metric;
+    }
+    if let Some(threads) = cli.threads {
+        config.threads = threads;
+    }
+    if let Some(build_threads) = cli.build_threads {
+        config.build_threads = build_threads;
+    }
+    debug!(config = ?config, "loaded configuration");
+    Ok(config)
+}
+
+/// Selects the nodes files given on the command line, if any.
+fn set_nodes(config: &mut Config, mut paths: Vec<PathBuf>) {
+    match paths.len() {
+        0 => {}
+        1 => {
+            config.paths.nodes = paths.remove(0);
+            config.paths.node_shards.clear();
+        }
+        _ => config.paths.node_shards = paths,
+    }
+}
+
+fn read_nodes(config: &Config) -> Result<NodesDataset, Box<dyn Error>> {
     let load_start_time = Instant::now();
-    println!("[+] Loading nodes dataset from: {}", source_path);
-    let nodes_dataset = NodesDataset::read(source_path);
-    assert!(nodes_dataset.is_ok(), "Failed to load nodes dataset");
-    let nodes_dataset = nodes_dataset.unwrap();
-    println!(
-        "[+] Loaded {} nodes in {:?}",
-        nodes_dataset.num_vectors,
-        load_start_time.elapsed()
+    let span = info_span!("load", dataset = "nodes", nodes = Empty, bytes = Empty).entered();
+    let mut nodes_dataset = if config.paths.node_shards.is_empty() {
+        let source_path = &config.paths.nodes;
+        info!(path = %source_path.display(), "loading nodes dataset");
+        read_nodes_file(source_path, config.dimensions)
+    } else {
+        let shards = &config.paths.node_shards;
+        info!(shards = shards.len(), "loading sharded nodes dataset");
+        read_node_shards(shards, config.dimensions)
+    }
+    .map_err(|e| format!("Failed to load nodes dataset: {}", e))?;
+    if config.check_finite {
+        nodes_dataset
+            .check_finite()
+            .map_err(|e| format!("Invalid nodes dataset: {}", e))?;
+    }
+    normalize_nodes(config, &mut nodes_dataset);
+    span.record("nodes", nodes_dataset.num_vectors);
+    span.record("bytes", memory::nodes_bytes(&nodes_dataset));
+    info!(
+        nodes = nodes_dataset.num_vectors,
+        dimensions = nodes_dataset.dimensions,
+        elapsed = ?load_start_time.elapsed(),
+        "loaded nodes dataset"
+    );
+    Ok(nodes_dataset)
+}
+
+/// Scales the node vectors to unit length under the cosine metric, nodes
+/// with a zero vector are deleted with a warning.
+fn normalize_nodes(config: &Config, nodes_dataset: &mut NodesDataset) {
+    if config.metric == Metric::Cosine {
+        nodes_dataset.normalize_vectors();
+    }
+}
+
+/// Streams the nodes file keeping a reservoir sample of `capacity` nodes,
+/// returns the sample and the solver searching it.
+fn read_reservoir(
+    config: &Config,
+    capacity: usize,
+) -> Result<(NodesDataset, Box<dyn Solver>), Box<dyn Error>> {
+    let load_start_time = Instant::now();
+    let span = info_span!("load", dataset = "nodes", nodes = Empty, bytes = Empty).entered();
+    let source_path = &config.paths.nodes;
+    info!(path = %source_path.display(), capacity, "sampling nodes dataset");
+    let (mut sample, ids) = NodesDataset::read_reservoir(
+        source_path,
+        config.dimensions,
+        capacity,
+        config.seed,
+        &ConsoleProgress::new(),
+    )
+    .map_err(|e| format!("Failed to sample nodes dataset: {}", e))?;
+    if config.check_finite {
+        sample
+            .check_finite()
+            .map_err(|e| format!("Invalid nodes dataset: {}", e))?;
+    }
+    normalize_nodes(config, &mut sample);
+    span.record("nodes", sample.num_vectors);
+    span.record("bytes", memory::nodes_bytes(&sample));
+    info!(
+        nodes = sample.num_vectors,
+        dimensions = sample.dimensions,
+        elapsed = ?load_start_time.elapsed(),
+        "sampled nodes dataset"
     );
+    Ok((sample, Box::new(Reservoir::new(ids))))
+}
 
+fn open_disk_index(
+    config: &Config,
+    disk_path: &Path,
+) -> Result<(NodesDataset, Box<dyn Solver>), Box<dyn Error>> {
     let load_start_time = Instant::now();
-    println!("[+] Loading queries dataset from: {}", query_path);
-    let queries_dataset = QueriesDataset::read(query_path);
-    assert!(queries_dataset.is_ok(), "Failed to load queries dataset");
-    let queries_dataset = queries_dataset.unwrap();
-    println!(
-        "[+] Loaded {} queries in {:?}",
-        queries_dataset.num_queries,
-        load_start_time.elapsed()
+    let span = info_span!("load", dataset = "disk_index", nodes = Empty, bytes = Empty).entered();
+    info!(path = %disk_path.display(), "opening disk index");
+    let (mut index, attributes) = DiskIndex::open(disk_path, config.metric)
+        .map_err(|e| format!("Failed to open disk index: {}", e))?;
+    index.set_ef_search(config.hnsw.ef_search);
+    index.set_adaptive(config.hnsw.adaptive);
+    span.record("nodes", attributes.num_vectors);
+    span.record(
+        "bytes",
+        memory::nodes_bytes(&attributes) + index.memory_bytes(),
+    );
+    info!(
+        nodes = attributes.num_vectors,
+        dimensions = attributes.dimensions,
+        max_degree = index.max_degree(),
+        ef_search = index.ef_search(),
+        adaptive = index.adaptive(),
+        elapsed = ?load_start_time.elapsed(),
+        "opened disk index"
     );
+    Ok((attributes, Box::new(index)))
+}
 
-    // Run baseline solution.
-    let algo_start_time = Instant::now();
-    println!("[!] Running baseline solution...");
-    let baseline_results = Baseline::run(&nodes_dataset, &queries_dataset);
-    assert!(baseline_results.is_ok(), "Failed to run baseline algorithm");
-    let baseline_results = baseline_results.unwrap();
-    println!(
-        "[*] Baseline solution completed in {:?}",
-        algo_start_time.elapsed()
+#[cfg(feature = "mmap")]
+fn open_mapped_index(
+    config: &Config,
+    mapped_path: &Path,
+) -> Result<(NodesDataset, Box<dyn Solver>), Box<dyn Error>> {
+    let load_start_time = Instant::now();
+    let span = info_span!(
+        "load",
+        dataset = "mapped_index",
+        nodes = Empty,
+        bytes = Empty
+    )
+    .entered();
+    info!(path = %mapped_path.display(), "mapping index");
+    let (mut index, attributes) = solvers::MappedHnsw::open(mapped_path, config.metric)
+        .map_err(|e| format!("Failed to open mapped index: {}", e))?;
+    index.set_ef_search(config.hnsw.ef_search);
+    index.set_adaptive(config.hnsw.adaptive);
+    span.record("nodes", attributes.num_vectors);
+    span.record("bytes", index.mapped_bytes());
+    info!(
+        nodes = attributes.num_vectors,
+        dimensions = attributes.dimensions,
+        m = index.m(),
+        ef_search = index.ef_search(),
+        adaptive = index.adaptive(),
+        mapped_bytes = index.mapped_bytes(),
+        elapsed = ?load_start_time.elapsed(),
+        "mapped index"
+    );
+    Ok((attributes, Box::new(index)))
+}
+
+fn read_queries(config: &Config) -> Result<QueriesDataset, Box<dyn Error>> {
+    let load_start_time = Instant::now();
+    let query_path = &config.paths.queries;
+    let span = info_span!("load", dataset = "queries", queries = Empty, bytes = Empty).entered();
+    info!(path = %query_path.display(), "loading queries dataset");
+    let mut queries_dataset = read_queries_file(query_path, config.dimensions)
+        .map_err(|e| format!("Failed to load queries dataset: {}", e))?;
+    if config.check_finite {
+        queries_dataset
+            .check_finite()
+            .map_err(|e| format!("Invalid queries dataset: {}", e))?;
+    }
+    if config.metric == Metric::Cosine {
+        queries_dataset
+            .normalize_vectors()
+            .map_err(|e| format!("Invalid queries dataset: {}", e))?;
+    }
+    let normalize = info_span!(
+        "normalize",
+        queries = queries_dataset.num_queries,
+        rewritten = Empty
+    )
+    .entered();
+    let rewritten = queries_dataset.normalize();
+    normalize.record("rewritten", rewritten);
+    if rewritten > 0 {
+        warn!(rewritten, "rewrote queries with missing filter values");
+    }
+    drop(normalize);
+    span.record("queries", queries_dataset.num_queries);
+    span.record("bytes", memory::queries_bytes(&queries_dataset));
+    info!(
+        queries = queries_dataset.num_queries,
+        elapsed = ?load_start_time.elapsed(),
+        "loaded queries dataset"
+    );
+    Ok(queries_dataset)
+}
+
+/// Parses the datasets of the run and logs their anomalies, fails if any
+/// makes the results wrong.
+fn validate_datasets(config: &Config) -> Result<(), Box<dyn Error>> {
+    let config = Config {
+        check_finite: false,
+        ..config.clone()
+    };
+    let nodes_dataset = read_nodes(&config)?;
+    // Queries are validated as written, before normalizing them.
+    let queries_dataset = read_queries_file(&config.paths.queries, config.dimensions)
+        .map_err(|e| format!("Failed to load queries dataset: {}", e))?;
+    if nodes_dataset.dimensions != queries_dataset.dimensions {
+        return Err(glasshouse::error::GlasshouseError::DimensionMismatch {
+            nodes: nodes_dataset.dimensions,
+            queries: queries_dataset.dimensions,
+        }
+        .into());
+    }
+
+    let mut anomalies = Vec::new();
+    if config.paths.node_shards.is_empty() && in_binary_dataset_format(&config.paths.nodes) {
+        anomalies.extend(validate::nodes_file(&config.paths.nodes, &nodes_dataset)?);
+    }
+    anomalies.extend(validate::nodes(&nodes_dataset));
+    if in_binary_dataset_format(&config.paths.queries) {
+        anomalies.extend(validate::queries_file(
+            &config.paths.queries,
+            &queries_dataset,
+        )?);
+    }
+    anomalies.extend(validate::queries(&nodes_dataset, &queries_dataset));
+
+    // Anomalies of a kind are logged once, with the first of them.
+    let mut kinds: Vec<(Anomaly, usize)> = Vec::new();
+    for anomaly in anomalies {
+        match kinds
+            .iter_mut()
+            .find(|(first, _)| mem::discriminant(first) == mem::discriminant(&anomaly))
+        {
+            Some((_, count)) => *count += 1,
+            None => kinds.push((anomaly, 1)),
+        }
+    }
+    let mut errors = 0;
+    for (first, count) in &kinds {
+        if first.is_error() {
+            errors += count;
+            error!(count, first = %first, "invalid values");
+        } else {
+            warn!(count, first = %first, "suspicious values");
+        }
+    }
+    if errors > 0 {
+        return Err(format!("{} values make the datasets invalid", errors).into());
+    }
+    info!(
+        nodes = nodes_dataset.num_vectors,
+        queries = queries_dataset.num_queries,
+        warnings = kinds.iter().map(|(_, count)| count).sum::<usize>(),
+        "datasets are valid"
+    );
+    Ok(())
+}
+
+/// Returns whether a dataset file is read in the contest binary format.
+fn in_binary_dataset_format(path: &Path) -> bool {
+    !matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("fvecs" | "bvecs" | "txt" | "hdf5" | "npy" | "npz" | "parquet" | "db" | "sqlite")
+    )
+}
+
+/// Fails when a dataset read from a format recording the dimensionality of
+/// its vectors does not have the given one.
+fn check_dimensions(
+    path: &Path,
+    rows: u32,
+    read: usize,
+    dimensions: Option<usize>,
+) -> glasshouse::error::Result<()> {
+    match dimensions {
+        Some(dimensions) if rows > 0 && read != dimensions => {
+            Err(glasshouse::error::GlasshouseError::Malformed(format!(
+                "{} holds vectors of {} dimensions, expected {}",
+                path.display(),
+                read,
+                dimensions
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Reads a nodes dataset split across several files of any format, the
+/// nodes of each shard follow those of the previous ones.
+fn read_node_shards(
+    shards: &[PathBuf],
+    dimensions: Option<usize>,
+) -> glasshouse::error::Result<NodesDataset> {
+    let mut nodes_dataset = NodesDataset::default();
+    for shard in shards {
+        nodes_dataset
+            .append(read_nodes_file(shard, dimensions)?)
+            .map_err(|e| {
+                glasshouse::error::GlasshouseError::Malformed(format!(
+                    "Shard {}: {}",
+                    shard.display(),
+                    e
+                ))
+            })?;
+    }
+    Ok(nodes_dataset)
+}
+
+/// Reads a nodes dataset in the format given by the extension, the
+/// contest binary format otherwise.
+fn read_nodes_file(
+    path: &Path,
+    dimensions: Option<usize>,
+) -> glasshouse::error::Result<NodesDataset> {
+    let nodes_dataset = match path.extension().and_then(|e| e.to_str()) {
+        Some("fvecs") => NodesDataset::read_fvecs(path),
+        Some("bvecs") => NodesDataset::read_bvecs(path),
+        Some("txt") => NodesDataset::read_text(path),
+        #[cfg(feature = "hdf5")]
+        Some("hdf5") => NodesDataset::read_hdf5(path),
+        #[cfg(feature = "npy")]
+        Some("npy") => NodesDataset::read_npy(path),
+        #[cfg(feature = "npy")]
+        Some("npz") => NodesDataset::read_npz(path),
+        #[cfg(feature = "parquet")]
+        Some("parquet") => NodesDataset::read_parquet(path),
+        #[cfg(feature = "sqlite")]
+        Some("db" | "sqlite") => NodesDataset::read_sqlite(path, "nodes"),
+        _ => NodesDataset::read_with_progress(path, dimensions, &ConsoleProgress::new()),
+    }?;
+    check_dimensions(
+        path,
+        nodes_dataset.num_vectors,
+        nodes_dataset.dimensions,
+        dimensions,
+    )?;
+    Ok(nodes_dataset)
+}
+
+/// Reads a queries dataset in the format given by the extension, the
+/// contest binary format otherwise.
+fn read_queries_file(
+    path: &Path,
+    dimensions: Option<usize>,
+) -> glasshouse::error::Result<QueriesDataset> {
+    let queries_dataset = match path.extension().and_then(|e| e.to_str()) {
+        Some("fvecs") => QueriesDataset::read_fvecs(path),
+        Some("bvecs") => QueriesDataset::read_bvecs(path),
+        Some("txt") => QueriesDataset::read_text(path),
+        #[cfg(feature = "hdf5")]
+        Some("hdf5") => QueriesDataset::read_hdf5(path),
+        #[cfg(feature = "npy")]
+        Some("npy") => QueriesDataset::read_npy(path),
+        #[cfg(feature = "npy")]
+        Some("npz") => QueriesDataset::read_npz(path),
+        #[cfg(feature = "parquet")]
+        Some("parquet") => QueriesDataset::read_parquet(path),
+        _ => QueriesDataset::read_with_progress(path, dimensions, &ConsoleProgress::new()),
+    }?;
+    check_dimensions(
+        path,
+        queries_dataset.num_queries,
+        queries_dataset.dimensions,
+        dimensions,
+    )?;
+    Ok(queries_dataset)
+}
+
+/// Builds the configured index and logs its parameters, returns `None`
+/// for solvers scanning the nodes without an index.
+fn build_index(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+) -> Result<Option<Index>, Box<dyn Error>> {
+    let span = info_span!(
+        "build",
+        solver = ?config.solver,
+        nodes = nodes_dataset.num_vectors,
+        bytes = Empty
+    )
+    .entered();
+    let build_start_time = Instant::now();
+    let index = match config.solver {
+        SolverKind::Baseline | SolverKind::Exact => return Ok(None),
+        SolverKind::Ivf => {
+            let ivf = config.on_build_threads(|| {
+                config
+                    .ivf_builder()
+                    .build_with_progress(nodes_dataset, &ConsoleProgress::new())
+            })?;
+            info!(
+                nlist = ivf.nlist(),
+                nprobe = ivf.nprobe(),
+                multi_probe = ivf.multi_probe(),
+                adaptive = ivf.adaptive(),
+                "built IVF index"
+            );
+            Index::Ivf(ivf)
+        }
+        SolverKind::Hnsw => {
+            let hnsw = config.on_build_threads(|| {
+                config
+                    .hnsw_builder()
+                    .build_with_progress(nodes_dataset, &ConsoleProgress::new())
+            })?;
+            info!(
+                m = hnsw.m(),
+                ef_construction = hnsw.ef_construction(),
+                ef_search = hnsw.ef_search(),
+                adaptive = hnsw.adaptive(),
+                levels = hnsw.max_level() + 1,
+                "built HNSW index"
+            );
+            Index::Hnsw(hnsw)
+        }
+    };
+    span.record("bytes", index.memory_bytes());
+    info!(elapsed = ?build_start_time.elapsed(), "built index");
+    Ok(Some(index))
+}
+
+/// Builds the configured solver and logs its parameters.
+fn build_solver(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+) -> Result<Box<dyn Solver>, Box<dyn Error>> {
+    if config.pca.dimensions > 0 {
+        return build_reduced(config, nodes_dataset);
+    }
+    info!(solver = ?config.solver, k = config.k(), "building solver");
+
+    match config.solver {
+        SolverKind::Baseline => {
+            let span = info_span!(
+                "build",
+                solver = ?config.solver,
+                nodes = nodes_dataset.num_vectors,
+                bytes = Empty
+            )
+            .entered();
+            let baseline =
+                config.on_build_threads(|| config.baseline_builder().build(nodes_dataset))?;
+            span.record("bytes", baseline.memory_bytes());
+            info!(
+                sample_proportion = config.baseline.sample_proportion,
+                sampled_points = baseline.num_to_sample(),
+                "built baseline solver"
+            );
+            Ok(Box::new(baseline))
+        }
+        SolverKind::Exact => Ok(solvers::exact(config)?),
+        SolverKind::Ivf | SolverKind::Hnsw => Ok(Box::new(
+            build_index(config, nodes_dataset)?.expect("indexed solvers always build an index"),
+        )),
+    }
+}
+
+/// Projects the nodes on their principal components and builds the solver
+/// over the projected vectors, reranking its candidates with the full ones.
+fn build_reduced(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+) -> Result<Box<dyn Solver>, Box<dyn Error>> {
+    let span = info_span!("reduce", dimensions = config.pca.dimensions, bytes = Empty).entered();
+    let reduce_start_time = Instant::now();
+    info!(
+        solver = ?config.solver,
+        dimensions = config.pca.dimensions,
+        sample_size = config.pca.sample_size,
+        "training PCA projection"
+    );
+    let reduced = solvers::build_reduced(config, nodes_dataset, &ConsoleProgress::new())?;
+    span.record("bytes", reduced.memory_bytes());
+    info!(
+        dimensions = reduced.pca().dimensions(),
+        explained_variance = reduced.pca().explained_variance(),
+        elapsed = ?reduce_start_time.elapsed(),
+        "built reduced solver"
     );
+    Ok(Box::new(reduced))
+}
+
+/// Loads a saved index, search-time parameters are taken from the configuration.
+fn load_index(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+    index_path: &Path,
+) -> Result<Box<dyn Solver>, Box<dyn Error>> {
+    let span = info_span!("load", dataset = "index", bytes = Empty).entered();
+    let load_start_time = Instant::now();
+    info!(path = %index_path.display(), "loading index");
+    let mut index = Index::load(index_path, nodes_dataset, config.metric)
+        .map_err(|e| format!("Failed to load index: {}", e))?;
+    match &mut index {
+        Index::Ivf(ivf) => {
+            ivf.set_nprobe(config.ivf.nprobe);
+            ivf.set_multi_probe(config.ivf.multi_probe);
+            ivf.set_adaptive(config.ivf.adaptive);
+            info!(
+                nlist = ivf.nlist(),
+                nprobe = ivf.nprobe(),
+                multi_probe = ivf.multi_probe(),
+                adaptive = ivf.adaptive(),
+                "loaded IVF index"
+            );
+        }
+        Index::Hnsw(hnsw) => {
+            hnsw.set_ef_search(config.hnsw.ef_search);
+            hnsw.set_adaptive(config.hnsw.adaptive);
+            info!(
+                m = hnsw.m(),
+                ef_search = hnsw.ef_search(),
+                adaptive = hnsw.adaptive(),
+                "loaded HNSW index"
+            );
+        }
+    }
+    span.record("bytes", index.memory_bytes());
+    info!(elapsed = ?load_start_time.elapsed(), "loaded index");
+    Ok(Box::new(index))
+}
+
+fn build(mut config: Config, args: BuildArgs) -> Result<(), Box<dyn Error>> {
+    set_nodes(&mut config, args.nodes);
+    if let Some(solver) = args.solver {
+        config.solver = solver;
+    }
+    if config.pca.dimensions > 0 {
+        return Err("Indexes over PCA projected vectors are not saved, search builds them".into());
+    }
+
+    let nodes_dataset = read_nodes(&config)?;
+    let index = build_index(&config, &nodes_dataset)?
+        .ok_or_else(|| format!("The {:?} solver does not build an index", config.solver))?;
+
+    if let Some(index_path) = args.save {
+        let span = info_span!("write", output = "index", bytes = Empty).entered();
+        let save_start_time = Instant::now();
+        info!(path = %index_path.display(), "saving index");
+        index.save(&index_path, &nodes_dataset, config.metric)?;
+        if let Ok(metadata) = fs::metadata(&index_path) {
+            span.record("bytes", metadata.len());
+        }
+        info!(elapsed = ?save_start_time.elapsed(), "saved index");
+    }
+    if let Some(graph_path) = args.graph {
+        let Index::Hnsw(hnsw) = &index else {
+            return Err(format!("The {:?} index is not a graph", config.solver).into());
+        };
+        hnsw.write_graph(&nodes_dataset, &graph_path)?;
+        info!(path = %graph_path.display(), "exported index graph");
+    }
+    if let Some(disk_path) = args.disk {
+        let Index::Hnsw(hnsw) = &index else {
+            return Err(format!("The {:?} index is not a graph", config.solver).into());
+        };
+        let span = info_span!("write", output = "disk_index", bytes = Empty).entered();
+        let write_start_time = Instant::now();
+        info!(path = %disk_path.display(), "writing disk index");
+        DiskIndex::write(&disk_path, hnsw, &nodes_dataset, config.metric)?;
+        if let Ok(metadata) = fs::metadata(&disk_path) {
+            span.record("bytes", metadata.len());
+        }
+        info!(elapsed = ?write_start_time.elapsed(), "wrote disk index");
+    }
+    #[cfg(feature = "mmap")]
+    if let Some(mapped_path) = args.mapped {
+        let Index::Hnsw(hnsw) = &index else {
+            return Err(format!("The {:?} index is not a graph", config.solver).into());
+        };
+        let span = info_span!("write", output = "mapped_index", bytes = Empty).entered();
+        let write_start_time = Instant::now();
+        info!(path = %mapped_path.display(), "writing mapped index");
+        solvers::MappedHnsw::write(&mapped_path, hnsw, &nodes_dataset, config.metric)?;
+        if let Ok(metadata) = fs::metadata(&mapped_path) {
+            span.record("bytes", metadata.len());
+        }
+        info!(elapsed = ?write_start_time.elapsed(), "wrote mapped index");
+    }
+    log_memory(&memory::report(
+        &nodes_dataset,
+        &QueriesDataset::default(),
+        &index,
+    ));
+    Ok(())
+}
+
+fn search(
+    mut config: Config,
+    args: SearchArgs,
+    token: &CancellationToken,
+    budget: Option<&Budget>,
+) -> Result<(), Box<dyn Error>> {
+    set_nodes(&mut config, args.nodes);
+    if let Some(path) = args.queries {
+        config.paths.queries = path;
+    }
+    if let Some(path) = args.output {
+        config.paths.output = path;
+    }
+    if let Some(solver) = args.solver {
+        config.solver = solver;
+    }
+    check_checksum_format(args.checksum, &config.paths.output, args.output_format)?;
+    if args.stream && !in_contest_format(&config.paths.output, args.output_format) {
+        return Err("--stream only applies to results in the contest format".into());
+    }
+    if args.validate_only {
+        return validate_datasets(&config);
+    }
+    if args.checkpoint.is_some() {
+        if config.solver != SolverKind::Exact {
+            return Err("--checkpoint only applies to the exact solver".into());
+        }
+        if token.deadline().is_some() {
+            return Err("--checkpoint does not apply to runs with a deadline or a budget".into());
+        }
+    }
+    if !args.remote.is_empty() {
+        if budget.is_some() {
+            return Err("--budget does not apply to --remote".into());
+        }
+        return search_remote(
+            config,
+            &args.remote,
+            args.remote_batch,
+            args.output_format,
+            args.ground_truth.as_deref(),
+        );
+    }
+    if let Some(workers) = args.workers {
+        if budget.is_some() {
+            return Err("--budget does not apply to --workers, use --deadline".into());
+        }
+        return search_sharded(
+            config,
+            workers,
+            args.output_format,
+            args.ground_truth.as_deref(),
+            token,
+        );
+    }
+
+    let mut report = RunReport::new(&config);
+    let start_time = Instant::now();
+    #[cfg(feature = "mmap")]
+    let mapped_index = args.mapped_index.as_deref();
+    #[cfg(not(feature = "mmap"))]
+    let mapped_index: Option<&Path> = None;
+    let (nodes_dataset, solver) = match (args.reservoir, &args.disk_index, mapped_index) {
+        #[cfg(feature = "mmap")]
+        (_, _, Some(mapped_path)) => {
+            let opened = open_mapped_index(&config, mapped_path)?;
+            report.phase("load_index", start_time.elapsed());
+            opened
+        }
+        (_, Some(disk_path), _) => {
+            let opened = open_disk_index(&config, disk_path)?;
+            report.phase("load_index", start_time.elapsed());
+            opened
+        }
+        (Some(capacity), None, _) => {
+            let sampled = read_reservoir(&config, capacity)?;
+            report.phase("load_nodes", start_time.elapsed());
+            sampled
+        }
+        (None, None, _) => {
+            let nodes_dataset = read_nodes(&config)?;
+            report.phase("load_nodes", start_time.elapsed());
+            let start_time = Instant::now();
+            let (phase, solver) = match &args.index {
+                Some(index_path) => (
+                    "load_index",
+                    load_index(&config, &nodes_dataset, index_path)?,
+                ),
+                None => ("build", build_solver(&config, &nodes_dataset)?),
+            };
+            report.phase(phase, start_time.elapsed());
+            (nodes_dataset, solver)
+        }
+    };
+    let start_time = Instant::now();
+    let queries_dataset = read_queries(&config)?;
+    report.phase("load_queries", start_time.elapsed());
+    report.datasets(&nodes_dataset, &queries_dataset);
+    let distinct = args.dedup_queries.then(|| {
+        let (distinct, rows) = queries_dataset.dedup();
+        info!(
+            queries = queries_dataset.num_queries,
+            distinct = distinct.num_queries,
+            "dropped duplicate queries"
+        );
+        (distinct, rows)
+    });
+    let searched = distinct
+        .as_ref()
+        .map_or(&queries_dataset, |(distinct, _)| distinct);
+    let mut solver = solvers::with_scoring(&config, solver)?;
+    if let Some(budget) = budget {
+        let span =
+            info_span!("plan", queries = searched.num_queries, degradations = Empty).entered();
+        let start_time = Instant::now();
+        let available = budget.search_time_left();
+        let fit = budget::fit(
+            solver.as_mut(),
+            &nodes_dataset,
+            searched,
+            config.k(),
+            available,
+        )?;
+        span.record("degradations", fit.degradations);
+        report.phase("plan", start_time.elapsed());
+        if fit.degradations > 0 {
+            warn!(
+                degradations = fit.degradations,
+                estimate = ?fit.estimate,
+                available = ?available,
+                "lowered the search effort to fit the budget"
+            );
+        } else {
+            info!(estimate = ?fit.estimate, available = ?available, "search fits the budget");
+        }
+    }
+
+    if args.explain {
+        let _span = info_span!("plan", queries = searched.num_queries).entered();
+        explain(solver.as_ref(), &nodes_dataset, searched, config.k());
+        return Ok(());
+    }
+    log_plans(solver.as_ref(), &nodes_dataset, searched, config.k());
+
+    if let Some(queries) = args.warm_up {
+        let _span = info_span!("warm-up").entered();
+        let warm_up = warmup::warm_up(
+            solver.as_ref(),
+            &nodes_dataset,
+            searched,
+            queries,
+            config.k(),
+        )?;
+        info!(
+            pages = warm_up.pages,
+            queries = warm_up.queries,
+            elapsed = ?warm_up.elapsed,
+            "warmed up"
+        );
+        report.phase("warm_up", warm_up.elapsed);
+    }
+
+    #[cfg(feature = "profiling")]
+    let profiler = start_profiler();
+    if args.stream {
+        let start_time = Instant::now();
+        stream_results(
+            solver.as_ref(),
+            &nodes_dataset,
+            searched,
+            &config.paths.output,
+            config.pad_id(),
+            config.k(),
+            token,
+        )?;
+        report.phase("search", start_time.elapsed());
+        #[cfg(feature = "profiling")]
+        write_flamegraph(profiler, &config.paths.output);
+        let memory = memory::report(&nodes_dataset, &queries_dataset, solver.as_ref());
+        log_memory(&memory);
+        report.memory(&memory);
+        return write_report(&report, &config.paths.output);
+    }
+
+    let live_truth = match (args.live_recall, &args.ground_truth) {
+        (Some(_), Some(path)) => Some(read_results(path, config.pad_id(), config.k())?),
+        _ => None,
+    };
+    let live_recall = live_truth
+        .as_deref()
+        .map(|truth| {
+            let live_recall =
+                eval::LiveRecall::new(truth, &queries_dataset.query_types, config.pad_id())?;
+            match &distinct {
+                Some((_, rows)) => live_recall.with_rows(rows),
+                None => Ok(live_recall),
+            }
+        })
+        .transpose()?;
+    let live_logged = Mutex::new(Instant::now());
+
+    // Run the configured solver.
+    let results = {
+        let span = info_span!(
+            "search",
+            queries = searched.num_queries,
+            answered = Empty,
+            cached = Empty
+        )
+        .entered();
+        let algo_start_time = Instant::now();
+        info!("running solver");
+        let partial = match &args.checkpoint {
+            Some(checkpoint_path) => {
+                let results = solvers::run_checkpointed(
+                    solver.as_ref(),
+                    &nodes_dataset,
+                    searched,
+                    read_checkpoint(
+                        &nodes_dataset,
+                        searched,
+                        config.k(),
+                        checkpoint_path,
+                        args.resume,
+                    )?,
+                    checkpoint_path,
+                    args.checkpoint_interval,
+                    &ConsoleProgress::new(),
+                )?;
+                // Checkpointed runs answer every query but do not time them.
+                solvers::PartialResults {
+                    answered: results.len() as u32,
+                    cached: 0,
+                    latencies: vec![None; results.len()],
+                    results,
+                }
+            }
+            None => solvers::run_observed(
+                solver.as_ref(),
+                &nodes_dataset,
+                searched,
+                config.k(),
+                !args.no_query_cache,
+                &ConsoleProgress::new(),
+                token,
+                |index, result| {
+                    if let Some(live_recall) = &live_recall {
+                        live_recall.record(
+                            index,
+                            &solvers::padded_ids(result, config.k(), config.pad_id()),
+                        );
+                        let interval = Duration::from_secs(args.live_recall.unwrap_or_default());
+                        log_live_recall(live_recall, &live_logged, interval);
+                    }
+                },
+            )?,
+        };
+        span.record("answered", partial.answered);
+        span.record("cached", partial.cached);
+        if partial.cached > 0 {
+            info!(
+                cached = partial.cached,
+                "reused the results of identical queries"
+            );
+        }
+        let under_filled = partial.under_filled(config.k());
+        if under_filled > 0 {
+            info!(
+                queries = under_filled,
+                k = config.k(),
+                "queries with fewer than k neighbours, padded in the results"
+            );
+        }
+        if partial.answered < searched.num_queries {
+            warn!(
+                answered = partial.answered,
+                queries = searched.num_queries,
+                "deadline reached, unanswered queries are padded"
+            );
+        }
+        info!(elapsed = ?algo_start_time.elapsed(), "solver completed");
+        report.phase("search", algo_start_time.elapsed());
+        let latencies = latency::report(&partial.latencies, &searched.query_types);
+        report.latencies(&latencies);
+        if let Some(overall) = latencies.overall {
+            log_latencies("all", &overall);
+        }
+        for (query_type, percentiles) in &latencies.by_type {
+            log_latencies(&format!("{:?}", query_type), percentiles);
+        }
+        for (query_type, under_filled) in
+            partial.under_filled_by_type(config.k(), &searched.query_types)
+        {
+            info!(
+                query_type = ?query_type,
+                queries = under_filled,
+                "queries with fewer than k neighbours"
+            );
+        }
+        match &distinct {
+            Some((_, rows)) => rows
+                .iter()
+                .map(|&row| partial.results[row as usize].clone())
+                .collect(),
+            None => partial.results,
+        }
+    };
+    #[cfg(feature = "profiling")]
+    write_flamegraph(profiler, &config.paths.output);
 
     // Write results to disk.
+    let span = info_span!(
+        "write",
+        output = "results",
+        results = results.len(),
+        bytes = Empty
+    )
+    .entered();
     let save_start_time = Instant::now();
-    println!("[*] Writing results to {}", knn_save_path);
-    let _ = io::write(&baseline_results, knn_save_path);
-    println!("[*] Writing results took {:?}", save_start_time.elapsed());
+    let knn_save_path = &config.paths.output;
+    info!(path = %knn_save_path.display(), "writing results");
+    write_scored_results(
+        &results,
+        &queries_dataset,
+        knn_save_path,
+        args.output_format,
+        config.metric,
+        config.pad_id(),
+        config.k(),
+    )?;
+    if args.checksum {
+        append_checksums(
+            &results,
+            &nodes_dataset,
+            &queries_dataset,
+            knn_save_path,
+            config.pad_id(),
+            config.k(),
+        )?;
+    }
+    if let Ok(metadata) = fs::metadata(knn_save_path) {
+        span.record("bytes", metadata.len());
+    }
+    info!(elapsed = ?save_start_time.elapsed(), "wrote results");
+    report.phase("write", save_start_time.elapsed());
+    drop(span);
+    if let Some(checkpoint_path) = &args.checkpoint {
+        remove_checkpoint(checkpoint_path)?;
+    }
+    if let Some(fraction) = args.estimate_recall {
+        let start_time = Instant::now();
+        let estimate = estimate_recall(
+            &config,
+            &nodes_dataset,
+            &queries_dataset,
+            &results,
+            fraction,
+        )?;
+        report.phase("estimate_recall", start_time.elapsed());
+        report.estimated_recall(&estimate);
+    }
+    if let Some(path) = &args.ground_truth {
+        let start_time = Instant::now();
+        let recall = measure_recall(&config, path, &queries_dataset, &results)?;
+        report.phase("recall", start_time.elapsed());
+        report.recall(&recall);
+    }
+    let memory = memory::report(&nodes_dataset, &queries_dataset, solver.as_ref());
+    log_memory(&memory);
+    report.memory(&memory);
+    write_report(&report, &config.paths.output)
+}
 
-    let total_duration = program_start_time.elapsed();
-    println!("[*] Total runtime was {:?}", total_duration);
+/// Answers the queries with one worker process per partition of the nodes
+/// and writes the merged results. The workers run this executable with the
+/// configuration of the run, sharing the worker threads between them unless
+/// their number is configured.
+fn search_sharded(
+    mut config: Config,
+    workers: usize,
+    output_format: OutputFormat,
+    ground_truth: Option<&Path>,
+    token: &CancellationToken,
+) -> Result<(), Box<dyn Error>> {
+    if workers == 0 {
+        return Err("--workers must be at least 1".into());
+    }
+    if !config.paths.node_shards.is_empty() {
+        return Err("--workers partitions a single nodes file".into());
+    }
+    let mut report = RunReport::new(&config);
+    let start_time = Instant::now();
+    let queries_dataset = read_queries(&config)?;
+    report.phase("load_queries", start_time.elapsed());
+    if config.threads == 0 {
+        let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        config.threads = cores.div_ceil(workers);
+    }
+    let dir =
+        WorkersDir(std::env::temp_dir().join(format!("glasshouse-workers-{}", process::id())));
+    fs::create_dir_all(&dir.0)?;
+    let config_path = dir.0.join("config.toml");
+    fs::write(&config_path, toml::to_string(&config)?)?;
+    let outputs: Vec<PathBuf> = (0..workers)
+        .map(|part| dir.0.join(format!("part-{}.bin", part)))
+        .collect();
+
+    let span = info_span!("search", workers, queries = queries_dataset.num_queries).entered();
+    let search_start_time = Instant::now();
+    info!(workers, threads = config.threads, "starting workers");
+    let executable = std::env::current_exe()?;
+    let mut children: Vec<process::Child> = Vec::with_capacity(workers);
+    for (part, output) in outputs.iter().enumerate() {
+        let mut command = process::Command::new(&executable);
+        command.arg("--config").arg(&config_path).arg("--quiet");
+        // The workers stop searching at the deadline of the run and write
+        // the results they have so far.
+        if let Some(deadline) = token.deadline() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            command.args(["--deadline", &left.as_secs_f64().to_string()]);
+        }
+        let spawned = command
+            .arg("shard")
+            .args(["--part", &part.to_string(), "--parts", &workers.to_string()])
+            .arg("--output")
+            .arg(output)
+            .spawn();
+        match spawned {
+            Ok(child) => children.push(child),
+            Err(e) => {
+                stop_workers(&mut children);
+                return Err(format!("Failed to start worker {}: {}", part, e).into());
+            }
+        }
+    }
+    // Poll the workers rather than wait for each in turn, so that the run
+    // stops them as soon as one fails, or once they were given time to
+    // write their results past the deadline.
+    let mut statuses = vec![None; workers];
+    let mut cancelled_at = None;
+    while statuses.iter().any(Option::is_none) {
+        for (part, child) in children.iter_mut().enumerate() {
+            if statuses[part].is_some() {
+                continue;
+            }
+            statuses[part] = match child.try_wait() {
+                Ok(status) => status,
+                Err(e) => {
+                    stop_workers(&mut children);
+                    return Err(e.into());
+                }
+            };
+            if let Some(status) = statuses[part].filter(|status| !status.success()) {
+                stop_workers(&mut children);
+                return Err(format!("Worker {} failed: {}", part, status).into());
+            }
+        }
+        if token.is_cancelled() {
+            let cancelled_at = *cancelled_at.get_or_insert_with(Instant::now);
+            if cancelled_at.elapsed() >= WORKERS_GRACE {
+                stop_workers(&mut children);
+                break;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    info!(elapsed = ?search_start_time.elapsed(), "workers completed");
+    report.phase("search", search_start_time.elapsed());
+    drop(span);
+
+    // Workers stopped past the deadline answered none of the queries.
+    let merge_start_time = Instant::now();
+    let num_queries = queries_dataset.num_queries as usize;
+    let mut partitions = Vec::with_capacity(workers);
+    let mut answered = Vec::with_capacity(workers);
+    for (output, status) in outputs.iter().zip(&statuses) {
+        if status.is_some() {
+            partitions.push(io::read_scored(output, config.k(), config.pad_id())?);
+            answered.push(read_answered(output, num_queries)?);
+        } else {
+            partitions.push(vec![Vec::new(); num_queries]);
+            answered.push(Vec::new());
+        }
+    }
+    let mut results = shard::merge(partitions, config.k())?;
+    let complete = shard::drop_unanswered(&mut results, &answered);
+    drop(dir);
+    if complete < queries_dataset.num_queries {
+        warn!(
+            answered = complete,
+            queries = queries_dataset.num_queries,
+            "deadline reached, unanswered queries are padded"
+        );
+    }
+    info!(elapsed = ?merge_start_time.elapsed(), "merged worker results");
+    report.phase("merge", merge_start_time.elapsed());
+    write_merged(
+        &config,
+        &results,
+        &queries_dataset,
+        output_format,
+        ground_truth,
+        report,
+    )
+}
+
+/// Writes the results merged from the workers of a run, then measures
+/// their recall against the ground truth and writes the run report.
+fn write_merged(
+    config: &Config,
+    results: &ScoredResults,
+    queries_dataset: &QueriesDataset,
+    output_format: OutputFormat,
+    ground_truth: Option<&Path>,
+    mut report: RunReport,
+) -> Result<(), Box<dyn Error>> {
+    let save_start_time = Instant::now();
+    info!(path = %config.paths.output.display(), "writing results");
+    write_scored_results(
+        results,
+        queries_dataset,
+        &config.paths.output,
+        output_format,
+        config.metric,
+        config.pad_id(),
+        config.k(),
+    )?;
+    info!(elapsed = ?save_start_time.elapsed(), "wrote results");
+    report.phase("write", save_start_time.elapsed());
+    if let Some(path) = ground_truth {
+        let start_time = Instant::now();
+        let recall = measure_recall(config, path, queries_dataset, results)?;
+        report.phase("recall", start_time.elapsed());
+        report.recall(&recall);
+    }
+    write_report(&report, &config.paths.output)
+}
+
+/// Answers the queries with the `serve-shard` workers listening on the
+/// addresses, which hold a partition of the nodes each, and writes the
+/// merged results.
+fn search_remote(
+    config: Config,
+    addrs: &[String],
+    batch: usize,
+    output_format: OutputFormat,
+    ground_truth: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let mut report = RunReport::new(&config);
+    let start_time = Instant::now();
+    let queries_dataset = read_queries(&config)?;
+    report.phase("load_queries", start_time.elapsed());
+    let mut shards = addrs
+        .iter()
+        .map(|addr| {
+            RemoteShard::connect(addr)
+                .map_err(|e| format!("Failed to connect to worker {}: {}", addr, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let span = info_span!(
+        "search",
+        workers = shards.len(),
+        queries = queries_dataset.num_queries
+    )
+    .entered();
+    let search_start_time = Instant::now();
+    info!(workers = shards.len(), batch, "sending queries to workers");
+    let results = remote::search(
+        &mut shards,
+        &queries_dataset,
+        config.k(),
+        batch,
+        &ConsoleProgress::new(),
+    )?;
+    info!(elapsed = ?search_start_time.elapsed(), "workers completed");
+    report.phase("search", search_start_time.elapsed());
+    drop(span);
+    write_merged(
+        &config,
+        &results,
+        &queries_dataset,
+        output_format,
+        ground_truth,
+        report,
+    )
+}
+
+/// Serves a partition of the nodes to the coordinators of `search
+/// --remote` until the process is stopped.
+fn serve_shard(mut config: Config, args: ServeShardArgs) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = args.nodes {
+        config.paths.nodes = path;
+    }
+    if let Some(solver) = args.solver {
+        config.solver = solver;
+    }
+    let (nodes_dataset, first_id) = read_partition(&config, args.part, args.parts)?;
+    let solver = solvers::with_scoring(&config, build_solver(&config, &nodes_dataset)?)?;
+    let listener = std::net::TcpListener::bind(args.addr)?;
+    info!(addr = %args.addr, part = args.part, parts = args.parts, "serving partition");
+    ShardServer::new(
+        nodes_dataset,
+        solver,
+        first_id,
+        args.part as u32,
+        args.parts as u32,
+    )
+    .serve(listener)?;
+    Ok(())
+}
+
+/// Reads a partition of the nodes file, returning it with the ID of its
+/// first node in the file.
+fn read_partition(
+    config: &Config,
+    part: usize,
+    parts: usize,
+) -> Result<(NodesDataset, u32), Box<dyn Error>> {
+    if part >= parts {
+        return Err(format!("Partition {} of {} does not exist", part, parts).into());
+    }
+    let load_start_time = Instant::now();
+    let span = info_span!("load", dataset = "nodes", part, nodes = Empty).entered();
+    let partition = if in_binary_dataset_format(&config.paths.nodes) {
+        NodesDataset::read_partition(
+            &config.paths.nodes,
+            config.dimensions,
+            part,
+            parts,
+            &ConsoleProgress::new(),
+        )
+    } else {
+        // Other formats cannot skip rows, the partition is kept from the
+        // whole dataset.
+        read_nodes_file(&config.paths.nodes, config.dimensions).and_then(|nodes_dataset| {
+            let range = shard::partition(nodes_dataset.num_vectors, part, parts);
+            let (start, end) = (range.start as usize, range.end as usize);
+            let dimensions = nodes_dataset.dimensions;
+            let part = NodesDataset::from_parts(
+                dimensions,
+                nodes_dataset.c_attrs[start..end].to_vec(),
+                nodes_dataset.t_attrs[start..end].to_vec(),
+                nodes_dataset.vectors[start * dimensions..end * dimensions].to_vec(),
+            )?;
+            Ok((part, range.start))
+        })
+    };
+    let (mut nodes_dataset, first_id) =
+        partition.map_err(|e| format!("Failed to load nodes partition: {}", e))?;
+    if config.check_finite {
+        nodes_dataset
+            .check_finite()
+            .map_err(|e| format!("Invalid nodes dataset: {}", e))?;
+    }
+    normalize_nodes(config, &mut nodes_dataset);
+    span.record("nodes", nodes_dataset.num_vectors);
+    info!(
+        first_id,
+        nodes = nodes_dataset.num_vectors,
+        elapsed = ?load_start_time.elapsed(),
+        "loaded nodes partition"
+    );
+    Ok((nodes_dataset, first_id))
+}
+
+/// Temporary directory of the workers of a run, removed with the files
+/// they wrote once the run ends, whether it succeeds or not.
+struct WorkersDir(PathBuf);
+
+impl Drop for WorkersDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Kills the workers still running, and waits for them to exit.
+fn stop_workers(children: &mut [process::Child]) {
+    for child in children {
+        if let Ok(None) = child.try_wait() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Answers the queries over a partition of the nodes, as a worker of
+/// [`search_sharded`]. When the deadline is reached the results computed so
+/// far are written, next to the queries they answer, see [`read_answered`].
+fn search_shard(
+    config: Config,
+    args: ShardArgs,
+    token: &CancellationToken,
+) -> Result<(), Box<dyn Error>> {
+    let (nodes_dataset, first_id) = read_partition(&config, args.part, args.parts)?;
+    let solver = solvers::with_scoring(&config, build_solver(&config, &nodes_dataset)?)?;
+    let queries_dataset = read_queries(&config)?;
+    let partial = solvers::run_cancellable(
+        solver.as_ref(),
+        &nodes_dataset,
+        &queries_dataset,
+        config.k(),
+        true,
+        &ConsoleProgress::new(),
+        token,
+    )?;
+    if partial.answered < queries_dataset.num_queries {
+        warn!(
+            answered = partial.answered,
+            queries = queries_dataset.num_queries,
+            "deadline reached, unanswered queries are padded"
+        );
+    }
+    let answered: Vec<u8> = partial
+        .latencies
+        .iter()
+        .map(|latency| latency.is_some() as u8)
+        .collect();
+    let mut results = partial.results;
+    shard::offset(&mut results, first_id);
+    io::write_scored(&results, &args.output, config.k(), config.pad_id())?;
+    fs::write(answered_path(&args.output), answered)?;
+    Ok(())
+}
+
+/// Returns the path of the file listing the queries answered by a worker,
+/// one byte per query set to 1 when it was answered before the deadline.
+fn answered_path(output: &Path) -> PathBuf {
+    output.with_extension("answered")
+}
+
+/// Reads whether each query was answered by the worker writing `output`.
+fn read_answered(output: &Path, num_queries: usize) -> Result<Vec<bool>, Box<dyn Error>> {
+    let bytes = fs::read(answered_path(output))?;
+    if bytes.len() != num_queries {
+        return Err(format!(
+            "Worker answered {} queries but the run holds {}",
+            bytes.len(),
+            num_queries
+        )
+        .into());
+    }
+    Ok(bytes.into_iter().map(|answered| answered == 1).collect())
+}
+
+/// Writes the report of the run next to its output file.
+fn write_report(report: &RunReport, output: &Path) -> Result<(), Box<dyn Error>> {
+    let path = report::report_path(output);
+    report.write(&path)?;
+    info!(path = %path.display(), "wrote run report");
+    Ok(())
+}
+
+/// Logs the recall of the results against the ground truth, over all
+/// queries and over each query type.
+fn measure_recall(
+    config: &Config,
+    ground_truth_path: &Path,
+    queries_dataset: &QueriesDataset,
+    results: &ScoredResults,
+) -> Result<eval::RecallReport, Box<dyn Error>> {
+    let _span = info_span!("recall").entered();
+    let (pad_id, k) = (config.pad_id(), config.k());
+    let ground_truth = read_results(ground_truth_path, pad_id, k)?;
+    let recall = eval::recall_by_type(
+        &result_ids(results, pad_id, k),
+        &ground_truth,
+        &queries_dataset.query_types,
+        pad_id,
+    )?;
+    info!(
+        queries = recall.overall.queries,
+        recall = recall.overall.recall,
+        under_filled = recall.overall.under_filled,
+        "recall@{} against the ground truth",
+        k
+    );
+    for (query_type, by_type) in &recall.by_type {
+        info!(
+            query_type = ?query_type,
+            queries = by_type.queries,
+            recall = by_type.recall,
+            under_filled = by_type.under_filled,
+            "recall@{} against the ground truth",
+            k
+        );
+    }
+    Ok(recall)
+}
+
+/// Logs the recall of the queries answered so far once `interval` elapsed
+/// since it was last logged.
+fn log_live_recall(live_recall: &eval::LiveRecall, logged: &Mutex<Instant>, interval: Duration) {
+    let mut logged = logged.lock().unwrap();
+    if logged.elapsed() < interval {
+        return;
+    }
+    *logged = Instant::now();
+    let recall = live_recall.report();
+    info!(
+        queries = recall.overall.queries,
+        recall = recall.overall.recall,
+        "running recall"
+    );
+    for (query_type, by_type) in &recall.by_type {
+        info!(
+            query_type = ?query_type,
+            queries = by_type.queries,
+            recall = by_type.recall,
+            "running recall"
+        );
+    }
+}
+
+/// Prints the plan of the solver for each query and the number of queries
+/// answered from the attribute indexes.
+fn explain(
+    solver: &dyn Solver,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+) {
+    let mut filter_first = 0;
+    for index in 0..queries_dataset.num_queries as usize {
+        let query = queries_dataset
+            .get(index)
+            .expect("query indices are in range");
+        let plan = solver.explain(nodes_dataset, &query, k);
+        let candidates = plan
+            .candidates
+            .map_or("no filter".to_string(), |c| format!("{} candidates", c));
+        println!(
+            "[*] Query {} {:?}: {}, selectivity {:.4}, {}, {} nodes visited",
+            index, query.query_type, plan.strategy, plan.selectivity, candidates, plan.visited
+        );
+        if plan.strategy == Strategy::FilterFirst {
+            filter_first += 1;
+        }
+    }
+    println!(
+        "[*] {} of {} queries scan the nodes passing their filters",
+        filter_first, queries_dataset.num_queries
+    );
+}
+
+/// Logs how many queries the solver answers with each strategy at the
+/// debug level, and the plan of every query at the trace level.
+fn log_plans(
+    solver: &dyn Solver,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+) {
+    if !tracing::enabled!(Level::DEBUG) {
+        return;
+    }
+    let _span = info_span!("plan", queries = queries_dataset.num_queries).entered();
+    // Strategies in the order first planned, with their number of queries
+    // and of nodes visited.
+    let mut strategies: Vec<(Strategy, u32, usize)> = Vec::new();
+    for index in 0..queries_dataset.num_queries as usize {
+        let query = queries_dataset
+            .get(index)
+            .expect("query indices are in range");
+        let plan = solver.explain(nodes_dataset, &query, k);
+        trace!(
+            query = index,
+            query_type = ?query.query_type,
+            strategy = %plan.strategy,
+            selectivity = plan.selectivity,
+            candidates = ?plan.candidates,
+            visited = plan.visited,
+            "planned query"
+        );
+        match strategies
+            .iter_mut()
+            .find(|(strategy, ..)| *strategy == plan.strategy)
+        {
+            Some((_, queries, visited)) => {
+                *queries += 1;
+                *visited += plan.visited;
+            }
+            None => strategies.push((plan.strategy, 1, plan.visited)),
+        }
+    }
+    for (strategy, queries, visited) in strategies {
+        debug!(
+            strategy = %strategy,
+            queries,
+            mean_visited = visited / queries as usize,
+            "planned queries"
+        );
+    }
+}
+
+/// Answers a random sample of the queries exactly and logs the recall of
+/// the run estimated from it.
+fn estimate_recall(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    results: &ScoredResults,
+    fraction: f64,
+) -> Result<eval::RecallEstimate, Box<dyn Error>> {
+    let _span = info_span!("estimate").entered();
+    let start_time = Instant::now();
+    let k = config.k();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let sampled = sample::indices(&mut rng, results.len(), fraction);
+    info!(queries = sampled.len(), "answering sampled queries exactly");
+    let ground_truth: Vec<_> = solvers::run_blocked(
+        nodes_dataset,
+        &sample::select_queries(queries_dataset, &sampled),
+        k,
+        &NoProgress,
+    )?
+    .iter()
+    .map(|result| solvers::ids(result, k))
+    .collect();
+    let sampled_results: Vec<_> = sampled
+        .iter()
+        .map(|&i| solvers::ids(&results[i], k))
+        .collect();
+    let estimate = eval::estimate_recall(
+        &sampled_results,
+        &ground_truth,
+        solvers::DEFAULT_PAD_ID,
+        results.len(),
+    )?;
+    info!(
+        queries = estimate.queries,
+        recall = estimate.recall,
+        lower = estimate.lower,
+        upper = estimate.upper,
+        elapsed = ?start_time.elapsed(),
+        "estimated recall@{} with a 95% confidence interval",
+        k
+    );
+    Ok(estimate)
+}
+
+/// Starts sampling the stacks of the search phase, the run goes on without
+/// a flamegraph if the profiler cannot start.
+#[cfg(feature = "profiling")]
+fn start_profiler() -> Option<profiling::Profiler> {
+    profiling::Profiler::start()
+        .inspect_err(|e| warn!("cannot start the profiler: {}", e))
+        .ok()
+}
+
+/// Writes the flamegraph of the search phase next to the output file.
+#[cfg(feature = "profiling")]
+fn write_flamegraph(profiler: Option<profiling::Profiler>, output: &Path) {
+    let Some(profiler) = profiler else {
+        return;
+    };
+    let path = profiling::flamegraph_path(output);
+    match profiler.write_flamegraph(&path) {
+        Ok(samples) => info!(path = %path.display(), samples, "wrote search flamegraph"),
+        Err(e) => warn!(path = %path.display(), "cannot write the search flamegraph: {}", e),
+    }
+}
+
+/// Answers the queries and writes their results in the contest format as
+/// they complete, in query order, overlapping the writes with the search.
+fn stream_results(
+    solver: &dyn Solver,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    path: &Path,
+    pad_id: u32,
+    k: usize,
+    token: &CancellationToken,
+) -> Result<(), Box<dyn Error>> {
+    let _span = info_span!(
+        "search",
+        queries = queries_dataset.num_queries,
+        streamed = true
+    )
+    .entered();
+    let algo_start_time = Instant::now();
+    info!(path = %path.display(), "running solver, streaming results");
+    let mut writer = io::ResultsWriter::create(path)?;
+    let mut write_error = None;
+    // Queries are no longer pulled once the deadline is reached, the
+    // answered ones always form a prefix of the dataset.
+    let queries = (0..queries_dataset.num_queries as usize)
+        .map(|index| {
+            queries_dataset
+                .get(index)
+                .expect("query indices are in range")
+        })
+        .take_while(|_| !token.is_cancelled());
+    solvers::stream(
+        solver,
+        nodes_dataset,
+        queries,
+        k,
+        Delivery::Ordered,
+        |_, mut result| {
+            if write_error.is_none() {
+                for id in result.iter_mut() {
+                    if *id == solvers::DEFAULT_PAD_ID {
+                        *id = pad_id;
+                    }
+                }
+                write_error = writer.push(&result).err();
+            }
+        },
+    );
+    if let Some(error) = write_error {
+        return Err(error.into());
+    }
+
+    let answered = writer.written();
+    if answered < queries_dataset.num_queries {
+        warn!(
+            answered,
+            queries = queries_dataset.num_queries,
+            "deadline reached, unanswered queries are padded"
+        );
+        let padding = vec![pad_id; k];
+        for _ in answered..queries_dataset.num_queries {
+            writer.push(&padding)?;
+        }
+    }
+    writer.finish()?;
+    info!(elapsed = ?algo_start_time.elapsed(), "solver completed, results written");
+    Ok(())
+}
+
+fn log_memory(report: &memory::MemoryReport) {
+    let mib = |bytes: usize| bytes as f64 / (1 << 20) as f64;
+    info!(
+        peak_rss_mib = report.peak_rss.map(|bytes| mib(bytes as usize)),
+        vectors_mib = mib(report.vectors),
+        attributes_mib = mib(report.attributes),
+        queries_mib = mib(report.queries),
+        index_mib = mib(report.index),
+        "memory usage"
+    );
+}
+
+fn log_latencies(query_type: &str, percentiles: &latency::Percentiles) {
+    info!(
+        query_type,
+        queries = percentiles.queries,
+        p50 = ?percentiles.p50,
+        p95 = ?percentiles.p95,
+        p99 = ?percentiles.p99,
+        max = ?percentiles.max,
+        "query latencies"
+    );
+}
+
+/// Reads the nodes and builds or loads the solver answering the requests.
+#[cfg(any(feature = "server", feature = "grpc", feature = "flight"))]
+fn load_served(
+    mut config: Config,
+    args: ServedIndexArgs,
+) -> Result<(NodesDataset, Box<dyn Solver>), Box<dyn Error>> {
+    set_nodes(&mut config, args.nodes);
+    if let Some(solver) = args.solver {
+        config.solver = solver;
+    }
+
+    let nodes_dataset = read_nodes(&config)?;
+    let solver = match &args.index {
+        Some(index_path) => load_index(&config, &nodes_dataset, index_path)?,
+        None => build_solver(&config, &nodes_dataset)?,
+    };
+    Ok((nodes_dataset, solvers::with_scoring(&config, solver)?))
+}
+
+#[cfg(feature = "server")]
+fn serve(config: Config, args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    let k = config.k();
+    let metric = config.metric;
+    let (nodes_dataset, solver) = load_served(config, args.served)?;
+    info!(addr = %args.addr, "serving HTTP search requests");
+    let state = glasshouse::server::ServerState::new(nodes_dataset, solver)
+        .with_k(k)
+        .with_metric(metric);
+    tokio::runtime::Runtime::new()?.block_on(glasshouse::server::serve(args.addr, state))?;
+    Ok(())
+}
+
+#[cfg(feature = "grpc")]
+fn serve_grpc(config: Config, args: ServeGrpcArgs) -> Result<(), Box<dyn Error>> {
+    let k = config.k();
+    let metric = config.metric;
+    let (nodes_dataset, solver) = load_served(config, args.served)?;
+    info!(addr = %args.addr, "serving gRPC search requests");
+    let service = glasshouse::grpc::SearchService::new(nodes_dataset, solver)
+        .with_k(k)
+        .with_metric(metric);
+    tokio::runtime::Runtime::new()?.block_on(glasshouse::grpc::serve(args.addr, service))?;
+    Ok(())
+}
+
+#[cfg(feature = "flight")]
+fn serve_flight(config: Config, args: ServeFlightArgs) -> Result<(), Box<dyn Error>> {
+    let k = config.k();
+    let metric = config.metric;
+    let (nodes_dataset, solver) = load_served(config, args.served)?;
+    info!(addr = %args.addr, "serving Arrow Flight exchanges");
+    let service = glasshouse::flight::SearchFlightService::new(nodes_dataset, solver)
+        .with_k(k)
+        .with_metric(metric);
+    tokio::runtime::Runtime::new()?.block_on(glasshouse::flight::serve(args.addr, service))?;
+    Ok(())
+}
+
+fn evaluate(config: Config, args: EvalArgs) -> Result<(), Box<dyn Error>> {
+    let pad_id = config.pad_id();
+    let k = config.k();
+    let results = read_results(&args.results, pad_id, k)?;
+    let ground_truth = read_results(&args.ground_truth, pad_id, k)?;
+    let checksums = match (
+        read_checksums(&args.results, k)?,
+        read_checksums(&args.ground_truth, k)?,
+    ) {
+        (Some(left), Some(right)) if (left.nodes, left.queries) != (right.nodes, right.queries) => {
+            return Err("The results and the ground truth answer different datasets".into());
+        }
+        (left, right) => left.or(right),
+    };
+    let Some(queries) = args.queries else {
+        let recall = eval::recall(&results, &ground_truth, pad_id)?;
+        info!(queries = results.len(), recall, "recall@{}", k);
+        return Ok(());
+    };
+
+    let mut queries_dataset = match config.dimensions {
+        Some(dimensions) => QueriesDataset::read_with_dimensions(&queries, dimensions)?,
+        None => QueriesDataset::read(&queries)?,
+    };
+    // The runs checksum and report the queries once normalized.
+    queries_dataset.normalize();
+    if checksums.is_some_and(|checksums| checksums.queries != queries_dataset.checksum()) {
+        return Err(format!("The results do not answer {}", queries.display()).into());
+    }
+    let report = eval::recall_by_type(
+        &results,
+        &ground_truth,
+        &queries_dataset.query_types,
+        pad_id,
+    )?;
+    info!(
+        queries = report.overall.queries,
+        recall = report.overall.recall,
+        under_filled = report.overall.under_filled,
+        "recall@{}",
+        k
+    );
+    for (query_type, recall) in report.by_type {
+        info!(
+            query_type = ?query_type,
+            queries = recall.queries,
+            recall = recall.recall,
+            under_filled = recall.under_filled,
+            "recall@{}",
+            k
+        );
+    }
+    Ok(())
+}
+
+fn tune(mut config: Config, args: TuneArgs) -> Result<(), Box<dyn Error>> {
+    set_nodes(&mut config, args.nodes);
+    if let Some(path) = args.queries {
+        config.paths.queries = path;
+    }
+    if let Some(solver) = args.solver {
+        config.solver = solver;
+    }
+
+    let nodes_dataset = read_nodes(&config)?;
+    let queries_dataset = read_queries(&config)?;
+    let ground_truth = read_results(&args.ground_truth, config.pad_id(), config.k())?;
+    let tuning = {
+        let _span = info_span!("tune", solver = ?config.solver).entered();
+        tune::tune_with_progress(
+            &config,
+            &nodes_dataset,
+            &queries_dataset,
+            &ground_truth,
+            &ConsoleProgress::new(),
+        )?
+    };
+    for trial in &tuning.trials {
+        info!(
+            recall = trial.recall,
+            elapsed = ?trial.elapsed,
+            sample_proportion = trial.config.baseline.sample_proportion,
+            nprobe = trial.config.ivf.nprobe,
+            ef_search = trial.config.hnsw.ef_search,
+            "trial"
+        );
+    }
+
+    let Some(best) = tuning.best(args.target) else {
+        return Err(format!("No trial reached a recall of {}", args.target).into());
+    };
+    info!(recall = best.recall, elapsed = ?best.elapsed, "fastest trial reaching the target");
+    let tuned = toml::to_string(&best.config)?;
+    match args.save {
+        Some(path) => std::fs::write(path, tuned)?,
+        None => print!("{}", tuned),
+    }
+    Ok(())
+}
+
+fn run_bench(mut config: Config, args: BenchArgs) -> Result<(), Box<dyn Error>> {
+    set_nodes(&mut config, args.nodes);
+    if let Some(path) = args.queries {
+        config.paths.queries = path;
+    }
+    if let Some(solver) = args.solver {
+        config.solver = solver;
+    }
+    let seconds = |name: &str, value: f64| {
+        Duration::try_from_secs_f64(value).map_err(|e| format!("Invalid {} {}: {}", name, value, e))
+    };
+    let (warm_up, duration) = (
+        seconds("warm-up", args.warm_up)?,
+        seconds("duration", args.duration)?,
+    );
+    if duration.is_zero() {
+        return Err("The benchmark duration must be positive".into());
+    }
+
+    let nodes_dataset = read_nodes(&config)?;
+    let solver = match &args.index {
+        Some(index_path) => load_index(&config, &nodes_dataset, index_path)?,
+        None => build_solver(&config, &nodes_dataset)?,
+    };
+    let solver = solvers::with_scoring(&config, solver)?;
+    let queries_dataset = read_queries(&config)?;
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let sampled = sample::queries(&mut rng, &queries_dataset, args.fraction);
+
+    let _span = info_span!("bench", queries = sampled.num_queries).entered();
+    info!(
+        queries = sampled.num_queries,
+        warm_up = ?warm_up,
+        duration = ?duration,
+        "benchmarking solver"
+    );
+    let throughput = bench::bench(
+        solver.as_ref(),
+        &nodes_dataset,
+        &sampled,
+        config.k(),
+        warm_up,
+        duration,
+    )?;
+    info!(
+        queries = throughput.queries,
+        warm_up_queries = throughput.warm_up_queries,
+        qps = throughput.qps(),
+        "sustained throughput"
+    );
+    if let Some(overall) = throughput.latencies.overall {
+        log_latencies("all", &overall);
+    }
+    for (query_type, percentiles) in &throughput.latencies.by_type {
+        log_latencies(&format!("{:?}", query_type), percentiles);
+    }
+    Ok(())
+}
+
+fn sweep(mut config: Config, args: SweepArgs) -> Result<(), Box<dyn Error>> {
+    set_nodes(&mut config, args.nodes);
+    if let Some(path) = args.queries {
+        config.paths.queries = path;
+    }
+    if let Some(solver) = args.solver {
+        config.solver = solver;
+    }
+
+    let nodes_dataset = read_nodes(&config)?;
+    let queries_dataset = read_queries(&config)?;
+    let ground_truth = read_results(&args.ground_truth, config.pad_id(), config.k())?;
+    let trials = {
+        let _span = info_span!("sweep", solver = ?config.solver).entered();
+        tune::sweep_with_progress(
+            &config,
+            &nodes_dataset,
+            &queries_dataset,
+            &ground_truth,
+            &args.params,
+            &ConsoleProgress::new(),
+        )?
+    };
+
+    let mut csv = String::from("solver");
+    for (name, _) in trials.first().map_or(&[][..], |trial| &trial.parameters) {
+        csv.push_str(&format!(",{}", name));
+    }
+    csv.push_str(",recall,qps,build_seconds,search_seconds\n");
+    for trial in &trials {
+        csv.push_str(&format!("{:?}", trial.config.solver).to_lowercase());
+        for (_, value) in &trial.parameters {
+            csv.push_str(&format!(",{}", value));
+        }
+        csv.push_str(&format!(
+            ",{:.6},{:.1},{:.6},{:.6}\n",
+            trial.recall,
+            trial.qps(queries_dataset.num_queries),
+            trial.build_time.as_secs_f64(),
+            trial.elapsed.as_secs_f64()
+        ));
+    }
+    match args.output {
+        Some(path) => std::fs::write(path, csv)?,
+        None => print!("{}", csv),
+    }
+    Ok(())
+}
+
+fn diff(config: Config, args: DiffArgs) -> Result<(), Box<dyn Error>> {
+    let left = read_results(&args.left, config.pad_id(), config.k())?;
+    let right = read_results(&args.right, config.pad_id(), config.k())?;
+    let mut diff = eval::diff(&left, &right)?;
+    info!(
+        differing = diff.differing.len(),
+        queries = diff.queries,
+        mean_jaccard = diff.mean_jaccard,
+        "compared results"
+    );
+
+    diff.differing
+        .sort_by(|a, b| a.jaccard.total_cmp(&b.jaccard).then(a.query.cmp(&b.query)));
+    for query in diff.differing.iter().take(args.show) {
+        info!(
+            query = query.query,
+            jaccard = query.jaccard,
+            only_left = ?query.only_left,
+            only_right = ?query.only_right,
+            "query differs"
+        );
+    }
+    Ok(())
+}
+
+fn convert(config: Config, args: ConvertArgs) -> Result<(), Box<dyn Error>> {
+    let reads_attributes = holds_vectors_only(&args.input);
+    let writes_attributes = !reads_attributes && holds_vectors_only(&args.output);
+    if args.attributes.is_some() && !reads_attributes && !writes_attributes {
+        return Err(
+            "Node attributes are only split from or joined to .fvecs, .bvecs, .hdf5 and .npy files"
+                .into(),
+        );
+    }
+    match args.kind {
+        DatasetKind::Nodes => {
+            let mut nodes_dataset = read_nodes_file(&args.input, config.dimensions)?;
+            if let (true, Some(attributes_path)) = (reads_attributes, &args.attributes) {
+                let (c_attrs, t_attrs) = read_attributes(attributes_path)?;
+                if c_attrs.len() != nodes_dataset.num_vectors as usize {
+                    return Err(format!(
+                        "Expected {} attribute rows, got {}",
+                        nodes_dataset.num_vectors,
+                        c_attrs.len()
+                    )
+                    .into());
+                }
+                nodes_dataset.c_attrs = c_attrs;
+                nodes_dataset.t_attrs = t_attrs;
+                nodes_dataset.index_attributes();
+            }
+            write_nodes_file(&nodes_dataset, &args.output)?;
+            match (&args.attributes, writes_attributes) {
+                (Some(attributes_path), true) => write_attributes(&nodes_dataset, attributes_path)?,
+                (None, true) => {
+                    warn!("the node attributes are dropped, pass --attributes to keep them")
+                }
+                _ => {}
+            }
+            info!(nodes = nodes_dataset.num_vectors, "converted nodes dataset");
+        }
+        DatasetKind::Queries => {
+            if args.attributes.is_some() {
+                return Err("Only nodes datasets have an attributes file".into());
+            }
+            let queries_dataset = read_queries_file(&args.input, config.dimensions)?;
+            match args.output.extension().and_then(|e| e.to_str()) {
+                Some("fvecs") => queries_dataset.write_fvecs(&args.output)?,
+                Some("txt") => queries_dataset.write_text(&args.output)?,
+                #[cfg(feature = "npy")]
+                Some("npy") => queries_dataset.write_npy(&args.output)?,
+                #[cfg(feature = "npy")]
+                Some("npz") => queries_dataset.write_npz(&args.output)?,
+                #[cfg(feature = "parquet")]
+                Some("parquet") => queries_dataset.write_parquet(&args.output)?,
+                _ => queries_dataset.write(&args.output)?,
+            }
+            let filtered = queries_dataset
+                .query_types
+                .iter()
+                .filter(|&&query_type| query_type != QueryType::VectorOnly)
+                .count();
+            if writes_attributes && filtered > 0 {
+                warn!(filtered, "the query filters are dropped");
+            }
+            info!(
+                queries = queries_dataset.num_queries,
+                "converted queries dataset"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Writes a nodes dataset in the format given by the extension, the
+/// contest binary format otherwise.
+fn write_nodes_file(nodes_dataset: &NodesDataset, path: &Path) -> Result<(), Box<dyn Error>> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("fvecs") => nodes_dataset.write_fvecs(path)?,
+        Some("txt") => nodes_dataset.write_text(path)?,
+        #[cfg(feature = "npy")]
+        Some("npy") => nodes_dataset.write_npy(path)?,
+        #[cfg(feature = "npy")]
+        Some("npz") => nodes_dataset.write_npz(path)?,
+        #[cfg(feature = "parquet")]
+        Some("parquet") => nodes_dataset.write_parquet(path)?,
+        _ => nodes_dataset.write(path)?,
+    }
+    Ok(())
+}
+
+/// Returns true for the formats storing vectors without attributes.
+fn holds_vectors_only(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("fvecs" | "bvecs" | "hdf5" | "npy")
+    )
+}
+
+/// Reads the categorical and timestamp attributes of nodes from rows of
+/// two components in an `.fvecs` or `.npy` file.
+fn read_attributes(path: &Path) -> Result<(Vec<i32>, Vec<f32>), Box<dyn Error>> {
+    let (dimensions, values) = match path.extension().and_then(|e| e.to_str()) {
+        #[cfg(feature = "npy")]
+        Some("npy") => {
+            let array = io::npy::read_npy(BufReader::new(File::open(path)?))?;
+            (array.shape.get(1).copied().unwrap_or(0), array.values)
+        }
+        _ => io::vecs::read_fvecs(BufReader::new(File::open(path)?))?,
+    };
+    if dimensions != 2 && !values.is_empty() {
+        return Err(format!(
+            "Expected rows of a categorical and a timestamp attribute, got {} components",
+            dimensions
+        )
+        .into());
+    }
+    let mut c_attrs = Vec::with_capacity(values.len() / 2);
+    let mut t_attrs = Vec::with_capacity(values.len() / 2);
+    for row in values.chunks_exact(2) {
+        c_attrs.push(io::categorical(row[0])?);
+        t_attrs.push(row[1]);
+    }
+    Ok((c_attrs, t_attrs))
+}
+
+/// Writes the categorical and timestamp attributes of nodes as rows of two
+/// components to an `.fvecs` or `.npy` file.
+fn write_attributes(nodes_dataset: &NodesDataset, path: &Path) -> Result<(), Box<dyn Error>> {
+    let values: Vec<f32> = nodes_dataset
+        .c_attrs
+        .iter()
+        .zip(&nodes_dataset.t_attrs)
+        .flat_map(|(&c_attr, &t_attr)| [c_attr as f32, t_attr])
+        .collect();
+    let mut writer = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|e| e.to_str()) {
+        #[cfg(feature = "npy")]
+        Some("npy") => io::npy::write_npy(&mut writer, &[values.len() / 2, 2], &values)?,
+        _ => io::vecs::write_fvecs(&mut writer, 2, &values)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn merge(config: Config, args: MergeArgs) -> Result<(), Box<dyn Error>> {
+    let mut nodes_dataset = NodesDataset::default();
+    let mut offsets = String::from("path,first_id,nodes\n");
+    for input in &args.inputs {
+        let part = read_nodes_file(input, config.dimensions)?;
+        let nodes = part.num_vectors;
+        let first_id = nodes_dataset
+            .append(part)
+            .map_err(|e| format!("{}: {}", input.display(), e))?;
+        info!(path = %input.display(), first_id, nodes, "appended nodes");
+        offsets.push_str(&format!("{},{},{}\n", input.display(), first_id, nodes));
+    }
+    write_nodes_file(&nodes_dataset, &args.output)?;
+    info!(
+        nodes = nodes_dataset.num_vectors,
+        path = %args.output.display(),
+        "wrote merged nodes"
+    );
+    if let Some(path) = args.offsets {
+        std::fs::write(&path, offsets)?;
+        info!(path = %path.display(), "wrote node ID offsets");
+    }
+    Ok(())
+}
+
+fn report_stats(mut config: Config, args: StatsArgs) -> Result<(), Box<dyn Error>> {
+    set_nodes(&mut config, args.nodes);
+    if let Some(path) = args.queries {
+        config.paths.queries = path;
+    }
+
+    let nodes_dataset = read_nodes(&config)?;
+    let report = stats::nodes(&nodes_dataset, args.buckets);
+    info!(
+        nodes = report.nodes,
+        categories = report.categories.len(),
+        "node categories"
+    );
+    for &(category, count) in report.categories.iter().take(args.top) {
+        info!(
+            category,
+            nodes = count,
+            share = count as f64 / report.nodes as f64,
+            "category"
+        );
+    }
+    if let Some((min, max)) = report.timestamps {
+        info!(min, max, "node timestamps");
+        let width = (max - min) / report.timestamp_histogram.len() as f32;
+        for (bucket, count) in report.timestamp_histogram.iter().enumerate() {
+            info!(
+                lower = min + bucket as f32 * width,
+                upper = min + (bucket + 1) as f32 * width,
+                nodes = count,
+                "timestamp bucket"
+            );
+        }
+    }
+
+    let queries_dataset = read_queries(&config)?;
+    let report = stats::queries(&nodes_dataset, &queries_dataset);
+    info!(
+        queries = report.overall.queries,
+        selectivity = report.overall.selectivity,
+        "mean filter selectivity"
+    );
+    for (query_type, selectivity) in report.by_type {
+        info!(
+            query_type = ?query_type,
+            queries = selectivity.queries,
+            selectivity = selectivity.selectivity,
+            "mean filter selectivity"
+        );
+    }
+    Ok(())
+}
+
+fn sample_datasets(config: Config, args: SampleArgs) -> Result<(), Box<dyn Error>> {
+    if args.nodes.is_none() && args.queries.is_none() {
+        return Err("Nothing to sample, pass --nodes or --queries".into());
+    }
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    if let (Some(input), Some(output)) = (&args.nodes, &args.nodes_output) {
+        let nodes_dataset = read_nodes_file(input, config.dimensions)?;
+        let sampled = sample::nodes(&mut rng, &nodes_dataset, args.fraction);
+        sampled.write(output)?;
+        info!(
+            nodes = sampled.num_vectors,
+            of = nodes_dataset.num_vectors,
+            path = %output.display(),
+            "wrote sampled nodes"
+        );
+    }
+    if let (Some(input), Some(output)) = (&args.queries, &args.queries_output) {
+        let queries_dataset = read_queries_file(input, config.dimensions)?;
+        let sampled = sample::queries(&mut rng, &queries_dataset, args.fraction);
+        sampled.write(output)?;
+        info!(
+            queries = sampled.num_queries,
+            of = queries_dataset.num_queries,
+            path = %output.display(),
+            "wrote sampled queries"
+        );
+    }
+    Ok(())
+}
+
+fn shuffle_nodes(config: Config, args: ShuffleArgs) -> Result<(), Box<dyn Error>> {
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let nodes_dataset = read_nodes_file(&args.nodes, config.dimensions)?;
+    let (shuffled, permutation) = sample::shuffle(&mut rng, &nodes_dataset);
+    shuffled.write(&args.output)?;
+    info!(
+        nodes = shuffled.num_vectors,
+        path = %args.output.display(),
+        "wrote shuffled nodes"
+    );
+    if let Some(path) = args.permutation {
+        let ids: Vec<i32> = permutation.iter().map(|&id| id as i32).collect();
+        let mut writer = BufWriter::new(File::create(&path)?);
+        io::vecs::write_ivecs(&mut writer, ids.len(), &ids)?;
+        writer.flush()?;
+        info!(path = %path.display(), "wrote node permutation");
+    }
+    Ok(())
+}
+
+fn gen_datasets(config: Config, args: GenArgs) -> Result<(), Box<dyn Error>> {
+    let dimensions = config.dimensions.unwrap_or(VECTOR_DIMENSIONS);
+    let mut rng = StdRng::seed_from_u64(args.seed);
+
+    let nodes_dataset = generate::nodes(&mut rng, args.num_nodes, dimensions, args.categories);
+    nodes_dataset.write(&args.nodes)?;
+    info!(
+        nodes = nodes_dataset.num_vectors,
+        path = %args.nodes.display(),
+        "wrote nodes dataset"
+    );
+
+    let queries_dataset =
+        generate::queries(&mut rng, args.num_queries, dimensions, args.categories);
+    queries_dataset.write(&args.queries)?;
+    info!(
+        queries = queries_dataset.num_queries,
+        path = %args.queries.display(),
+        "wrote queries dataset"
+    );
+    Ok(())
+}
+
+fn fetch_benchmark(args: FetchArgs) -> Result<(), Box<dyn Error>> {
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    info!(benchmark = %args.benchmark, url = args.benchmark.url(), "loading benchmark");
+    let mut loaded = args.benchmark.load(&args.dir)?;
+    benchmarks::synthesize_attributes(&mut rng, &mut loaded.nodes, args.categories);
+    let path = |suffix: &str| args.dir.join(format!("{}-{}.bin", args.benchmark, suffix));
+
+    loaded.nodes.write(path("nodes"))?;
+    loaded.queries.write(path("queries"))?;
+    io::write(&loaded.ground_truth, path("truth"))?;
+    info!(
+        benchmark = %args.benchmark,
+        nodes = loaded.nodes.num_vectors,
+        queries = loaded.queries.num_queries,
+        dimensions = loaded.nodes.dimensions,
+        nodes_path = %path("nodes").display(),
+        queries_path = %path("queries").display(),
+        truth_path = %path("truth").display(),
+        "wrote benchmark"
+    );
+    Ok(())
+}
+
+fn gen_queries(config: Config, args: GenQueriesArgs) -> Result<(), Box<dyn Error>> {
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let nodes_dataset = read_nodes_file(&args.nodes, config.dimensions)?;
+    let workload = generate::Workload {
+        type_weights: args
+            .type_weights
+            .try_into()
+            .map_err(|_| "Expected four query type weights")?,
+        category_skew: args.category_skew,
+        window: (args.min_window, args.max_window),
+        noise: args.noise,
+    };
+    let queries_dataset =
+        generate::workload(&mut rng, &nodes_dataset, args.num_queries, &workload)?;
+    queries_dataset.write(&args.output)?;
+    info!(
+        queries = queries_dataset.num_queries,
+        path = %args.output.display(),
+        "wrote queries dataset"
+    );
+    Ok(())
+}
+
+fn gen_ground_truth(mut config: Config, args: GenGtArgs) -> Result<(), Box<dyn Error>> {
+    set_nodes(&mut config, args.nodes);
+    if let Some(path) = args.queries {
+        config.paths.queries = path;
+    }
+    if let Some(path) = args.output {
+        config.paths.output = path;
+    }
+    check_checksum_format(args.checksum, &config.paths.output, args.output_format)?;
+
+    let nodes_dataset = read_nodes(&config)?;
+    let queries_dataset = read_queries(&config)?;
+    let results = {
+        let _span = info_span!("search", queries = queries_dataset.num_queries).entered();
+        let search_start_time = Instant::now();
+        info!(
+            spill_batch = config.exact.spill_batch,
+            "computing exact neighbours"
+        );
+        let exact = solvers::exact(&config)?;
+        let results = match &args.checkpoint {
+            Some(checkpoint_path) => solvers::run_checkpointed(
+                exact.as_ref(),
+                &nodes_dataset,
+                &queries_dataset,
+                read_checkpoint(
+                    &nodes_dataset,
+                    &queries_dataset,
+                    config.k(),
+                    checkpoint_path,
+                    args.resume,
+                )?,
+                checkpoint_path,
+                args.checkpoint_interval,
+                &ConsoleProgress::new(),
+            )?,
+            None if config.exact.spill_batch > 0 => solvers::run_scored_with_progress(
+                exact.as_ref(),
+                &nodes_dataset,
+                &queries_dataset,
+                config.k(),
+                &ConsoleProgress::new(),
+            )?,
+            // Without spilling, scan the nodes once per block of queries.
+            None => solvers::run_blocked(
+                &nodes_dataset,
+                &queries_dataset,
+                config.k(),
+                &ConsoleProgress::new(),
+            )?,
+        };
+        info!(elapsed = ?search_start_time.elapsed(), "computed exact neighbours");
+        results
+    };
+
+    let _span = info_span!("write", output = "ground truth").entered();
+    write_scored_results(
+        &results,
+        &queries_dataset,
+        &config.paths.output,
+        args.output_format,
+        config.metric,
+        config.pad_id(),
+        config.k(),
+    )?;
+    if args.checksum {
+        append_checksums(
+            &results,
+            &nodes_dataset,
+            &queries_dataset,
+            &config.paths.output,
+            config.pad_id(),
+            config.k(),
+        )?;
+    }
+    info!(path = %config.paths.output.display(), "wrote exact results");
+    let distances_path = args
+        .distances
+        .unwrap_or_else(|| config.paths.output.with_extension("dist"));
+    io::write_distances(
+        &reported(&results, config.metric),
+        &distances_path,
+        config.k(),
+    )?;
+    info!(path = %distances_path.display(), "wrote exact distances");
+    if let Some(checkpoint_path) = &args.checkpoint {
+        remove_checkpoint(checkpoint_path)?;
+    }
+    Ok(())
+}
+
+/// Removes the checkpoint of a run once its results are written.
+fn remove_checkpoint(checkpoint_path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(checkpoint_path) {
+        Ok(()) => info!(path = %checkpoint_path.display(), "removed checkpoint"),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Returns the checkpoint to resume from when `resume` is set and it
+/// exists, the checkpoint of a new run otherwise.
+fn read_checkpoint(
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    checkpoint_path: &Path,
+    resume: bool,
+) -> Result<Checkpoint, Box<dyn Error>> {
+    if !resume {
+        return Ok(Checkpoint::new(nodes_dataset, queries_dataset, k));
+    }
+    let checkpoint = match Checkpoint::load(checkpoint_path) {
+        Ok(checkpoint) => checkpoint,
+        Err(glasshouse::error::GlasshouseError::Io(e)) if e.kind() == ErrorKind::NotFound => {
+            warn!(path = %checkpoint_path.display(), "no checkpoint to resume from, starting over");
+            return Ok(Checkpoint::new(nodes_dataset, queries_dataset, k));
+        }
+        Err(e) => return Err(format!("Failed to load checkpoint: {}", e).into()),
+    };
+    if checkpoint.k != k {
+        return Err(format!(
+            "The checkpoint holds the {} nearest neighbours of each query, not {}",
+            checkpoint.k, k
+        )
+        .into());
+    }
+    info!(
+        path = %checkpoint_path.display(),
+        answered = checkpoint.results.len(),
+        queries = checkpoint.num_queries,
+        "resuming from checkpoint"
+    );
+    Ok(checkpoint)
+}
+
+/// Parses a duration made of a number and an optional `s`, `m` or `h`
+/// unit, seconds by default.
+fn parse_budget(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(split) => value.split_at(split),
+        None => (value, "s"),
+    };
+    let scale = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("Unknown unit {:?}, expected s, m or h", unit)),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|e| format!("Invalid budget {:?}: {}", value, e))?;
+    Duration::try_from_secs_f64(number * scale)
+        .map_err(|e| format!("Invalid budget {:?}: {}", value, e))
+}
+
+/// Parses a fraction within `(0, 1]`.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value
+        .parse()
+        .map_err(|e| format!("Invalid fraction {:?}: {}", value, e))?;
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err(format!("Fraction {} is not within (0, 1]", fraction));
+    }
+    Ok(fraction)
+}
+
+/// Parses a swept parameter given as `name=value,value,...`.
+fn parse_axis(value: &str) -> Result<(String, Vec<String>), String> {
+    let Some((name, values)) = value.split_once('=') else {
+        return Err(format!("Expected name=value,... but got {:?}", value));
+    };
+    let values: Vec<String> = values.split(',').map(|v| v.trim().to_string()).collect();
+    if values.iter().any(String::is_empty) {
+        return Err(format!("Empty value in {:?}", value));
+    }
+    Config::default()
+        .set(name, &values[0])
+        .map_err(|e| e.to_string())?;
+    Ok((name.to_string(), values))
+}
+
+fn main() {
+    let program_start_time = Instant::now();
+    let cli = Cli::parse();
+    let trace = init_logging(log_level(&cli), cli.log_format, cli.trace_spans.as_deref());
+    HEARTBEAT
+        .set(Duration::from_secs(cli.heartbeat))
+        .expect("the heartbeat is set once");
+
+    let outcome = load_config(&cli).and_then(|config| {
+        let deadline = match cli.deadline {
+            Some(seconds) => Some(
+                program_start_time
+                    + Duration::try_from_secs_f64(seconds)
+                        .map_err(|e| format!("Invalid deadline {}: {}", seconds, e))?,
+            ),
+            None => None,
+        };
+        let budget = cli
+            .budget
+            .map(|limit| Budget::new(program_start_time, limit));
+        // The search stops at whichever of the deadline and the budget
+        // comes first.
+        let token = match deadline
+            .into_iter()
+            .chain(budget.map(|budget| budget.search_deadline()))
+            .min()
+        {
+            Some(deadline) => CancellationToken::with_deadline(deadline),
+            None => CancellationToken::new(),
+        };
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .build_global()?;
+        match cli.command {
+            Command::Build(args) => build(config, args),
+            Command::Search(args) => search(config, args, &token, budget.as_ref()),
+            Command::Eval(args) => evaluate(config, args),
+            Command::Tune(args) => tune(config, args),
+            Command::Sweep(args) => sweep(config, args),
+            Command::Bench(args) => run_bench(config, args),
+            Command::Diff(args) => diff(config, args),
+            Command::Convert(args) => convert(config, args),
+            Command::Merge(args) => merge(config, args),
+            Command::Stats(args) => report_stats(config, args),
+            Command::Sample(args) => sample_datasets(config, args),
+            Command::Shuffle(args) => shuffle_nodes(config, args),
+            Command::Gen(args) => gen_datasets(config, args),
+            Command::GenQueries(args) => gen_queries(config, args),
+            Command::GenGt(args) => gen_ground_truth(config, args),
+            Command::Fetch(args) => fetch_benchmark(args),
+            Command::Shard(args) => search_shard(config, args, &token),
+            Command::ServeShard(args) => serve_shard(config, args),
+            #[cfg(feature = "server")]
+            Command::Serve(args) => serve(config, args),
+            #[cfg(feature = "grpc")]
+            Command::ServeGrpc(args) => serve_grpc(config, args),
+            #[cfg(feature = "flight")]
+            Command::ServeFlight(args) => serve_flight(config, args),
+        }
+    });
+    if let Err(e) = outcome {
+        error!("{}", e);
+        drop(trace);
+        std::process::exit(1);
+    }
+
+    info!(elapsed = ?program_start_time.elapsed(), "total runtime");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("glasshouse-main-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn merges_nodes_with_their_offsets() {
+        let (first, second) = (temp_path("first.txt"), temp_path("second.txt"));
+        let (output, offsets) = (temp_path("merged.txt"), temp_path("offsets.csv"));
+        NodesDataset::from_parts(2, vec![1, 2], vec![0.1, 0.2], vec![1.0, 2.0, 3.0, 4.0])
+            .unwrap()
+            .write_text(&first)
+            .unwrap();
+        NodesDataset::from_parts(2, vec![3], vec![0.3], vec![5.0, 6.0])
+            .unwrap()
+            .write_text(&second)
+            .unwrap();
+
+        let args = MergeArgs {
+            inputs: vec![first.clone(), second.clone()],
+            output: output.clone(),
+            offsets: Some(offsets.clone()),
+        };
+        merge(Config::default(), args).unwrap();
+        let merged = NodesDataset::read_text(&output).unwrap();
+        let written = fs::read_to_string(&offsets).unwrap();
+        for path in [&first, &second, &output, &offsets] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(merged.num_vectors, 3);
+        assert_eq!(merged.c_attrs, vec![1, 2, 3]);
+        assert_eq!(merged.vector(2), &[5.0, 6.0]);
+        assert_eq!(
+            written,
+            format!(
+                "path,first_id,nodes\n{},0,2\n{},2,1\n",
+                first.display(),
+                second.display()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_merging_different_dimensions() {
+        let (first, second) = (temp_path("narrow.txt"), temp_path("wide.txt"));
+        let output = temp_path("mismatched.txt");
+        NodesDataset::from_parts(1, vec![1], vec![0.1], vec![1.0])
+            .unwrap()
+            .write_text(&first)
+            .unwrap();
+        NodesDataset::from_parts(2, vec![2], vec![0.2], vec![2.0, 3.0])
+            .unwrap()
+            .write_text(&second)
+            .unwrap();
+
+        let args = MergeArgs {
+            inputs: vec![first.clone(), second.clone()],
+            output: output.clone(),
+            offsets: None,
+        };
+        let merged = merge(Config::default(), args);
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+
+        let error = merged.unwrap_err().to_string();
+        assert!(
+            error.starts_with(&second.display().to_string()),
+            "{}",
+            error
+        );
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn resumes_from_the_checkpoint_when_asked() {
+        let nodes = NodesDataset::from_parts(1, vec![1], vec![0.1], vec![1.0]).unwrap();
+        let queries = QueriesDataset::default();
+        let path = temp_path("resumed.ckpt");
+        let mut saved = Checkpoint::new(&nodes, &queries, 2);
+        saved.num_queries = 1;
+        saved.results = vec![vec![glasshouse::types::ScoredNeighbor {
+            id: 0,
+            distance: 1.0,
+        }]];
+        saved.save(&path).unwrap();
+
+        let fresh = read_checkpoint(&nodes, &queries, 2, &path, false).unwrap();
+        let resumed = read_checkpoint(&nodes, &queries, 2, &path, true).unwrap();
+        let mismatched = read_checkpoint(&nodes, &queries, 3, &path, true);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(fresh, Checkpoint::new(&nodes, &queries, 2));
+        assert_eq!(resumed, saved);
+        assert!(mismatched.unwrap_err().to_string().contains("not 3"));
+    }
+
+    #[test]
+    fn starts_over_without_a_checkpoint() {
+        let nodes = NodesDataset::from_parts(1, vec![1], vec![0.1], vec![1.0]).unwrap();
+        let queries = QueriesDataset::default();
+        let missing = temp_path("missing.ckpt");
+        let corrupt = temp_path("corrupt.ckpt");
+        fs::write(&corrupt, b"not a checkpoint").unwrap();
+
+        let started = read_checkpoint(&nodes, &queries, 2, &missing, true).unwrap();
+        let malformed = read_checkpoint(&nodes, &queries, 2, &corrupt, true);
+        fs::remove_file(&corrupt).unwrap();
+
+        assert_eq!(started, Checkpoint::new(&nodes, &queries, 2));
+        assert!(malformed.is_err());
+    }
+
+    #[test]
+    fn maps_the_verbosity_flags_to_a_level() {
+        let level = |args: &[&str]| {
+            let cli =
+                Cli::try_parse_from(["glasshouse"].iter().chain(args).chain(&["diff", "a", "b"]))
+                    .unwrap();
+            log_level(&cli)
+        };
+
+        assert_eq!(level(&[]), LogLevel::Info);
+        assert_eq!(level(&["--log-level", "error"]), LogLevel::Error);
+        assert_eq!(level(&["-q"]), LogLevel::Warn);
+        assert_eq!(level(&["-v"]), LogLevel::Debug);
+        assert_eq!(level(&["-vv"]), LogLevel::Trace);
+        assert_eq!(level(&["-vvv"]), LogLevel::Trace);
+    }
+
+    #[test]
+    fn rejects_conflicting_verbosity_flags() {
+        for args in [
+            ["-q", "-v"],
+            ["-q", "--log-level=debug"],
+            ["-v", "--log-level=debug"],
+        ] {
+            let parsed = Cli::try_parse_from(
+                ["glasshouse"]
+                    .iter()
+                    .chain(&args)
+                    .chain(&["diff", "a", "b"]),
+            );
+            assert!(parsed.is_err(), "{:?}", args);
+        }
+    }
+
+    fn logged_progress(heartbeat: Option<Duration>) -> ConsoleProgress {
+        let now = Instant::now();
+        ConsoleProgress {
+            terminal: false,
+            heartbeat,
+            rendered: Mutex::new(Rendered {
+                phase: None,
+                done: 0,
+                started: now,
+                logged: now,
+            }),
+        }
+    }
+
+    #[test]
+    fn skips_late_progress_updates() {
+        let progress = logged_progress(None);
+        let done = || {
+            let rendered = progress.rendered.lock().unwrap();
+            (rendered.phase, rendered.done)
+        };
+
+        progress.update(Phase::Load, 5, 10);
+        progress.update(Phase::Load, 3, 10);
+        assert_eq!(done(), (Some(Phase::Load), 5));
+        progress.update(Phase::Build, 1, 10);
+        assert_eq!(done(), (Some(Phase::Build), 1));
+        progress.update(Phase::Build, 0, 10);
+        assert_eq!(done(), (Some(Phase::Build), 0));
+        progress.update(Phase::Search, 4, 0);
+        assert_eq!(done(), (Some(Phase::Build), 0));
+    }
+
+    #[test]
+    fn logs_the_progress_at_the_heartbeat() {
+        let progress = logged_progress(Some(Duration::from_millis(50)));
+        let logged = || {
+            let rendered = progress.rendered.lock().unwrap();
+            rendered.logged > rendered.started
+        };
+
+        progress.update(Phase::Search, 1, 10);
+        progress.update(Phase::Search, 2, 10);
+        assert!(!logged());
+        std::thread::sleep(Duration::from_millis(60));
+        progress.update(Phase::Search, 10, 10);
+        assert!(!logged());
+        progress.update(Phase::Search, 0, 10);
+        std::thread::sleep(Duration::from_millis(60));
+        progress.update(Phase::Search, 3, 10);
+        assert!(logged());
+    }
+
+    #[test]
+    fn validation_rejects_mismatched_dimensions() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut config = Config::default();
+        config.paths.nodes = temp_path("validated-nodes.txt");
+        config.paths.queries = temp_path("validated-queries.txt");
+        glasshouse::generate::nodes(&mut rng, 50, 4, 4)
+            .write_text(&config.paths.nodes)
+            .unwrap();
+        glasshouse::generate::queries(&mut rng, 10, 4, 4)
+            .write_text(&config.paths.queries)
+            .unwrap();
+        let valid = validate_datasets(&config);
+        glasshouse::generate::queries(&mut rng, 10, 3, 4)
+            .write_text(&config.paths.queries)
+            .unwrap();
+        let mismatched = validate_datasets(&config);
+        fs::remove_file(&config.paths.nodes).unwrap();
+        fs::remove_file(&config.paths.queries).unwrap();
+
+        assert!(valid.is_ok(), "{:?}", valid);
+        assert!(matches!(
+            mismatched
+                .unwrap_err()
+                .downcast_ref::<glasshouse::error::GlasshouseError>(),
+            Some(glasshouse::error::GlasshouseError::DimensionMismatch {
+                nodes: 4,
+                queries: 3
+            })
+        ));
+    }
 }
@@ -0,0 +1,142 @@
+//! Memory used by a run, the contest machines have hard memory limits.
+use std::mem;
+
+use crate::solvers::Solver;
+use crate::types::{NodesDataset, QueriesDataset};
+
+/// Peak resident set size of the process and estimates of the heap memory
+/// held by the main structures, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// `None` when the platform does not expose it.
+    pub peak_rss: Option<u64>,
+    /// Node vectors.
+    pub vectors: usize,
+    /// Node attributes, the timestamp index and tombstones.
+    pub attributes: usize,
+    /// Queries dataset.
+    pub queries: usize,
+    /// Index held by the solver.
+    pub index: usize,
+}
+
+/// Measures the peak RSS so far and estimates the memory of the datasets
+/// and of the solver.
+pub fn report(
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    solver: &dyn Solver,
+) -> MemoryReport {
+    MemoryReport {
+        peak_rss: peak_rss(),
+        vectors: bytes(&nodes_dataset.vectors),
+        attributes: attribute_bytes(nodes_dataset),
+        queries: queries_bytes(queries_dataset),
+        index: solver.memory_bytes(),
+    }
+}
+
+/// Estimated heap memory of the nodes dataset, in bytes.
+pub fn nodes_bytes(nodes_dataset: &NodesDataset) -> usize {
+    bytes(&nodes_dataset.vectors) + attribute_bytes(nodes_dataset)
+}
+
+/// Estimated heap memory of the queries dataset, in bytes.
+pub fn queries_bytes(queries_dataset: &QueriesDataset) -> usize {
+    bytes(&queries_dataset.query_types)
+        + bytes(&queries_dataset.v_categoricals)
+        + bytes(&queries_dataset.t_lower_bounds)
+        + bytes(&queries_dataset.t_upper_bounds)
+        + bytes(&queries_dataset.query_vectors)
+}
+
+fn attribute_bytes(nodes_dataset: &NodesDataset) -> usize {
+    bytes(&nodes_dataset.c_attrs)
+        + bytes(&nodes_dataset.t_attrs)
+        + nodes_dataset.t_index.get().map_or(0, bytes)
+        + nodes_dataset.c_index.get().map_or(0, bytes)
+        + bytes(&nodes_dataset.category_stats)
+        + nodes_dataset
+            .category_stats
+            .iter()
+            .map(|stats| bytes(&stats.centroid))
+            .sum::<usize>()
+        + bytes(&nodes_dataset.tombstones)
+}
+
+/// Peak resident set size of the process, read from `VmHWM` in
+/// `/proc/self/status`.
+#[cfg(all(feature = "fs", target_os = "linux"))]
+pub fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_vm_hwm(&status)
+}
+
+/// Peak resident set size of the process, only measured on Linux.
+#[cfg(not(all(feature = "fs", target_os = "linux")))]
+pub fn peak_rss() -> Option<u64> {
+    None
+}
+
+fn bytes<T>(values: &Vec<T>) -> usize {
+    values.capacity() * mem::size_of::<T>()
+}
+
+/// Extracts the `VmHWM` line of a `/proc/<pid>/status` file, in bytes.
+#[cfg_attr(not(all(feature = "fs", target_os = "linux")), allow(dead_code))]
+fn parse_vm_hwm(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solvers::Exact;
+
+    #[test]
+    fn parses_the_peak_rss() {
+        let status =
+            "Name:\tglasshouse\nVmPeak:\t  20000 kB\nVmHWM:\t    1536 kB\nVmRSS:\t 1024 kB\n";
+        assert_eq!(parse_vm_hwm(status), Some(1536 * 1024));
+        assert_eq!(parse_vm_hwm("Name:\tglasshouse\n"), None);
+    }
+
+    #[test]
+    fn estimates_the_dataset_memory() {
+        let nodes =
+            NodesDataset::from_parts(2, vec![1, 2], vec![0.5, 0.25], vec![1.0, 2.0, 3.0, 4.0])
+                .unwrap();
+        let report = report(&nodes, &QueriesDataset::default(), &Exact);
+
+        assert!(report.vectors >= 4 * mem::size_of::<f32>());
+        assert!(report.attributes >= 4 * mem::size_of::<f32>());
+        assert_eq!((report.queries, report.index), (0, 0));
+    }
+
+    #[test]
+    fn dataset_bytes_cover_their_indexes() {
+        let mut nodes =
+            NodesDataset::from_parts(2, vec![1, 2], vec![0.5, 0.25], vec![1.0, 2.0, 3.0, 4.0])
+                .unwrap();
+        let indexed = nodes_bytes(&nodes);
+        let t_index = nodes.t_index.take().unwrap();
+
+        assert!(indexed >= 8 * mem::size_of::<f32>());
+        assert_eq!(indexed - nodes_bytes(&nodes), bytes(&t_index));
+
+        assert_eq!(queries_bytes(&QueriesDataset::default()), 0);
+        let queries = QueriesDataset {
+            query_vectors: vec![0.0; 8],
+            ..QueriesDataset::default()
+        };
+        assert!(queries_bytes(&queries) >= 8 * mem::size_of::<f32>());
+    }
+}
@@ -0,0 +1,297 @@
+//! Principal component analysis reducing the dimensionality of the vectors.
+//!
+//! [`Pca::train`] computes the covariance of a sample of the node vectors
+//! and keeps its eigenvectors with the largest eigenvalues, the directions
+//! along which the nodes vary the most. Projecting the nodes and queries on
+//! them keeps most of their distances in a fraction of the dimensions, so
+//! that a solver searching the projected vectors finds candidates cheaply,
+//! reranked with the full vectors by `solvers::Reduced`.
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, QueriesDataset};
+
+/// Sweeps of the Jacobi eigenvalue algorithm after which it gives up on
+/// converging further, it converges in well under 20 in practice.
+const MAX_SWEEPS: usize = 50;
+
+/// Projection of vectors on their principal components.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pca {
+    input_dimensions: usize,
+    /// Mean of the sampled vectors, subtracted before projecting.
+    mean: Vec<f32>,
+    /// Principal components stored contiguously, largest variance first,
+    /// `input_dimensions` entries per component.
+    components: Vec<f32>,
+    /// Variance of the sample along each component.
+    variances: Vec<f32>,
+    /// Total variance of the sample over every dimension.
+    total_variance: f32,
+}
+
+impl Pca {
+    /// Computes the `dimensions` principal components of the nodes that
+    /// were not deleted, typically a sample of the dataset.
+    pub fn train(nodes_dataset: &NodesDataset, dimensions: usize) -> error::Result<Self> {
+        let input_dimensions = nodes_dataset.dimensions;
+        if dimensions == 0 || dimensions >= input_dimensions {
+            return Err(GlasshouseError::Config(format!(
+                "PCA dimensions must be between 1 and {}, got {}",
+                input_dimensions.saturating_sub(1),
+                dimensions
+            )));
+        }
+        let live: Vec<usize> = (0..nodes_dataset.num_vectors)
+            .filter(|&id| !nodes_dataset.is_deleted(id))
+            .map(|id| id as usize)
+            .collect();
+        if live.len() < 2 {
+            return Err(GlasshouseError::Config(
+                "PCA needs at least two nodes to train on".to_string(),
+            ));
+        }
+
+        // Accumulate in f64, sums over millions of vectors lose the small
+        // variances in f32.
+        let mut mean = vec![0.0f64; input_dimensions];
+        for &id in &live {
+            for (sum, &value) in mean.iter_mut().zip(nodes_dataset.vector(id)) {
+                *sum += value as f64;
+            }
+        }
+        for sum in &mut mean {
+            *sum /= live.len() as f64;
+        }
+        let mut covariance = vec![0.0f64; input_dimensions * input_dimensions];
+        let mut centered = vec![0.0f64; input_dimensions];
+        for &id in &live {
+            for ((centered, &value), mean) in
+                centered.iter_mut().zip(nodes_dataset.vector(id)).zip(&mean)
+            {
+                *centered = value as f64 - mean;
+            }
+            for (i, &a) in centered.iter().enumerate() {
+                let row = &mut covariance[i * input_dimensions..];
+                for (j, &b) in centered.iter().enumerate().skip(i) {
+                    row[j] += a * b;
+                }
+            }
+        }
+        for i in 0..input_dimensions {
+            for j in i..input_dimensions {
+                let value = covariance[i * input_dimensions + j] / (live.len() - 1) as f64;
+                covariance[i * input_dimensions + j] = value;
+                covariance[j * input_dimensions + i] = value;
+            }
+        }
+
+        let (eigenvalues, eigenvectors) = eigen(covariance, input_dimensions);
+        let mut order: Vec<usize> = (0..input_dimensions).collect();
+        order.sort_by(|&a, &b| eigenvalues[b].total_cmp(&eigenvalues[a]));
+        order.truncate(dimensions);
+        let components = order
+            .iter()
+            .flat_map(|&column| {
+                eigenvectors
+                    .chunks_exact(input_dimensions)
+                    .map(move |row| row[column] as f32)
+            })
+            .collect();
+        Ok(Pca {
+            input_dimensions,
+            mean: mean.iter().map(|&mean| mean as f32).collect(),
+            components,
+            variances: order.iter().map(|&i| eigenvalues[i] as f32).collect(),
+            total_variance: eigenvalues.iter().sum::<f64>() as f32,
+        })
+    }
+
+    /// Dimensionality of the vectors projected.
+    pub fn input_dimensions(&self) -> usize {
+        self.input_dimensions
+    }
+
+    /// Dimensionality of the projected vectors.
+    pub fn dimensions(&self) -> usize {
+        self.variances.len()
+    }
+
+    /// Fraction of the variance of the sample kept by the components.
+    pub fn explained_variance(&self) -> f32 {
+        if self.total_variance > 0.0 {
+            self.variances.iter().sum::<f32>() / self.total_variance
+        } else {
+            1.0
+        }
+    }
+
+    /// Projects the vector on the components, appending the coordinates to
+    /// `projected`.
+    pub fn project_into(&self, vector: &[f32], projected: &mut Vec<f32>) {
+        debug_assert_eq!(vector.len(), self.input_dimensions);
+        projected.extend(
+            self.components
+                .chunks_exact(self.input_dimensions)
+                .map(|component| {
+                    component
+                        .iter()
+                        .zip(vector.iter().zip(&self.mean))
+                        .map(|(weight, (value, mean))| weight * (value - mean))
+                        .sum::<f32>()
+                }),
+        );
+    }
+
+    /// Returns the projection of the vector on the components.
+    pub fn project(&self, vector: &[f32]) -> Vec<f32> {
+        let mut projected = Vec::with_capacity(self.dimensions());
+        self.project_into(vector, &mut projected);
+        projected
+    }
+
+    /// Returns the nodes with their vectors projected, keeping their IDs,
+    /// attributes and deletions.
+    pub fn project_nodes(&self, nodes_dataset: &NodesDataset) -> error::Result<NodesDataset> {
+        self.check(nodes_dataset.dimensions)?;
+        let mut vectors =
+            Vec::with_capacity(nodes_dataset.num_vectors as usize * self.dimensions());
+        for id in 0..nodes_dataset.num_vectors as usize {
+            self.project_into(nodes_dataset.vector(id), &mut vectors);
+        }
+        let mut projected = NodesDataset::from_parts(
+            self.dimensions(),
+            nodes_dataset.c_attrs.clone(),
+            nodes_dataset.t_attrs.clone(),
+            vectors,
+        )?;
+        projected.tombstones = nodes_dataset.tombstones.clone();
+        Ok(projected)
+    }
+
+    /// Returns the queries with their vectors projected.
+    pub fn project_queries(
+        &self,
+        queries_dataset: &QueriesDataset,
+    ) -> error::Result<QueriesDataset> {
+        self.check(queries_dataset.dimensions)?;
+        let mut query_vectors =
+            Vec::with_capacity(queries_dataset.num_queries as usize * self.dimensions());
+        for index in 0..queries_dataset.num_queries as usize {
+            self.project_into(queries_dataset.query_vector(index), &mut query_vectors);
+        }
+        Ok(QueriesDataset {
+            num_queries: queries_dataset.num_queries,
+            dimensions: self.dimensions(),
+            query_types: queries_dataset.query_types.clone(),
+            v_categoricals: queries_dataset.v_categoricals.clone(),
+            t_lower_bounds: queries_dataset.t_lower_bounds.clone(),
+            t_upper_bounds: queries_dataset.t_upper_bounds.clone(),
+            query_vectors,
+        })
+    }
+
+    /// Heap memory held by the projection in bytes.
+    pub fn memory_bytes(&self) -> usize {
+        (self.mean.len() + self.components.len() + self.variances.len()) * size_of::<f32>()
+    }
+
+    fn check(&self, dimensions: usize) -> error::Result<()> {
+        if dimensions != self.input_dimensions {
+            return Err(GlasshouseError::Config(format!(
+                "The PCA was trained on {}-dimensional vectors, got {}",
+                self.input_dimensions, dimensions
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Eigenvalues and eigenvectors of a symmetric matrix stored row-major,
+/// computed with the cyclic Jacobi algorithm. Eigenvectors are the columns
+/// of the returned matrix, in the order of the eigenvalues.
+fn eigen(mut matrix: Vec<f64>, n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut vectors = vec![0.0; n * n];
+    for i in 0..n {
+        vectors[i * n + i] = 1.0;
+    }
+    let scale: f64 = matrix.iter().map(|value| value * value).sum();
+    for _ in 0..MAX_SWEEPS {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|p| (0..n).filter(move |&q| q != p).map(move |q| (p, q)))
+            .map(|(p, q)| matrix[p * n + q] * matrix[p * n + q])
+            .sum();
+        if off_diagonal <= scale * 1e-24 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = matrix[p * n + q];
+                if apq == 0.0 {
+                    continue;
+                }
+                // Rotation zeroing the (p, q) entry.
+                let theta = (matrix[q * n + q] - matrix[p * n + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (matrix[k * n + p], matrix[k * n + q]);
+                    matrix[k * n + p] = c * akp - s * akq;
+                    matrix[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (matrix[p * n + k], matrix[q * n + k]);
+                    matrix[p * n + k] = c * apk - s * aqk;
+                    matrix[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (vectors[k * n + p], vectors[k * n + q]);
+                    vectors[k * n + p] = c * vkp - s * vkq;
+                    vectors[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    let values = (0..n).map(|i| matrix[i * n + i]).collect();
+    (values, vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_follow_the_largest_variance() {
+        // Points along the diagonal of the first two dimensions, with a
+        // small spread along the third.
+        let vectors: Vec<f32> = (0..100)
+            .flat_map(|i| {
+                let along = i as f32 - 50.0;
+                let across = if i % 2 == 0 { 0.5 } else { -0.5 };
+                [along + 3.0, along - 1.0, across]
+            })
+            .collect();
+        let nodes = NodesDataset::from_parts(3, vec![0; 100], vec![0.0; 100], vectors).unwrap();
+        let pca = Pca::train(&nodes, 1).unwrap();
+        assert_eq!((pca.input_dimensions(), pca.dimensions()), (3, 1));
+        assert!(pca.explained_variance() > 0.999);
+
+        let component = &pca.components[..3];
+        let sign = component[0].signum();
+        let expected = [
+            std::f32::consts::FRAC_1_SQRT_2,
+            std::f32::consts::FRAC_1_SQRT_2,
+            0.0,
+        ];
+        for (value, expected) in component.iter().zip(expected) {
+            assert!((value * sign - expected).abs() < 1e-3, "{:?}", component);
+        }
+        // Distances along the diagonal are kept, the centre maps to zero.
+        let projected = pca.project_nodes(&nodes).unwrap();
+        let distance = (projected.vector(10)[0] - projected.vector(30)[0]).abs();
+        assert!((distance - 20.0 * 2f32.sqrt()).abs() < 1e-3);
+        assert!(pca.project(&[2.5, -1.5, 0.0])[0].abs() < 1e-3);
+
+        assert!(Pca::train(&nodes, 3).is_err());
+        assert!(pca.project_nodes(&NodesDataset::default()).is_err());
+    }
+}
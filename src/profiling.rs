@@ -0,0 +1,86 @@
+//! Sampling profiler of the search phase, built with the `profiling`
+//! feature.
+//!
+//! The stacks of every thread are sampled on a timer signal while the
+//! profiler runs and rendered as a flamegraph SVG once it is stopped, so
+//! contest-sized runs can be profiled without `perf` or other tooling.
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use pprof::{ProfilerGuard, ProfilerGuardBuilder};
+
+use crate::error;
+
+/// Libraries whose frames are not sampled, unwinding through them from a
+/// signal handler can deadlock.
+const BLOCKLIST: [&str; 4] = ["libc", "libgcc", "pthread", "vdso"];
+
+/// Profiler sampling the stacks of the process until dropped.
+pub struct Profiler {
+    guard: ProfilerGuard<'static>,
+}
+
+impl Profiler {
+    /// Samples per second, a prime so sampling does not run in lockstep
+    /// with periodic work.
+    pub const FREQUENCY: i32 = 997;
+
+    /// Starts sampling, fails if another profiler is running.
+    pub fn start() -> error::Result<Self> {
+        let guard = ProfilerGuardBuilder::default()
+            .frequency(Self::FREQUENCY)
+            .blocklist(&BLOCKLIST)
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(Profiler { guard })
+    }
+
+    /// Writes the flamegraph of the stacks sampled so far, returns the
+    /// number of samples.
+    pub fn write_flamegraph<P: AsRef<Path>>(&self, file_path: P) -> error::Result<usize> {
+        let report = self.guard.report().build().map_err(io::Error::other)?;
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        report.flamegraph(&mut writer).map_err(io::Error::other)?;
+        writer.flush()?;
+        Ok(report.data.values().map(|&count| count as usize).sum())
+    }
+}
+
+/// Path of the flamegraph written next to an output file.
+pub fn flamegraph_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".flamegraph.svg");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn flamegraphs_are_written_next_to_the_output() {
+        let output = crate::testing::temp_path("profiled.bin");
+        let path = flamegraph_path(&output);
+        assert_eq!(
+            path.file_name().unwrap().to_str().unwrap(),
+            format!(
+                "glasshouse-{}-profiled.bin.flamegraph.svg",
+                std::process::id()
+            )
+        );
+
+        let profiler = Profiler::start().unwrap();
+        let start = Instant::now();
+        let mut sum = 0u64;
+        while start.elapsed() < Duration::from_millis(200) {
+            sum = black_box(sum.wrapping_add(1));
+        }
+        profiler.write_flamegraph(&path).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().contains("<svg"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+//! Progress reporting for the long-running phases of a run.
+//!
+//! Loading datasets, building indexes and answering queries accept a
+//! [`Progress`] receiving the number of items processed so far, front-ends
+//! implement it to render progress bars without polling.
+//!
+//! ```ignore
+//! let progress = |phase: Phase, done: u64, total: u64| eprintln!("{:?} {}/{}", phase, done, total);
+//! let results = solvers::run_with_progress(&solver, &nodes, &queries, &progress)?;
+//! ```
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Long-running phases reporting their progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Reading the rows of a dataset.
+    Load,
+    /// Building an index over the nodes.
+    Build,
+    /// Answering the queries.
+    Search,
+}
+
+/// Receives progress updates. Phases running in parallel call `update` from
+/// their worker threads, `done` never decreases within a phase and equals
+/// `total` once the phase completes.
+pub trait Progress: Sync {
+    fn update(&self, phase: Phase, done: u64, total: u64);
+}
+
+/// Ignores all progress updates.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn update(&self, _phase: Phase, _done: u64, _total: u64) {}
+}
+
+impl<F: Fn(Phase, u64, u64) + Sync> Progress for F {
+    fn update(&self, phase: Phase, done: u64, total: u64) {
+        self(phase, done, total)
+    }
+}
+
+/// Counts the items processed by a phase and forwards at most
+/// `Tracker::UPDATES` updates to the progress.
+pub(crate) struct Tracker<'a> {
+    progress: &'a dyn Progress,
+    phase: Phase,
+    total: u64,
+    step: u64,
+    done: AtomicU64,
+}
+
+impl<'a> Tracker<'a> {
+    const UPDATES: u64 = 1000;
+
+    /// Starts tracking a phase, reporting that no item was processed yet.
+    pub(crate) fn new(progress: &'a dyn Progress, phase: Phase, total: u64) -> Self {
+        progress.update(phase, 0, total);
+        Tracker {
+            progress,
+            phase,
+            total,
+            step: total.div_ceil(Self::UPDATES).max(1),
+            done: AtomicU64::new(0),
+        }
+    }
+
+    /// Records that `items` more items were processed.
+    pub(crate) fn advance(&self, items: u64) {
+        let done = self.done.fetch_add(items, Ordering::Relaxed) + items;
+        if done / self.step != (done - items) / self.step || done == self.total {
+            self.progress
+                .update(self.phase, done.min(self.total), self.total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use rayon::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn trackers_throttle_and_complete() {
+        let updates = Mutex::new(Vec::new());
+        let progress = |phase: Phase, done: u64, total: u64| {
+            assert_eq!((phase, total), (Phase::Search, 100_000));
+            updates.lock().unwrap().push(done);
+        };
+
+        let tracker = Tracker::new(&progress, Phase::Search, 100_000);
+        (0..100_000)
+            .into_par_iter()
+            .for_each(|_| tracker.advance(1));
+
+        let updates = updates.into_inner().unwrap();
+        assert!(updates.len() <= Tracker::UPDATES as usize + 1);
+        assert_eq!(updates.first(), Some(&0));
+        assert_eq!(updates.iter().max(), Some(&100_000));
+    }
+}
@@ -0,0 +1,568 @@
+//! Search over partitions of the nodes held by remote workers.
+//!
+//! Each worker loads a partition of the nodes, see [`crate::shard`], builds
+//! its own index and answers batches of queries sent over TCP by a
+//! coordinator, which merges the neighbours found by every worker into the
+//! global answers.
+//!
+//! Every message is a frame made of its length as a little-endian `u32`
+//! followed by its body. A request body holds the `GHRQ` magic, the
+//! protocol version, `k`, the dimensionality of the queries and the queries
+//! in the contest binary format. A response body holds a status, `0` for
+//! results and `1` for an error followed by its UTF-8 message. Results are
+//! the index of the partition and the number of partitions, the ID of the
+//! first node of the partition and its number of nodes, the number of
+//! queries, then for each query its number of neighbours and their ID and
+//! distance, with IDs in the whole nodes dataset. The coordinator checks
+//! that the partitions of its workers hold every node once before merging
+//! their neighbours. A connection answers requests in order until the
+//! coordinator closes it, or until no frame arrives for [`READ_TIMEOUT`].
+//! A worker answers at most [`MAX_CONNECTIONS`] connections at once.
+use std::fmt;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::error::{self, GlasshouseError};
+use crate::progress::{Phase, Progress, Tracker};
+use crate::sample;
+use crate::shard::{self, Partition};
+use crate::solvers::{self, Solver};
+use crate::types::{NodesDataset, QueriesDataset, ScoredNeighbor, ScoredResults};
+
+const MAGIC: [u8; 4] = *b"GHRQ";
+/// Current version of the protocol, bumped on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 3;
+/// Longest wait for a frame, after which the peer is considered gone and
+/// the connection fails rather than blocking forever.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(600);
+/// Longest frame accepted, so that a corrupted length does not allocate
+/// the memory of the machine.
+const MAX_FRAME_BYTES: u32 = 1 << 30;
+/// Most connections a worker answers at once, further ones wait to be
+/// accepted until one of them is closed.
+pub const MAX_CONNECTIONS: usize = 64;
+/// Wait before accepting connections again after the listener failed, so
+/// that a lasting failure such as running out of file descriptors does not
+/// spin.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+const STATUS_RESULTS: u32 = 0;
+const STATUS_ERROR: u32 = 1;
+
+/// Partition of the nodes answering the requests of coordinators.
+pub struct ShardServer {
+    nodes_dataset: NodesDataset,
+    solver: Box<dyn Solver>,
+    /// ID of the first node of the partition in the whole dataset.
+    first_id: u32,
+    /// Index of the partition and number of partitions of the nodes.
+    part: u32,
+    parts: u32,
+}
+
+impl ShardServer {
+    /// Serves partition `part` of `parts`, starting at `first_id` in the
+    /// whole dataset.
+    pub fn new(
+        nodes_dataset: NodesDataset,
+        solver: Box<dyn Solver>,
+        first_id: u32,
+        part: u32,
+        parts: u32,
+    ) -> Self {
+        ShardServer {
+            nodes_dataset,
+            solver,
+            first_id,
+            part,
+            parts,
+        }
+    }
+
+    /// Accepts connections until the process is stopped, answering each of
+    /// them on a thread of its own. Failures to accept a connection are
+    /// logged and accepting resumes.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        let open = (Mutex::new(0usize), Condvar::new());
+        thread::scope(|scope| {
+            loop {
+                let mut connections = open.0.lock().unwrap();
+                while *connections >= MAX_CONNECTIONS {
+                    connections = open.1.wait(connections).unwrap();
+                }
+                drop(connections);
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!(error = %e, "failed to accept a connection");
+                        thread::sleep(ACCEPT_RETRY);
+                        continue;
+                    }
+                };
+                *open.0.lock().unwrap() += 1;
+                let open = &open;
+                scope.spawn(move || {
+                    // A failing connection only concerns its coordinator,
+                    // which sees it closed.
+                    let _ = self.answer(stream);
+                    *open.0.lock().unwrap() -= 1;
+                    open.1.notify_one();
+                });
+            }
+        })
+    }
+
+    /// Answers the requests received on the stream until it is closed.
+    pub fn answer(&self, stream: TcpStream) -> error::Result<()> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        while let Some(body) = read_frame(&mut reader)? {
+            let mut response = Vec::new();
+            match self.search(&body) {
+                Ok(results) => write_results(&mut response, &self.partition(), &results)?,
+                Err(e) => {
+                    write_u32(&mut response, STATUS_ERROR)?;
+                    response.extend_from_slice(e.to_string().as_bytes());
+                }
+            }
+            write_frame(&mut writer, &response)?;
+        }
+        Ok(())
+    }
+
+    /// Partition of the nodes served.
+    fn partition(&self) -> Partition {
+        Partition {
+            part: self.part,
+            parts: self.parts,
+            ids: self.first_id..self.first_id + self.nodes_dataset.num_vectors,
+        }
+    }
+
+    /// Answers the queries of a request body.
+    fn search(&self, body: &[u8]) -> error::Result<ScoredResults> {
+        let (queries_dataset, k) = decode_request(body)?;
+        // There are never more neighbours than nodes, larger values would
+        // only size the search buffers.
+        let k = k.min(self.nodes_dataset.num_vectors as usize);
+        let mut results = solvers::run_scored(
+            self.solver.as_ref(),
+            &self.nodes_dataset,
+            &queries_dataset,
+            k,
+        )?;
+        shard::offset(&mut results, self.first_id);
+        Ok(results)
+    }
+}
+
+/// Connection of a coordinator to a worker.
+pub struct RemoteShard {
+    addr: String,
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    /// Partition reported by the worker in its last response.
+    partition: Option<Partition>,
+}
+
+impl fmt::Debug for RemoteShard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteShard")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl RemoteShard {
+    /// Connects to the worker listening on the address.
+    pub fn connect<A: ToSocketAddrs + fmt::Display>(addr: A) -> error::Result<Self> {
+        let stream = TcpStream::connect(&addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        Ok(RemoteShard {
+            addr: addr.to_string(),
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+            partition: None,
+        })
+    }
+
+    /// Address of the worker.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Partition of the nodes the worker reported answering from, `None`
+    /// until it answered a request.
+    pub fn partition(&self) -> Option<&Partition> {
+        self.partition.as_ref()
+    }
+
+    /// Returns the `k` nearest neighbours of every query among the nodes of
+    /// the worker.
+    pub fn search(
+        &mut self,
+        queries_dataset: &QueriesDataset,
+        k: usize,
+    ) -> error::Result<ScoredResults> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&MAGIC);
+        write_u32(&mut body, PROTOCOL_VERSION)?;
+        write_u32(&mut body, k as u32)?;
+        write_u32(&mut body, queries_dataset.dimensions as u32)?;
+        queries_dataset.write_to(&mut body)?;
+        write_frame(&mut self.writer, &body)?;
+
+        let response = read_frame(&mut self.reader)?.ok_or_else(|| {
+            GlasshouseError::Io(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Worker {} closed the connection", self.addr),
+            ))
+        })?;
+        let (partition, results) = decode_results(&response).map_err(|e| match e {
+            GlasshouseError::Malformed(message) => {
+                GlasshouseError::Malformed(format!("Worker {}: {}", self.addr, message))
+            }
+            e => e,
+        })?;
+        self.partition = Some(partition);
+        if results.len() != queries_dataset.num_queries as usize {
+            return Err(GlasshouseError::Malformed(format!(
+                "Worker {} answered {} of {} queries",
+                self.addr,
+                results.len(),
+                queries_dataset.num_queries
+            )));
+        }
+        Ok(results)
+    }
+}
+
+/// Sends the queries to every worker in batches of `batch` queries and
+/// merges their results into the `k` nearest neighbours of every query,
+/// reporting the queries answered by all workers to the progress. Fails
+/// unless the partitions of the workers hold every node once, see
+/// [`shard::check_coverage`].
+pub fn search(
+    shards: &mut [RemoteShard],
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    batch: usize,
+    progress: &dyn Progress,
+) -> error::Result<ScoredResults> {
+    if batch == 0 {
+        return Err(GlasshouseError::Config(
+            "Batches must hold at least one query".to_string(),
+        ));
+    }
+    let num_queries = queries_dataset.num_queries as usize;
+    let tracker = Tracker::new(progress, Phase::Search, num_queries as u64);
+    let mut results = Vec::with_capacity(num_queries);
+    for start in (0..num_queries).step_by(batch) {
+        let indices: Vec<usize> = (start..(start + batch).min(num_queries)).collect();
+        let queries = sample::select_queries(queries_dataset, &indices);
+        let partitions = thread::scope(|scope| {
+            let handles: Vec<_> = shards
+                .iter_mut()
+                .map(|shard| scope.spawn(|| shard.search(&queries, k)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("workers do not panic"))
+                .collect::<error::Result<Vec<_>>>()
+        })?;
+        let served: Vec<Partition> = shards
+            .iter()
+            .filter_map(|shard| shard.partition().cloned())
+            .collect();
+        shard::check_coverage(&served)?;
+        results.extend(shard::merge(partitions, k)?);
+        tracker.advance(indices.len() as u64);
+    }
+    Ok(results)
+}
+
+fn decode_request(body: &[u8]) -> error::Result<(QueriesDataset, usize)> {
+    let mut reader = body;
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(GlasshouseError::Malformed(
+            "Not a glasshouse search request".to_string(),
+        ));
+    }
+    let version = read_u32(&mut reader)?;
+    if version != PROTOCOL_VERSION {
+        return Err(GlasshouseError::Malformed(format!(
+            "Unsupported protocol version {}, expected {}",
+            version, PROTOCOL_VERSION
+        )));
+    }
+    let k = read_u32(&mut reader)? as usize;
+    let dimensions = read_u32(&mut reader)? as usize;
+    let queries_dataset = QueriesDataset::from_reader(reader, dimensions)?;
+    Ok((queries_dataset, k))
+}
+
+fn write_results<W: Write>(
+    writer: &mut W,
+    partition: &Partition,
+    results: &ScoredResults,
+) -> io::Result<()> {
+    write_u32(writer, STATUS_RESULTS)?;
+    write_u32(writer, partition.part)?;
+    write_u32(writer, partition.parts)?;
+    write_u32(writer, partition.ids.start)?;
+    write_u32(writer, partition.ids.len() as u32)?;
+    write_u32(writer, results.len() as u32)?;
+    for result in results {
+        write_u32(writer, result.len() as u32)?;
+        for neighbor in result {
+            write_u32(writer, neighbor.id)?;
+            writer.write_all(&neighbor.distance.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a response body into the partition of the worker and its
+/// results, failing with the message of error responses and on neighbours
+/// outside of the partition.
+fn decode_results(body: &[u8]) -> error::Result<(Partition, ScoredResults)> {
+    let mut reader = body;
+    let truncated = |_| GlasshouseError::Malformed("Truncated response".to_string());
+    match read_u32(&mut reader).map_err(truncated)? {
+        STATUS_RESULTS => {}
+        STATUS_ERROR => {
+            return Err(GlasshouseError::Malformed(
+                String::from_utf8_lossy(reader).into_owned(),
+            ));
+        }
+        status => {
+            return Err(GlasshouseError::Malformed(format!(
+                "Unknown response status {}",
+                status
+            )));
+        }
+    }
+    let part = read_u32(&mut reader).map_err(truncated)?;
+    let parts = read_u32(&mut reader).map_err(truncated)?;
+    if part >= parts {
+        return Err(GlasshouseError::Malformed(format!(
+            "Partition {} of {} does not exist",
+            part, parts
+        )));
+    }
+    let first_id = read_u32(&mut reader).map_err(truncated)?;
+    let num_nodes = read_u32(&mut reader).map_err(truncated)?;
+    let partition = first_id
+        .checked_add(num_nodes)
+        .map(|end| first_id..end)
+        .ok_or_else(|| {
+            GlasshouseError::Malformed(format!(
+                "Partition of {} nodes from {} overflows",
+                num_nodes, first_id
+            ))
+        })?;
+    let num_queries = read_u32(&mut reader).map_err(truncated)? as usize;
+    let mut results = Vec::with_capacity(num_queries.min(body.len() / 4));
+    for _ in 0..num_queries {
+        let len = read_u32(&mut reader).map_err(truncated)? as usize;
+        let mut result = Vec::with_capacity(len.min(reader.len() / 8));
+        for _ in 0..len {
+            let id = read_u32(&mut reader).map_err(truncated)?;
+            if !partition.contains(&id) {
+                return Err(GlasshouseError::Malformed(format!(
+                    "Neighbour {} is outside of the partition {:?}",
+                    id, partition
+                )));
+            }
+            let distance = f32::from_bits(read_u32(&mut reader).map_err(truncated)?);
+            result.push(ScoredNeighbor { id, distance });
+        }
+        results.push(result);
+    }
+    let partition = Partition {
+        part,
+        parts,
+        ids: partition,
+    };
+    Ok((partition, results))
+}
+
+/// Reads the body of the next frame, `None` if the stream was closed
+/// between two frames.
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = u32::from_le_bytes(length);
+    if length > MAX_FRAME_BYTES {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Frame of {} bytes exceeds {} bytes",
+                length, MAX_FRAME_BYTES
+            ),
+        ));
+    }
+    let mut body = vec![0u8; length as usize];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_frame<W: Write>(writer: &mut W, body: &[u8]) -> io::Result<()> {
+    let length = u32::try_from(body.len())
+        .ok()
+        .filter(|&length| length <= MAX_FRAME_BYTES)
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Frame of {} bytes exceeds {} bytes",
+                    body.len(),
+                    MAX_FRAME_BYTES
+                ),
+            )
+        })?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::progress::NoProgress;
+    use crate::solvers::Exact;
+
+    #[test]
+    fn remote_partitions_answer_like_the_whole_dataset() {
+        let mut rng = StdRng::seed_from_u64(59);
+        let nodes = generate::nodes(&mut rng, 300, 4, 4);
+        let queries = generate::queries(&mut rng, 25, 4, 4);
+        let mut shards = Vec::new();
+        for part in 0..2 {
+            let range = shard::partition(nodes.num_vectors, part, 2);
+            let (start, end) = (range.start as usize, range.end as usize);
+            let partition = NodesDataset::from_parts(
+                4,
+                nodes.c_attrs[start..end].to_vec(),
+                nodes.t_attrs[start..end].to_vec(),
+                nodes.vectors[start * 4..end * 4].to_vec(),
+            )
+            .unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = ShardServer::new(partition, Box::new(Exact), range.start, part as u32, 2);
+            thread::spawn(move || server.serve(listener));
+            shards.push(RemoteShard::connect(addr).unwrap());
+        }
+
+        let results = search(&mut shards, &queries, 10, 7, &NoProgress).unwrap();
+        assert_eq!(
+            results,
+            solvers::run_scored(&Exact, &nodes, &queries, 10).unwrap()
+        );
+        // Workers return at most their nodes however many are asked for.
+        let everything = shards[0].search(&queries, u32::MAX as usize).unwrap();
+        assert!(everything.iter().all(|result| result.len() <= 150));
+        let other = generate::queries(&mut rng, 1, 3, 4);
+        let error = shards[0].search(&other, 10).unwrap_err();
+        assert!(error.to_string().contains("dimension"), "{}", error);
+    }
+
+    #[test]
+    fn workers_must_hold_every_node_once() {
+        let mut rng = StdRng::seed_from_u64(61);
+        let nodes = generate::nodes(&mut rng, 100, 4, 4);
+        let queries = generate::queries(&mut rng, 5, 4, 4);
+        // Both workers serve the first of two partitions.
+        let mut shards = Vec::new();
+        for _ in 0..2 {
+            let range = shard::partition(nodes.num_vectors, 0, 2);
+            let end = range.end as usize;
+            let partition = NodesDataset::from_parts(
+                4,
+                nodes.c_attrs[..end].to_vec(),
+                nodes.t_attrs[..end].to_vec(),
+                nodes.vectors[..end * 4].to_vec(),
+            )
+            .unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = ShardServer::new(partition, Box::new(Exact), 0, 0, 2);
+            thread::spawn(move || server.serve(listener));
+            shards.push(RemoteShard::connect(addr).unwrap());
+        }
+
+        let error = search(&mut shards, &queries, 10, 5, &NoProgress).unwrap_err();
+        assert!(error.to_string().contains("several workers"), "{}", error);
+        // A single worker of the two partitions misses half of the nodes.
+        let error = search(&mut shards[..1], &queries, 10, 5, &NoProgress).unwrap_err();
+        assert!(error.to_string().contains("among 1 workers"), "{}", error);
+    }
+
+    #[test]
+    fn results_outside_of_the_partition_are_rejected() {
+        let results = vec![vec![ScoredNeighbor {
+            id: 12,
+            distance: 1.0,
+        }]];
+        let partition = |ids| Partition {
+            part: 1,
+            parts: 2,
+            ids,
+        };
+        let mut body = Vec::new();
+        write_results(&mut body, &partition(10..20), &results).unwrap();
+        assert_eq!(
+            decode_results(&body).unwrap(),
+            (partition(10..20), results.clone())
+        );
+        assert!(matches!(
+            decode_results(&body[..body.len() - 1]),
+            Err(GlasshouseError::Malformed(message)) if message == "Truncated response"
+        ));
+        body.clear();
+        write_results(&mut body, &partition(0..10), &results).unwrap();
+        assert!(matches!(
+            decode_results(&body),
+            Err(GlasshouseError::Malformed(message)) if message.contains("outside")
+        ));
+        body.clear();
+        let missing = Partition {
+            part: 2,
+            ..partition(10..20)
+        };
+        write_results(&mut body, &missing, &results).unwrap();
+        assert!(matches!(
+            decode_results(&body),
+            Err(GlasshouseError::Malformed(message)) if message.contains("does not exist")
+        ));
+    }
+}
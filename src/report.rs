@@ -0,0 +1,255 @@
+//! Machine-readable report of a search run.
+//!
+//! The report gathers what the logs of a run spread over many lines: the
+//! configuration, the size of the datasets, the duration of each phase, the
+//! latency percentiles of each query type and the recall when it was
+//! measured. It serializes to JSON so runs can be compared by scripts.
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::{BufWriter, Write};
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::config::Config;
+#[cfg(feature = "fs")]
+use crate::error;
+use crate::eval::{RecallEstimate, RecallReport};
+use crate::latency::{LatencyReport, Percentiles};
+use crate::memory::{self, MemoryReport};
+use crate::types::{NodesDataset, QueriesDataset, QueryType};
+
+/// Report of a search run, filled in as its phases complete.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub config: Config,
+    pub nodes: Option<DatasetSize>,
+    pub queries: Option<DatasetSize>,
+    /// Phases in the order they ran.
+    pub phases: Vec<PhaseTime>,
+    /// Percentiles over all answered queries first, then over the answered
+    /// queries of each type.
+    pub latencies: Vec<LatencySummary>,
+    /// Recall over all queries first, then over the queries of each type,
+    /// empty without ground truth.
+    pub recall: Vec<RecallSummary>,
+    pub estimated_recall: Option<EstimateSummary>,
+    pub memory: Option<MemorySummary>,
+}
+
+/// Rows, dimensionality and estimated heap memory of a dataset.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DatasetSize {
+    pub rows: u32,
+    pub dimensions: usize,
+    pub bytes: usize,
+}
+
+/// Wall-clock duration of a phase of the run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseTime {
+    pub phase: String,
+    pub seconds: f64,
+}
+
+/// Latency percentiles of a query type, `None` for all queries, in
+/// milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub query_type: Option<QueryType>,
+    pub queries: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Recall of a query type, `None` for all queries.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RecallSummary {
+    pub query_type: Option<QueryType>,
+    pub queries: usize,
+    pub recall: f64,
+    pub under_filled: usize,
+}
+
+/// Recall estimated from the exact answers of sampled queries.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct EstimateSummary {
+    pub queries: usize,
+    pub recall: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Peak resident set size and estimated heap memory, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MemorySummary {
+    pub peak_rss: Option<u64>,
+    pub vectors: usize,
+    pub attributes: usize,
+    pub queries: usize,
+    pub index: usize,
+}
+
+impl RunReport {
+    /// Returns an empty report of a run with the configuration.
+    pub fn new(config: &Config) -> Self {
+        RunReport {
+            config: config.clone(),
+            nodes: None,
+            queries: None,
+            phases: Vec::new(),
+            latencies: Vec::new(),
+            recall: Vec::new(),
+            estimated_recall: None,
+            memory: None,
+        }
+    }
+
+    /// Records the duration of a phase.
+    pub fn phase(&mut self, phase: &str, elapsed: Duration) {
+        self.phases.push(PhaseTime {
+            phase: phase.to_string(),
+            seconds: elapsed.as_secs_f64(),
+        });
+    }
+
+    /// Records the sizes of the datasets.
+    pub fn datasets(&mut self, nodes_dataset: &NodesDataset, queries_dataset: &QueriesDataset) {
+        self.nodes = Some(DatasetSize {
+            rows: nodes_dataset.num_vectors,
+            dimensions: nodes_dataset.dimensions,
+            bytes: memory::nodes_bytes(nodes_dataset),
+        });
+        self.queries = Some(DatasetSize {
+            rows: queries_dataset.num_queries,
+            dimensions: queries_dataset.dimensions,
+            bytes: memory::queries_bytes(queries_dataset),
+        });
+    }
+
+    /// Records the latency percentiles.
+    pub fn latencies(&mut self, report: &LatencyReport) {
+        let summary = |query_type, percentiles: &Percentiles| {
+            let ms = |duration: Duration| duration.as_secs_f64() * 1e3;
+            LatencySummary {
+                query_type,
+                queries: percentiles.queries,
+                p50_ms: ms(percentiles.p50),
+                p95_ms: ms(percentiles.p95),
+                p99_ms: ms(percentiles.p99),
+                max_ms: ms(percentiles.max),
+            }
+        };
+        self.latencies = report
+            .overall
+            .iter()
+            .map(|percentiles| summary(None, percentiles))
+            .chain(
+                report
+                    .by_type
+                    .iter()
+                    .map(|(query_type, percentiles)| summary(Some(*query_type), percentiles)),
+            )
+            .collect();
+    }
+
+    /// Records the recall measured against ground truth.
+    pub fn recall(&mut self, report: &RecallReport) {
+        let overall = std::iter::once((None, &report.overall));
+        let by_type = report
+            .by_type
+            .iter()
+            .map(|(query_type, recall)| (Some(*query_type), recall));
+        self.recall = overall
+            .chain(by_type)
+            .map(|(query_type, recall)| RecallSummary {
+                query_type,
+                queries: recall.queries,
+                recall: recall.recall,
+                under_filled: recall.under_filled,
+            })
+            .collect();
+    }
+
+    /// Records the recall estimated from sampled queries.
+    pub fn estimated_recall(&mut self, estimate: &RecallEstimate) {
+        self.estimated_recall = Some(EstimateSummary {
+            queries: estimate.queries,
+            recall: estimate.recall,
+            lower: estimate.lower,
+            upper: estimate.upper,
+        });
+    }
+
+    /// Records the memory used by the run.
+    pub fn memory(&mut self, report: &MemoryReport) {
+        self.memory = Some(MemorySummary {
+            peak_rss: report.peak_rss,
+            vectors: report.vectors,
+            attributes: report.attributes,
+            queries: report.queries,
+            index: report.index,
+        });
+    }
+
+    /// Writes the report as indented JSON.
+    #[cfg(feature = "fs")]
+    pub fn write<P: AsRef<Path>>(&self, file_path: P) -> error::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(std::io::Error::other)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Path of the report written next to an output file.
+#[cfg(feature = "fs")]
+pub fn report_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".report.json");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Recall;
+
+    #[test]
+    fn reports_serialize_every_section() {
+        let mut report = RunReport::new(&Config::default());
+        report.phase("search", Duration::from_millis(1500));
+        report.latencies(&LatencyReport {
+            overall: Percentiles::of(&[Duration::from_millis(2)]),
+            by_type: vec![(
+                QueryType::VectorOnly,
+                Percentiles::of(&[Duration::from_millis(2)]).unwrap(),
+            )],
+        });
+        let recall = Recall {
+            queries: 4,
+            recall: 0.75,
+            under_filled: 1,
+        };
+        report.recall(&RecallReport {
+            overall: recall,
+            by_type: vec![(QueryType::VectorOnly, recall)],
+        });
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["phases"][0]["phase"], "search");
+        assert_eq!(json["phases"][0]["seconds"], 1.5);
+        assert_eq!(json["latencies"][0]["query_type"], serde_json::Value::Null);
+        assert_eq!(json["latencies"][1]["query_type"], "VectorOnly");
+        assert_eq!(json["latencies"][1]["p99_ms"], 2.0);
+        assert_eq!(json["recall"][0]["recall"], 0.75);
+        assert_eq!(json["config"]["solver"], "baseline");
+        assert_eq!(json["estimated_recall"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,147 @@
+//! Random subsets of datasets, keeping the order of the sampled rows so
+//! that small development slices follow the attribute distributions of
+//! the full datasets, and random permutations of the nodes so that prefix
+//! sampling does not depend on the order of the file.
+use rand::Rng;
+use rand::seq::{SliceRandom, index};
+
+use crate::types::{NodesDataset, QueriesDataset};
+
+/// Returns the sorted indices of `fraction` of `len` rows, at least one row
+/// of a non-empty dataset is kept.
+pub fn indices<R: Rng>(rng: &mut R, len: usize, fraction: f64) -> Vec<usize> {
+    let amount = ((len as f64 * fraction.clamp(0.0, 1.0)).round() as usize).clamp(len.min(1), len);
+    let mut indices = index::sample(rng, len, amount).into_vec();
+    indices.sort_unstable();
+    indices
+}
+
+/// Samples `fraction` of the nodes that were not deleted.
+pub fn nodes<R: Rng>(rng: &mut R, nodes_dataset: &NodesDataset, fraction: f64) -> NodesDataset {
+    let live: Vec<usize> = (0..nodes_dataset.num_vectors)
+        .filter(|&id| !nodes_dataset.is_deleted(id))
+        .map(|id| id as usize)
+        .collect();
+    let mut sample = NodesDataset {
+        dimensions: nodes_dataset.dimensions,
+        ..Default::default()
+    };
+    for position in indices(rng, live.len(), fraction) {
+        let id = live[position];
+        sample.c_attrs.push(nodes_dataset.c_attrs[id]);
+        sample.t_attrs.push(nodes_dataset.t_attrs[id]);
+        sample.vectors.extend_from_slice(nodes_dataset.vector(id));
+        sample.num_vectors += 1;
+    }
+    sample.index_attributes();
+    sample
+}
+
+/// Shuffles the nodes, returns the shuffled dataset and the previous ID of
+/// every node indexed by its new ID. Deleted nodes stay deleted.
+pub fn shuffle<R: Rng>(rng: &mut R, nodes_dataset: &NodesDataset) -> (NodesDataset, Vec<u32>) {
+    let mut permutation: Vec<u32> = (0..nodes_dataset.num_vectors).collect();
+    permutation.shuffle(rng);
+    let mut shuffled = NodesDataset {
+        dimensions: nodes_dataset.dimensions,
+        num_vectors: nodes_dataset.num_vectors,
+        ..Default::default()
+    };
+    for &id in &permutation {
+        shuffled.c_attrs.push(nodes_dataset.c_attrs[id as usize]);
+        shuffled.t_attrs.push(nodes_dataset.t_attrs[id as usize]);
+        shuffled
+            .vectors
+            .extend_from_slice(nodes_dataset.vector(id as usize));
+    }
+    for (new_id, &id) in permutation.iter().enumerate() {
+        if nodes_dataset.is_deleted(id) {
+            shuffled.delete(new_id as u32);
+        }
+    }
+    shuffled.index_attributes();
+    (shuffled, permutation)
+}
+
+/// Samples `fraction` of the queries.
+pub fn queries<R: Rng>(
+    rng: &mut R,
+    queries_dataset: &QueriesDataset,
+    fraction: f64,
+) -> QueriesDataset {
+    let indices = indices(rng, queries_dataset.num_queries as usize, fraction);
+    select_queries(queries_dataset, &indices)
+}
+
+/// Returns the queries at the given indices, in that order.
+pub fn select_queries(queries_dataset: &QueriesDataset, indices: &[usize]) -> QueriesDataset {
+    let mut sample = QueriesDataset {
+        dimensions: queries_dataset.dimensions,
+        ..Default::default()
+    };
+    for &id in indices {
+        sample.query_types.push(queries_dataset.query_types[id]);
+        sample
+            .v_categoricals
+            .push(queries_dataset.v_categoricals[id]);
+        sample
+            .t_lower_bounds
+            .push(queries_dataset.t_lower_bounds[id]);
+        sample
+            .t_upper_bounds
+            .push(queries_dataset.t_upper_bounds[id]);
+        sample
+            .query_vectors
+            .extend_from_slice(queries_dataset.query_vector(id));
+        sample.num_queries += 1;
+    }
+    sample
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+
+    #[test]
+    fn samples_keep_rows_in_order() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut nodes_dataset = generate::nodes(&mut rng, 200, 4, 5);
+        nodes_dataset.delete(0);
+        let sample = nodes(&mut rng, &nodes_dataset, 0.1);
+
+        assert_eq!(sample.num_vectors, 20);
+        assert_eq!(sample.vectors.len(), 20 * 4);
+        let mut previous = 0;
+        for index in 0..sample.num_vectors as usize {
+            let id = (1..200)
+                .find(|&id| nodes_dataset.vector(id) == sample.vector(index))
+                .unwrap();
+            assert!(id > previous);
+            assert_eq!(sample.c_attrs[index], nodes_dataset.c_attrs[id]);
+            previous = id;
+        }
+        assert_eq!(indices(&mut rng, 10, 0.0).len(), 1);
+        assert_eq!(indices(&mut rng, 10, 2.0), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shuffled_nodes_follow_the_permutation() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut nodes_dataset = generate::nodes(&mut rng, 50, 3, 4);
+        nodes_dataset.delete(7);
+        let (shuffled, permutation) = shuffle(&mut rng, &nodes_dataset);
+
+        let mut sorted = permutation.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..50).collect::<Vec<_>>());
+        for (new_id, &id) in permutation.iter().enumerate() {
+            assert_eq!(shuffled.vector(new_id), nodes_dataset.vector(id as usize));
+            assert_eq!(shuffled.t_attrs[new_id], nodes_dataset.t_attrs[id as usize]);
+            assert_eq!(shuffled.is_deleted(new_id as u32), id == 7);
+        }
+    }
+}
@@ -0,0 +1,483 @@
+//! HTTP search server answering JSON queries against a loaded nodes dataset.
+//!
+//! The server exposes four endpoints:
+//!
+//! - `GET /health` returns the number of indexed nodes.
+//! - `GET /metrics` exports Prometheus metrics, see [`Metrics`].
+//! - `POST /search` answers a single query.
+//! - `POST /search/batch` answers an array of queries.
+//!
+//! A query holds its vector and optional filters, the query type is derived
+//! from the filters that are set:
+//!
+//! ```json
+//! { "vector": [0.1, 0.2], "category": 3, "t_lower": 0.25, "t_upper": 0.5 }
+//! ```
+//!
+//! Each answer lists the `k` nearest neighbours with their distance, a
+//! query may set its own `k` to override the one of the server. Under the
+//! cosine metric query vectors are scaled to unit length as the nodes are,
+//! queries with a zero vector are rejected, and distances are cosine
+//! distances, `1 - cos`.
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use prometheus::{Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Metric;
+use crate::constants::K_NEAREST;
+use crate::distance;
+use crate::solvers::Solver;
+use crate::types::{NodesDataset, ParsedQuery, QueryType, ScoredNeighbor};
+
+/// Nodes, solver and metrics shared by the request handlers.
+pub struct ServerState {
+    pub nodes_dataset: NodesDataset,
+    pub solver: Box<dyn Solver>,
+    /// Number of neighbours returned for queries that do not set `k`.
+    pub k: usize,
+    /// Metric the nodes were loaded under, queries are scaled to unit
+    /// length under the cosine metric.
+    pub metric: Metric,
+    metrics: Metrics,
+}
+
+impl ServerState {
+    pub fn new(nodes_dataset: NodesDataset, solver: Box<dyn Solver>) -> Self {
+        let metrics = Metrics::new().expect("metrics have unique names");
+        metrics.index_memory.set(solver.memory_bytes() as i64);
+        ServerState {
+            nodes_dataset,
+            solver,
+            k: K_NEAREST,
+            metric: Metric::L2,
+            metrics,
+        }
+    }
+
+    /// Sets the number of neighbours returned for queries that do not set
+    /// `k`.
+    pub fn with_k(mut self, k: usize) -> Self {
+        self.k = k;
+        self
+    }
+
+    /// Sets the metric the nodes were loaded under.
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+}
+
+/// Prometheus metrics of the search endpoints:
+///
+/// - `glasshouse_queries_total` counts the answered queries, its rate is the
+///   throughput of the server.
+/// - `glasshouse_query_latency_seconds` is the histogram of the time spent
+///   answering each query.
+/// - `glasshouse_distance_computations` is the histogram of the number of
+///   distances computed per query, as estimated by the plan of the solver
+///   (see [`Solver::explain`]) so the distance kernels stay free of counters.
+/// - `glasshouse_index_memory_bytes` is the memory held by the index.
+pub struct Metrics {
+    registry: Registry,
+    queries: IntCounter,
+    query_latency: Histogram,
+    distance_computations: Histogram,
+    index_memory: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let queries = IntCounter::new("glasshouse_queries_total", "Number of answered queries")?;
+        let query_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "glasshouse_query_latency_seconds",
+                "Time spent answering a query",
+            )
+            .buckets(prometheus::exponential_buckets(1e-5, 2.0, 20)?),
+        )?;
+        let distance_computations = Histogram::with_opts(
+            HistogramOpts::new(
+                "glasshouse_distance_computations",
+                "Estimated number of distances computed to answer a query",
+            )
+            .buckets(prometheus::exponential_buckets(1.0, 2.0, 25)?),
+        )?;
+        let index_memory = IntGauge::new(
+            "glasshouse_index_memory_bytes",
+            "Memory held by the index in bytes",
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(queries.clone()))?;
+        registry.register(Box::new(query_latency.clone()))?;
+        registry.register(Box::new(distance_computations.clone()))?;
+        registry.register(Box::new(index_memory.clone()))?;
+        Ok(Metrics {
+            registry,
+            queries,
+            query_latency,
+            distance_computations,
+            index_memory,
+        })
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    fn render(&self) -> Result<String, prometheus::Error> {
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
+
+/// Query received by the server.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchRequest {
+    pub vector: Vec<f32>,
+    /// Categorical attribute the neighbours must have.
+    #[serde(default)]
+    pub category: Option<i32>,
+    /// Inclusive lower bound of the neighbours timestamps.
+    #[serde(default)]
+    pub t_lower: Option<f32>,
+    /// Inclusive upper bound of the neighbours timestamps.
+    #[serde(default)]
+    pub t_upper: Option<f32>,
+    /// Number of neighbours to return, the `k` of the server when not set.
+    #[serde(default)]
+    pub k: Option<usize>,
+    /// Candidate list width of graph indexes for this query, the width of
+    /// the index when not set.
+    #[serde(default)]
+    pub ef_search: Option<usize>,
+}
+
+/// Answer to a single query.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SearchResponse {
+    pub neighbors: Vec<ScoredNeighbor>,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    nodes: u32,
+    dimensions: usize,
+}
+
+type ApiError = (StatusCode, String);
+
+impl SearchRequest {
+    fn query_type(&self) -> Result<QueryType, ApiError> {
+        let time_range = match (self.t_lower, self.t_upper) {
+            (Some(_), Some(_)) => true,
+            (None, None) => false,
+            _ => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "t_lower and t_upper must be set together".to_string(),
+                ));
+            }
+        };
+        Ok(match (self.category.is_some(), time_range) {
+            (false, false) => QueryType::VectorOnly,
+            (true, false) => QueryType::CategoricalConstraint,
+            (false, true) => QueryType::TimestampConstraint,
+            (true, true) => QueryType::BothConstraints,
+        })
+    }
+}
+
+/// Returns the router serving the search endpoints.
+pub fn router(state: Arc<ServerState>) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/metrics", get(metrics))
+        .route("/search", post(search))
+        .route("/search/batch", post(search_batch))
+        .with_state(state)
+}
+
+/// Serves the search endpoints on the given address until the process exits.
+pub async fn serve(addr: SocketAddr, state: ServerState) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router(Arc::new(state))).await
+}
+
+async fn health(State(state): State<Arc<ServerState>>) -> Json<HealthResponse> {
+    Json(HealthResponse {
+        nodes: state.nodes_dataset.num_vectors,
+        dimensions: state.nodes_dataset.dimensions,
+    })
+}
+
+async fn metrics(State(state): State<Arc<ServerState>>) -> Result<String, ApiError> {
+    state
+        .metrics
+        .render()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn search(
+    State(state): State<Arc<ServerState>>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    let mut responses = answer(state, vec![request]).await?;
+    Ok(Json(responses.remove(0)))
+}
+
+async fn search_batch(
+    State(state): State<Arc<ServerState>>,
+    Json(requests): Json<Vec<SearchRequest>>,
+) -> Result<Json<Vec<SearchResponse>>, ApiError> {
+    Ok(Json(answer(state, requests).await?))
+}
+
+/// Answers the queries on the blocking thread pool.
+async fn answer(
+    state: Arc<ServerState>,
+    mut requests: Vec<SearchRequest>,
+) -> Result<Vec<SearchResponse>, ApiError> {
+    for request in &requests {
+        if request.vector.len() != state.nodes_dataset.dimensions {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Expected a vector of dimension {}, got {}",
+                    state.nodes_dataset.dimensions,
+                    request.vector.len()
+                ),
+            ));
+        }
+        request.query_type()?;
+        if request.k == Some(0) {
+            return Err((StatusCode::BAD_REQUEST, "k must be positive".to_string()));
+        }
+        if request.ef_search == Some(0) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "ef_search must be positive".to_string(),
+            ));
+        }
+    }
+    if state.metric == Metric::Cosine {
+        for (index, request) in requests.iter_mut().enumerate() {
+            if !distance::normalize(&mut request.vector) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Query {} has a zero vector, which has no cosine similarity",
+                        index
+                    ),
+                ));
+            }
+        }
+    }
+
+    tokio::task::spawn_blocking(move || {
+        requests
+            .iter()
+            .map(|request| {
+                let query = ParsedQuery {
+                    query_type: request.query_type().expect("validated above"),
+                    v_categorical: request.category,
+                    t_lower_bound: request.t_lower,
+                    t_upper_bound: request.t_upper,
+                    query_vector: &request.vector,
+                };
+                let start = Instant::now();
+                // Larger values would only size the search buffers, there
+                // are never more neighbours than nodes.
+                let num_vectors = state.nodes_dataset.num_vectors as usize;
+                let k = request.k.unwrap_or(state.k).min(num_vectors);
+                let mut neighbors = match request.ef_search {
+                    Some(ef_search) => state.solver.search_scored_with_ef(
+                        &state.nodes_dataset,
+                        &query,
+                        k,
+                        ef_search.min(num_vectors),
+                    ),
+                    None => state.solver.search_scored(&state.nodes_dataset, &query, k),
+                };
+                state.metric.report(&mut neighbors);
+                state
+                    .metrics
+                    .query_latency
+                    .observe(start.elapsed().as_secs_f64());
+                let plan = state.solver.explain(&state.nodes_dataset, &query, k);
+                state
+                    .metrics
+                    .distance_computations
+                    .observe(plan.visited as f64);
+                state.metrics.queries.inc();
+                SearchResponse { neighbors }
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::solvers::Exact;
+
+    fn state() -> Arc<ServerState> {
+        let nodes_dataset = NodesDataset::from_parts(
+            2,
+            vec![0, 1, 1],
+            vec![0.1, 0.5, 0.9],
+            vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0],
+        )
+        .unwrap();
+        Arc::new(ServerState::new(nodes_dataset, Box::new(Exact)))
+    }
+
+    async fn post(uri: &str, body: &str) -> (StatusCode, Vec<u8>) {
+        let request = Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router(state()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn answers_filtered_queries() {
+        let (status, body) = post(
+            "/search",
+            r#"{"vector": [2.0, 2.0], "category": 1, "t_lower": 0.0, "t_upper": 0.6}"#,
+        )
+        .await;
+        let response: SearchResponse = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(status, StatusCode::OK);
+        // Only node 1 passes the filters, the response is not padded to k.
+        assert_eq!(
+            response.neighbors,
+            [ScoredNeighbor {
+                id: 1,
+                distance: 2.0
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn queries_can_set_k() {
+        let (status, body) = post("/search", r#"{"vector": [2.0, 2.0], "k": 2}"#).await;
+        let response: SearchResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let ids: Vec<u32> = response.neighbors.iter().map(|n| n.id).collect();
+        assert_eq!(ids, [2, 1]);
+
+        let (status, _) = post("/search", r#"{"vector": [2.0, 2.0], "k": 0}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = post(
+            "/search",
+            r#"{"vector": [2.0, 2.0], "k": 18446744073709551615}"#,
+        )
+        .await;
+        let response: SearchResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.neighbors.len(), 3);
+
+        let (status, body) = post("/search", r#"{"vector": [2.0, 2.0], "ef_search": 8}"#).await;
+        let response: SearchResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.neighbors[0].id, 2);
+        let (status, _) = post("/search", r#"{"vector": [2.0, 2.0], "ef_search": 0}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn scales_queries_under_the_cosine_metric() {
+        let mut nodes_dataset =
+            NodesDataset::from_parts(2, vec![0, 0], vec![0.0, 0.0], vec![1.0, 0.0, 0.0, 2.0])
+                .unwrap();
+        nodes_dataset.normalize_vectors();
+        let state =
+            Arc::new(ServerState::new(nodes_dataset, Box::new(Exact)).with_metric(Metric::Cosine));
+        let post = |body: &'static str| {
+            let state = state.clone();
+            async move {
+                let request = Request::post("/search")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap();
+                let response = router(state).oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, body)
+            }
+        };
+        let (_, body) = post(r#"{"vector": [0.0, 5.0], "k": 1}"#).await;
+        let response: SearchResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            response.neighbors,
+            [ScoredNeighbor {
+                id: 1,
+                distance: 0.0
+            }]
+        );
+
+        // Distances are cosine distances, 1 - cos.
+        let (_, body) = post(r#"{"vector": [1.0, 1.0]}"#).await;
+        let response: SearchResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response.neighbors.len(), 2);
+        for neighbor in &response.neighbors {
+            assert!((neighbor.distance - (1.0 - std::f32::consts::FRAC_1_SQRT_2)).abs() < 1e-6);
+        }
+
+        let (status, _) = post(r#"{"vector": [0.0, 0.0]}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn exports_metrics() {
+        let state = state();
+        let request = Request::post("/search/batch")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"[{"vector": [2.0, 2.0]}, {"vector": [0.0, 0.0]}]"#,
+            ))
+            .unwrap();
+        router(state.clone()).oneshot(request).await.unwrap();
+
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = router(state).oneshot(request).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(metrics.contains("glasshouse_queries_total 2"));
+        assert!(metrics.contains("glasshouse_query_latency_seconds_count 2"));
+        // The exact solver computes one distance per node.
+        assert!(metrics.contains("glasshouse_distance_computations_sum 6"));
+        assert!(metrics.contains("glasshouse_index_memory_bytes 0"));
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_queries() {
+        let (status, _) = post("/search", r#"{"vector": [2.0]}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = post(
+            "/search/batch",
+            r#"[{"vector": [2.0, 2.0], "t_lower": 0.1}]"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,264 @@
+//! Partitioning of the nodes across worker processes.
+//!
+//! Machines that cannot hold every node and index in a single process split
+//! the nodes in contiguous partitions, each searched by a worker process
+//! with an index of its own over its partition only. Every worker answers
+//! all queries with the `k` nearest neighbours of its partition, under
+//! their ID in the whole dataset, and the global answer of a query is the
+//! `k` closest of the neighbours found by the workers.
+use std::ops::Range;
+
+use crate::error::{self, GlasshouseError};
+use crate::types::{ScoredNeighbor, ScoredResults};
+
+/// Partition of the nodes held by a worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// Index of the partition, from 0.
+    pub part: u32,
+    /// Number of partitions the nodes are split in.
+    pub parts: u32,
+    /// IDs of the nodes of the partition in the whole dataset.
+    pub ids: Range<u32>,
+}
+
+/// Returns the IDs of the nodes of a partition among `parts` contiguous
+/// partitions of `num_vectors` nodes, the first partitions holding one more
+/// node when the nodes do not divide evenly.
+///
+/// # Panics
+///
+/// Panics if `part` is not below `parts`.
+pub fn partition(num_vectors: u32, part: usize, parts: usize) -> Range<u32> {
+    assert!(
+        part < parts,
+        "Partition {} of {} does not exist",
+        part,
+        parts
+    );
+    let (len, extra) = (num_vectors as usize / parts, num_vectors as usize % parts);
+    let start = part * len + part.min(extra);
+    let end = start + len + usize::from(part < extra);
+    start as u32..end as u32
+}
+
+/// Checks that the partitions of the workers of a run hold every node once:
+/// the workers hold each partition of the same split, whose nodes follow
+/// each other from the first node without overlapping.
+pub fn check_coverage(partitions: &[Partition]) -> error::Result<()> {
+    let malformed = |message: String| Err(GlasshouseError::Malformed(message));
+    if let Some(partition) = partitions
+        .iter()
+        .find(|partition| partition.parts as usize != partitions.len())
+    {
+        return malformed(format!(
+            "Partition {} of {} is served among {} workers",
+            partition.part,
+            partition.parts,
+            partitions.len()
+        ));
+    }
+    let mut sorted: Vec<&Partition> = partitions.iter().collect();
+    sorted.sort_by_key(|partition| partition.part);
+    let mut end = 0;
+    for (part, partition) in sorted.into_iter().enumerate() {
+        if (partition.part as usize) < part {
+            return malformed(format!(
+                "Partition {} is served by several workers",
+                partition.part
+            ));
+        }
+        if partition.part as usize > part {
+            return malformed(format!("Partition {} is served by no worker", part));
+        }
+        if partition.ids.start != end {
+            return malformed(format!(
+                "Partition {} holds nodes {:?} but the previous partitions end at node {}",
+                part, partition.ids, end
+            ));
+        }
+        end = partition.ids.end;
+    }
+    Ok(())
+}
+
+/// Shifts the IDs of the neighbours found in a partition by the ID of its
+/// first node, so that they are IDs in the whole dataset.
+pub fn offset(results: &mut ScoredResults, first_id: u32) {
+    for neighbor in results.iter_mut().flatten() {
+        neighbor.id += first_id;
+    }
+}
+
+/// Merges the results of every partition into the `k` closest neighbours
+/// of each query, by increasing ID at equal distances. All partitions must
+/// answer the same queries.
+pub fn merge(partitions: Vec<ScoredResults>, k: usize) -> error::Result<ScoredResults> {
+    let mut partitions = partitions.into_iter();
+    let Some(mut merged) = partitions.next() else {
+        return Ok(Vec::new());
+    };
+    for (part, results) in partitions.enumerate() {
+        if results.len() != merged.len() {
+            return Err(GlasshouseError::Malformed(format!(
+                "Partition {} answers {} queries but partition 0 answers {}",
+                part + 1,
+                results.len(),
+                merged.len()
+            )));
+        }
+        for (merged, result) in merged.iter_mut().zip(results) {
+            merged.extend(result);
+        }
+    }
+    for result in &mut merged {
+        result.sort_by(|a: &ScoredNeighbor, b| {
+            a.distance.total_cmp(&b.distance).then(a.id.cmp(&b.id))
+        });
+        result.truncate(k);
+    }
+    Ok(merged)
+}
+
+/// Clears the merged results of the queries left unanswered by any
+/// partition, `answered` holding whether each partition answered each
+/// query. They are padded in the results like the queries a single process
+/// leaves unanswered at the deadline, rather than answered from the other
+/// partitions only. Returns the number of queries answered by every
+/// partition.
+pub fn drop_unanswered(results: &mut ScoredResults, answered: &[Vec<bool>]) -> u32 {
+    let mut complete = 0;
+    for (query, result) in results.iter_mut().enumerate() {
+        if answered
+            .iter()
+            .all(|answered| answered.get(query) == Some(&true))
+        {
+            complete += 1;
+        } else {
+            result.clear();
+        }
+    }
+    complete
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::solvers::{Exact, run_scored};
+    use crate::types::NodesDataset;
+
+    #[test]
+    fn merged_partitions_answer_like_the_whole_dataset() {
+        let ranges: Vec<_> = (0..3).map(|part| partition(10, part, 3)).collect();
+        assert_eq!(ranges, [0..4, 4..7, 7..10]);
+        assert_eq!(partition(2, 2, 3), 2..2);
+
+        let mut rng = StdRng::seed_from_u64(53);
+        let nodes = generate::nodes(&mut rng, 300, 4, 4);
+        let queries = generate::queries(&mut rng, 20, 4, 4);
+        let partitions = (0..3)
+            .map(|part| {
+                let range = partition(nodes.num_vectors, part, 3);
+                let (start, end) = (range.start as usize, range.end as usize);
+                let slice = NodesDataset::from_parts(
+                    nodes.dimensions,
+                    nodes.c_attrs[start..end].to_vec(),
+                    nodes.t_attrs[start..end].to_vec(),
+                    nodes.vectors[start * 4..end * 4].to_vec(),
+                )
+                .unwrap();
+                let mut results = run_scored(&Exact, &slice, &queries, 10).unwrap();
+                offset(&mut results, range.start);
+                results
+            })
+            .collect();
+
+        let merged = merge(partitions, 10).unwrap();
+        assert_eq!(merged, run_scored(&Exact, &nodes, &queries, 10).unwrap());
+        assert!(matches!(
+            merge(vec![vec![Vec::new()], Vec::new()], 10),
+            Err(GlasshouseError::Malformed(_))
+        ));
+        assert_eq!(merge(Vec::new(), 10).unwrap(), ScoredResults::new());
+    }
+
+    #[test]
+    fn empty_partitions_find_no_neighbours() {
+        let mut rng = StdRng::seed_from_u64(57);
+        let nodes = generate::nodes(&mut rng, 2, 4, 4);
+        let queries = generate::queries(&mut rng, 5, 4, 4);
+        let partitions: Vec<_> = (0..3)
+            .map(|part| Partition {
+                part,
+                parts: 3,
+                ids: partition(nodes.num_vectors, part as usize, 3),
+            })
+            .collect();
+        assert_eq!(partitions[2].ids, 2..2);
+        assert!(check_coverage(&partitions).is_ok());
+
+        let results = partitions
+            .iter()
+            .map(|partition| {
+                let (start, end) = (partition.ids.start as usize, partition.ids.end as usize);
+                let slice = NodesDataset::from_parts(
+                    nodes.dimensions,
+                    nodes.c_attrs[start..end].to_vec(),
+                    nodes.t_attrs[start..end].to_vec(),
+                    nodes.vectors[start * 4..end * 4].to_vec(),
+                )
+                .unwrap();
+                let mut results = run_scored(&Exact, &slice, &queries, 10).unwrap();
+                offset(&mut results, partition.ids.start);
+                results
+            })
+            .collect::<Vec<_>>();
+        assert!(results[2].iter().all(Vec::is_empty));
+
+        let merged = merge(results, 10).unwrap();
+        assert_eq!(merged, run_scored(&Exact, &nodes, &queries, 10).unwrap());
+    }
+
+    #[test]
+    fn partitions_must_hold_every_node_once() {
+        let partitions: Vec<_> = (0..3)
+            .map(|part| Partition {
+                part,
+                parts: 3,
+                ids: partition(10, part as usize, 3),
+            })
+            .collect();
+        assert!(check_coverage(&partitions).is_ok());
+        assert!(check_coverage(&[]).is_ok());
+
+        // A missing worker.
+        assert!(check_coverage(&partitions[..2]).is_err());
+        // Two workers serving the same partition.
+        let mut duplicated = partitions.clone();
+        duplicated[2] = partitions[1].clone();
+        assert!(check_coverage(&duplicated).is_err());
+        // Workers splitting the nodes in different ways.
+        let mut overlapping = partitions.clone();
+        overlapping[1].ids = 3..7;
+        assert!(check_coverage(&overlapping).is_err());
+        let mut gap = partitions;
+        gap[2].ids = 8..10;
+        assert!(check_coverage(&gap).is_err());
+    }
+
+    #[test]
+    fn queries_unanswered_by_a_partition_are_padded() {
+        let neighbor = |id| ScoredNeighbor { id, distance: 1.0 };
+        let mut results = vec![vec![neighbor(0)], vec![neighbor(1)], vec![neighbor(2)]];
+        let answered = [vec![true, true, false], vec![true, false, false]];
+
+        assert_eq!(drop_unanswered(&mut results, &answered), 1);
+        assert_eq!(results, [vec![neighbor(0)], Vec::new(), Vec::new()]);
+        // A partition missing its flags answered none of the queries.
+        assert_eq!(drop_unanswered(&mut results, &[Vec::new()]), 0);
+    }
+}
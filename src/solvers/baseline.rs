@@ -0,0 +1,175 @@
+//! Baseline solution scanning a random sample of the nodes.
+//!
+//! The sample is drawn uniformly over every node ID from a seed, rather
+//! than taken from the start of the file, which is biased when the nodes
+//! are ordered by category or timestamp.
+use std::mem;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::index;
+
+use crate::distance::l2;
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, ParsedQuery, ScoredResult};
+
+use super::plan::{explain, filter_first};
+use super::{Neighbor, Plan, Solver, Strategy, top_k};
+
+/// Baseline solution.
+#[derive(Debug, Clone)]
+pub struct Baseline {
+    /// Sampled node IDs in increasing order, scanned for each query.
+    sample: Vec<u32>,
+    seed: u64,
+}
+
+/// Builder validating the parameters of the [`Baseline`] solver.
+#[derive(Debug, Clone)]
+pub struct BaselineBuilder {
+    sample_proportion: f32,
+    seed: u64,
+}
+
+impl Default for BaselineBuilder {
+    fn default() -> Self {
+        BaselineBuilder {
+            sample_proportion: Baseline::DEFAULT_SAMPLE_PROPORTION,
+            seed: 0,
+        }
+    }
+}
+
+impl BaselineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Proportion of the nodes scanned for each query, in `(0, 1]`.
+    pub fn sample_proportion(mut self, sample_proportion: f32) -> Self {
+        self.sample_proportion = sample_proportion;
+        self
+    }
+
+    /// Seed from which the sampled nodes are drawn.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Checks that the parameters describe a valid baseline.
+    pub fn validate(&self) -> error::Result<()> {
+        if !(self.sample_proportion > 0.0 && self.sample_proportion <= 1.0) {
+            return Err(GlasshouseError::Config(format!(
+                "Baseline sample proportion must be in (0, 1], got {}",
+                self.sample_proportion
+            )));
+        }
+        Ok(())
+    }
+
+    /// Validates the parameters and creates a baseline over the nodes.
+    pub fn build(&self, nodes_dataset: &NodesDataset) -> error::Result<Baseline> {
+        self.validate()?;
+        let num_to_sample = ((nodes_dataset.num_vectors as f32 * self.sample_proportion) as u32)
+            .max(1)
+            .min(nodes_dataset.num_vectors);
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut sample: Vec<u32> = index::sample(
+            &mut rng,
+            nodes_dataset.num_vectors as usize,
+            num_to_sample as usize,
+        )
+        .into_iter()
+        .map(|node_id| node_id as u32)
+        .collect();
+        // Scanning in ID order keeps the vector reads sequential.
+        sample.sort_unstable();
+        Ok(Baseline {
+            sample,
+            seed: self.seed,
+        })
+    }
+}
+
+impl Baseline {
+    pub const DEFAULT_SAMPLE_PROPORTION: f32 = 0.001;
+
+    /// Number of nodes scanned for each query.
+    pub fn num_to_sample(&self) -> u32 {
+        self.sample.len() as u32
+    }
+
+    /// Sampled node IDs, in increasing order.
+    pub fn sample(&self) -> &[u32] {
+        &self.sample
+    }
+
+    /// Keeps a uniform random subset of `amount` of the sampled nodes, a
+    /// uniform sample of the nodes itself.
+    fn resample(&mut self, amount: usize) {
+        let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(amount as u64));
+        let mut sample: Vec<u32> = index::sample(&mut rng, self.sample.len(), amount)
+            .into_iter()
+            .map(|position| self.sample[position])
+            .collect();
+        sample.sort_unstable();
+        self.sample = sample;
+    }
+}
+
+impl Solver for Baseline {
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        // Every node passing a selective filter is scanned rather than the
+        // sample when there are fewer of them.
+        if let Some(result) = filter_first(nodes_dataset, query, k, self.sample.len()) {
+            return result;
+        }
+
+        let mut qualified_candidates: Vec<Neighbor> = Vec::new();
+
+        for &node_id in &self.sample {
+            let Some(node) = nodes_dataset.get(node_id as usize) else {
+                continue;
+            };
+
+            if query.matches(&node) {
+                let distance = l2(query.query_vector, node.vector);
+                qualified_candidates.push(Neighbor {
+                    distance,
+                    id: node_id,
+                });
+            }
+        }
+
+        top_k(qualified_candidates, k)
+    }
+
+    fn explain(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, _k: usize) -> Plan {
+        let index = format!("random sample of {} nodes", self.sample.len());
+        explain(
+            nodes_dataset,
+            query,
+            self.sample.len(),
+            Strategy::PostFilter { index },
+        )
+    }
+
+    fn degrade(&mut self, k: usize) -> bool {
+        // A sample smaller than k cannot hold k neighbours.
+        if self.sample.len() <= k.max(1) {
+            return false;
+        }
+        self.resample((self.sample.len() / 2).max(k));
+        true
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.sample.capacity() * mem::size_of::<u32>()
+    }
+}
@@ -0,0 +1,135 @@
+//! Grouping of the queries run back-to-back.
+//!
+//! Queries of the same type and category over nearby timestamps visit the
+//! same nodes, index lists and attribute index entries. Running them next
+//! to each other keeps those in cache, their results are put back in query
+//! order once answered. Identical queries are only answered once.
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::types::{ParsedQuery, QueriesDataset};
+
+/// Number of buckets the normalized timestamps are grouped into.
+const TIME_BUCKETS: f32 = 64.0;
+
+/// Returns the indices of the queries in the order they are run, grouped by
+/// type, category and bucket of their lower timestamp bound. Queries of the
+/// same group keep their relative order.
+pub(crate) fn order(queries_dataset: &QueriesDataset) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..queries_dataset.num_queries as usize).collect();
+    order.sort_by_cached_key(|&index| {
+        key(&queries_dataset
+            .get(index)
+            .expect("query indices are in range"))
+    });
+    order
+}
+
+/// Returns, for each query, the index of the first query identical to it,
+/// its own index if there is none. Queries are identical when they have the
+/// same type, filters and vector, bit for bit.
+pub(crate) fn first_duplicates(queries_dataset: &QueriesDataset) -> Vec<usize> {
+    let mut first: HashMap<QueryKey<'_>, usize> = HashMap::new();
+    (0..queries_dataset.num_queries as usize)
+        .map(|index| {
+            let query = queries_dataset
+                .get(index)
+                .expect("query indices are in range");
+            *first.entry(QueryKey(query)).or_insert(index)
+        })
+        .collect()
+}
+
+/// Compares and hashes the bits of a query.
+struct QueryKey<'a>(ParsedQuery<'a>);
+
+impl QueryKey<'_> {
+    fn bits(&self) -> (u8, Option<i32>, Option<u32>, Option<u32>) {
+        let query = &self.0;
+        (
+            query.query_type as u8,
+            query.v_categorical,
+            query.t_lower_bound.map(f32::to_bits),
+            query.t_upper_bound.map(f32::to_bits),
+        )
+    }
+}
+
+impl PartialEq for QueryKey<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits() == other.bits()
+            && self.0.query_vector.len() == other.0.query_vector.len()
+            && self
+                .0
+                .query_vector
+                .iter()
+                .zip(other.0.query_vector)
+                .all(|(a, b)| a.to_bits() == b.to_bits())
+    }
+}
+
+impl Eq for QueryKey<'_> {}
+
+impl Hash for QueryKey<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits().hash(state);
+        for value in self.0.query_vector {
+            value.to_bits().hash(state);
+        }
+    }
+}
+
+fn key(query: &ParsedQuery<'_>) -> (u8, Option<i32>, Option<i64>) {
+    (
+        query.query_type as u8,
+        query.v_categorical,
+        // Open bounds saturate to the first or last bucket.
+        query
+            .t_lower_bound
+            .map(|lower| (lower * TIME_BUCKETS).floor() as i64),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{OptionalFilterValue, QueryType};
+
+    #[test]
+    fn queries_are_grouped_by_type_category_and_time() {
+        let rows = [
+            (QueryType::BothConstraints, 2.0, 0.5),
+            (QueryType::VectorOnly, -1.0, -1.0),
+            (QueryType::CategoricalConstraint, 3.0, -1.0),
+            (QueryType::BothConstraints, 2.0, 0.1),
+            (QueryType::CategoricalConstraint, 1.0, -1.0),
+            (QueryType::VectorOnly, -1.0, -1.0),
+            (QueryType::BothConstraints, 2.0, 0.501),
+        ];
+        let queries = QueriesDataset {
+            num_queries: rows.len() as u32,
+            dimensions: 1,
+            query_types: rows.iter().map(|row| row.0).collect(),
+            v_categoricals: rows
+                .iter()
+                .map(|row| OptionalFilterValue::new(row.1))
+                .collect(),
+            t_lower_bounds: rows
+                .iter()
+                .map(|row| OptionalFilterValue::new(row.2))
+                .collect(),
+            t_upper_bounds: rows
+                .iter()
+                .map(|row| OptionalFilterValue::new(if row.2 < 0.0 { -1.0 } else { 1.0 }))
+                .collect(),
+            query_vectors: vec![0.0; rows.len()],
+        };
+
+        assert_eq!(order(&queries), [1, 5, 4, 2, 3, 0, 6]);
+        assert_eq!(first_duplicates(&queries), [0, 1, 2, 3, 4, 1, 6]);
+
+        let mut queries = queries;
+        queries.query_vectors[5] = 1.0;
+        assert_eq!(first_duplicates(&queries), [0, 1, 2, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,383 @@
+//! Checkpoints of the results of long runs.
+//!
+//! A checkpoint file starts with a header made of the `GHCP` magic, the
+//! format version, `k`, the number of queries of the run and the CRC32 of
+//! the nodes and queries datasets. It is followed by the results of the queries
+//! answered so far, in query order: the number of neighbours of each query,
+//! then their IDs and distances. All integers and floats are stored in
+//! little-endian order.
+#[cfg(feature = "fs")]
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+#[cfg(feature = "fs")]
+use std::io::{BufReader, BufWriter};
+use std::mem;
+#[cfg(feature = "fs")]
+use std::path::Path;
+#[cfg(feature = "fs")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "fs")]
+use rayon::prelude::*;
+
+use crate::error;
+#[cfg(feature = "fs")]
+use crate::error::GlasshouseError;
+#[cfg(feature = "fs")]
+use crate::progress::{Phase, Progress, Tracker};
+use crate::types::{NodesDataset, QueriesDataset, ScoredNeighbor, ScoredResult, ScoredResults};
+
+#[cfg(feature = "fs")]
+use super::Solver;
+use super::persist::{MAX_PREALLOCATED_BYTES, malformed, read_u32, write_u32};
+
+const MAGIC: [u8; 4] = *b"GHCP";
+/// Current version of the checkpoint format.
+const FORMAT_VERSION: u32 = 2;
+
+/// Number of queries answered between two chances to save a checkpoint.
+#[cfg(feature = "fs")]
+const CHUNK_QUERIES: usize = 1024;
+
+/// Results of the first queries of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// Number of neighbours searched for each query.
+    pub k: usize,
+    /// Number of queries of the run.
+    pub num_queries: u32,
+    /// CRC32 of the nodes dataset of the run.
+    pub nodes_checksum: u32,
+    /// CRC32 of the queries dataset of the run.
+    pub queries_checksum: u32,
+    /// Results of the queries answered so far, in query order.
+    pub results: ScoredResults,
+}
+
+impl Checkpoint {
+    /// Returns the checkpoint of a run searching the `k` nearest neighbours
+    /// of the queries among the nodes that did not answer any yet.
+    pub fn new(nodes_dataset: &NodesDataset, queries_dataset: &QueriesDataset, k: usize) -> Self {
+        Checkpoint {
+            k,
+            num_queries: queries_dataset.num_queries,
+            nodes_checksum: nodes_dataset.checksum(),
+            queries_checksum: queries_dataset.checksum(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Saves the checkpoint to a file. The checkpoint is written next to it
+    /// first and then renamed, so a crash while saving keeps the previous
+    /// checkpoint intact.
+    #[cfg(feature = "fs")]
+    pub fn save<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        let mut partial = file_path.as_os_str().to_owned();
+        partial.push(".partial");
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        self.write_to(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&partial, file_path)
+    }
+
+    /// Loads a checkpoint from a file.
+    #[cfg(feature = "fs")]
+    pub fn load<P: AsRef<Path>>(file_path: P) -> error::Result<Self> {
+        let file = File::open(file_path)?;
+        Self::read_from(&mut BufReader::new(file))
+    }
+
+    /// Serializes the checkpoint.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        write_u32(writer, FORMAT_VERSION)?;
+        write_u32(writer, self.k as u32)?;
+        write_u32(writer, self.num_queries)?;
+        write_u32(writer, self.nodes_checksum)?;
+        write_u32(writer, self.queries_checksum)?;
+        write_u32(writer, self.results.len() as u32)?;
+        for result in &self.results {
+            write_u32(writer, result.len() as u32)?;
+            for neighbor in result {
+                write_u32(writer, neighbor.id)?;
+                writer.write_all(&neighbor.distance.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deserializes a checkpoint.
+    pub fn read_from<R: Read>(reader: &mut R) -> error::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(malformed("Not a glasshouse checkpoint file".to_string()));
+        }
+        let version = read_u32(reader)?;
+        if version != FORMAT_VERSION {
+            return Err(malformed(format!(
+                "Unsupported checkpoint format version {}, expected {}",
+                version, FORMAT_VERSION
+            )));
+        }
+        let k = read_u32(reader)? as usize;
+        let num_queries = read_u32(reader)?;
+        let nodes_checksum = read_u32(reader)?;
+        let queries_checksum = read_u32(reader)?;
+        let answered = read_u32(reader)?;
+        if answered > num_queries {
+            return Err(malformed(format!(
+                "Checkpoint holds {} results of a run of {} queries",
+                answered, num_queries
+            )));
+        }
+
+        // Counts are only trusted up to `MAX_PREALLOCATED_BYTES`, so a
+        // corrupt file fails on the first missing result instead.
+        let mut results = Vec::with_capacity(
+            (answered as usize).min(MAX_PREALLOCATED_BYTES / mem::size_of::<ScoredResult>()),
+        );
+        for query in 0..answered {
+            let len = read_u32(reader)? as usize;
+            if len > k {
+                return Err(malformed(format!(
+                    "Query {} holds {} neighbours, more than k = {}",
+                    query, len, k
+                )));
+            }
+            let mut result = Vec::with_capacity(
+                len.min(MAX_PREALLOCATED_BYTES / mem::size_of::<ScoredNeighbor>()),
+            );
+            for _ in 0..len {
+                let id = read_u32(reader)?;
+                let mut distance = [0u8; 4];
+                reader.read_exact(&mut distance)?;
+                result.push(ScoredNeighbor {
+                    id,
+                    distance: f32::from_le_bytes(distance),
+                });
+            }
+            results.push(result);
+        }
+        Ok(Checkpoint {
+            k,
+            num_queries,
+            nodes_checksum,
+            queries_checksum,
+            results,
+        })
+    }
+}
+
+/// Same as [`run_scored_with_progress`](super::run_scored_with_progress)
+/// for the `k` of the checkpoint, answering the queries in order by chunks
+/// and saving the results answered so far to a checkpoint at
+/// `checkpoint_path` whenever `interval` elapsed since the previous one.
+///
+/// Runs start from [`Checkpoint::new`], runs resumed from the checkpoint of
+/// an interrupted one skip the queries it answered. The checkpoint must
+/// answer the same queries against the same nodes.
+#[cfg(feature = "fs")]
+pub fn run_checkpointed<S: Solver + ?Sized>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    mut checkpoint: Checkpoint,
+    checkpoint_path: &Path,
+    interval: Duration,
+    progress: &dyn Progress,
+) -> error::Result<ScoredResults> {
+    if nodes_dataset.dimensions != queries_dataset.dimensions {
+        return Err(GlasshouseError::DimensionMismatch {
+            nodes: nodes_dataset.dimensions,
+            queries: queries_dataset.dimensions,
+        });
+    }
+    if checkpoint.num_queries != queries_dataset.num_queries
+        || checkpoint.queries_checksum != queries_dataset.checksum()
+    {
+        return Err(GlasshouseError::Config(
+            "Checkpoint was saved by a run of other queries".to_string(),
+        ));
+    }
+    if checkpoint.nodes_checksum != nodes_dataset.checksum() {
+        return Err(GlasshouseError::Config(
+            "Checkpoint was saved by a run against other nodes".to_string(),
+        ));
+    }
+
+    let k = checkpoint.k;
+    let num_queries = queries_dataset.num_queries as usize;
+    let tracker = Tracker::new(progress, Phase::Search, num_queries as u64);
+    tracker.advance(checkpoint.results.len() as u64);
+    let mut saved = Instant::now();
+    while checkpoint.results.len() < num_queries {
+        let start = checkpoint.results.len();
+        let end = (start + CHUNK_QUERIES).min(num_queries);
+        let chunk: ScoredResults = (start..end)
+            .into_par_iter()
+            .map(|i| {
+                let query = queries_dataset.get(i).expect("query indices are in range");
+                let result = solver.search_scored(nodes_dataset, &query, k);
+                tracker.advance(1);
+                result
+            })
+            .collect();
+        checkpoint.results.extend(chunk);
+
+        if checkpoint.results.len() < num_queries && saved.elapsed() >= interval {
+            checkpoint.save(checkpoint_path)?;
+            saved = Instant::now();
+        }
+    }
+    Ok(checkpoint.results)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::error::GlasshouseError;
+    use crate::generate;
+    #[cfg(feature = "fs")]
+    use crate::progress::NoProgress;
+    use crate::solvers::{Exact, run_scored};
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let mut rng = StdRng::seed_from_u64(59);
+        let nodes = generate::nodes(&mut rng, 200, 4, 4);
+        let queries = generate::queries(&mut rng, 20, 4, 4);
+        let checkpoint = Checkpoint {
+            k: 10,
+            num_queries: 30,
+            nodes_checksum: 5,
+            queries_checksum: 7,
+            results: run_scored(&Exact, &nodes, &queries, 10).unwrap(),
+        };
+
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        assert_eq!(Checkpoint::read_from(&mut &bytes[..]).unwrap(), checkpoint);
+        assert!(Checkpoint::read_from(&mut &bytes[..bytes.len() - 1]).is_err());
+        bytes[0] = b'X';
+        assert!(Checkpoint::read_from(&mut &bytes[..]).is_err());
+
+        // Corrupt counts fail on the missing results without reserving them.
+        let huge = Checkpoint {
+            k: u32::MAX as usize,
+            num_queries: u32::MAX,
+            nodes_checksum: 0,
+            queries_checksum: 0,
+            results: vec![Vec::new()],
+        };
+        let mut bytes = Vec::new();
+        huge.write_to(&mut bytes).unwrap();
+        let answered = bytes.len() - 8;
+        bytes[answered..answered + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes[answered + 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Checkpoint::read_from(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let checkpoint = Checkpoint {
+            k: 1,
+            num_queries: 1,
+            nodes_checksum: 0,
+            queries_checksum: 0,
+            results: vec![vec![ScoredNeighbor {
+                id: 3,
+                distance: 0.5,
+            }]],
+        };
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        let malformed = |offset: usize, value: u32| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            matches!(
+                Checkpoint::read_from(&mut &bytes[..]),
+                Err(GlasshouseError::Malformed(_))
+            )
+        };
+
+        // Version, then k, the number of queries and the number answered.
+        assert!(malformed(4, FORMAT_VERSION + 1));
+        assert!(malformed(8, 0));
+        assert!(malformed(12, 0));
+        assert!(malformed(24, 2));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn runs_save_the_answered_queries() {
+        let mut rng = StdRng::seed_from_u64(61);
+        let nodes = generate::nodes(&mut rng, 200, 4, 4);
+        let queries = generate::queries(&mut rng, CHUNK_QUERIES as u32 + 100, 4, 4);
+        let path = crate::testing::temp_path("checkpoint.bin");
+        let expected = run_scored(&Exact, &nodes, &queries, 10).unwrap();
+
+        let results = run_checkpointed(
+            &Exact,
+            &nodes,
+            &queries,
+            Checkpoint::new(&nodes, &queries, 10),
+            &path,
+            Duration::ZERO,
+            &NoProgress,
+        )
+        .unwrap();
+        assert_eq!(results, expected);
+        // Only the first chunk is saved, the last one completes the run.
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.num_queries, queries.num_queries);
+        assert_eq!(checkpoint.nodes_checksum, nodes.checksum());
+        assert_eq!(checkpoint.queries_checksum, queries.checksum());
+        assert_eq!(checkpoint.results, expected[..CHUNK_QUERIES]);
+
+        let resumed = |checkpoint: Checkpoint| {
+            run_checkpointed(
+                &Exact,
+                &nodes,
+                &queries,
+                checkpoint,
+                &path,
+                Duration::MAX,
+                &NoProgress,
+            )
+        };
+        assert_eq!(resumed(checkpoint.clone()).unwrap(), expected);
+        let other = Checkpoint {
+            queries_checksum: checkpoint.queries_checksum ^ 1,
+            ..checkpoint.clone()
+        };
+        assert!(resumed(other).is_err());
+        let other = Checkpoint {
+            nodes_checksum: checkpoint.nodes_checksum ^ 1,
+            ..checkpoint
+        };
+        assert!(resumed(other).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let narrow = generate::queries(&mut rng, 10, 2, 4);
+        assert!(matches!(
+            run_checkpointed(
+                &Exact,
+                &nodes,
+                &narrow,
+                Checkpoint::new(&nodes, &narrow, 10),
+                &path,
+                Duration::MAX,
+                &NoProgress,
+            ),
+            Err(GlasshouseError::DimensionMismatch {
+                nodes: 4,
+                queries: 2
+            })
+        ));
+    }
+}
@@ -0,0 +1,617 @@
+//! Graph index searched from disk, for datasets larger than memory.
+//!
+//! The bottom layer of an [`Hnsw`] graph is written to a file along with the
+//! full-precision vectors, in one fixed-size record per node holding its
+//! vector followed by its adjacency list, so that expanding a node during
+//! the search costs a single positioned read. Only the node attributes and
+//! vectors quantized to one byte per dimension stay in memory: the beam
+//! search is steered by the distances to the quantized vectors, and every
+//! node it expands is ranked by its exact distance read from disk.
+//!
+//! The file starts with the `GHDK` magic and the format version, then holds
+//! the number and dimensionality of the vectors, the maximum degree, the
+//! entry point, the search parameters, the metric the vectors are compared
+//! with, the per-dimension quantization bounds, the attributes and
+//! tombstones of the nodes, their quantized vectors and finally the
+//! records. Integers and floats are stored in little-endian order.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use crate::config::Metric;
+use crate::distance::l2;
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, ParsedNode, ParsedQuery, ScoredResult};
+
+use super::persist::{
+    check_metric, malformed, metric_code, read_f32s, read_u32, read_u64, write_f32s, write_u32,
+    write_u64,
+};
+use super::plan::{candidates, explain, widen};
+use super::{Hnsw, Neighbor, Plan, Solver, Strategy, top_k};
+
+const MAGIC: [u8; 4] = *b"GHDK";
+/// Current version of the disk index format, bumped on incompatible changes.
+pub const DISK_FORMAT_VERSION: u32 = 2;
+
+/// Entry point written for graphs without nodes.
+const NO_ENTRY_POINT: u32 = u32::MAX;
+
+/// Graph index whose vectors and adjacency lists are read from a file while
+/// searching, see the module documentation.
+#[derive(Debug)]
+pub struct DiskIndex {
+    file: File,
+    path: PathBuf,
+    num_vectors: usize,
+    dimensions: usize,
+    max_degree: usize,
+    entry_point: Option<u32>,
+    ef_search: usize,
+    /// Whether `ef_search` is widened for queries with selective filters.
+    adaptive: bool,
+    /// Lowest value of each dimension over the nodes.
+    minimums: Vec<f32>,
+    /// Width of a quantization step of each dimension.
+    steps: Vec<f32>,
+    /// Quantized vectors, `dimensions` bytes per node.
+    codes: Vec<u8>,
+    /// Offset of the first record in the file.
+    records_offset: u64,
+}
+
+impl DiskIndex {
+    /// Writes the bottom layer of the graph built over the nodes under the
+    /// metric to a file, entered from the node closest to their mean.
+    pub fn write<P: AsRef<Path>>(
+        file_path: P,
+        hnsw: &Hnsw,
+        nodes_dataset: &NodesDataset,
+        metric: Metric,
+    ) -> error::Result<()> {
+        if hnsw.len() != nodes_dataset.num_vectors as usize {
+            return Err(GlasshouseError::Config(format!(
+                "The graph holds {} nodes but the dataset {}",
+                hnsw.len(),
+                nodes_dataset.num_vectors
+            )));
+        }
+        let num_vectors = nodes_dataset.num_vectors as usize;
+        let dimensions = nodes_dataset.dimensions;
+        let max_degree = (0..num_vectors as u32)
+            .map(|node_id| hnsw.neighbors(node_id, 0).len())
+            .max()
+            .unwrap_or(0);
+        let (minimums, steps) = quantization_bounds(nodes_dataset);
+
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        writer.write_all(&MAGIC)?;
+        write_u32(&mut writer, DISK_FORMAT_VERSION)?;
+        write_u32(&mut writer, nodes_dataset.num_vectors)?;
+        write_u32(&mut writer, dimensions as u32)?;
+        write_u32(&mut writer, max_degree as u32)?;
+        write_u32(&mut writer, medoid(nodes_dataset).unwrap_or(NO_ENTRY_POINT))?;
+        write_u32(&mut writer, hnsw.ef_search() as u32)?;
+        write_u32(&mut writer, hnsw.adaptive() as u32)?;
+        write_u32(&mut writer, metric_code(metric))?;
+        write_f32s(&mut writer, &minimums)?;
+        write_f32s(&mut writer, &steps)?;
+        for &c_attr in &nodes_dataset.c_attrs {
+            write_u32(&mut writer, c_attr as u32)?;
+        }
+        write_f32s(&mut writer, &nodes_dataset.t_attrs)?;
+        write_u32(&mut writer, nodes_dataset.tombstones.len() as u32)?;
+        for &word in &nodes_dataset.tombstones {
+            write_u64(&mut writer, word)?;
+        }
+        for index in 0..num_vectors {
+            let codes: Vec<u8> = quantize(nodes_dataset.vector(index), &minimums, &steps).collect();
+            writer.write_all(&codes)?;
+        }
+        for index in 0..num_vectors {
+            write_f32s(&mut writer, nodes_dataset.vector(index))?;
+            let neighbors = hnsw.neighbors(index as u32, 0);
+            write_u32(&mut writer, neighbors.len() as u32)?;
+            for slot in 0..max_degree {
+                write_u32(&mut writer, neighbors.get(slot).copied().unwrap_or(0))?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Opens a disk index built under the metric, returning it with the
+    /// attributes of its nodes. The returned dataset holds no vectors, it is
+    /// only meant to be given to the index along with the queries.
+    pub fn open<P: AsRef<Path>>(
+        file_path: P,
+        metric: Metric,
+    ) -> error::Result<(Self, NodesDataset)> {
+        let path = file_path.as_ref().to_path_buf();
+        let mut reader = BufReader::new(File::open(&path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(malformed("Not a glasshouse disk index file".to_string()));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != DISK_FORMAT_VERSION {
+            return Err(malformed(format!(
+                "Unsupported disk index format version {}, expected {}",
+                version, DISK_FORMAT_VERSION
+            )));
+        }
+        let num_vectors = read_u32(&mut reader)?;
+        let dimensions = read_u32(&mut reader)? as usize;
+        let max_degree = read_u32(&mut reader)? as usize;
+        let entry_point = match read_u32(&mut reader)? {
+            NO_ENTRY_POINT => None,
+            id if id < num_vectors => Some(id),
+            id => return Err(malformed(format!("Entry point {} is out of range", id))),
+        };
+        let ef_search = read_u32(&mut reader)? as usize;
+        let adaptive = read_u32(&mut reader)? != 0;
+        check_metric(read_u32(&mut reader)?, metric)?;
+        let minimums = read_f32s(&mut reader, dimensions)?;
+        let steps = read_f32s(&mut reader, dimensions)?;
+        let num_nodes = num_vectors as usize;
+        let c_attrs = (0..num_nodes)
+            .map(|_| read_u32(&mut reader).map(|c_attr| c_attr as i32))
+            .collect::<error::Result<Vec<i32>>>()?;
+        let t_attrs = read_f32s(&mut reader, num_nodes)?;
+        let words = read_u32(&mut reader)? as usize;
+        let tombstones = (0..words)
+            .map(|_| read_u64(&mut reader))
+            .collect::<error::Result<Vec<u64>>>()?;
+        if !tombstones.is_empty() && words != num_nodes.div_ceil(64) {
+            return Err(malformed(format!(
+                "Expected {} tombstone words for {} nodes, got {}",
+                num_nodes.div_ceil(64),
+                num_nodes,
+                words
+            )));
+        }
+
+        // The sizes come from the header, so the file length is checked
+        // before reserving the quantized vectors.
+        let codes_len = num_nodes.checked_mul(dimensions);
+        let record_len = dimensions
+            .checked_add(1)
+            .and_then(|words| words.checked_add(max_degree))
+            .and_then(|words| words.checked_mul(4));
+        let codes_offset = reader.stream_position()?;
+        let expected = codes_len
+            .zip(record_len)
+            .and_then(|(codes_len, record_len)| {
+                let records_len = (num_vectors as u64).checked_mul(record_len as u64)?;
+                codes_offset
+                    .checked_add(codes_len as u64)?
+                    .checked_add(records_len)
+            });
+        let file_len = reader.get_ref().metadata()?.len();
+        let (Some(codes_len), Some(expected)) = (codes_len, expected) else {
+            return Err(malformed(
+                "Disk index sizes overflow, the header is corrupt".to_string(),
+            ));
+        };
+        if file_len != expected {
+            return Err(malformed(format!(
+                "Expected {} bytes in the disk index, got {}",
+                expected, file_len
+            )));
+        }
+        let mut codes = vec![0u8; codes_len];
+        reader.read_exact(&mut codes)?;
+        let records_offset = reader.stream_position()?;
+        let file = reader.into_inner();
+
+        // The attributes are indexed before the dimensionality is set, so
+        // the category statistics do not look for vectors.
+        let mut nodes_dataset = NodesDataset::from_parts(0, c_attrs, t_attrs, Vec::new())?;
+        nodes_dataset.dimensions = dimensions;
+        nodes_dataset.tombstones = tombstones;
+        let index = DiskIndex {
+            file,
+            path,
+            num_vectors: num_nodes,
+            dimensions,
+            max_degree,
+            entry_point,
+            ef_search,
+            adaptive,
+            minimums,
+            steps,
+            codes,
+            records_offset,
+        };
+        Ok((index, nodes_dataset))
+    }
+
+    /// Number of nodes of the graph.
+    pub fn len(&self) -> usize {
+        self.num_vectors
+    }
+
+    /// Returns true if the graph has no node.
+    pub fn is_empty(&self) -> bool {
+        self.entry_point.is_none()
+    }
+
+    /// Maximum number of neighbours of a node.
+    pub fn max_degree(&self) -> usize {
+        self.max_degree
+    }
+
+    /// Width of the candidate list used while searching the graph.
+    pub fn ef_search(&self) -> usize {
+        self.ef_search
+    }
+
+    /// Sets the width of the candidate list used while searching the graph.
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.ef_search = ef_search;
+    }
+
+    /// Whether the width is widened for queries with selective filters.
+    pub fn adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    /// Sets whether the width is widened for queries with selective
+    /// filters.
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+    }
+
+    /// Distance from the vector to the quantized vector of a node.
+    fn approximate(&self, vector: &[f32], node_id: u32) -> f32 {
+        let start = node_id as usize * self.dimensions;
+        let codes = &self.codes[start..start + self.dimensions];
+        vector
+            .iter()
+            .zip(codes)
+            .zip(self.minimums.iter().zip(&self.steps))
+            .map(|((value, &code), (minimum, step))| {
+                let difference = value - (minimum + code as f32 * step);
+                difference * difference
+            })
+            .sum()
+    }
+
+    /// Reads the vector and the neighbours of a node from its record.
+    /// Neighbours out of range, which only a corrupt file holds, are
+    /// skipped.
+    fn read_record(&self, node_id: u32, bytes: &mut [u8]) -> (Vec<f32>, Vec<u32>) {
+        let offset = self.records_offset + node_id as u64 * bytes.len() as u64;
+        read_exact_at(&self.file, bytes, offset).unwrap_or_else(|e| {
+            panic!(
+                "Cannot read node {} from the disk index {}: {}",
+                node_id,
+                self.path.display(),
+                e
+            )
+        });
+        let mut words = bytes
+            .chunks_exact(4)
+            .map(|chunk| [chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let vector = words
+            .by_ref()
+            .take(self.dimensions)
+            .map(f32::from_le_bytes)
+            .collect();
+        let degree = words.next().map_or(0, u32::from_le_bytes) as usize;
+        let neighbors = words
+            .take(degree.min(self.max_degree))
+            .map(u32::from_le_bytes)
+            .filter(|&id| (id as usize) < self.num_vectors)
+            .collect();
+        (vector, neighbors)
+    }
+
+    fn record_len(&self) -> usize {
+        (self.dimensions + 1 + self.max_degree) * 4
+    }
+
+    fn width(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>) -> usize {
+        if self.adaptive {
+            widen(self.ef_search, nodes_dataset, query)
+        } else {
+            self.ef_search
+        }
+    }
+}
+
+impl Solver for DiskIndex {
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        let ef_search = self.width(nodes_dataset, query);
+        self.search_scored_with_ef(nodes_dataset, query, k, ef_search)
+    }
+
+    fn explain(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> Plan {
+        let ef = self.width(nodes_dataset, query).max(k);
+        let index = format!("disk, ef_search {}", ef);
+        explain(
+            nodes_dataset,
+            query,
+            ef.saturating_mul(2),
+            Strategy::PostFilter { index },
+        )
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the records of the nodes cannot be read from the file.
+    fn search_scored_with_ef(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+        ef_search: usize,
+    ) -> ScoredResult {
+        let Some(entry_point) = self.entry_point else {
+            return top_k(Vec::new(), k);
+        };
+        let matches = |node_id: u32| {
+            let index = node_id as usize;
+            !nodes_dataset.is_deleted(node_id)
+                && query.matches(&ParsedNode {
+                    c_attr: nodes_dataset.c_attrs[index],
+                    t_attr: nodes_dataset.t_attrs[index],
+                    vector: &[],
+                })
+        };
+        let vector = query.query_vector;
+        let mut bytes = vec![0u8; self.record_len()];
+
+        // The search reads the records of up to `ef` expanded nodes and of
+        // up to `ef` reranked ones, reading the vectors of fewer nodes
+        // passing the filters is cheaper and exact.
+        let ef = ef_search.max(k);
+        if let Some(candidates) = candidates(nodes_dataset, query)
+            && candidates.len <= ef.saturating_mul(2)
+        {
+            let neighbors = candidates
+                .ids
+                .filter(|&node_id| matches(node_id))
+                .map(|id| Neighbor {
+                    distance: l2(vector, &self.read_record(id, &mut bytes).0),
+                    id,
+                })
+                .collect();
+            return top_k(neighbors, k);
+        }
+
+        // Beam search over the quantized vectors, keeping the `ef` closest
+        // matching nodes among every evaluated one rather than filtering the
+        // final beam, then reranking them by their exact distance.
+        let entry = Neighbor {
+            distance: self.approximate(vector, entry_point),
+            id: entry_point,
+        };
+        let mut visited = HashSet::from([entry_point]);
+        let mut frontier = BinaryHeap::from([Reverse(entry)]);
+        let mut beam = BinaryHeap::from([entry]);
+        let mut pool: BinaryHeap<Neighbor> =
+            BinaryHeap::with_capacity(ef.min(nodes_dataset.num_vectors as usize) + 1);
+        let mut exact: HashMap<u32, f32> = HashMap::new();
+        let keep = |pool: &mut BinaryHeap<Neighbor>, neighbor: Neighbor| {
+            if matches(neighbor.id) {
+                pool.push(neighbor);
+                if pool.len() > ef {
+                    pool.pop();
+                }
+            }
+        };
+        keep(&mut pool, entry);
+        while let Some(Reverse(candidate)) = frontier.pop() {
+            let furthest = beam.peek().map_or(f32::INFINITY, |n| n.distance);
+            if candidate.distance > furthest && beam.len() >= ef {
+                break;
+            }
+            let (record, neighbors) = self.read_record(candidate.id, &mut bytes);
+            exact.insert(candidate.id, l2(vector, &record));
+            for id in neighbors {
+                if !visited.insert(id) {
+                    continue;
+                }
+                let neighbor = Neighbor {
+                    distance: self.approximate(vector, id),
+                    id,
+                };
+                keep(&mut pool, neighbor);
+                let furthest = beam.peek().map_or(f32::INFINITY, |n| n.distance);
+                if beam.len() < ef || neighbor.distance < furthest {
+                    frontier.push(Reverse(neighbor));
+                    beam.push(neighbor);
+                    if beam.len() > ef {
+                        beam.pop();
+                    }
+                }
+            }
+        }
+
+        let reranked =
+            pool.into_iter()
+                .map(|neighbor| Neighbor {
+                    distance: exact.get(&neighbor.id).copied().unwrap_or_else(|| {
+                        l2(vector, &self.read_record(neighbor.id, &mut bytes).0)
+                    }),
+                    id: neighbor.id,
+                })
+                .collect();
+        top_k(reranked, k)
+    }
+
+    fn degrade(&mut self, k: usize) -> bool {
+        if self.ef_search <= k {
+            return false;
+        }
+        self.ef_search = (self.ef_search / 2).max(k);
+        true
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.codes.capacity()
+            + (self.minimums.capacity() + self.steps.capacity()) * mem::size_of::<f32>()
+    }
+}
+
+/// Lowest value and quantization step of each dimension, splitting the
+/// range of the live nodes in 255 steps.
+fn quantization_bounds(nodes_dataset: &NodesDataset) -> (Vec<f32>, Vec<f32>) {
+    let dimensions = nodes_dataset.dimensions;
+    let mut minimums = vec![f32::INFINITY; dimensions];
+    let mut maximums = vec![f32::NEG_INFINITY; dimensions];
+    for index in 0..nodes_dataset.num_vectors as usize {
+        for (dimension, &value) in nodes_dataset.vector(index).iter().enumerate() {
+            minimums[dimension] = minimums[dimension].min(value);
+            maximums[dimension] = maximums[dimension].max(value);
+        }
+    }
+    let steps = minimums
+        .iter_mut()
+        .zip(&maximums)
+        .map(|(minimum, maximum)| {
+            if !minimum.is_finite() || !maximum.is_finite() {
+                *minimum = 0.0;
+                return 0.0;
+            }
+            (maximum - *minimum) / 255.0
+        })
+        .collect();
+    (minimums, steps)
+}
+
+/// Quantizes a vector to one byte per dimension.
+fn quantize<'a>(
+    vector: &'a [f32],
+    minimums: &'a [f32],
+    steps: &'a [f32],
+) -> impl Iterator<Item = u8> + 'a {
+    vector
+        .iter()
+        .zip(minimums.iter().zip(steps))
+        .map(|(value, (minimum, step))| {
+            if *step > 0.0 {
+                ((value - minimum) / step).round().clamp(0.0, 255.0) as u8
+            } else {
+                0
+            }
+        })
+}
+
+/// Live node closest to the mean of the live nodes, `None` if every node
+/// was deleted.
+fn medoid(nodes_dataset: &NodesDataset) -> Option<u32> {
+    let live = || (0..nodes_dataset.num_vectors).filter(|&id| !nodes_dataset.is_deleted(id));
+    let mut mean = vec![0.0f64; nodes_dataset.dimensions];
+    let mut count = 0usize;
+    for node_id in live() {
+        for (sum, &value) in mean.iter_mut().zip(nodes_dataset.vector(node_id as usize)) {
+            *sum += value as f64;
+        }
+        count += 1;
+    }
+    let mean: Vec<f32> = mean
+        .into_iter()
+        .map(|sum| (sum / count.max(1) as f64) as f32)
+        .collect();
+    live()
+        .map(|id| Neighbor {
+            distance: l2(&mean, nodes_dataset.vector(id as usize)),
+            id,
+        })
+        .min()
+        .map(|neighbor| neighbor.id)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, bytes: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, bytes, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut bytes: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, bytes, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => {
+                bytes = &mut bytes[read..];
+                offset += read as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::constants::K_NEAREST;
+    use crate::eval;
+    use crate::generate;
+    use crate::solvers::{Exact, HnswBuilder, run};
+
+    #[test]
+    fn disk_indexes_search_without_the_vectors() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let mut nodes = generate::nodes(&mut rng, 2000, 8, 4);
+        nodes.delete(3);
+        let queries = generate::queries(&mut rng, 100, 8, 4);
+        let hnsw = HnswBuilder::new()
+            .m(16)
+            .ef_search(64)
+            .build(&nodes)
+            .unwrap();
+        let path = crate::testing::temp_path("disk-index.disk");
+        DiskIndex::write(&path, &hnsw, &nodes, Metric::L2).unwrap();
+
+        let (index, attributes) = DiskIndex::open(&path, Metric::L2).unwrap();
+        assert!(DiskIndex::open(&path, Metric::Cosine).is_err());
+        assert_eq!(index.len(), 2000);
+        assert!(attributes.vectors.is_empty() && attributes.is_deleted(3));
+        assert!(index.memory_bytes() < 2000 * 8 * 4 / 3);
+        let results = run(&index, &attributes, &queries, K_NEAREST).unwrap();
+        let exact = run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+        let recall = eval::recall(&results, &exact, u32::MAX).unwrap();
+        assert!(recall > 0.9, "recall {}", recall);
+        assert!(results.iter().all(|result| !result.contains(&3)));
+
+        // Neighbours out of range are skipped rather than read.
+        let mut bytes = std::fs::read(&path).unwrap();
+        let record_len = index.record_len();
+        let records = bytes.len() - 2000 * record_len;
+        for record in bytes[records..].chunks_exact_mut(record_len) {
+            record[(8 + 1) * 4..(8 + 2) * 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+        std::fs::write(&path, &bytes).unwrap();
+        let (corrupt, _) = DiskIndex::open(&path, Metric::L2).unwrap();
+        assert_eq!(
+            run(&corrupt, &attributes, &queries, K_NEAREST)
+                .unwrap()
+                .len(),
+            100
+        );
+
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(DiskIndex::open(&path, Metric::L2).is_err());
+        // A corrupt maximum degree fails on the size of the records.
+        bytes[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(DiskIndex::open(&path, Metric::L2).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
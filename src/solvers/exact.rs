@@ -0,0 +1,150 @@
+//! Exact solution scanning every node.
+use std::collections::BinaryHeap;
+
+use rayon::prelude::*;
+
+use crate::distance::{QUERY_BLOCK, QueryBlock};
+use crate::error::{self, GlasshouseError};
+use crate::progress::{Phase, Progress, Tracker};
+use crate::types::{NodesDataset, ParsedQuery, QueriesDataset, ScoredResult, ScoredResults};
+
+use super::plan::{candidates, scan};
+use super::{Neighbor, Solver, batch, top_k};
+
+/// Brute-force solver computing the exact filtered nearest neighbours.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Exact;
+
+impl Solver for Exact {
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        // Filtered queries only visit the nodes passing their most
+        // selective filter, looked up in the attribute indexes.
+        match candidates(nodes_dataset, query) {
+            Some(candidates) => scan(nodes_dataset, query, candidates.ids, k),
+            None => scan(nodes_dataset, query, 0..nodes_dataset.num_vectors, k),
+        }
+    }
+}
+
+/// Answers every query exactly, with the results of [`Exact`], returning
+/// them in query order. The queries scanning every node are answered
+/// [`QUERY_BLOCK`] at a time in a single pass over the nodes, comparing
+/// each node with the whole block through [`QueryBlock`]. The others only
+/// scan the nodes passing their most selective filter, one at a time.
+pub fn run_blocked(
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    progress: &dyn Progress,
+) -> error::Result<ScoredResults> {
+    if nodes_dataset.dimensions != queries_dataset.dimensions {
+        return Err(GlasshouseError::DimensionMismatch {
+            nodes: nodes_dataset.dimensions,
+            queries: queries_dataset.dimensions,
+        });
+    }
+    let tracker = Tracker::new(progress, Phase::Search, queries_dataset.num_queries as u64);
+    let query = |index: usize| {
+        queries_dataset
+            .get(index)
+            .expect("query indices are in range")
+    };
+    let (scanned, filtered): (Vec<usize>, Vec<usize>) = batch::order(queries_dataset)
+        .into_iter()
+        .partition(|&index| candidates(nodes_dataset, &query(index)).is_none());
+
+    let filtered = filtered.par_iter().map(|&index| {
+        let result = Exact.search_scored(nodes_dataset, &query(index), k);
+        tracker.advance(1);
+        (index, result)
+    });
+    let scanned = scanned.par_chunks(QUERY_BLOCK).flat_map_iter(|indices| {
+        let block: Vec<ParsedQuery<'_>> = indices.iter().map(|&index| query(index)).collect();
+        let results = scan_block(nodes_dataset, &block, k);
+        tracker.advance(indices.len() as u64);
+        indices.iter().copied().zip(results)
+    });
+    let answers: Vec<(usize, ScoredResult)> = filtered.chain(scanned).collect();
+
+    let mut results = vec![Vec::new(); queries_dataset.num_queries as usize];
+    for (index, result) in answers {
+        results[index] = result;
+    }
+    Ok(results)
+}
+
+/// Scans every node once for a block of at most [`QUERY_BLOCK`] queries,
+/// returning the `k` closest nodes passing the filters of each.
+fn scan_block(
+    nodes_dataset: &NodesDataset,
+    queries: &[ParsedQuery<'_>],
+    k: usize,
+) -> Vec<ScoredResult> {
+    let vectors: Vec<&[f32]> = queries.iter().map(|query| query.query_vector).collect();
+    let block = QueryBlock::new(&vectors);
+    let mut found: Vec<BinaryHeap<Neighbor>> = queries
+        .iter()
+        .map(|_| BinaryHeap::with_capacity(k.min(nodes_dataset.num_vectors as usize) + 1))
+        .collect();
+    for node_id in 0..nodes_dataset.num_vectors {
+        let Some(node) = nodes_dataset.get(node_id as usize) else {
+            continue;
+        };
+        let distances = block.l2(node.vector);
+        for ((query, found), &distance) in queries.iter().zip(&mut found).zip(&distances) {
+            if distance.is_nan() || !query.matches(&node) {
+                continue;
+            }
+            let candidate = Neighbor {
+                distance,
+                id: node_id,
+            };
+            if found.len() < k {
+                found.push(candidate);
+            } else if found.peek().is_some_and(|furthest| candidate < *furthest) {
+                found.pop();
+                found.push(candidate);
+            }
+        }
+    }
+    found
+        .into_iter()
+        .map(|found| top_k(found.into_vec(), k))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::progress::NoProgress;
+
+    #[test]
+    fn blocked_runs_reject_mismatched_dimensions() {
+        let mut rng = StdRng::seed_from_u64(19);
+        let nodes = generate::nodes(&mut rng, 100, 11, 4);
+        let queries = generate::queries(&mut rng, 10, 8, 4);
+
+        assert!(matches!(
+            run_blocked(&nodes, &queries, 10, &NoProgress),
+            Err(GlasshouseError::DimensionMismatch {
+                nodes: 11,
+                queries: 8
+            })
+        ));
+        let no_queries = generate::queries(&mut rng, 0, 11, 4);
+        assert!(
+            run_blocked(&nodes, &no_queries, 10, &NoProgress)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,666 @@
+//! Hierarchical navigable small world graph index.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::io::{self, Read, Write};
+use std::mem;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::distance::l2;
+use crate::error::{self, GlasshouseError};
+use crate::progress::{NoProgress, Phase, Progress, Tracker};
+use crate::types::{NodesDataset, ParsedQuery, ScoredResult};
+
+use super::persist::{malformed, read_ids, read_u32, read_u64, write_ids, write_u32, write_u64};
+use super::plan::{explain, filter_first, widen};
+use super::{Neighbor, Plan, Solver, Strategy, top_k};
+
+/// Hierarchical navigable small world graph, queries descend greedily
+/// through the upper layers and run a beam search on the bottom layer
+/// keeping the closest nodes that pass the query filters.
+#[derive(Debug, Clone)]
+pub struct Hnsw {
+    m: usize,
+    ef_construction: usize,
+    ef_search: usize,
+    /// Whether `ef_search` is widened for queries with selective filters.
+    adaptive: bool,
+    /// Seed from which the level of each node is derived.
+    seed: u64,
+    entry_point: Option<u32>,
+    max_level: usize,
+    /// Adjacency lists of each node, one per level the node appears on.
+    neighbors: Vec<Vec<Vec<u32>>>,
+}
+
+/// Builder validating the parameters of an [`Hnsw`] graph.
+///
+/// ```ignore
+/// let hnsw = HnswBuilder::new().m(16).ef_construction(200).build(&nodes)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct HnswBuilder {
+    m: usize,
+    ef_construction: usize,
+    ef_search: usize,
+    adaptive: bool,
+    seed: u64,
+}
+
+impl Default for HnswBuilder {
+    fn default() -> Self {
+        HnswBuilder {
+            m: Hnsw::DEFAULT_M,
+            ef_construction: Hnsw::DEFAULT_EF_CONSTRUCTION,
+            ef_search: Hnsw::DEFAULT_EF_SEARCH,
+            adaptive: false,
+            seed: 0,
+        }
+    }
+}
+
+impl HnswBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of neighbours per node on the upper layers, the bottom
+    /// layer keeps up to twice as many. Must be at least 2.
+    pub fn m(mut self, m: usize) -> Self {
+        self.m = m;
+        self
+    }
+
+    /// Width of the candidate list used while building the graph. Must be
+    /// at least `m`.
+    pub fn ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction;
+        self
+    }
+
+    /// Width of the candidate list used while searching the graph. Must be
+    /// at least 1, widths below the number of neighbours searched
+    /// are raised to it.
+    pub fn ef_search(mut self, ef_search: usize) -> Self {
+        self.ef_search = ef_search;
+        self
+    }
+
+    /// Widens the candidate list for queries whose filters leave few
+    /// nodes, in inverse proportion to the estimated fraction they leave.
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Seed from which the level of each node is derived.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Checks that the parameters describe a valid graph.
+    pub fn validate(&self) -> error::Result<()> {
+        if self.m < 2 {
+            return Err(GlasshouseError::Config(format!(
+                "HNSW m must be at least 2, got {}",
+                self.m
+            )));
+        }
+        if self.ef_construction < self.m {
+            return Err(GlasshouseError::Config(format!(
+                "HNSW ef_construction must be at least m = {}, got {}",
+                self.m, self.ef_construction
+            )));
+        }
+        if self.ef_search == 0 {
+            return Err(GlasshouseError::Config(
+                "HNSW ef_search must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validates the parameters and builds the graph over the nodes.
+    pub fn build(&self, nodes_dataset: &NodesDataset) -> error::Result<Hnsw> {
+        self.build_with_progress(nodes_dataset, &NoProgress)
+    }
+
+    /// Same as [`HnswBuilder::build`], reporting the number of nodes
+    /// inserted so far.
+    pub fn build_with_progress(
+        &self,
+        nodes_dataset: &NodesDataset,
+        progress: &dyn Progress,
+    ) -> error::Result<Hnsw> {
+        self.validate()?;
+        let mut hnsw = Hnsw::build(
+            nodes_dataset,
+            self.m,
+            self.ef_construction,
+            self.ef_search,
+            self.seed,
+            progress,
+        );
+        hnsw.set_adaptive(self.adaptive);
+        Ok(hnsw)
+    }
+}
+
+impl Hnsw {
+    pub const DEFAULT_M: usize = 16;
+    pub const DEFAULT_EF_CONSTRUCTION: usize = 200;
+    pub const DEFAULT_EF_SEARCH: usize = 128;
+
+    /// Builds the graph by inserting every node of the dataset in order.
+    fn build(
+        nodes_dataset: &NodesDataset,
+        m: usize,
+        ef_construction: usize,
+        ef_search: usize,
+        seed: u64,
+        progress: &dyn Progress,
+    ) -> Self {
+        let mut index = Hnsw {
+            m,
+            ef_construction,
+            ef_search,
+            adaptive: false,
+            seed,
+            entry_point: None,
+            max_level: 0,
+            neighbors: Vec::with_capacity(nodes_dataset.num_vectors as usize),
+        };
+
+        let tracker = Tracker::new(progress, Phase::Build, nodes_dataset.num_vectors as u64);
+        for node_id in 0..nodes_dataset.num_vectors {
+            index.insert_at_level(nodes_dataset, node_id, index.random_level(node_id));
+            tracker.advance(1);
+        }
+        index
+    }
+
+    /// Inserts a node pushed to the dataset after the graph was built, nodes
+    /// must be inserted in the order they were added to the dataset.
+    pub fn insert(&mut self, nodes_dataset: &NodesDataset, node_id: u32) -> error::Result<()> {
+        if node_id as usize != self.neighbors.len() || node_id >= nodes_dataset.num_vectors {
+            return Err(GlasshouseError::Config(format!(
+                "Cannot insert node {}, the next node to index is {} out of {}",
+                node_id,
+                self.neighbors.len(),
+                nodes_dataset.num_vectors
+            )));
+        }
+        self.insert_at_level(nodes_dataset, node_id, self.random_level(node_id));
+        Ok(())
+    }
+
+    /// Rebuilds the graph over a compacted dataset with the same parameters.
+    /// Until then deleted nodes are still traversed but never returned.
+    pub fn compact(&mut self, nodes_dataset: &NodesDataset) {
+        let adaptive = self.adaptive;
+        *self = Hnsw::build(
+            nodes_dataset,
+            self.m,
+            self.ef_construction,
+            self.ef_search,
+            self.seed,
+            &NoProgress,
+        );
+        self.adaptive = adaptive;
+    }
+
+    /// Number of nodes in the graph.
+    pub fn len(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    /// Returns true if the graph holds no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.neighbors.is_empty()
+    }
+
+    /// Maximum number of neighbours per node on each layer.
+    pub fn m(&self) -> usize {
+        self.m
+    }
+
+    /// Width of the candidate list used while building the graph.
+    pub fn ef_construction(&self) -> usize {
+        self.ef_construction
+    }
+
+    /// Width of the candidate list used while searching the graph.
+    pub fn ef_search(&self) -> usize {
+        self.ef_search
+    }
+
+    /// Number of layers above the bottom layer.
+    pub fn max_level(&self) -> usize {
+        self.max_level
+    }
+
+    /// Node the searches start from, `None` for an empty graph.
+    pub fn entry_point(&self) -> Option<u32> {
+        self.entry_point
+    }
+
+    /// Number of layers the node appears on, including the bottom layer.
+    pub fn levels(&self, node_id: u32) -> usize {
+        self.neighbors.get(node_id as usize).map_or(0, Vec::len)
+    }
+
+    /// Neighbours of the node on a layer, empty if it does not appear on it.
+    pub fn neighbors(&self, node_id: u32, level: usize) -> &[u32] {
+        self.neighbors
+            .get(node_id as usize)
+            .and_then(|levels| levels.get(level))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Sets the width of the candidate list used while searching the graph.
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.ef_search = ef_search;
+    }
+
+    /// Whether the candidate list is widened for queries with selective
+    /// filters, see [`HnswBuilder::adaptive`].
+    pub fn adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    /// Sets whether the candidate list is widened for queries with selective
+    /// filters.
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u32(writer, self.m as u32)?;
+        write_u32(writer, self.ef_construction as u32)?;
+        write_u32(writer, self.ef_search as u32)?;
+        write_u64(writer, self.seed)?;
+        write_u32(writer, self.entry_point.unwrap_or(u32::MAX))?;
+        write_u32(writer, self.max_level as u32)?;
+        for levels in &self.neighbors {
+            write_u32(writer, levels.len() as u32)?;
+            for adjacency in levels {
+                write_ids(writer, adjacency)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_from<R: Read>(reader: &mut R, num_vectors: u32) -> error::Result<Self> {
+        let m = read_u32(reader)? as usize;
+        let ef_construction = read_u32(reader)? as usize;
+        let ef_search = read_u32(reader)? as usize;
+        let seed = read_u64(reader)?;
+        let entry_point = Some(read_u32(reader)?).filter(|&id| id != u32::MAX);
+        let max_level = read_u32(reader)? as usize;
+
+        let mut neighbors = Vec::with_capacity(num_vectors as usize);
+        for _ in 0..num_vectors {
+            let num_levels = read_u32(reader)? as usize;
+            if num_levels == 0 || num_levels > max_level + 1 {
+                return Err(malformed(format!(
+                    "Node appears on {} levels of a graph with {} levels",
+                    num_levels,
+                    max_level + 1
+                )));
+            }
+            let levels = (0..num_levels)
+                .map(|_| read_ids(reader, num_vectors))
+                .collect::<error::Result<Vec<_>>>()?;
+            neighbors.push(levels);
+        }
+        // Every neighbour must appear on the level it is linked from.
+        for levels in &neighbors {
+            for (level, adjacency) in levels.iter().enumerate() {
+                if adjacency
+                    .iter()
+                    .any(|&id| neighbors[id as usize].len() <= level)
+                {
+                    return Err(malformed(format!(
+                        "Edge on level {} points to a node absent from it",
+                        level
+                    )));
+                }
+            }
+        }
+        if entry_point
+            .is_some_and(|id| id >= num_vectors || neighbors[id as usize].len() != max_level + 1)
+        {
+            return Err(malformed("Invalid graph entry point".to_string()));
+        }
+
+        Ok(Hnsw {
+            m,
+            ef_construction,
+            ef_search,
+            adaptive: false,
+            seed,
+            entry_point,
+            max_level,
+            neighbors,
+        })
+    }
+
+    /// Draws the top level of a node from an exponential distribution,
+    /// derived from the node ID so that incremental inserts are reproducible.
+    fn random_level(&self, node_id: u32) -> usize {
+        let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(node_id as u64));
+        let level_multiplier = 1.0 / (self.m as f64).ln();
+        (-(1.0 - rng.random::<f64>()).ln() * level_multiplier).floor() as usize
+    }
+
+    fn max_connections(&self, level: usize) -> usize {
+        if level == 0 { 2 * self.m } else { self.m }
+    }
+
+    fn insert_at_level(&mut self, nodes_dataset: &NodesDataset, node_id: u32, level: usize) {
+        debug_assert_eq!(self.neighbors.len(), node_id as usize);
+        self.neighbors.push(vec![Vec::new(); level + 1]);
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(node_id);
+            self.max_level = level;
+            return;
+        };
+
+        let vector = nodes_dataset.vector(node_id as usize);
+        let mut entry = Neighbor {
+            distance: l2(vector, nodes_dataset.vector(entry_point as usize)),
+            id: entry_point,
+        };
+        for layer in (level + 1..=self.max_level).rev() {
+            entry = self.greedy_search(nodes_dataset, vector, entry, layer);
+        }
+
+        let mut entry_points = vec![entry];
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(
+                nodes_dataset,
+                vector,
+                &entry_points,
+                self.ef_construction,
+                layer,
+                |_| {},
+            );
+            let selected = select_neighbors(nodes_dataset, &candidates, self.m);
+            for &neighbor in &selected {
+                self.connect(nodes_dataset, neighbor, node_id, layer);
+            }
+            self.neighbors[node_id as usize][layer] = selected;
+            entry_points = candidates;
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry_point = Some(node_id);
+        }
+    }
+
+    /// Adds an edge from `from` to `to`, pruning the adjacency list of
+    /// `from` when it overflows.
+    fn connect(&mut self, nodes_dataset: &NodesDataset, from: u32, to: u32, layer: usize) {
+        let max_connections = self.max_connections(layer);
+        let adjacency = &mut self.neighbors[from as usize][layer];
+        adjacency.push(to);
+        if adjacency.len() <= max_connections {
+            return;
+        }
+
+        let vector = nodes_dataset.vector(from as usize);
+        let mut candidates: Vec<Neighbor> = adjacency
+            .iter()
+            .map(|&id| Neighbor {
+                distance: l2(vector, nodes_dataset.vector(id as usize)),
+                id,
+            })
+            .collect();
+        candidates.sort_unstable();
+        *adjacency = select_neighbors(nodes_dataset, &candidates, max_connections);
+    }
+
+    /// Moves greedily towards the vector on the given layer.
+    fn greedy_search(
+        &self,
+        nodes_dataset: &NodesDataset,
+        vector: &[f32],
+        mut entry: Neighbor,
+        layer: usize,
+    ) -> Neighbor {
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for &id in &self.neighbors[entry.id as usize][layer] {
+                let distance = l2(vector, nodes_dataset.vector(id as usize));
+                if distance < entry.distance {
+                    entry = Neighbor { distance, id };
+                    improved = true;
+                }
+            }
+        }
+        entry
+    }
+
+    /// Beam search over a single layer returning the `ef` closest nodes
+    /// found sorted by distance, `visit` is called on every evaluated node.
+    fn search_layer(
+        &self,
+        nodes_dataset: &NodesDataset,
+        vector: &[f32],
+        entry_points: &[Neighbor],
+        ef: usize,
+        layer: usize,
+        mut visit: impl FnMut(Neighbor),
+    ) -> Vec<Neighbor> {
+        let mut visited: HashSet<u32> = entry_points.iter().map(|n| n.id).collect();
+        let mut candidates: BinaryHeap<Reverse<Neighbor>> =
+            entry_points.iter().copied().map(Reverse).collect();
+        let mut results: BinaryHeap<Neighbor> = entry_points.iter().copied().collect();
+        entry_points.iter().copied().for_each(&mut visit);
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let furthest = results.peek().map_or(f32::INFINITY, |n| n.distance);
+            if candidate.distance > furthest && results.len() >= ef {
+                break;
+            }
+
+            for &id in &self.neighbors[candidate.id as usize][layer] {
+                if !visited.insert(id) {
+                    continue;
+                }
+                let neighbor = Neighbor {
+                    distance: l2(vector, nodes_dataset.vector(id as usize)),
+                    id,
+                };
+                visit(neighbor);
+
+                let furthest = results.peek().map_or(f32::INFINITY, |n| n.distance);
+                if results.len() < ef || neighbor.distance < furthest {
+                    candidates.push(Reverse(neighbor));
+                    results.push(neighbor);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        results.into_sorted_vec()
+    }
+}
+
+impl Solver for Hnsw {
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        let ef_search = if self.adaptive {
+            widen(self.ef_search, nodes_dataset, query)
+        } else {
+            self.ef_search
+        };
+        self.search_scored_with_ef(nodes_dataset, query, k, ef_search)
+    }
+
+    fn explain(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> Plan {
+        let ef = if self.adaptive {
+            widen(self.ef_search, nodes_dataset, query)
+        } else {
+            self.ef_search
+        }
+        .max(k);
+        let visited = ef.saturating_mul(self.max_connections(0));
+        let index = format!("hnsw, ef_search {}", ef);
+        explain(
+            nodes_dataset,
+            query,
+            visited,
+            Strategy::PostFilter { index },
+        )
+    }
+
+    fn search_scored_with_ef(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+        ef_search: usize,
+    ) -> ScoredResult {
+        let Some(entry_point) = self.entry_point else {
+            return top_k(Vec::new(), k);
+        };
+        // The bottom layer search evaluates up to `ef` nodes and their
+        // neighbours, scanning fewer nodes passing the filters is cheaper.
+        let ef = ef_search.max(k);
+        let visited = ef.saturating_mul(self.max_connections(0));
+        if let Some(result) = filter_first(nodes_dataset, query, k, visited) {
+            return result;
+        }
+
+        let vector = query.query_vector;
+        let mut entry = Neighbor {
+            distance: l2(vector, nodes_dataset.vector(entry_point as usize)),
+            id: entry_point,
+        };
+        for layer in (1..=self.max_level).rev() {
+            entry = self.greedy_search(nodes_dataset, vector, entry, layer);
+        }
+
+        // Keep the closest matching nodes among every node evaluated on the
+        // bottom layer rather than filtering the final beam.
+        let mut matches: BinaryHeap<Neighbor> =
+            BinaryHeap::with_capacity(k.min(nodes_dataset.num_vectors as usize) + 1);
+        self.search_layer(nodes_dataset, vector, &[entry], ef, 0, |neighbor| {
+            let Some(node) = nodes_dataset.get(neighbor.id as usize) else {
+                return;
+            };
+            if query.matches(&node) {
+                matches.push(neighbor);
+                if matches.len() > k {
+                    matches.pop();
+                }
+            }
+        });
+
+        top_k(matches.into_vec(), k)
+    }
+
+    fn degrade(&mut self, k: usize) -> bool {
+        // Widths below k are raised to it while searching, so the width is
+        // not lowered past k.
+        if self.ef_search <= k {
+            return false;
+        }
+        self.ef_search = (self.ef_search / 2).max(k);
+        true
+    }
+
+    fn memory_bytes(&self) -> usize {
+        let mut bytes = self.neighbors.capacity() * mem::size_of::<Vec<Vec<u32>>>();
+        for levels in &self.neighbors {
+            bytes += levels.capacity() * mem::size_of::<Vec<u32>>();
+            for adjacency in levels {
+                bytes += adjacency.capacity() * mem::size_of::<u32>();
+            }
+        }
+        bytes
+    }
+}
+
+/// Selects up to `m` neighbours among candidates sorted by distance, skipping
+/// candidates closer to an already selected neighbour than to the base node
+/// and filling the remaining slots with the closest skipped candidates.
+fn select_neighbors(nodes_dataset: &NodesDataset, candidates: &[Neighbor], m: usize) -> Vec<u32> {
+    let mut selected: Vec<u32> = Vec::with_capacity(m);
+    let mut skipped: Vec<u32> = Vec::new();
+    for candidate in candidates {
+        if selected.len() >= m {
+            break;
+        }
+        let vector = nodes_dataset.vector(candidate.id as usize);
+        let diverse = selected
+            .iter()
+            .all(|&id| l2(vector, nodes_dataset.vector(id as usize)) > candidate.distance);
+        if diverse {
+            selected.push(candidate.id);
+        } else {
+            skipped.push(candidate.id);
+        }
+    }
+
+    let missing = m.saturating_sub(selected.len());
+    selected.extend(skipped.into_iter().take(missing));
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+
+    #[test]
+    fn nodes_are_inserted_in_order() {
+        let mut rng = StdRng::seed_from_u64(71);
+        let nodes = generate::nodes(&mut rng, 20, 4, 2);
+        let mut hnsw = HnswBuilder::new().m(4).build(&nodes).unwrap();
+
+        // Every node of the dataset is already indexed.
+        assert!(matches!(
+            hnsw.insert(&nodes, 19),
+            Err(GlasshouseError::Config(_))
+        ));
+        assert!(matches!(
+            hnsw.insert(&nodes, 20),
+            Err(GlasshouseError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn degrading_keeps_the_width_needed_for_k() {
+        let mut rng = StdRng::seed_from_u64(73);
+        let nodes = generate::nodes(&mut rng, 20, 4, 2);
+        let mut hnsw = HnswBuilder::new()
+            .m(4)
+            .ef_search(400)
+            .build(&nodes)
+            .unwrap();
+
+        assert!(hnsw.degrade(150));
+        assert_eq!(hnsw.ef_search(), 200);
+        assert!(hnsw.degrade(150));
+        assert_eq!(hnsw.ef_search(), 150);
+        assert!(!hnsw.degrade(150));
+        assert!(hnsw.degrade(1));
+        assert_eq!(hnsw.ef_search(), 75);
+    }
+}
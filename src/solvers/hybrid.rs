@@ -0,0 +1,127 @@
+//! Time-aware scoring blending vector distance and timestamp proximity.
+//!
+//! Instead of a hard filter, the timestamp range of a query gives a target
+//! timestamp, the middle of the range. Neighbours are ranked by their
+//! distance plus `weight` times how far their timestamp is from the target,
+//! so close vectors slightly out of the range still rank above distant ones
+//! within it.
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, ParsedQuery, QueryType, ScoredNeighbor, ScoredResult};
+
+use super::{Plan, Solver};
+
+/// Solver rescoring the neighbours found by another solver with the
+/// timestamp proximity of each node to the target of the query.
+pub struct Hybrid {
+    inner: Box<dyn Solver>,
+    weight: f32,
+}
+
+impl Hybrid {
+    /// Number of candidates fetched from the inner solver per neighbour
+    /// returned, the ranking of the closest vectors changes once rescored.
+    pub const OVERSAMPLING: usize = 4;
+
+    /// Wraps the solver, `weight` scales the distance between timestamps
+    /// added to the vector distance and must be non-negative.
+    pub fn new(inner: Box<dyn Solver>, weight: f32) -> error::Result<Self> {
+        if !(weight >= 0.0 && weight.is_finite()) {
+            return Err(GlasshouseError::Config(format!(
+                "Hybrid weight must be a non-negative number, got {}",
+                weight
+            )));
+        }
+        Ok(Hybrid { inner, weight })
+    }
+
+    /// Weight of the timestamp proximity in the score.
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+impl Solver for Hybrid {
+    /// Returns the `k` nodes with the lowest blended score, reported as
+    /// their distance. Queries without a timestamp range are answered by
+    /// the inner solver.
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        let Some((relaxed, target)) = relax(query) else {
+            return self.inner.search_scored(nodes_dataset, query, k);
+        };
+
+        let mut candidates: ScoredResult = self
+            .inner
+            .search_scored(
+                nodes_dataset,
+                &relaxed,
+                k.saturating_mul(Self::OVERSAMPLING),
+            )
+            .into_iter()
+            .map(|neighbor| ScoredNeighbor {
+                id: neighbor.id,
+                distance: neighbor.distance
+                    + self.weight * (nodes_dataset.t_attrs[neighbor.id as usize] - target).abs(),
+            })
+            .collect();
+        candidates.sort_unstable_by(|a, b| a.distance.total_cmp(&b.distance).then(a.id.cmp(&b.id)));
+        candidates.truncate(k);
+        candidates
+    }
+
+    /// Plan of the inner solver for the query without its timestamp filter.
+    fn explain(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> Plan {
+        match relax(query) {
+            Some((relaxed, _)) => self.inner.explain(
+                nodes_dataset,
+                &relaxed,
+                k.saturating_mul(Self::OVERSAMPLING),
+            ),
+            None => self.inner.explain(nodes_dataset, query, k),
+        }
+    }
+
+    /// Range searches keep the hard filters of the query.
+    fn search_range(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        radius: f32,
+    ) -> ScoredResult {
+        self.inner.search_range(nodes_dataset, query, radius)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.inner.memory_bytes()
+    }
+
+    fn degrade(&mut self, k: usize) -> bool {
+        self.inner.degrade(k)
+    }
+}
+
+/// Returns the query without its timestamp filter and the target
+/// timestamp in the middle of its range, `None` for queries without a
+/// timestamp range.
+fn relax<'a>(query: &ParsedQuery<'a>) -> Option<(ParsedQuery<'a>, f32)> {
+    let (Some(lower), Some(upper)) = (query.t_lower_bound, query.t_upper_bound) else {
+        return None;
+    };
+    let query_type = match query.query_type {
+        QueryType::TimestampConstraint => QueryType::VectorOnly,
+        QueryType::BothConstraints => QueryType::CategoricalConstraint,
+        _ => return None,
+    };
+    let relaxed = ParsedQuery {
+        query_type,
+        v_categorical: query.v_categorical,
+        t_lower_bound: None,
+        t_upper_bound: None,
+        query_vector: query.query_vector,
+    };
+    Some((relaxed, (lower + upper) / 2.0))
+}
@@ -0,0 +1,512 @@
+//! Inverted file index partitioning the nodes around k-means centroids.
+use std::io::{self, Read, Write};
+use std::mem;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rayon::prelude::*;
+
+use crate::distance::l2;
+use crate::error::{self, GlasshouseError};
+use crate::progress::{NoProgress, Phase, Progress, Tracker};
+use crate::types::{NodesDataset, ParsedQuery, ScoredResult};
+
+use super::persist::{malformed, read_f32s, read_ids, read_u32, write_f32s, write_ids, write_u32};
+use super::plan::{candidates, explain, filter_first, scan_range, widen};
+use super::{Neighbor, Plan, Solver, Strategy, top_k};
+
+/// Inverted file index, each query scans the lists of its `nprobe`
+/// closest centroids in order of distance, and of the centroids barely
+/// further away when multi-probing is enabled.
+#[derive(Debug, Clone)]
+pub struct Ivf {
+    dimensions: usize,
+    /// Centroids stored contiguously, `dimensions` entries per centroid.
+    centroids: Vec<f32>,
+    /// Node IDs assigned to each centroid.
+    lists: Vec<Vec<u32>>,
+    nprobe: usize,
+    /// Relative slack on the distance of the last probed centroid within
+    /// which further lists are probed, `0.0` probes exactly `nprobe` lists.
+    multi_probe: f32,
+    /// Whether `nprobe` is widened for queries with selective filters.
+    adaptive: bool,
+}
+
+/// Builder validating the parameters of an [`Ivf`] index.
+///
+/// ```ignore
+/// let ivf = IvfBuilder::new().nlist(1024).nprobe(32).build(&nodes)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct IvfBuilder {
+    nlist: usize,
+    nprobe: usize,
+    multi_probe: f32,
+    adaptive: bool,
+    seed: u64,
+}
+
+impl Default for IvfBuilder {
+    fn default() -> Self {
+        IvfBuilder {
+            nlist: Ivf::DEFAULT_NLIST,
+            nprobe: Ivf::DEFAULT_NPROBE,
+            multi_probe: 0.0,
+            adaptive: false,
+            seed: 0,
+        }
+    }
+}
+
+impl IvfBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of inverted lists, capped by the number of nodes. Must be at
+    /// least 1.
+    pub fn nlist(mut self, nlist: usize) -> Self {
+        self.nlist = nlist;
+        self
+    }
+
+    /// Number of lists scanned for each query. Must be between 1 and `nlist`.
+    pub fn nprobe(mut self, nprobe: usize) -> Self {
+        self.nprobe = nprobe;
+        self
+    }
+
+    /// Also probes the lists whose centroid is at most `1.0 + multi_probe`
+    /// times as far from the query, in squared distance, as the last of the
+    /// `nprobe` closest, which a slightly perturbed query would probe. Must
+    /// be non-negative.
+    pub fn multi_probe(mut self, multi_probe: f32) -> Self {
+        self.multi_probe = multi_probe;
+        self
+    }
+
+    /// Probes more lists for queries whose filters leave few nodes, in
+    /// inverse proportion to the estimated fraction of nodes they leave.
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Seed used to sample the initial centroids and training points.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Checks that the parameters describe a valid index.
+    pub fn validate(&self) -> error::Result<()> {
+        if self.nlist == 0 {
+            return Err(GlasshouseError::Config(
+                "IVF nlist must be at least 1".to_string(),
+            ));
+        }
+        if self.nprobe == 0 || self.nprobe > self.nlist {
+            return Err(GlasshouseError::Config(format!(
+                "IVF nprobe must be between 1 and nlist = {}, got {}",
+                self.nlist, self.nprobe
+            )));
+        }
+        if !(self.multi_probe >= 0.0 && self.multi_probe.is_finite()) {
+            return Err(GlasshouseError::Config(format!(
+                "IVF multi_probe must be a non-negative number, got {}",
+                self.multi_probe
+            )));
+        }
+        Ok(())
+    }
+
+    /// Validates the parameters and trains the index over the nodes.
+    pub fn build(&self, nodes_dataset: &NodesDataset) -> error::Result<Ivf> {
+        self.build_with_progress(nodes_dataset, &NoProgress)
+    }
+
+    /// Same as [`IvfBuilder::build`], reporting the number of points
+    /// assigned to a centroid so far, over all k-means iterations and the
+    /// final assignment of the nodes.
+    pub fn build_with_progress(
+        &self,
+        nodes_dataset: &NodesDataset,
+        progress: &dyn Progress,
+    ) -> error::Result<Ivf> {
+        self.validate()?;
+        if nodes_dataset.dimensions == 0 && nodes_dataset.num_vectors > 0 {
+            return Err(GlasshouseError::Config(
+                "IVF cannot index nodes with empty vectors".to_string(),
+            ));
+        }
+        let mut ivf = Ivf::build(nodes_dataset, self.nlist, self.nprobe, self.seed, progress);
+        ivf.set_multi_probe(self.multi_probe);
+        ivf.set_adaptive(self.adaptive);
+        Ok(ivf)
+    }
+}
+
+impl Ivf {
+    pub const DEFAULT_NLIST: usize = 256;
+    pub const DEFAULT_NPROBE: usize = 16;
+    /// Number of Lloyd iterations used to train the centroids.
+    const KMEANS_ITERATIONS: usize = 10;
+    /// Number of training points sampled per centroid.
+    const TRAINING_POINTS_PER_LIST: usize = 64;
+
+    /// Trains `nlist` centroids over a sample of the nodes and assigns
+    /// every node to its closest centroid.
+    fn build(
+        nodes_dataset: &NodesDataset,
+        nlist: usize,
+        nprobe: usize,
+        seed: u64,
+        progress: &dyn Progress,
+    ) -> Self {
+        let num_vectors = nodes_dataset.num_vectors as usize;
+        let dimensions = nodes_dataset.dimensions;
+        let nlist = nlist.clamp(1, num_vectors.max(1));
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut centroids = Vec::with_capacity(nlist * dimensions);
+        let num_lists = Self::built_nlist(nlist, nodes_dataset.num_vectors);
+        for node_id in sample(&mut rng, num_vectors, num_lists) {
+            centroids.extend_from_slice(nodes_dataset.vector(node_id));
+        }
+
+        let num_training = (nlist * Self::TRAINING_POINTS_PER_LIST).min(num_vectors);
+        let training = sample(&mut rng, num_vectors, num_training).into_vec();
+        let tracker = Tracker::new(
+            progress,
+            Phase::Build,
+            (Self::KMEANS_ITERATIONS * num_training + num_vectors) as u64,
+        );
+        for _ in 0..Self::KMEANS_ITERATIONS {
+            let assignments: Vec<usize> = training
+                .par_iter()
+                .map(|&node_id| {
+                    tracker.advance(1);
+                    nearest_centroid(&centroids, dimensions, nodes_dataset.vector(node_id))
+                })
+                .collect();
+
+            let num_centroids = centroids.len() / dimensions.max(1);
+            let mut sums = vec![0.0f32; centroids.len()];
+            let mut counts = vec![0usize; num_centroids];
+            for (&node_id, &centroid) in training.iter().zip(assignments.iter()) {
+                // Malformed vectors would turn the centroid into NaN.
+                let vector = nodes_dataset.vector(node_id);
+                if !vector.iter().all(|value| value.is_finite()) {
+                    continue;
+                }
+                let sum = &mut sums[centroid * dimensions..(centroid + 1) * dimensions];
+                for (acc, value) in sum.iter_mut().zip(vector) {
+                    *acc += value;
+                }
+                counts[centroid] += 1;
+            }
+            // Empty clusters keep their previous centroid.
+            for (centroid, &count) in counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+                let range = centroid * dimensions..(centroid + 1) * dimensions;
+                for (value, sum) in centroids[range.clone()].iter_mut().zip(&sums[range]) {
+                    *value = sum / count as f32;
+                }
+            }
+        }
+
+        let mut lists = vec![Vec::new(); centroids.len() / dimensions.max(1)];
+        let assignments: Vec<usize> = (0..num_vectors)
+            .into_par_iter()
+            .map(|node_id| {
+                tracker.advance(1);
+                nearest_centroid(&centroids, dimensions, nodes_dataset.vector(node_id))
+            })
+            .collect();
+        for (node_id, centroid) in assignments.into_iter().enumerate() {
+            lists[centroid].push(node_id as u32);
+        }
+
+        Ivf {
+            dimensions,
+            centroids,
+            lists,
+            nprobe,
+            multi_probe: 0.0,
+            adaptive: false,
+        }
+    }
+
+    /// Assigns a node pushed to the dataset after the index was built to its
+    /// closest centroid, the centroids themselves are not retrained.
+    pub fn insert(&mut self, nodes_dataset: &NodesDataset, node_id: u32) -> error::Result<()> {
+        if node_id >= nodes_dataset.num_vectors {
+            return Err(GlasshouseError::Config(format!(
+                "Cannot insert node {}, the dataset holds {} nodes",
+                node_id, nodes_dataset.num_vectors
+            )));
+        }
+        if self.lists.is_empty() {
+            // An index built over an empty dataset seeds its single centroid
+            // with the first inserted node.
+            self.centroids = nodes_dataset.vector(node_id as usize).to_vec();
+            self.dimensions = nodes_dataset.dimensions;
+            self.lists.push(Vec::new());
+        }
+
+        let vector = nodes_dataset.vector(node_id as usize);
+        let centroid = nearest_centroid(&self.centroids, self.dimensions, vector);
+        self.lists[centroid].push(node_id);
+        Ok(())
+    }
+
+    /// Drops deleted nodes from the lists and renumbers the remaining ones
+    /// after the dataset was compacted, the centroids are kept as is.
+    pub fn compact(&mut self, remap: &[Option<u32>]) {
+        for list in self.lists.iter_mut() {
+            *list = list
+                .iter()
+                .filter_map(|&node_id| remap.get(node_id as usize).copied().flatten())
+                .collect();
+        }
+    }
+
+    /// Number of inverted lists of an index built with `nlist` lists over
+    /// `num_vectors` nodes, which never holds more lists than nodes.
+    pub fn built_nlist(nlist: usize, num_vectors: u32) -> usize {
+        nlist.min(num_vectors as usize)
+    }
+
+    /// Number of inverted lists in the index.
+    pub fn nlist(&self) -> usize {
+        self.lists.len()
+    }
+
+    /// Number of lists scanned for each query.
+    pub fn nprobe(&self) -> usize {
+        self.nprobe
+    }
+
+    /// Sets the number of lists scanned for each query.
+    pub fn set_nprobe(&mut self, nprobe: usize) {
+        self.nprobe = nprobe;
+    }
+
+    /// Relative slack within which lists beyond the `nprobe` closest are
+    /// probed, see [`IvfBuilder::multi_probe`].
+    pub fn multi_probe(&self) -> f32 {
+        self.multi_probe
+    }
+
+    /// Sets the slack within which lists beyond the `nprobe` closest are
+    /// probed.
+    pub fn set_multi_probe(&mut self, multi_probe: f32) {
+        self.multi_probe = multi_probe;
+    }
+
+    /// Whether more lists are probed for queries with selective filters,
+    /// see [`IvfBuilder::adaptive`].
+    pub fn adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    /// Sets whether more lists are probed for queries with selective filters.
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+    }
+
+    /// Number of lists probed for the query before multi-probing.
+    fn effective_nprobe(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>) -> usize {
+        if self.adaptive {
+            widen(self.nprobe, nodes_dataset, query).min(self.nlist())
+        } else {
+            self.nprobe
+        }
+    }
+
+    /// Returns the nodes passing the filters of the query in the lists of
+    /// its `nprobe` closest centroids, and of those within the multi-probe
+    /// reach.
+    fn probe(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        nprobe: usize,
+    ) -> Vec<Neighbor> {
+        let mut probes: Vec<Neighbor> = self
+            .centroids
+            .chunks_exact(self.dimensions.max(1))
+            .enumerate()
+            .map(|(centroid, vector)| Neighbor {
+                distance: l2(query.query_vector, vector),
+                id: centroid as u32,
+            })
+            .collect();
+        probes.sort_unstable();
+
+        // Queries close to the boundary of the last probed list have
+        // neighbours in the lists just beyond it.
+        let reach = probes
+            .get(nprobe.saturating_sub(1))
+            .map_or(0.0, |last| last.distance * (1.0 + self.multi_probe));
+        let probed = probes
+            .iter()
+            .enumerate()
+            .take_while(|&(rank, probe)| rank < nprobe || probe.distance <= reach);
+
+        let mut candidates = Vec::new();
+        for (_, probe) in probed {
+            for &node_id in &self.lists[probe.id as usize] {
+                let Some(node) = nodes_dataset.get(node_id as usize) else {
+                    continue;
+                };
+                if query.matches(&node) {
+                    candidates.push(Neighbor {
+                        distance: l2(query.query_vector, node.vector),
+                        id: node_id,
+                    });
+                }
+            }
+        }
+        candidates
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u32(writer, self.nprobe as u32)?;
+        write_u32(writer, self.lists.len() as u32)?;
+        write_f32s(writer, &self.centroids)?;
+        for list in &self.lists {
+            write_ids(writer, list)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_from<R: Read>(
+        reader: &mut R,
+        num_vectors: u32,
+        dimensions: usize,
+    ) -> error::Result<Self> {
+        if dimensions == 0 {
+            return Err(malformed("IVF index over empty vectors".to_string()));
+        }
+        let nprobe = read_u32(reader)? as usize;
+        let nlist = read_u32(reader)? as usize;
+        let len = nlist
+            .checked_mul(dimensions)
+            .ok_or_else(|| malformed(format!("IVF index of {} lists is too large", nlist)))?;
+        let centroids = read_f32s(reader, len)?;
+        let lists = (0..nlist)
+            .map(|_| read_ids(reader, num_vectors))
+            .collect::<error::Result<Vec<_>>>()?;
+        Ok(Ivf {
+            dimensions,
+            centroids,
+            lists,
+            nprobe,
+            multi_probe: 0.0,
+            adaptive: false,
+        })
+    }
+}
+
+impl Solver for Ivf {
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        let nprobe = self.effective_nprobe(nodes_dataset, query);
+        // Probing lists that hold few matches costs more than scanning the
+        // nodes passing a selective filter.
+        let visited = nodes_dataset.num_vectors as usize * nprobe / self.nlist().max(1);
+        if let Some(result) = filter_first(nodes_dataset, query, k, visited) {
+            return result;
+        }
+        top_k(self.probe(nodes_dataset, query, nprobe), k)
+    }
+
+    fn explain(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, _k: usize) -> Plan {
+        let nprobe = self.effective_nprobe(nodes_dataset, query);
+        let visited = nodes_dataset.num_vectors as usize * nprobe / self.nlist().max(1);
+        let index = format!("ivf, nprobe {} of {} lists", nprobe, self.nlist());
+        explain(
+            nodes_dataset,
+            query,
+            visited,
+            Strategy::PostFilter { index },
+        )
+    }
+
+    fn search_range(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        radius: f32,
+    ) -> ScoredResult {
+        let nprobe = self.effective_nprobe(nodes_dataset, query);
+        let visited = nodes_dataset.num_vectors as usize * nprobe / self.nlist().max(1);
+        if let Some(candidates) = candidates(nodes_dataset, query)
+            && candidates.len <= visited
+        {
+            return scan_range(nodes_dataset, query, candidates.ids, radius);
+        }
+        let mut candidates = self.probe(nodes_dataset, query, nprobe);
+        candidates.retain(|candidate| candidate.distance <= radius);
+        top_k(candidates, usize::MAX)
+    }
+
+    fn degrade(&mut self, _k: usize) -> bool {
+        if self.nprobe <= 1 {
+            return false;
+        }
+        self.nprobe /= 2;
+        true
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.centroids.capacity() * mem::size_of::<f32>()
+            + self.lists.capacity() * mem::size_of::<Vec<u32>>()
+            + self
+                .lists
+                .iter()
+                .map(|list| list.capacity() * mem::size_of::<u32>())
+                .sum::<usize>()
+    }
+}
+
+/// Returns the index of the centroid closest to the vector, the first one
+/// if every distance is NaN.
+fn nearest_centroid(centroids: &[f32], dimensions: usize, vector: &[f32]) -> usize {
+    centroids
+        .chunks_exact(dimensions.max(1))
+        .enumerate()
+        .map(|(centroid, values)| Neighbor {
+            distance: l2(vector, values),
+            id: centroid as u32,
+        })
+        .min()
+        .map_or(0, |nearest| nearest.id as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+
+    #[test]
+    fn inserted_nodes_must_be_in_the_dataset() {
+        let mut rng = StdRng::seed_from_u64(73);
+        let nodes = generate::nodes(&mut rng, 20, 4, 2);
+        let mut ivf = IvfBuilder::new().nlist(2).nprobe(2).build(&nodes).unwrap();
+
+        assert!(matches!(
+            ivf.insert(&nodes, 20),
+            Err(GlasshouseError::Config(_))
+        ));
+    }
+}
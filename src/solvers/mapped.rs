@@ -0,0 +1,631 @@
+//! Read-only HNSW graph searched in place from a memory-mapped file, built
+//! with the `mmap` feature.
+//!
+//! The file holds the vectors, the attributes and every layer of an
+//! [`Hnsw`] graph in flat arrays located by offsets from the start of the
+//! file, so that it is searched straight from the mapping without being
+//! parsed or copied. Processes mapping the same file share its pages in the
+//! page cache, several search processes on a host hold a single copy of the
+//! index and vectors, and a file in `/dev/shm` is a shared memory segment.
+//!
+//! The header holds the `GHMM` magic, the format version, the number and
+//! dimensionality of the vectors, the graph parameters, the number of
+//! layers, the entry point and the metric the vectors are compared with,
+//! then the offsets of the categories, timestamps,
+//! tombstones and vectors, then for every layer the offsets of its
+//! adjacency offsets and of its adjacency lists with their total length.
+//! Each layer is stored in compressed sparse row form: the `n + 1` offsets
+//! of the list of every node in the concatenation of the lists. Sections
+//! start on 64-byte boundaries, integers and floats are little-endian.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::config::Metric;
+use crate::distance::l2;
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, ParsedNode, ParsedQuery, ScoredResult};
+
+use super::persist::{check_metric, malformed, metric_code, write_f32s, write_u32, write_u64};
+use super::plan::{candidates, explain, widen};
+use super::{Hnsw, Neighbor, Plan, Solver, Strategy, top_k};
+
+const MAGIC: [u8; 4] = *b"GHMM";
+/// Current version of the mapped index format, bumped on incompatible
+/// changes.
+pub const MAPPED_FORMAT_VERSION: u32 = 2;
+
+/// Entry point written for graphs without nodes.
+const NO_ENTRY_POINT: u32 = u32::MAX;
+/// Alignment of the sections, a cache line.
+const ALIGNMENT: usize = 64;
+/// Length of the fixed part of the header.
+const HEADER_BYTES: usize = 40 + 4 * 8;
+
+/// Location of the adjacency lists of a layer in the file.
+#[derive(Debug, Clone, Copy)]
+struct Layer {
+    offsets: usize,
+    ids: usize,
+    len: usize,
+}
+
+/// HNSW graph read in place from a memory-mapped file, see the module
+/// documentation.
+#[derive(Debug)]
+pub struct MappedHnsw {
+    map: Mmap,
+    num_vectors: usize,
+    dimensions: usize,
+    m: usize,
+    ef_search: usize,
+    /// Whether `ef_search` is widened for queries with selective filters.
+    adaptive: bool,
+    entry_point: Option<u32>,
+    vectors: usize,
+    layers: Vec<Layer>,
+}
+
+impl MappedHnsw {
+    /// Writes the graph built over the nodes under the metric to a file
+    /// meant to be mapped.
+    pub fn write<P: AsRef<Path>>(
+        file_path: P,
+        hnsw: &Hnsw,
+        nodes_dataset: &NodesDataset,
+        metric: Metric,
+    ) -> error::Result<()> {
+        let num_vectors = nodes_dataset.num_vectors as usize;
+        if hnsw.len() != num_vectors {
+            return Err(GlasshouseError::Config(format!(
+                "The graph holds {} nodes but the dataset {}",
+                hnsw.len(),
+                num_vectors
+            )));
+        }
+        let num_layers = hnsw.entry_point().map_or(0, |_| hnsw.max_level() + 1);
+        let lists = |level: usize| {
+            (0..num_vectors as u32).map(move |node_id| {
+                if hnsw.levels(node_id) > level {
+                    hnsw.neighbors(node_id, level)
+                } else {
+                    &[]
+                }
+            })
+        };
+
+        // Lay the sections out before writing them, so that the header
+        // holds their offsets.
+        let mut end = align(HEADER_BYTES + num_layers * 3 * 8);
+        let mut section = |bytes: usize| {
+            let offset = end;
+            end = align(end + bytes);
+            offset
+        };
+        let c_attrs = section(num_vectors * 4);
+        let t_attrs = section(num_vectors * 4);
+        let tombstones = section(num_vectors.div_ceil(64) * 8);
+        let vectors = section(num_vectors * nodes_dataset.dimensions * 4);
+        let layers: Vec<Layer> = (0..num_layers)
+            .map(|level| {
+                let len = lists(level).map(<[u32]>::len).sum();
+                Layer {
+                    offsets: section((num_vectors + 1) * 8),
+                    ids: section(len * 4),
+                    len,
+                }
+            })
+            .collect();
+
+        let mut writer = Padded::new(BufWriter::new(File::create(file_path)?));
+        writer.write_all(&MAGIC)?;
+        write_u32(&mut writer, MAPPED_FORMAT_VERSION)?;
+        write_u32(&mut writer, num_vectors as u32)?;
+        write_u32(&mut writer, nodes_dataset.dimensions as u32)?;
+        write_u32(&mut writer, hnsw.m() as u32)?;
+        write_u32(&mut writer, hnsw.ef_search() as u32)?;
+        write_u32(&mut writer, hnsw.adaptive() as u32)?;
+        write_u32(&mut writer, num_layers as u32)?;
+        write_u32(&mut writer, hnsw.entry_point().unwrap_or(NO_ENTRY_POINT))?;
+        write_u32(&mut writer, metric_code(metric))?;
+        for offset in [c_attrs, t_attrs, tombstones, vectors] {
+            write_u64(&mut writer, offset as u64)?;
+        }
+        for layer in &layers {
+            for value in [layer.offsets, layer.ids, layer.len] {
+                write_u64(&mut writer, value as u64)?;
+            }
+        }
+
+        writer.pad_to(c_attrs)?;
+        for &c_attr in &nodes_dataset.c_attrs {
+            write_u32(&mut writer, c_attr as u32)?;
+        }
+        writer.pad_to(t_attrs)?;
+        write_f32s(&mut writer, &nodes_dataset.t_attrs)?;
+        writer.pad_to(tombstones)?;
+        for word in 0..num_vectors.div_ceil(64) {
+            write_u64(
+                &mut writer,
+                nodes_dataset.tombstones.get(word).copied().unwrap_or(0),
+            )?;
+        }
+        writer.pad_to(vectors)?;
+        write_f32s(&mut writer, &nodes_dataset.vectors)?;
+        for (level, layer) in layers.iter().enumerate() {
+            writer.pad_to(layer.offsets)?;
+            let mut offset = 0u64;
+            write_u64(&mut writer, offset)?;
+            for list in lists(level) {
+                offset += list.len() as u64;
+                write_u64(&mut writer, offset)?;
+            }
+            writer.pad_to(layer.ids)?;
+            for list in lists(level) {
+                for &id in list {
+                    write_u32(&mut writer, id)?;
+                }
+            }
+        }
+        writer.pad_to(end)?;
+        writer.0.flush()?;
+        Ok(())
+    }
+
+    /// Maps an index file built under the metric, returning it with the
+    /// attributes of its nodes. The returned dataset holds no vectors, it is
+    /// only meant to be given to the index along with the queries.
+    pub fn open<P: AsRef<Path>>(
+        file_path: P,
+        metric: Metric,
+    ) -> error::Result<(Self, NodesDataset)> {
+        if cfg!(target_endian = "big") {
+            return Err(malformed(
+                "Mapped indexes are only searched on little-endian machines".to_string(),
+            ));
+        }
+        let file = File::open(file_path)?;
+        // SAFETY: the mapping is read-only, the file must not be truncated
+        // or modified while it is searched.
+        let map = unsafe { Mmap::map(&file)? };
+        let header = map
+            .get(..HEADER_BYTES)
+            .ok_or_else(|| malformed("Truncated mapped index header".to_string()))?;
+        if header[..4] != MAGIC {
+            return Err(malformed("Not a glasshouse mapped index file".to_string()));
+        }
+        let u32_at = |position: usize| read_u32(header, 4 + position * 4) as usize;
+        let version = u32_at(0) as u32;
+        if version != MAPPED_FORMAT_VERSION {
+            return Err(malformed(format!(
+                "Unsupported mapped index format version {}, expected {}",
+                version, MAPPED_FORMAT_VERSION
+            )));
+        }
+        let (num_vectors, dimensions, m, ef_search) = (u32_at(1), u32_at(2), u32_at(3), u32_at(4));
+        let adaptive = u32_at(5) != 0;
+        let num_layers = u32_at(6);
+        let entry_point = match u32_at(7) as u32 {
+            NO_ENTRY_POINT => None,
+            id if (id as usize) < num_vectors => Some(id),
+            id => return Err(malformed(format!("Entry point {} is out of range", id))),
+        };
+        check_metric(u32_at(8) as u32, metric)?;
+        let offset_at = |position: usize| read_u64(&map, 40 + position * 8);
+        let table = map
+            .get(..HEADER_BYTES + num_layers * 3 * 8)
+            .ok_or_else(|| malformed("Truncated mapped index header".to_string()))?;
+        let (c_attrs, t_attrs, tombstones, vectors) =
+            (offset_at(0), offset_at(1), offset_at(2), offset_at(3));
+        let layers: Vec<Layer> = (0..num_layers)
+            .map(|level| Layer {
+                offsets: read_u64(table, HEADER_BYTES + level * 24),
+                ids: read_u64(table, HEADER_BYTES + level * 24 + 8),
+                len: read_u64(table, HEADER_BYTES + level * 24 + 16),
+            })
+            .collect();
+
+        let index = MappedHnsw {
+            map,
+            num_vectors,
+            dimensions,
+            m,
+            ef_search,
+            adaptive,
+            entry_point,
+            vectors,
+            layers,
+        };
+        // The lengths come from the header, a corrupt one must not wrap
+        // them around to sections that fit.
+        let bytes = |count: Option<usize>, width: usize| count?.checked_mul(width);
+        let sections = [
+            (c_attrs, bytes(Some(num_vectors), 4)),
+            (t_attrs, bytes(Some(num_vectors), 4)),
+            (tombstones, bytes(Some(num_vectors.div_ceil(64)), 8)),
+            (vectors, bytes(num_vectors.checked_mul(dimensions), 4)),
+        ];
+        let lists = index.layers.iter().flat_map(|layer| {
+            [
+                (layer.offsets, bytes(num_vectors.checked_add(1), 8)),
+                (layer.ids, bytes(Some(layer.len), 4)),
+            ]
+        });
+        for (offset, len) in sections.into_iter().chain(lists) {
+            let Some(len) = len else {
+                return Err(malformed(
+                    "Mapped index sections overflow, the header is corrupt".to_string(),
+                ));
+            };
+            let fits = offset
+                .checked_add(len)
+                .is_some_and(|end| end <= index.map.len());
+            if !fits || offset % ALIGNMENT != 0 {
+                return Err(malformed(format!(
+                    "Section of {} bytes at offset {} is misplaced in the mapped index",
+                    len, offset
+                )));
+            }
+        }
+        if index.entry_point.is_some() && index.layers.is_empty() {
+            return Err(malformed(
+                "Mapped index has an entry point but no layer".to_string(),
+            ));
+        }
+        for level in 0..index.layers.len() {
+            index.check_layer(level)?;
+        }
+
+        let c_attrs = index
+            .u32s(c_attrs, num_vectors)
+            .iter()
+            .map(|&c_attr| c_attr as i32)
+            .collect();
+        let t_attrs = index.f32s(t_attrs, num_vectors).to_vec();
+        let words = index.map[tombstones..tombstones + num_vectors.div_ceil(64) * 8]
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().expect("chunks hold 8 bytes")));
+        // The attributes are indexed before the dimensionality is set, so
+        // the category statistics do not look for vectors.
+        let mut nodes_dataset = NodesDataset::from_parts(0, c_attrs, t_attrs, Vec::new())?;
+        nodes_dataset.dimensions = dimensions;
+        for (word, bits) in words.enumerate() {
+            for bit in (0..64).filter(|bit| bits & (1 << bit) != 0) {
+                nodes_dataset.delete((word * 64 + bit) as u32);
+            }
+        }
+        Ok((index, nodes_dataset))
+    }
+
+    /// Number of nodes of the graph.
+    pub fn len(&self) -> usize {
+        self.num_vectors
+    }
+
+    /// Returns true if the graph has no node.
+    pub fn is_empty(&self) -> bool {
+        self.entry_point.is_none()
+    }
+
+    /// Maximum number of neighbours per node on the upper layers.
+    pub fn m(&self) -> usize {
+        self.m
+    }
+
+    /// Width of the candidate list used while searching the graph.
+    pub fn ef_search(&self) -> usize {
+        self.ef_search
+    }
+
+    /// Sets the width of the candidate list used while searching the graph.
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.ef_search = ef_search;
+    }
+
+    /// Whether the width is widened for queries with selective filters.
+    pub fn adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    /// Sets whether the width is widened for queries with selective
+    /// filters.
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+    }
+
+    /// Bytes of the mapped file, shared with the other processes mapping
+    /// it.
+    pub fn mapped_bytes(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns the vector of a node.
+    pub fn vector(&self, node_id: u32) -> &[f32] {
+        let start = node_id as usize * self.dimensions;
+        &self.f32s(self.vectors, self.num_vectors * self.dimensions)[start..start + self.dimensions]
+    }
+
+    /// Returns the neighbours of a node on a layer, empty if the node is
+    /// not on that layer.
+    pub fn neighbors(&self, node_id: u32, level: usize) -> &[u32] {
+        let layer = self.layers[level];
+        let offsets = &self.map[layer.offsets..layer.offsets + (self.num_vectors + 1) * 8];
+        let start = read_u64(offsets, node_id as usize * 8);
+        let end = read_u64(offsets, node_id as usize * 8 + 8);
+        &self.u32s(layer.ids, layer.len)[start..end]
+    }
+
+    /// Checks that the adjacency lists of a layer lie in its section and
+    /// only hold nodes of the graph, so that searching never reads out of
+    /// bounds.
+    fn check_layer(&self, level: usize) -> error::Result<()> {
+        let layer = self.layers[level];
+        let offsets = &self.map[layer.offsets..layer.offsets + (self.num_vectors + 1) * 8];
+        let mut previous = 0;
+        for position in 0..=self.num_vectors {
+            let offset = read_u64(offsets, position * 8);
+            if offset < previous || offset > layer.len {
+                return Err(malformed(format!(
+                    "Adjacency offset {} of node {} on layer {} is out of order or range",
+                    offset, position, level
+                )));
+            }
+            previous = offset;
+        }
+        let ids = self.u32s(layer.ids, layer.len);
+        if let Some(id) = ids.iter().find(|&&id| id as usize >= self.num_vectors) {
+            return Err(malformed(format!(
+                "Layer {} links to node {} out of range",
+                level, id
+            )));
+        }
+        Ok(())
+    }
+
+    fn f32s(&self, offset: usize, len: usize) -> &[f32] {
+        let bytes = &self.map[offset..offset + len * 4];
+        // SAFETY: sections are checked to lie in the mapping on 64-byte
+        // boundaries when it is opened, and every bit pattern is a float.
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast::<f32>(), len) }
+    }
+
+    fn u32s(&self, offset: usize, len: usize) -> &[u32] {
+        let bytes = &self.map[offset..offset + len * 4];
+        // SAFETY: as for `f32s`.
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast::<u32>(), len) }
+    }
+
+    fn width(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>) -> usize {
+        if self.adaptive {
+            widen(self.ef_search, nodes_dataset, query)
+        } else {
+            self.ef_search
+        }
+    }
+
+    /// Moves greedily towards the vector on the given layer.
+    fn greedy_search(&self, vector: &[f32], mut entry: Neighbor, layer: usize) -> Neighbor {
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for &id in self.neighbors(entry.id, layer) {
+                let distance = l2(vector, self.vector(id));
+                if distance < entry.distance {
+                    entry = Neighbor { distance, id };
+                    improved = true;
+                }
+            }
+        }
+        entry
+    }
+}
+
+impl Solver for MappedHnsw {
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        let ef_search = self.width(nodes_dataset, query);
+        self.search_scored_with_ef(nodes_dataset, query, k, ef_search)
+    }
+
+    fn explain(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> Plan {
+        let ef = self.width(nodes_dataset, query).max(k);
+        let index = format!("mapped hnsw, ef_search {}", ef);
+        explain(
+            nodes_dataset,
+            query,
+            ef.saturating_mul(2 * self.m),
+            Strategy::PostFilter { index },
+        )
+    }
+
+    fn search_scored_with_ef(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+        ef_search: usize,
+    ) -> ScoredResult {
+        let Some(entry_point) = self.entry_point else {
+            return top_k(Vec::new(), k);
+        };
+        let matches = |node_id: u32| {
+            let index = node_id as usize;
+            !nodes_dataset.is_deleted(node_id)
+                && query.matches(&ParsedNode {
+                    c_attr: nodes_dataset.c_attrs[index],
+                    t_attr: nodes_dataset.t_attrs[index],
+                    vector: &[],
+                })
+        };
+        let vector = query.query_vector;
+        let neighbor = |id: u32| Neighbor {
+            distance: l2(vector, self.vector(id)),
+            id,
+        };
+
+        // As for `Hnsw`, scanning fewer nodes passing the filters than the
+        // bottom layer search evaluates is cheaper and exact.
+        let ef = ef_search.max(k);
+        if let Some(candidates) = candidates(nodes_dataset, query)
+            && candidates.len <= ef.saturating_mul(2 * self.m)
+        {
+            let found = candidates.ids.filter(|&id| matches(id)).map(neighbor);
+            return top_k(found.collect(), k);
+        }
+
+        let mut entry = neighbor(entry_point);
+        for layer in (1..self.layers.len()).rev() {
+            entry = self.greedy_search(vector, entry, layer);
+        }
+
+        // Beam search over the bottom layer, keeping the closest matching
+        // nodes among every evaluated one.
+        let mut found: BinaryHeap<Neighbor> =
+            BinaryHeap::with_capacity(k.min(self.num_vectors) + 1);
+        let mut keep = |candidate: Neighbor| {
+            if matches(candidate.id) {
+                found.push(candidate);
+                if found.len() > k {
+                    found.pop();
+                }
+            }
+        };
+        keep(entry);
+        let mut visited = HashSet::from([entry.id]);
+        let mut frontier = BinaryHeap::from([Reverse(entry)]);
+        let mut beam = BinaryHeap::from([entry]);
+        while let Some(Reverse(candidate)) = frontier.pop() {
+            let furthest = beam.peek().map_or(f32::INFINITY, |n| n.distance);
+            if candidate.distance > furthest && beam.len() >= ef {
+                break;
+            }
+            for &id in self.neighbors(candidate.id, 0) {
+                if !visited.insert(id) {
+                    continue;
+                }
+                let next = neighbor(id);
+                keep(next);
+                let furthest = beam.peek().map_or(f32::INFINITY, |n| n.distance);
+                if beam.len() < ef || next.distance < furthest {
+                    frontier.push(Reverse(next));
+                    beam.push(next);
+                    if beam.len() > ef {
+                        beam.pop();
+                    }
+                }
+            }
+        }
+
+        top_k(found.into_vec(), k)
+    }
+
+    fn degrade(&mut self, k: usize) -> bool {
+        if self.ef_search <= k {
+            return false;
+        }
+        self.ef_search = (self.ef_search / 2).max(k);
+        true
+    }
+}
+
+fn align(offset: usize) -> usize {
+    offset.next_multiple_of(ALIGNMENT)
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().expect("4 bytes"))
+}
+
+fn read_u64(bytes: &[u8], position: usize) -> usize {
+    u64::from_le_bytes(bytes[position..position + 8].try_into().expect("8 bytes")) as usize
+}
+
+/// Writer counting the bytes written, so that sections are padded to their
+/// offset.
+struct Padded<W>(W, usize);
+
+impl<W: Write> Padded<W> {
+    fn new(writer: W) -> Self {
+        Padded(writer, 0)
+    }
+
+    /// Writes zeros up to the offset.
+    fn pad_to(&mut self, offset: usize) -> io::Result<()> {
+        let padding = offset - self.1;
+        self.write_all(&vec![0; padding])
+    }
+}
+
+impl<W: Write> Write for Padded<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let written = self.0.write(bytes)?;
+        self.1 += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::solvers::{HnswBuilder, run_scored};
+    use crate::{constants::K_NEAREST, generate};
+
+    #[test]
+    fn mapped_graphs_answer_like_the_built_ones() {
+        let mut rng = StdRng::seed_from_u64(61);
+        let mut nodes = generate::nodes(&mut rng, 1000, 8, 4);
+        nodes.delete(5);
+        let queries = generate::queries(&mut rng, 50, 8, 4);
+        let hnsw = HnswBuilder::new().m(8).ef_search(32).build(&nodes).unwrap();
+        let path = crate::testing::temp_path("mapped.hnsw");
+        MappedHnsw::write(&path, &hnsw, &nodes, Metric::L2).unwrap();
+
+        let (mapped, attributes) = MappedHnsw::open(&path, Metric::L2).unwrap();
+        assert!(MappedHnsw::open(&path, Metric::Cosine).is_err());
+        assert_eq!((mapped.len(), mapped.m()), (1000, 8));
+        assert!(attributes.vectors.is_empty() && attributes.is_deleted(5));
+        assert_eq!(mapped.vector(7), nodes.vector(7));
+        assert_eq!(mapped.neighbors(7, 0), hnsw.neighbors(7, 0));
+        assert_eq!(
+            run_scored(&mapped, &attributes, &queries, K_NEAREST).unwrap(),
+            run_scored(&hnsw, &nodes, &queries, K_NEAREST).unwrap()
+        );
+
+        // Corrupt headers and graphs fail to open rather than to search.
+        let bytes = std::fs::read(&path).unwrap();
+        let corrupt = |position: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[position..position + value.len()].copy_from_slice(value);
+            std::fs::write(&path, bytes).unwrap();
+            MappedHnsw::open(&path, Metric::L2).is_err()
+        };
+        let (offsets, ids) = (
+            read_u64(&bytes, HEADER_BYTES),
+            read_u64(&bytes, HEADER_BYTES + 8),
+        );
+        assert!(corrupt(
+            8,
+            &[u32::MAX.to_le_bytes(), u32::MAX.to_le_bytes()].concat()
+        ));
+        assert!(corrupt(offsets + 8, &u64::MAX.to_le_bytes()));
+        assert!(corrupt(ids, &1000u32.to_le_bytes()));
+        std::fs::write(&path, b"GHMM").unwrap();
+        assert!(MappedHnsw::open(&path, Metric::L2).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1295 @@
+//! Solvers answering filtered k-nearest neighbour queries.
+//!
+//! Every solver implements [`Solver`] which answers a single query against
+//! the nodes dataset, [`run`] takes care of answering a full queries dataset
+//! in parallel and [`stream`] delivers results as they complete.
+//!
+//! The approximate solvers answer queries whose filters leave fewer nodes
+//! than they would visit by scanning those nodes instead, which is exact.
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+
+use crate::cancel::CancellationToken;
+use crate::config::{Config, SolverKind};
+use crate::error::{self, GlasshouseError};
+use crate::pca::Pca;
+use crate::progress::{NoProgress, Phase, Progress, Tracker};
+use crate::sample;
+use crate::types::{
+    NodesDataset, ParsedQuery, QueriesDataset, QueryResult, QueryResults, QueryType,
+    ScoredNeighbor, ScoredResult, ScoredResults,
+};
+
+mod baseline;
+pub(crate) mod batch;
+mod checkpoint;
+#[cfg(feature = "fs")]
+mod disk;
+mod exact;
+mod hnsw;
+mod hybrid;
+mod ivf;
+#[cfg(feature = "mmap")]
+mod mapped;
+mod persist;
+mod plan;
+mod reduced;
+mod reservoir;
+#[cfg(feature = "fs")]
+mod spill;
+mod stream;
+mod window;
+
+pub use baseline::{Baseline, BaselineBuilder};
+pub use checkpoint::Checkpoint;
+#[cfg(feature = "fs")]
+pub use checkpoint::run_checkpointed;
+#[cfg(feature = "fs")]
+pub use disk::{DISK_FORMAT_VERSION, DiskIndex};
+pub use exact::{Exact, run_blocked};
+pub use hnsw::{Hnsw, HnswBuilder};
+pub use hybrid::Hybrid;
+pub use ivf::{Ivf, IvfBuilder};
+#[cfg(feature = "mmap")]
+pub use mapped::{MAPPED_FORMAT_VERSION, MappedHnsw};
+pub use persist::{FORMAT_VERSION, Index};
+pub use plan::{Plan, Strategy};
+pub use reduced::Reduced;
+pub use reservoir::Reservoir;
+#[cfg(feature = "fs")]
+pub use spill::SpillingExact;
+pub use stream::{Delivery, stream, stream_scored};
+pub use window::{SlidingWindow, SlidingWindowBuilder};
+
+/// Identifier used to pad results when fewer than `k` nodes match, never a
+/// node ID so padding is not mistaken for a neighbour.
+pub const DEFAULT_PAD_ID: u32 = u32::MAX;
+
+/// Common interface of all solvers.
+pub trait Solver: Send + Sync {
+    /// Returns the `k` nodes closest to the query that pass its filters with
+    /// their distance, closest first.
+    fn search_scored(
+        &self,
+        nodes: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult;
+
+    /// Same as [`Solver::search_scored`] with the candidate list width of
+    /// graph indexes set to `ef_search` for this query only, solvers without
+    /// such a width ignore it.
+    fn search_scored_with_ef(
+        &self,
+        nodes: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+        ef_search: usize,
+    ) -> ScoredResult {
+        let _ = ef_search;
+        self.search_scored(nodes, query, k)
+    }
+
+    /// Returns the nodes passing the filters of the query whose distance to
+    /// it is at most `radius`, closest first. Distances are squared
+    /// Euclidean distances as in [`ScoredNeighbor`]. Solvers without a
+    /// range search of their own scan the nodes passing the filters.
+    fn search_range(
+        &self,
+        nodes: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        radius: f32,
+    ) -> ScoredResult {
+        plan::exact_range(nodes, query, radius)
+    }
+
+    /// Returns how the solver would answer the query, without searching it.
+    /// Solvers without a plan of their own are reported as scanning every
+    /// node, or the nodes passing the most selective filter.
+    fn explain(&self, nodes: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> Plan {
+        let _ = k;
+        plan::explain(nodes, query, nodes.num_vectors as usize, Strategy::Scan)
+    }
+
+    /// Returns the IDs of the nodes found by [`Solver::search_scored`],
+    /// padded to `k` with `DEFAULT_PAD_ID` if there are not enough.
+    fn search(&self, nodes: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> QueryResult {
+        ids(&self.search_scored(nodes, query, k), k)
+    }
+
+    /// Heap memory held by the solver in bytes, the nodes dataset excluded.
+    fn memory_bytes(&self) -> usize {
+        0
+    }
+
+    /// Halves the search effort, trading recall for speed, keeping enough
+    /// of it to find `k` neighbours. Returns false when the effort is
+    /// already minimal or cannot be tuned.
+    fn degrade(&mut self, _k: usize) -> bool {
+        false
+    }
+}
+
+/// Builds the solver selected by the configuration over the nodes.
+pub fn build(config: &Config, nodes_dataset: &NodesDataset) -> error::Result<Box<dyn Solver>> {
+    build_with_progress(config, nodes_dataset, &NoProgress)
+}
+
+/// Builds the solver selected by the configuration over the nodes,
+/// reporting the progress of the index construction.
+pub fn build_with_progress(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+    progress: &dyn Progress,
+) -> error::Result<Box<dyn Solver>> {
+    if config.k() == 0 {
+        return Err(GlasshouseError::Config(
+            "The number of neighbours k must be at least 1".to_string(),
+        ));
+    }
+    if config.pca.dimensions > 0 {
+        let solver = build_reduced(config, nodes_dataset, progress)?;
+        return with_scoring(config, Box::new(solver));
+    }
+    let solver = config.on_build_threads(|| {
+        let solver: Box<dyn Solver> = match config.solver {
+            SolverKind::Baseline => Box::new(config.baseline_builder().build(nodes_dataset)?),
+            SolverKind::Exact => exact(config)?,
+            SolverKind::Ivf => Box::new(
+                config
+                    .ivf_builder()
+                    .build_with_progress(nodes_dataset, progress)?,
+            ),
+            SolverKind::Hnsw => Box::new(
+                config
+                    .hnsw_builder()
+                    .build_with_progress(nodes_dataset, progress)?,
+            ),
+        };
+        Ok(solver)
+    })?;
+    with_scoring(config, solver)
+}
+
+/// Builds the [`Reduced`] solver of the configuration: the solver it selects
+/// searches the nodes projected on their principal components, and its
+/// candidates are reranked with the full vectors. The hybrid scoring is left
+/// to the caller, see [`with_scoring`].
+pub fn build_reduced(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+    progress: &dyn Progress,
+) -> error::Result<Reduced> {
+    let (pca, reduced) = reduce(config, nodes_dataset)?;
+    let mut inner_config = config.clone();
+    inner_config.pca.dimensions = 0;
+    inner_config.hybrid.weight = 0.0;
+    let inner = build_with_progress(&inner_config, &reduced, progress)?;
+    Reduced::new(pca, reduced, inner, config.pca.oversampling)
+}
+
+/// Trains the projection of the configuration over a sample of
+/// `pca.sample_size` nodes, returns it with the projected nodes searched by
+/// a [`Reduced`] solver.
+pub fn reduce(config: &Config, nodes_dataset: &NodesDataset) -> error::Result<(Pca, NodesDataset)> {
+    let live = (nodes_dataset.num_vectors - nodes_dataset.num_deleted()) as usize;
+    let fraction = config.pca.sample_size as f64 / live.max(1) as f64;
+    let sample = sample::nodes(
+        &mut StdRng::seed_from_u64(config.seed),
+        nodes_dataset,
+        fraction,
+    );
+    let pca = Pca::train(&sample, config.pca.dimensions)?;
+    let reduced = pca.project_nodes(nodes_dataset)?;
+    Ok((pca, reduced))
+}
+
+/// Returns the exact solver, spilling the candidates of each query to disk
+/// beyond `exact.spill_batch` of them when the configuration sets it.
+pub fn exact(config: &Config) -> error::Result<Box<dyn Solver>> {
+    #[cfg(feature = "fs")]
+    if config.exact.spill_batch > 0 {
+        let dir = config
+            .exact
+            .spill_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        return Ok(Box::new(SpillingExact::new(config.exact.spill_batch, dir)?));
+    }
+    #[cfg(not(feature = "fs"))]
+    let _ = config;
+    Ok(Box::new(Exact))
+}
+
+/// Wraps the solver in a [`Hybrid`] one when the configuration blends
+/// timestamp proximity into the scores, returns it unchanged otherwise.
+pub fn with_scoring(config: &Config, solver: Box<dyn Solver>) -> error::Result<Box<dyn Solver>> {
+    if config.hybrid.weight == 0.0 {
+        return Ok(solver);
+    }
+    Ok(Box::new(Hybrid::new(solver, config.hybrid.weight)?))
+}
+
+/// Runs the solver over every query of the dataset in parallel, returning
+/// the `k` nearest neighbours of each query in query order, in the contest
+/// format: results with fewer than `k` neighbours are padded with
+/// [`DEFAULT_PAD_ID`]. [`run_scored`] leaves them as they are.
+pub fn run<S: Solver + ?Sized>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+) -> error::Result<QueryResults> {
+    run_with_progress(solver, nodes_dataset, queries_dataset, k, &NoProgress)
+}
+
+/// Same as [`run`], reporting the number of queries answered so far.
+pub fn run_with_progress<S: Solver + ?Sized>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    progress: &dyn Progress,
+) -> error::Result<QueryResults> {
+    // Not timed, `Instant` is unavailable on wasm32-unknown-unknown.
+    let token = CancellationToken::new();
+    let (results, _) = answer(
+        nodes_dataset,
+        queries_dataset,
+        true,
+        progress,
+        &token,
+        |_, query| solver.search(nodes_dataset, query, k),
+    )?;
+    Ok(results
+        .into_iter()
+        .map(|result| result.expect("runs that are never cancelled answer every query"))
+        .collect())
+}
+
+/// Results of [`run_cancellable`], in query order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialResults {
+    /// Scored results of every query, the queries left unanswered when the
+    /// run was cancelled have no neighbours.
+    pub results: ScoredResults,
+    /// Number of queries answered before the run was cancelled.
+    pub answered: u32,
+    /// Number of answered queries given the results of an identical query
+    /// instead of being searched.
+    pub cached: u32,
+    /// Wall-clock time spent answering each query, `None` for the queries
+    /// left unanswered. Zero on wasm32-unknown-unknown, which has no clock.
+    pub latencies: Vec<Option<Duration>>,
+}
+
+impl PartialResults {
+    /// Returns the IDs of the results in the contest format padded to `k`,
+    /// unanswered queries only hold `DEFAULT_PAD_ID`.
+    pub fn ids(&self, k: usize) -> QueryResults {
+        self.results.iter().map(|result| ids(result, k)).collect()
+    }
+
+    /// Number of answered queries with fewer than `k` neighbours, because
+    /// fewer nodes pass their filters or the solver missed some of them.
+    /// Their results are only padded once converted with [`PartialResults::ids`].
+    pub fn under_filled(&self, k: usize) -> u32 {
+        self.results
+            .iter()
+            .zip(&self.latencies)
+            .filter(|(result, latency)| latency.is_some() && result.len() < k)
+            .count() as u32
+    }
+
+    /// Same as [`PartialResults::under_filled`] for the queries of each
+    /// type, `query_types` holds the type of each query. Types without any
+    /// under-filled query are omitted.
+    pub fn under_filled_by_type(
+        &self,
+        k: usize,
+        query_types: &[QueryType],
+    ) -> Vec<(QueryType, u32)> {
+        QueryType::ALL
+            .into_iter()
+            .map(|query_type| {
+                let count = self
+                    .results
+                    .iter()
+                    .zip(&self.latencies)
+                    .zip(query_types)
+                    .filter(|((result, latency), t)| {
+                        **t == query_type && latency.is_some() && result.len() < k
+                    })
+                    .count() as u32;
+                (query_type, count)
+            })
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+}
+
+/// Same as [`run_with_progress`], stops answering queries once the token is
+/// cancelled and returns the results computed until then.
+///
+/// Identical queries are searched once unless `cache_duplicates` is false,
+/// the others are given the same results and latency.
+pub fn run_cancellable<S: Solver + ?Sized>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    cache_duplicates: bool,
+    progress: &dyn Progress,
+    token: &CancellationToken,
+) -> error::Result<PartialResults> {
+    run_observed(
+        solver,
+        nodes_dataset,
+        queries_dataset,
+        k,
+        cache_duplicates,
+        progress,
+        token,
+        |_, _| {},
+    )
+}
+
+/// Calls `f` and measures its wall-clock time, zero on
+/// wasm32-unknown-unknown where `Instant` is unavailable.
+fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        let start = std::time::Instant::now();
+        let value = f();
+        (value, start.elapsed())
+    }
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    (f(), Duration::ZERO)
+}
+
+/// Same as [`run_cancellable`], calling `observe` from the worker threads
+/// with the index and results of each query as soon as it is searched.
+/// Queries given the results of an identical one are not observed.
+#[allow(clippy::too_many_arguments)]
+pub fn run_observed<S, F>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    cache_duplicates: bool,
+    progress: &dyn Progress,
+    token: &CancellationToken,
+    observe: F,
+) -> error::Result<PartialResults>
+where
+    S: Solver + ?Sized,
+    F: Fn(usize, &ScoredResult) + Sync,
+{
+    let (results, cached) = answer(
+        nodes_dataset,
+        queries_dataset,
+        cache_duplicates,
+        progress,
+        token,
+        |index, query| {
+            let (result, latency) = timed(|| solver.search_scored(nodes_dataset, query, k));
+            observe(index, &result);
+            (result, latency)
+        },
+    )?;
+    let answered = results.iter().filter(|result| result.is_some()).count() as u32;
+    let (results, latencies) = results
+        .into_iter()
+        .map(|result| match result {
+            Some((result, latency)) => (result, Some(latency)),
+            None => (Vec::new(), None),
+        })
+        .unzip();
+    Ok(PartialResults {
+        results,
+        answered,
+        cached,
+        latencies,
+    })
+}
+
+/// Same as [`run`], returning the distance of every neighbour. Results are
+/// not padded, queries with fewer than `k` matching nodes have fewer
+/// neighbours.
+pub fn run_scored<S: Solver + ?Sized>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+) -> error::Result<ScoredResults> {
+    run_scored_with_progress(solver, nodes_dataset, queries_dataset, k, &NoProgress)
+}
+
+/// Same as [`run_scored`], reporting the number of queries answered so far.
+pub fn run_scored_with_progress<S: Solver + ?Sized>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    k: usize,
+    progress: &dyn Progress,
+) -> error::Result<ScoredResults> {
+    let token = CancellationToken::new();
+    let (results, _) = answer(
+        nodes_dataset,
+        queries_dataset,
+        true,
+        progress,
+        &token,
+        |_, query| solver.search_scored(nodes_dataset, query, k),
+    )?;
+    Ok(results
+        .into_iter()
+        .map(|result| result.expect("runs that are never cancelled answer every query"))
+        .collect())
+}
+
+/// Runs a range search of the solver for every query of the dataset in
+/// parallel, see [`Solver::search_range`], returning the results in query
+/// order.
+pub fn run_range<S: Solver + ?Sized>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    radius: f32,
+) -> error::Result<ScoredResults> {
+    let token = CancellationToken::new();
+    let (results, _) = answer(
+        nodes_dataset,
+        queries_dataset,
+        true,
+        &NoProgress,
+        &token,
+        |_, query| solver.search_range(nodes_dataset, query, radius),
+    )?;
+    Ok(results
+        .into_iter()
+        .map(|result| result.expect("runs that are never cancelled answer every query"))
+        .collect())
+}
+
+/// Answers the queries in parallel, grouped by [`batch::order`], calling
+/// `search` with the index of each query, and returns their results in
+/// query order with the number of queries given
+/// the results of an identical one. Queries left once the token is
+/// cancelled are `None`.
+fn answer<T, F>(
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    cache_duplicates: bool,
+    progress: &dyn Progress,
+    token: &CancellationToken,
+    search: F,
+) -> error::Result<(Vec<Option<T>>, u32)>
+where
+    T: Clone + Send,
+    F: Fn(usize, &ParsedQuery<'_>) -> T + Sync,
+{
+    if nodes_dataset.dimensions != queries_dataset.dimensions {
+        return Err(GlasshouseError::DimensionMismatch {
+            nodes: nodes_dataset.dimensions,
+            queries: queries_dataset.dimensions,
+        });
+    }
+
+    let tracker = Tracker::new(progress, Phase::Search, queries_dataset.num_queries as u64);
+    let first = if cache_duplicates {
+        batch::first_duplicates(queries_dataset)
+    } else {
+        (0..queries_dataset.num_queries as usize).collect()
+    };
+    let mut order = batch::order(queries_dataset);
+    order.retain(|&i| first[i] == i);
+    let answers: Vec<Option<T>> = order
+        .par_iter()
+        .map(|&i| {
+            if token.is_cancelled() {
+                return None;
+            }
+            let query = queries_dataset.get(i).expect("query indices are in range");
+            let result = search(i, &query);
+            tracker.advance(1);
+            Some(result)
+        })
+        .collect();
+
+    let mut results: Vec<Option<T>> = first.iter().map(|_| None).collect();
+    for (i, answer) in order.into_iter().zip(answers) {
+        results[i] = answer;
+    }
+    let mut cached = 0;
+    for (i, &first) in first.iter().enumerate().filter(|&(i, &first)| first != i) {
+        results[i] = results[first].clone();
+        if results[i].is_some() {
+            tracker.advance(1);
+            cached += 1;
+        }
+    }
+    Ok((results, cached))
+}
+
+/// How a composite index merging the neighbours of several parts treats a
+/// node found by more than one of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Duplicates {
+    /// Keeps the closest occurrence of every node, then the `k` closest
+    /// nodes, so results hold `k` distinct IDs whenever that many nodes
+    /// match. The contest expects distinct IDs, a repeated ID takes the
+    /// slot of a neighbour and lowers recall.
+    #[default]
+    BeforeTruncation,
+    /// Keeps the `k` closest candidates, then drops repeated IDs, so
+    /// results may hold fewer than `k` neighbours.
+    AfterTruncation,
+    /// Keeps repeated IDs.
+    Keep,
+}
+
+/// A candidate node and its distance to the query, ordered by distance and
+/// then by ID so candidates at equal distances rank the same on every run.
+/// NaN distances, computed from malformed vectors, rank after all others.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Neighbor {
+    pub distance: f32,
+    pub id: u32,
+}
+
+impl Eq for Neighbor {}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbor {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or_else(|| self.distance.is_nan().cmp(&other.distance.is_nan()))
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+/// Sorts the candidates by distance and keeps the `k` closest, candidates
+/// at a NaN distance are dropped.
+pub(crate) fn top_k(mut candidates: Vec<Neighbor>, k: usize) -> ScoredResult {
+    candidates.retain(|candidate| !candidate.distance.is_nan());
+    candidates.sort_unstable();
+    candidates
+        .into_iter()
+        .take(k)
+        .map(|candidate| ScoredNeighbor {
+            id: candidate.id,
+            distance: candidate.distance,
+        })
+        .collect()
+}
+
+/// Same as [`top_k`], treating candidates with the same ID as set by
+/// `duplicates`.
+pub(crate) fn merge_top_k(
+    mut candidates: Vec<Neighbor>,
+    k: usize,
+    duplicates: Duplicates,
+) -> ScoredResult {
+    if duplicates == Duplicates::Keep {
+        return top_k(candidates, k);
+    }
+    candidates.retain(|candidate| !candidate.distance.is_nan());
+    candidates.sort_unstable();
+    if duplicates == Duplicates::AfterTruncation {
+        candidates.truncate(k);
+    }
+    let mut seen = HashSet::with_capacity(k.min(candidates.len()));
+    candidates.retain(|candidate| seen.insert(candidate.id));
+    top_k(candidates, k)
+}
+
+/// Returns the IDs of a scored result in the contest format, padded with
+/// `DEFAULT_PAD_ID` if there are fewer than `k` neighbours.
+pub fn ids(result: &[ScoredNeighbor], k: usize) -> QueryResult {
+    padded_ids(result, k, DEFAULT_PAD_ID)
+}
+
+/// Same as [`ids`], padded with `pad_id` instead.
+pub fn padded_ids(result: &[ScoredNeighbor], k: usize, pad_id: u32) -> QueryResult {
+    let mut ids: QueryResult = vec![pad_id; k].into_boxed_slice();
+    for (slot, neighbor) in ids.iter_mut().zip(result) {
+        *slot = neighbor.id;
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::constants::K_NEAREST;
+    use crate::distance::l2;
+    use crate::generate;
+    use crate::types::ParsedNodeOwned;
+
+    #[test]
+    fn scored_results_match_ids() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let nodes = generate::nodes(&mut rng, 300, 8, 4);
+        let queries = generate::queries(&mut rng, 30, 8, 4);
+        let scored = run_scored(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+
+        assert_eq!(
+            scored
+                .iter()
+                .map(|result| ids(result, K_NEAREST))
+                .collect::<Vec<_>>(),
+            run(&Exact, &nodes, &queries, K_NEAREST).unwrap()
+        );
+        for (i, result) in scored.iter().enumerate() {
+            let query = queries.get(i).unwrap();
+            assert!(result.len() <= K_NEAREST);
+            assert!(result.windows(2).all(|w| w[0].distance <= w[1].distance));
+            for neighbor in result {
+                let node = nodes.get(neighbor.id as usize).unwrap();
+                assert!(query.matches(&node));
+                assert_eq!(neighbor.distance, l2(query.query_vector, node.vector));
+            }
+        }
+    }
+
+    #[test]
+    fn runs_reject_mismatched_dimensions() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let nodes = generate::nodes(&mut rng, 100, 8, 4);
+        let queries = generate::queries(&mut rng, 10, 6, 4);
+
+        let mismatch = |result: error::Result<()>| {
+            matches!(
+                result,
+                Err(GlasshouseError::DimensionMismatch {
+                    nodes: 8,
+                    queries: 6
+                })
+            )
+        };
+        assert!(mismatch(run(&Exact, &nodes, &queries, K_NEAREST).map(drop)));
+        assert!(mismatch(
+            run_scored(&Exact, &nodes, &queries, K_NEAREST).map(drop)
+        ));
+    }
+
+    #[test]
+    fn blocked_runs_match_the_exact_solver() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let mut nodes = generate::nodes(&mut rng, 500, 11, 4);
+        nodes.delete(2);
+        let queries = generate::queries(&mut rng, 45, 11, 4);
+
+        for k in [0, 1, K_NEAREST] {
+            assert_eq!(
+                run_blocked(&nodes, &queries, k, &NoProgress).unwrap(),
+                run_scored(&Exact, &nodes, &queries, k).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn smaller_k_keeps_the_closest_neighbours() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let nodes = generate::nodes(&mut rng, 300, 8, 4);
+        let queries = generate::queries(&mut rng, 20, 8, 4);
+        let top_100 = run(&Exact, &nodes, &queries, 100).unwrap();
+        let top_10 = run(&Exact, &nodes, &queries, 10).unwrap();
+
+        for (short, long) in top_10.iter().zip(&top_100) {
+            assert_eq!(short.len(), 10);
+            assert_eq!(short[..], long[..10]);
+        }
+    }
+
+    #[test]
+    fn equal_distances_rank_by_id() {
+        // Four interleaved copies of three vectors, every candidate ties with
+        // three others.
+        let vectors: Vec<f32> = (0..12).map(|i| (i % 3) as f32).collect();
+        let nodes = NodesDataset::from_parts(1, vec![0; 12], vec![0.0; 12], vectors).unwrap();
+        let queries = QueriesDataset::from_vectors(1, vec![0.0]);
+        let expected: Vec<u32> = vec![0, 3, 6, 9, 1, 4, 7, 10, 2, 5, 8, 11];
+
+        let baseline = BaselineBuilder::new()
+            .sample_proportion(1.0)
+            .build(&nodes)
+            .unwrap();
+        let ivf = IvfBuilder::new().nlist(2).nprobe(2).build(&nodes).unwrap();
+        let hnsw = HnswBuilder::new().ef_search(32).build(&nodes).unwrap();
+        let solvers: [&dyn Solver; 4] = [&Exact, &baseline, &ivf, &hnsw];
+        for solver in solvers {
+            let result = run_scored(solver, &nodes, &queries, K_NEAREST).unwrap();
+            let ids: Vec<u32> = result[0].iter().map(|neighbor| neighbor.id).collect();
+            assert_eq!(ids, expected);
+        }
+    }
+
+    #[test]
+    fn nan_distances_are_never_returned() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut nodes = generate::nodes(&mut rng, 200, 8, 1);
+        let queries = generate::queries(&mut rng, 10, 8, 1);
+        for node_id in (0..200).step_by(7) {
+            nodes.vectors[node_id * 8] = f32::NAN;
+        }
+
+        let ivf = IvfBuilder::new().nlist(4).nprobe(4).build(&nodes).unwrap();
+        let hnsw = HnswBuilder::new().ef_search(200).build(&nodes).unwrap();
+        let solvers: [&dyn Solver; 3] = [&Exact, &ivf, &hnsw];
+        for solver in solvers {
+            for result in run_scored(solver, &nodes, &queries, K_NEAREST).unwrap() {
+                assert!(result.iter().all(|neighbor| neighbor.id % 7 != 0));
+                assert!(result.windows(2).all(|w| w[0].distance <= w[1].distance));
+            }
+        }
+    }
+
+    #[test]
+    fn multi_probe_widens_the_probed_lists() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let nodes = generate::nodes(&mut rng, 400, 8, 4);
+        let queries = generate::queries(&mut rng, 30, 8, 4);
+        let exact = run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+
+        let mut ivf = IvfBuilder::new().nlist(8).nprobe(1).build(&nodes).unwrap();
+        assert_ne!(run(&ivf, &nodes, &queries, K_NEAREST).unwrap(), exact);
+        ivf.set_multi_probe(1e6);
+        assert_eq!(run(&ivf, &nodes, &queries, K_NEAREST).unwrap(), exact);
+    }
+
+    #[test]
+    fn queries_can_widen_the_hnsw_search() {
+        let mut rng = StdRng::seed_from_u64(19);
+        let nodes = generate::nodes(&mut rng, 400, 8, 4);
+        let queries = generate::queries(&mut rng, 20, 8, 4);
+        let narrow = HnswBuilder::new().ef_search(8).build(&nodes).unwrap();
+        let mut wide = narrow.clone();
+        wide.set_ef_search(200);
+
+        for i in 0..queries.num_queries as usize {
+            let query = queries.get(i).unwrap();
+            assert_eq!(
+                narrow.search_scored_with_ef(&nodes, &query, 10, 200),
+                wide.search_scored(&nodes, &query, 10)
+            );
+            assert_eq!(
+                Exact.search_scored_with_ef(&nodes, &query, 10, 200),
+                Exact.search_scored(&nodes, &query, 10)
+            );
+        }
+    }
+
+    #[test]
+    fn adaptive_search_finds_more_filtered_neighbours() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let nodes = generate::nodes(&mut rng, 2000, 8, 8);
+        let queries = generate::queries(&mut rng, 40, 8, 8);
+        let exact = run(&Exact, &nodes, &queries, 10).unwrap();
+        let found = |solver: &dyn Solver| -> usize {
+            run(solver, &nodes, &queries, 10)
+                .unwrap()
+                .iter()
+                .zip(&exact)
+                .map(|(result, expected)| result.iter().filter(|id| expected.contains(id)).count())
+                .sum()
+        };
+
+        let mut ivf = IvfBuilder::new().nlist(32).nprobe(1).build(&nodes).unwrap();
+        let fixed = found(&ivf);
+        ivf.set_adaptive(true);
+        assert!(found(&ivf) > fixed);
+
+        let mut hnsw = HnswBuilder::new()
+            .m(4)
+            .ef_construction(8)
+            .ef_search(1)
+            .build(&nodes)
+            .unwrap();
+        let fixed = found(&hnsw);
+        hnsw.set_adaptive(true);
+        assert!(found(&hnsw) >= fixed);
+    }
+
+    #[test]
+    fn explain_reports_the_strategy_of_each_query() {
+        let mut rng = StdRng::seed_from_u64(47);
+        let nodes = generate::nodes(&mut rng, 2000, 4, 8);
+        let query = |query_type, v_categorical, bounds: Option<(f32, f32)>| ParsedQuery {
+            query_type,
+            v_categorical,
+            t_lower_bound: bounds.map(|b| b.0),
+            t_upper_bound: bounds.map(|b| b.1),
+            query_vector: &[0.5; 4],
+        };
+        let vector_only = query(QueryType::VectorOnly, None, None);
+        let categorical = query(QueryType::CategoricalConstraint, Some(0), None);
+        let narrow = query(QueryType::BothConstraints, Some(0), Some((0.5, 0.51)));
+
+        let plan = Exact.explain(&nodes, &vector_only, 10);
+        assert_eq!(plan.strategy, Strategy::Scan);
+        assert_eq!((plan.candidates, plan.visited), (None, 2000));
+        assert_eq!(plan.selectivity, 1.0);
+
+        let ivf = IvfBuilder::new().nlist(32).nprobe(1).build(&nodes).unwrap();
+        let plan = ivf.explain(&nodes, &categorical, 10);
+        assert!(matches!(plan.strategy, Strategy::PostFilter { .. }));
+        assert_eq!(plan.candidates, Some(nodes.category_len(0)));
+        assert_eq!(plan.visited, 2000 / 32);
+        assert!(plan.selectivity < 1.0);
+
+        let plan = ivf.explain(&nodes, &narrow, 10);
+        assert_eq!(plan.strategy, Strategy::FilterFirst);
+        assert_eq!(plan.candidates, Some(plan.visited));
+        assert!(plan.visited <= 2000 / 32);
+    }
+
+    #[test]
+    fn range_search_returns_the_neighbours_within_the_radius() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let nodes = generate::nodes(&mut rng, 500, 4, 4);
+        let queries = generate::queries(&mut rng, 30, 4, 4);
+        let radius = 0.5;
+        let nearest = run_scored(&Exact, &nodes, &queries, 500).unwrap();
+        let expected: ScoredResults = nearest
+            .into_iter()
+            .map(|mut result| {
+                result.retain(|neighbor| neighbor.distance <= radius);
+                result
+            })
+            .collect();
+        assert!(expected.iter().any(|result| result.len() > 1));
+
+        let ivf = IvfBuilder::new().nlist(8).nprobe(8).build(&nodes).unwrap();
+        let hnsw = HnswBuilder::new().build(&nodes).unwrap();
+        let solvers: [&dyn Solver; 3] = [&Exact, &ivf, &hnsw];
+        for solver in solvers {
+            assert_eq!(
+                run_range(solver, &nodes, &queries, radius).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn hybrid_scores_blend_timestamp_proximity() {
+        let nodes = NodesDataset::from_parts(
+            1,
+            vec![0; 4],
+            vec![0.5, 0.9, 0.1, 0.45],
+            vec![3.0, 0.0, 1.0, 2.0],
+        )
+        .unwrap();
+        let query = ParsedQuery {
+            query_type: QueryType::TimestampConstraint,
+            v_categorical: None,
+            t_lower_bound: Some(0.4),
+            t_upper_bound: Some(0.6),
+            query_vector: &[0.0],
+        };
+        let ids = |weight: f32| -> Vec<u32> {
+            Hybrid::new(Box::new(Exact), weight)
+                .unwrap()
+                .search_scored(&nodes, &query, 4)
+                .iter()
+                .map(|neighbor| neighbor.id)
+                .collect()
+        };
+
+        assert_eq!(ids(0.0), [1, 2, 3, 0]);
+        assert_eq!(ids(1000.0), [0, 3, 1, 2]);
+        assert!(Hybrid::new(Box::new(Exact), -1.0).is_err());
+    }
+
+    #[test]
+    fn reduced_solvers_rerank_with_the_full_vectors() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let nodes = generate::nodes(&mut rng, 1000, 16, 4);
+        let queries = generate::queries(&mut rng, 40, 16, 4);
+        let mut config = Config {
+            solver: SolverKind::Exact,
+            pca: crate::config::PcaConfig {
+                dimensions: 12,
+                oversampling: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let reduced = build(&config, &nodes).unwrap();
+
+        let results = run_scored(reduced.as_ref(), &nodes, &queries, K_NEAREST).unwrap();
+        let exact = run_scored(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+        let ids = |results: &ScoredResults| -> QueryResults {
+            results
+                .iter()
+                .map(|result| super::ids(result, K_NEAREST))
+                .collect()
+        };
+        let recall = crate::eval::recall(&ids(&results), &ids(&exact), DEFAULT_PAD_ID).unwrap();
+        assert!(recall > 0.9, "recall {}", recall);
+        for (i, result) in results.iter().enumerate() {
+            let query = queries.get(i).unwrap();
+            for neighbor in result {
+                let node = nodes.get(neighbor.id as usize).unwrap();
+                assert!(query.matches(&node));
+                assert_eq!(neighbor.distance, l2(query.query_vector, node.vector));
+            }
+        }
+
+        config.pca.dimensions = 16;
+        assert!(build(&config, &nodes).is_err());
+    }
+
+    #[test]
+    fn baseline_samples_nodes_at_random() {
+        let mut rng = StdRng::seed_from_u64(37);
+        let nodes = generate::nodes(&mut rng, 1000, 4, 4);
+        let builder = BaselineBuilder::new().sample_proportion(0.1).seed(7);
+        let mut baseline = builder.build(&nodes).unwrap();
+
+        let sample = baseline.sample().to_vec();
+        assert_eq!(sample.len(), 100);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        assert!(sample[99] >= 500);
+        assert_eq!(builder.build(&nodes).unwrap().sample(), sample);
+        assert_ne!(builder.seed(8).build(&nodes).unwrap().sample(), sample);
+
+        // The sample keeps at least k nodes.
+        assert!(!baseline.clone().degrade(K_NEAREST));
+        assert!(baseline.degrade(10));
+        assert_eq!(baseline.num_to_sample(), 50);
+        assert!(baseline.sample().iter().all(|id| sample.contains(id)));
+    }
+
+    #[test]
+    fn cancelled_runs_keep_answered_queries() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let nodes = generate::nodes(&mut rng, 200, 8, 4);
+        let queries = generate::queries(&mut rng, 50, 8, 4);
+        let expected = run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+
+        let token = CancellationToken::new();
+        let complete = run_cancellable(
+            &Exact,
+            &nodes,
+            &queries,
+            K_NEAREST,
+            true,
+            &NoProgress,
+            &token,
+        )
+        .unwrap();
+        assert_eq!(complete.answered, 50);
+        assert_eq!(complete.ids(K_NEAREST), expected);
+        // Filtered queries match fewer than K of the 200 nodes, their
+        // results are only padded by `ids`.
+        let padded = expected
+            .iter()
+            .filter(|result| result.contains(&DEFAULT_PAD_ID))
+            .count();
+        assert!(padded > 0);
+        assert_eq!(complete.under_filled(K_NEAREST) as usize, padded);
+        let by_type = complete.under_filled_by_type(K_NEAREST, &queries.query_types);
+        assert!(by_type.iter().all(|(t, _)| *t != QueryType::VectorOnly));
+        assert_eq!(
+            by_type
+                .iter()
+                .map(|&(_, count)| count as usize)
+                .sum::<usize>(),
+            padded
+        );
+        assert!(
+            complete
+                .results
+                .iter()
+                .all(|result| result.iter().all(|neighbor| neighbor.id != DEFAULT_PAD_ID))
+        );
+
+        let token = CancellationToken::with_deadline(std::time::Instant::now());
+        let expired = run_cancellable(
+            &Exact,
+            &nodes,
+            &queries,
+            K_NEAREST,
+            true,
+            &NoProgress,
+            &token,
+        )
+        .unwrap();
+        assert_eq!(expired.answered, 0);
+        assert_eq!(expired.under_filled(K_NEAREST), 0);
+        assert!(
+            expired
+                .ids(K_NEAREST)
+                .iter()
+                .all(|r| r.iter().all(|&id| id == DEFAULT_PAD_ID))
+        );
+    }
+
+    #[test]
+    fn under_filled_results_are_counted_per_query_type() {
+        let neighbor = |id| ScoredNeighbor { id, distance: 0.0 };
+        let partial = PartialResults {
+            results: vec![
+                vec![neighbor(0), neighbor(1)],
+                vec![neighbor(0)],
+                vec![neighbor(1)],
+                vec![],
+            ],
+            answered: 3,
+            cached: 0,
+            latencies: vec![
+                Some(Duration::ZERO),
+                Some(Duration::ZERO),
+                Some(Duration::ZERO),
+                None,
+            ],
+        };
+        let types = [
+            QueryType::VectorOnly,
+            QueryType::CategoricalConstraint,
+            QueryType::CategoricalConstraint,
+            QueryType::TimestampConstraint,
+        ];
+
+        // The unanswered query is not under-filled, it was never searched.
+        assert_eq!(partial.under_filled(2), 2);
+        assert_eq!(
+            partial.under_filled_by_type(2, &types),
+            vec![(QueryType::CategoricalConstraint, 2)]
+        );
+        assert!(partial.under_filled_by_type(1, &types).is_empty());
+    }
+
+    #[test]
+    fn duplicate_queries_are_searched_once() {
+        struct Counting(std::sync::atomic::AtomicUsize);
+
+        impl Solver for Counting {
+            fn search_scored(
+                &self,
+                nodes: &NodesDataset,
+                query: &ParsedQuery<'_>,
+                k: usize,
+            ) -> ScoredResult {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                Exact.search_scored(nodes, query, k)
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(8);
+        let nodes = generate::nodes(&mut rng, 200, 8, 4);
+        let unique = generate::queries(&mut rng, 10, 8, 4);
+        let queries = QueriesDataset {
+            num_queries: 20,
+            dimensions: 8,
+            query_types: unique.query_types.repeat(2),
+            v_categoricals: unique.v_categoricals.repeat(2),
+            t_lower_bounds: unique.t_lower_bounds.repeat(2),
+            t_upper_bounds: unique.t_upper_bounds.repeat(2),
+            query_vectors: unique.query_vectors.repeat(2),
+        };
+        let expected = run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+
+        for (cache_duplicates, searched) in [(true, 10), (false, 20)] {
+            let solver = Counting(Default::default());
+            let token = CancellationToken::new();
+            let results = run_cancellable(
+                &solver,
+                &nodes,
+                &queries,
+                K_NEAREST,
+                cache_duplicates,
+                &NoProgress,
+                &token,
+            )
+            .unwrap();
+            assert_eq!(solver.0.into_inner(), searched);
+            assert_eq!(results.cached as usize, 20 - searched);
+            assert_eq!(results.answered, 20);
+            assert_eq!(results.ids(K_NEAREST), expected);
+        }
+    }
+
+    #[test]
+    fn inserted_nodes_are_found_by_indexes() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let all_nodes = generate::nodes(&mut rng, 600, 8, 4);
+        let mut nodes = NodesDataset::default();
+        for node_id in 0..300 {
+            let node = all_nodes.get(node_id).unwrap();
+            nodes
+                .push(ParsedNodeOwned {
+                    c_attr: node.c_attr,
+                    t_attr: node.t_attr,
+                    vector: node.vector.to_vec(),
+                })
+                .unwrap();
+        }
+
+        let mut indexes = [
+            Index::Ivf(IvfBuilder::new().nlist(8).nprobe(8).build(&nodes).unwrap()),
+            Index::Hnsw(
+                HnswBuilder::new()
+                    .m(8)
+                    .ef_construction(32)
+                    .ef_search(32)
+                    .build(&nodes)
+                    .unwrap(),
+            ),
+        ];
+        for node_id in 300..600 {
+            let node = all_nodes.get(node_id).unwrap();
+            let pushed = nodes
+                .push(ParsedNodeOwned {
+                    c_attr: node.c_attr,
+                    t_attr: node.t_attr,
+                    vector: node.vector.to_vec(),
+                })
+                .unwrap();
+            for index in indexes.iter_mut() {
+                index.insert(&nodes, pushed).unwrap();
+            }
+        }
+
+        for index in &indexes {
+            for node_id in 300..600 {
+                let query = ParsedQuery {
+                    query_type: QueryType::VectorOnly,
+                    v_categorical: None,
+                    t_lower_bound: None,
+                    t_upper_bound: None,
+                    query_vector: nodes.vector(node_id),
+                };
+                assert_eq!(index.search(&nodes, &query, K_NEAREST)[0], node_id as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn deleted_nodes_are_skipped_and_compacted_away() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut nodes = generate::nodes(&mut rng, 500, 8, 4);
+        let queries = generate::queries(&mut rng, 20, 8, 4);
+        let mut indexes = [
+            Index::Ivf(IvfBuilder::new().nlist(8).nprobe(8).build(&nodes).unwrap()),
+            Index::Hnsw(
+                HnswBuilder::new()
+                    .m(8)
+                    .ef_construction(32)
+                    .ef_search(32)
+                    .build(&nodes)
+                    .unwrap(),
+            ),
+        ];
+
+        for node_id in (0..500).step_by(3) {
+            assert!(nodes.delete(node_id));
+        }
+        assert!(!nodes.delete(0));
+        assert_eq!(nodes.num_deleted(), 167);
+
+        let exact = run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+        for index in &indexes {
+            for result in run(index, &nodes, &queries, K_NEAREST).unwrap() {
+                assert!(result.iter().all(|&id| id % 3 != 0 || id == DEFAULT_PAD_ID));
+            }
+        }
+
+        let remap = nodes.compact();
+        assert_eq!(nodes.num_vectors, 333);
+        assert_eq!(remap[4], Some(2));
+        let remapped: QueryResults = exact
+            .iter()
+            .map(|result| {
+                result
+                    .iter()
+                    .map(|&id| {
+                        remap
+                            .get(id as usize)
+                            .copied()
+                            .flatten()
+                            .unwrap_or(DEFAULT_PAD_ID)
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(run(&Exact, &nodes, &queries, K_NEAREST).unwrap(), remapped);
+        for index in indexes.iter_mut() {
+            index.compact(&nodes, &remap);
+            let results = run(index, &nodes, &queries, K_NEAREST).unwrap();
+            assert!(
+                results
+                    .iter()
+                    .flatten()
+                    .all(|&id| id < 333 || id == DEFAULT_PAD_ID)
+            );
+        }
+    }
+
+    #[test]
+    fn builders_reject_invalid_parameters() {
+        let nodes = NodesDataset::default();
+
+        assert!(HnswBuilder::new().m(1).build(&nodes).is_err());
+        assert!(
+            HnswBuilder::new()
+                .m(32)
+                .ef_construction(16)
+                .build(&nodes)
+                .is_err()
+        );
+        assert!(HnswBuilder::new().ef_search(0).validate().is_err());
+        assert!(IvfBuilder::new().nlist(0).validate().is_err());
+        assert!(IvfBuilder::new().nlist(4).nprobe(8).validate().is_err());
+        assert!(IvfBuilder::new().multi_probe(-0.5).validate().is_err());
+        assert!(IvfBuilder::new().multi_probe(f32::NAN).validate().is_err());
+        assert!(
+            BaselineBuilder::new()
+                .sample_proportion(0.0)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            BaselineBuilder::new()
+                .sample_proportion(1.5)
+                .validate()
+                .is_err()
+        );
+        assert!(HnswBuilder::new().build(&nodes).is_ok());
+        assert!(IvfBuilder::new().build(&nodes).is_ok());
+
+        let mut flat = NodesDataset::default();
+        flat.push(ParsedNodeOwned {
+            c_attr: 0,
+            t_attr: 0.0,
+            vector: Vec::new(),
+        })
+        .unwrap();
+        assert!(IvfBuilder::new().build(&flat).is_err());
+
+        let config = Config {
+            k: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            build(&config, &nodes),
+            Err(GlasshouseError::Config(_))
+        ));
+    }
+}
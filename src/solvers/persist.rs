@@ -0,0 +1,392 @@
+//! Versioned binary format used to save built indexes to disk.
+//!
+//! An index file starts with a header made of the `GHIX` magic, the format
+//! version, the kind of index, the number and dimensionality of the vectors
+//! it was built over and the metric they were compared with. The header is
+//! followed by the index specific payload, all integers and floats are
+//! stored in little-endian order.
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::{self, Read, Write};
+#[cfg(feature = "fs")]
+use std::io::{BufReader, BufWriter};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::config::Metric;
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, ParsedQuery, ScoredResult};
+
+use super::{Hnsw, Ivf, Plan, Solver};
+
+const MAGIC: [u8; 4] = *b"GHIX";
+/// Current version of the index format, bumped on incompatible changes.
+pub const FORMAT_VERSION: u32 = 3;
+
+const KIND_IVF: u32 = 1;
+const KIND_HNSW: u32 = 2;
+
+/// A built index that can be saved to and loaded from disk.
+#[derive(Debug, Clone)]
+pub enum Index {
+    Ivf(Ivf),
+    Hnsw(Hnsw),
+}
+
+impl Index {
+    /// Saves the index built over the nodes dataset under the metric to a
+    /// file.
+    #[cfg(feature = "fs")]
+    pub fn save<P: AsRef<Path>>(
+        &self,
+        file_path: P,
+        nodes_dataset: &NodesDataset,
+        metric: Metric,
+    ) -> io::Result<()> {
+        let file = File::create(file_path)?;
+        let mut writer = BufWriter::new(file);
+        self.write_to(&mut writer, nodes_dataset, metric)?;
+        writer.flush()
+    }
+
+    /// Loads an index from a file, checking that it was built over a
+    /// dataset of the same shape as the given one and under the metric.
+    #[cfg(feature = "fs")]
+    pub fn load<P: AsRef<Path>>(
+        file_path: P,
+        nodes_dataset: &NodesDataset,
+        metric: Metric,
+    ) -> error::Result<Self> {
+        let file = File::open(file_path)?;
+        Self::read_from(&mut BufReader::new(file), nodes_dataset, metric)
+    }
+
+    /// Serializes the index built over the nodes dataset under the metric.
+    pub fn write_to<W: Write>(
+        &self,
+        writer: &mut W,
+        nodes_dataset: &NodesDataset,
+        metric: Metric,
+    ) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        write_u32(writer, FORMAT_VERSION)?;
+        write_u32(
+            writer,
+            match self {
+                Index::Ivf(_) => KIND_IVF,
+                Index::Hnsw(_) => KIND_HNSW,
+            },
+        )?;
+        write_u32(writer, nodes_dataset.num_vectors)?;
+        write_u32(writer, nodes_dataset.dimensions as u32)?;
+        write_u32(writer, metric_code(metric))?;
+
+        match self {
+            Index::Ivf(ivf) => ivf.write_to(writer),
+            Index::Hnsw(hnsw) => hnsw.write_to(writer),
+        }
+    }
+
+    /// Deserializes an index, checking that it was built over a dataset of
+    /// the same shape as the given one and under the metric.
+    pub fn read_from<R: Read>(
+        reader: &mut R,
+        nodes_dataset: &NodesDataset,
+        metric: Metric,
+    ) -> error::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(malformed("Not a glasshouse index file".to_string()));
+        }
+        let version = read_u32(reader)?;
+        if version != FORMAT_VERSION {
+            return Err(malformed(format!(
+                "Unsupported index format version {}, expected {}",
+                version, FORMAT_VERSION
+            )));
+        }
+        let kind = read_u32(reader)?;
+        let num_vectors = read_u32(reader)?;
+        let dimensions = read_u32(reader)? as usize;
+        if num_vectors != nodes_dataset.num_vectors || dimensions != nodes_dataset.dimensions {
+            return Err(malformed(format!(
+                "Index was built over {} nodes of dimension {} but the dataset holds {} nodes of dimension {}",
+                num_vectors, dimensions, nodes_dataset.num_vectors, nodes_dataset.dimensions
+            )));
+        }
+        check_metric(read_u32(reader)?, metric)?;
+
+        match kind {
+            KIND_IVF => Ok(Index::Ivf(Ivf::read_from(reader, num_vectors, dimensions)?)),
+            KIND_HNSW => Ok(Index::Hnsw(Hnsw::read_from(reader, num_vectors)?)),
+            _ => Err(malformed(format!("Unknown index kind {}", kind))),
+        }
+    }
+}
+
+impl Index {
+    /// Inserts a node pushed to the dataset after the index was built.
+    pub fn insert(&mut self, nodes_dataset: &NodesDataset, node_id: u32) -> error::Result<()> {
+        match self {
+            Index::Ivf(ivf) => ivf.insert(nodes_dataset, node_id),
+            Index::Hnsw(hnsw) => hnsw.insert(nodes_dataset, node_id),
+        }
+    }
+
+    /// Updates the index after the dataset was compacted, `remap` is the
+    /// mapping of node IDs returned by [`NodesDataset::compact`].
+    pub fn compact(&mut self, nodes_dataset: &NodesDataset, remap: &[Option<u32>]) {
+        match self {
+            Index::Ivf(ivf) => ivf.compact(remap),
+            Index::Hnsw(hnsw) => hnsw.compact(nodes_dataset),
+        }
+    }
+}
+
+impl Solver for Index {
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        match self {
+            Index::Ivf(ivf) => ivf.search_scored(nodes_dataset, query, k),
+            Index::Hnsw(hnsw) => hnsw.search_scored(nodes_dataset, query, k),
+        }
+    }
+
+    fn search_scored_with_ef(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+        ef_search: usize,
+    ) -> ScoredResult {
+        match self {
+            Index::Ivf(ivf) => ivf.search_scored(nodes_dataset, query, k),
+            Index::Hnsw(hnsw) => hnsw.search_scored_with_ef(nodes_dataset, query, k, ef_search),
+        }
+    }
+
+    fn search_range(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        radius: f32,
+    ) -> ScoredResult {
+        match self {
+            Index::Ivf(ivf) => ivf.search_range(nodes_dataset, query, radius),
+            Index::Hnsw(hnsw) => hnsw.search_range(nodes_dataset, query, radius),
+        }
+    }
+
+    fn explain(&self, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> Plan {
+        match self {
+            Index::Ivf(ivf) => ivf.explain(nodes_dataset, query, k),
+            Index::Hnsw(hnsw) => hnsw.explain(nodes_dataset, query, k),
+        }
+    }
+
+    fn memory_bytes(&self) -> usize {
+        match self {
+            Index::Ivf(ivf) => ivf.memory_bytes(),
+            Index::Hnsw(hnsw) => hnsw.memory_bytes(),
+        }
+    }
+
+    fn degrade(&mut self, k: usize) -> bool {
+        match self {
+            Index::Ivf(ivf) => ivf.degrade(k),
+            Index::Hnsw(hnsw) => hnsw.degrade(k),
+        }
+    }
+}
+
+pub(crate) fn malformed(message: String) -> GlasshouseError {
+    GlasshouseError::Malformed(message)
+}
+
+/// Code recording the metric of an index in the headers of its files.
+pub(crate) fn metric_code(metric: Metric) -> u32 {
+    match metric {
+        Metric::L2 => 0,
+        Metric::Cosine => 1,
+    }
+}
+
+/// Checks that an index recorded as built under the metric of `code` is
+/// loaded under `metric`, as its distances are meaningless under another.
+pub(crate) fn check_metric(code: u32, metric: Metric) -> error::Result<()> {
+    let built = match code {
+        0 => Metric::L2,
+        1 => Metric::Cosine,
+        code => return Err(malformed(format!("Unknown metric {}", code))),
+    };
+    if built != metric {
+        return Err(GlasshouseError::Config(format!(
+            "Index was built under the {:?} metric, not {:?}",
+            built, metric
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> error::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> error::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Largest number of bytes reserved for a list before reading it.
+pub(crate) const MAX_PREALLOCATED_BYTES: usize = 1 << 20;
+
+/// Reads the bytes of `len` 32-bit words. Lengths read from the file are
+/// untrusted, so the buffer grows with the bytes actually read and a
+/// corrupt length fails at the end of the file instead of on a huge
+/// allocation.
+fn read_words<R: Read>(reader: &mut R, len: usize) -> error::Result<Vec<u8>> {
+    let byte_len = len
+        .checked_mul(4)
+        .ok_or_else(|| malformed(format!("List of {} entries is too long", len)))?;
+    let mut bytes = Vec::with_capacity(byte_len.min(MAX_PREALLOCATED_BYTES));
+    reader.take(byte_len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < byte_len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(bytes)
+}
+
+/// Writes a length-prefixed list of IDs.
+pub(crate) fn write_ids<W: Write>(writer: &mut W, ids: &[u32]) -> io::Result<()> {
+    write_u32(writer, ids.len() as u32)?;
+    for &id in ids {
+        write_u32(writer, id)?;
+    }
+    Ok(())
+}
+
+/// Reads a length-prefixed list of IDs, all of which must be below `bound`.
+pub(crate) fn read_ids<R: Read>(reader: &mut R, bound: u32) -> error::Result<Vec<u32>> {
+    let len = read_u32(reader)? as usize;
+    let bytes = read_words(reader, len)?;
+    let ids: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    if let Some(id) = ids.iter().find(|&&id| id >= bound) {
+        return Err(malformed(format!("Node ID {} is out of range", id)));
+    }
+    Ok(ids)
+}
+
+pub(crate) fn write_f32s<W: Write>(writer: &mut W, values: &[f32]) -> io::Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_f32s<R: Read>(reader: &mut R, len: usize) -> error::Result<Vec<f32>> {
+    let bytes = read_words(reader, len)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect())
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::constants::K_NEAREST;
+    use crate::generate;
+    use crate::solvers::{HnswBuilder, IvfBuilder, run};
+
+    #[test]
+    fn saved_indexes_load_back_identically() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let nodes = generate::nodes(&mut rng, 500, 8, 4);
+        let queries = generate::queries(&mut rng, 50, 8, 4);
+        let path = crate::testing::temp_path("persisted-index.bin");
+
+        for index in [
+            Index::Ivf(IvfBuilder::new().nlist(16).nprobe(4).build(&nodes).unwrap()),
+            Index::Hnsw(
+                HnswBuilder::new()
+                    .m(8)
+                    .ef_construction(32)
+                    .ef_search(32)
+                    .build(&nodes)
+                    .unwrap(),
+            ),
+        ] {
+            index.save(&path, &nodes, Metric::L2).unwrap();
+            let loaded = Index::load(&path, &nodes, Metric::L2).unwrap();
+            assert!(Index::load(&path, &nodes, Metric::Cosine).is_err());
+
+            assert_eq!(
+                run(&loaded, &nodes, &queries, K_NEAREST).unwrap(),
+                run(&index, &nodes, &queries, K_NEAREST).unwrap()
+            );
+        }
+
+        let other_nodes = generate::nodes(&mut rng, 400, 8, 4);
+        assert!(Index::load(&path, &other_nodes, Metric::L2).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let nodes = generate::nodes(&mut rng, 50, 4, 4);
+        let index = Index::Hnsw(HnswBuilder::new().m(4).build(&nodes).unwrap());
+        let mut bytes = Vec::new();
+        index.write_to(&mut bytes, &nodes, Metric::L2).unwrap();
+        let malformed = |offset: usize, value: u32| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            matches!(
+                Index::read_from(&mut bytes.as_slice(), &nodes, Metric::L2),
+                Err(GlasshouseError::Malformed(_))
+            )
+        };
+
+        assert!(Index::read_from(&mut bytes.as_slice(), &nodes, Metric::L2).is_ok());
+        // Magic, version, kind, number of nodes, dimensions and the entry
+        // point of the graph.
+        assert!(malformed(0, u32::from_le_bytes(*b"GHIY")));
+        assert!(malformed(4, FORMAT_VERSION + 1));
+        assert!(malformed(8, 7));
+        assert!(malformed(12, nodes.num_vectors + 1));
+        assert!(malformed(16, 3));
+        assert!(malformed(44, nodes.num_vectors));
+    }
+
+    #[test]
+    fn corrupt_lengths_fail_without_allocating_them() {
+        let mut bytes = u32::MAX.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0; 8]);
+
+        assert!(read_ids(&mut bytes.as_slice(), u32::MAX).is_err());
+        assert!(read_f32s(&mut &bytes[4..], usize::MAX).is_err());
+        assert!(read_f32s(&mut &bytes[4..], 1 << 40).is_err());
+    }
+}
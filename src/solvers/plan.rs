@@ -0,0 +1,315 @@
+//! Filter-first execution of selective queries.
+//!
+//! The attribute indexes of the nodes dataset give the exact number of
+//! nodes within a category or a timestamp range. When the narrowest filter
+//! of a query leaves fewer nodes than an index would visit, scanning them
+//! all is both cheaper and exact, so the index is skipped.
+//!
+//! Queries whose filters leave more nodes than that are answered by the
+//! index, post-filtering what it visits. Adaptive solvers widen their search
+//! for them in proportion to the fraction of nodes filtered out, otherwise
+//! too few of the nodes visited match to fill `k` results.
+//!
+//! [`Solver::explain`](super::Solver::explain) reports which of these
+//! strategies a solver picks for a query without searching it.
+use std::fmt;
+
+use crate::distance::l2;
+use crate::types::{NodesDataset, ParsedQuery, QueryType, ScoredResult};
+
+use super::{Neighbor, top_k};
+
+/// How a solver answers a query, reported by
+/// [`Solver::explain`](super::Solver::explain).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub strategy: Strategy,
+    /// Estimated fraction of the nodes passing the filters of the query.
+    pub selectivity: f64,
+    /// Number of nodes passing the most selective filter, `None` for
+    /// queries without a filter narrowing down the nodes.
+    pub candidates: Option<usize>,
+    /// Estimated number of nodes whose distance to the query is computed.
+    pub visited: usize,
+}
+
+/// Strategy picked to answer a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Strategy {
+    /// Every node is scanned.
+    Scan,
+    /// The nodes passing the most selective filter are scanned, the index is
+    /// skipped.
+    FilterFirst,
+    /// The index is searched and the nodes it visits are filtered, `index`
+    /// names the index and its search width.
+    PostFilter { index: String },
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::Scan => write!(f, "scan"),
+            Strategy::FilterFirst => write!(f, "filter-first"),
+            Strategy::PostFilter { index } => write!(f, "post-filter ({})", index),
+        }
+    }
+}
+
+/// Most times the search width of a query is widened for its filters.
+pub(crate) const MAX_WIDENING: usize = 16;
+
+/// Nodes that can match a query, enumerated from the attribute index of its
+/// most selective filter.
+pub(crate) struct Candidates<'a> {
+    /// Number of nodes enumerated, an upper bound on the number of matches.
+    pub len: usize,
+    pub ids: Box<dyn Iterator<Item = u32> + 'a>,
+}
+
+/// Returns the nodes passing the most selective filter of the query, `None`
+/// if it has no filter narrowing down the nodes.
+pub(crate) fn candidates<'a>(
+    nodes_dataset: &'a NodesDataset,
+    query: &ParsedQuery<'_>,
+) -> Option<Candidates<'a>> {
+    let by_category = category(query).map(|category| Candidates {
+        len: nodes_dataset.category_len(category),
+        ids: Box::new(nodes_dataset.category_nodes(category)),
+    });
+    let by_time = time_range(query).map(|(lower, upper)| Candidates {
+        len: nodes_dataset.timestamp_range_len(lower, upper),
+        ids: Box::new(nodes_dataset.timestamp_range(lower, upper)),
+    });
+    match (by_category, by_time) {
+        (Some(by_category), Some(by_time)) if by_time.len < by_category.len => Some(by_time),
+        (by_category, by_time) => by_category.or(by_time),
+    }
+}
+
+/// Estimated fraction of the nodes passing the filters of the query,
+/// assuming categories and timestamps are independent unless no timestamp
+/// of the category falls in the range.
+pub(crate) fn selectivity(nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>) -> f64 {
+    if let (Some(category), Some((lower, upper))) = (category(query), time_range(query)) {
+        let overlaps = nodes_dataset
+            .category_stats(category)
+            .and_then(|stats| stats.timestamps)
+            .is_some_and(|(min, max)| min <= upper && max >= lower);
+        if !overlaps {
+            return 0.0;
+        }
+    }
+    let num_nodes = nodes_dataset.num_vectors.max(1) as f64;
+    let by_category = category(query).map_or(1.0, |category| {
+        nodes_dataset.category_len(category) as f64 / num_nodes
+    });
+    let by_time = time_range(query).map_or(1.0, |(lower, upper)| {
+        nodes_dataset.timestamp_range_len(lower, upper) as f64 / num_nodes
+    });
+    by_category * by_time
+}
+
+/// Returns the search width divided by the selectivity of the query, so
+/// that as many matching nodes are visited as without filters, and at most
+/// [`MAX_WIDENING`] times wider.
+pub(crate) fn widen(width: usize, nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>) -> usize {
+    let widest = width.saturating_mul(MAX_WIDENING);
+    let selectivity = selectivity(nodes_dataset, query);
+    if selectivity * MAX_WIDENING as f64 <= 1.0 {
+        return widest;
+    }
+    ((width as f64 / selectivity).ceil() as usize).min(widest)
+}
+
+fn category(query: &ParsedQuery<'_>) -> Option<i32> {
+    match query.query_type {
+        QueryType::CategoricalConstraint | QueryType::BothConstraints => query.v_categorical,
+        _ => None,
+    }
+}
+
+/// Timestamp range the query filters on, if any.
+pub(crate) fn time_range(query: &ParsedQuery<'_>) -> Option<(f32, f32)> {
+    match (query.query_type, query.t_lower_bound, query.t_upper_bound) {
+        (QueryType::TimestampConstraint | QueryType::BothConstraints, Some(lower), Some(upper)) => {
+            Some((lower, upper))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the exact `k` nearest neighbours of the query among the
+/// candidates that pass its filters.
+pub(crate) fn scan(
+    nodes_dataset: &NodesDataset,
+    query: &ParsedQuery<'_>,
+    ids: impl Iterator<Item = u32>,
+    k: usize,
+) -> ScoredResult {
+    top_k(matching(nodes_dataset, query, ids).collect(), k)
+}
+
+/// Returns every candidate passing the filters of the query within
+/// `radius` of it, closest first.
+pub(crate) fn scan_range(
+    nodes_dataset: &NodesDataset,
+    query: &ParsedQuery<'_>,
+    ids: impl Iterator<Item = u32>,
+    radius: f32,
+) -> ScoredResult {
+    let candidates = matching(nodes_dataset, query, ids)
+        .filter(|candidate| candidate.distance <= radius)
+        .collect();
+    top_k(candidates, usize::MAX)
+}
+
+/// Returns every node passing the filters of the query within `radius` of
+/// it, scanning the nodes passing its most selective filter.
+pub(crate) fn exact_range(
+    nodes_dataset: &NodesDataset,
+    query: &ParsedQuery<'_>,
+    radius: f32,
+) -> ScoredResult {
+    match candidates(nodes_dataset, query) {
+        Some(candidates) => scan_range(nodes_dataset, query, candidates.ids, radius),
+        None => scan_range(nodes_dataset, query, 0..nodes_dataset.num_vectors, radius),
+    }
+}
+
+/// Candidates passing the filters of the query with their distance to it.
+pub(crate) fn matching<'a>(
+    nodes_dataset: &'a NodesDataset,
+    query: &'a ParsedQuery<'_>,
+    ids: impl Iterator<Item = u32> + 'a,
+) -> impl Iterator<Item = Neighbor> + 'a {
+    ids.filter_map(|node_id| {
+        let node = nodes_dataset.get(node_id as usize)?;
+        query.matches(&node).then(|| Neighbor {
+            distance: l2(query.query_vector, node.vector),
+            id: node_id,
+        })
+    })
+}
+
+/// Answers the query exactly from the attribute indexes when its filters
+/// leave at most `max_candidates` nodes, `None` if the solver should search
+/// its index instead.
+pub(crate) fn filter_first(
+    nodes_dataset: &NodesDataset,
+    query: &ParsedQuery<'_>,
+    k: usize,
+    max_candidates: usize,
+) -> Option<ScoredResult> {
+    let candidates = candidates(nodes_dataset, query)?;
+    (candidates.len <= max_candidates).then(|| scan(nodes_dataset, query, candidates.ids, k))
+}
+
+/// Plan of a solver visiting `visited` nodes with `strategy`, or the nodes
+/// passing the most selective filter when there are at most that many, as
+/// [`filter_first`] does.
+pub(crate) fn explain(
+    nodes_dataset: &NodesDataset,
+    query: &ParsedQuery<'_>,
+    visited: usize,
+    strategy: Strategy,
+) -> Plan {
+    let candidates = candidates(nodes_dataset, query).map(|candidates| candidates.len);
+    let (strategy, visited) = match candidates {
+        Some(len) if len <= visited => (Strategy::FilterFirst, len),
+        _ => (strategy, visited),
+    };
+    Plan {
+        strategy,
+        selectivity: selectivity(nodes_dataset, query),
+        candidates,
+        visited,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrowest_filter_is_enumerated() {
+        let nodes = NodesDataset::from_parts(
+            1,
+            vec![1, 1, 1, 2],
+            vec![0.1, 0.2, 0.9, 0.15],
+            vec![0.0, 1.0, 2.0, 3.0],
+        )
+        .unwrap();
+        let query = |query_type, v_categorical, bounds: Option<(f32, f32)>| ParsedQuery {
+            query_type,
+            v_categorical,
+            t_lower_bound: bounds.map(|b| b.0),
+            t_upper_bound: bounds.map(|b| b.1),
+            query_vector: &[0.0],
+        };
+        let ids = |query: &ParsedQuery<'_>| {
+            candidates(&nodes, query).map(|c| (c.len, c.ids.collect::<Vec<_>>()))
+        };
+
+        assert_eq!(ids(&query(QueryType::VectorOnly, None, None)), None);
+        assert_eq!(
+            ids(&query(QueryType::CategoricalConstraint, Some(1), None)),
+            Some((3, vec![0, 1, 2]))
+        );
+        assert_eq!(
+            ids(&query(
+                QueryType::BothConstraints,
+                Some(1),
+                Some((0.1, 0.15))
+            )),
+            Some((2, vec![0, 3]))
+        );
+        assert_eq!(
+            ids(&query(
+                QueryType::BothConstraints,
+                Some(2),
+                Some((0.0, 1.0))
+            )),
+            Some((1, vec![3]))
+        );
+
+        let both = query(QueryType::BothConstraints, Some(1), Some((0.1, 0.2)));
+        assert_eq!(filter_first(&nodes, &both, 10, 2), None);
+        let ids: Vec<u32> = filter_first(&nodes, &both, 10, 3)
+            .unwrap()
+            .iter()
+            .map(|neighbor| neighbor.id)
+            .collect();
+        assert_eq!(ids, [0, 1]);
+    }
+
+    #[test]
+    fn selective_queries_are_widened() {
+        let nodes = NodesDataset::from_parts(
+            1,
+            (0..64).map(|i| i % 4).collect(),
+            (0..64).map(|i| i as f32 / 64.0).collect(),
+            vec![0.0; 64],
+        )
+        .unwrap();
+        let query = |query_type, v_categorical, bounds: Option<(f32, f32)>| ParsedQuery {
+            query_type,
+            v_categorical,
+            t_lower_bound: bounds.map(|b| b.0),
+            t_upper_bound: bounds.map(|b| b.1),
+            query_vector: &[0.0],
+        };
+
+        let unfiltered = query(QueryType::VectorOnly, None, None);
+        assert_eq!(selectivity(&nodes, &unfiltered), 1.0);
+        assert_eq!(widen(10, &nodes, &unfiltered), 10);
+        let category = query(QueryType::CategoricalConstraint, Some(1), None);
+        assert_eq!(selectivity(&nodes, &category), 0.25);
+        assert_eq!(widen(10, &nodes, &category), 40);
+        let both = query(QueryType::BothConstraints, Some(1), Some((0.0, 0.49)));
+        assert_eq!(selectivity(&nodes, &both), 0.125);
+        assert_eq!(widen(10, &nodes, &both), 80);
+        let none = query(QueryType::CategoricalConstraint, Some(7), None);
+        assert_eq!(widen(10, &nodes, &none), 10 * MAX_WIDENING);
+    }
+}
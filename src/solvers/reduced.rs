@@ -0,0 +1,182 @@
+//! Search in a reduced space followed by a rerank in the full one.
+//!
+//! The nodes are projected on their principal components, see [`Pca`], and
+//! another solver is built over the projected vectors. Each query is
+//! projected the same way, the inner solver finds `oversampling` times the
+//! requested number of candidates with the cheaper distances of the reduced
+//! space and they are reranked with their distance to the full vectors.
+use crate::distance::l2;
+use crate::error::{self, GlasshouseError};
+use crate::memory;
+use crate::pca::Pca;
+use crate::types::{NodesDataset, ParsedQuery, ScoredResult};
+
+use super::{Neighbor, Plan, Solver, top_k};
+
+/// Solver searching projected vectors with another solver and reranking
+/// its candidates with the full vectors.
+pub struct Reduced {
+    pca: Pca,
+    /// Nodes with their projected vectors, searched by the inner solver.
+    nodes: NodesDataset,
+    inner: Box<dyn Solver>,
+    oversampling: usize,
+}
+
+impl Reduced {
+    /// Default number of nodes sampled to train the projection.
+    pub const DEFAULT_SAMPLE_SIZE: usize = 100_000;
+    /// Default number of candidates found in the reduced space per
+    /// neighbour returned.
+    pub const DEFAULT_OVERSAMPLING: usize = 4;
+
+    /// Wraps the solver built over the nodes projected by `pca`, fetching
+    /// `oversampling` candidates per neighbour returned.
+    pub fn new(
+        pca: Pca,
+        nodes: NodesDataset,
+        inner: Box<dyn Solver>,
+        oversampling: usize,
+    ) -> error::Result<Self> {
+        if oversampling == 0 {
+            return Err(GlasshouseError::Config(
+                "PCA oversampling must be at least 1".to_string(),
+            ));
+        }
+        if nodes.dimensions != pca.dimensions() {
+            return Err(GlasshouseError::DimensionMismatch {
+                nodes: nodes.dimensions,
+                queries: pca.dimensions(),
+            });
+        }
+        Ok(Reduced {
+            pca,
+            nodes,
+            inner,
+            oversampling,
+        })
+    }
+
+    /// Projection of the vectors.
+    pub fn pca(&self) -> &Pca {
+        &self.pca
+    }
+
+    /// Number of candidates found in the reduced space per neighbour
+    /// returned.
+    pub fn oversampling(&self) -> usize {
+        self.oversampling
+    }
+
+    /// Finds candidates for the projected query with `search` and returns
+    /// the `k` closest to the full query vector.
+    fn rerank(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+        search: impl FnOnce(&ParsedQuery<'_>, usize) -> ScoredResult,
+    ) -> ScoredResult {
+        let projected = self.pca.project(query.query_vector);
+        let candidates = search(
+            &ParsedQuery {
+                query_type: query.query_type,
+                v_categorical: query.v_categorical,
+                t_lower_bound: query.t_lower_bound,
+                t_upper_bound: query.t_upper_bound,
+                query_vector: &projected,
+            },
+            k.saturating_mul(self.oversampling),
+        );
+        let reranked = candidates
+            .into_iter()
+            .map(|candidate| Neighbor {
+                distance: l2(
+                    query.query_vector,
+                    nodes_dataset.vector(candidate.id as usize),
+                ),
+                id: candidate.id,
+            })
+            .collect();
+        top_k(reranked, k)
+    }
+}
+
+impl Solver for Reduced {
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        self.rerank(nodes_dataset, query, k, |projected, k| {
+            self.inner.search_scored(&self.nodes, projected, k)
+        })
+    }
+
+    fn search_scored_with_ef(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+        ef_search: usize,
+    ) -> ScoredResult {
+        self.rerank(nodes_dataset, query, k, |projected, k| {
+            self.inner
+                .search_scored_with_ef(&self.nodes, projected, k, ef_search)
+        })
+    }
+
+    /// Plan of the inner solver for the projected query.
+    fn explain(&self, _nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> Plan {
+        let projected = self.pca.project(query.query_vector);
+        let query = ParsedQuery {
+            query_type: query.query_type,
+            v_categorical: query.v_categorical,
+            t_lower_bound: query.t_lower_bound,
+            t_upper_bound: query.t_upper_bound,
+            query_vector: &projected,
+        };
+        self.inner
+            .explain(&self.nodes, &query, k.saturating_mul(self.oversampling))
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.inner.memory_bytes() + memory::nodes_bytes(&self.nodes) + self.pca.memory_bytes()
+    }
+
+    fn degrade(&mut self, k: usize) -> bool {
+        self.inner.degrade(k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::solvers::Exact;
+
+    #[test]
+    fn rejects_unprojected_nodes_and_no_oversampling() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let nodes = generate::nodes(&mut rng, 200, 8, 4);
+        let pca = Pca::train(&nodes, 4).unwrap();
+        let projected = pca.project_nodes(&nodes).unwrap();
+
+        assert!(matches!(
+            Reduced::new(pca.clone(), projected.clone(), Box::new(Exact), 0),
+            Err(GlasshouseError::Config(_))
+        ));
+        assert!(matches!(
+            Reduced::new(pca.clone(), nodes, Box::new(Exact), 4),
+            Err(GlasshouseError::DimensionMismatch {
+                nodes: 8,
+                queries: 4
+            })
+        ));
+        assert!(Reduced::new(pca, projected, Box::new(Exact), 4).is_ok());
+    }
+}
@@ -0,0 +1,55 @@
+//! Low-memory baseline over a reservoir sample of the nodes.
+//!
+//! The nodes file is streamed once and only a uniform random sample of it
+//! is kept, see [`NodesDataset::read_reservoir`], so the memory used is
+//! bounded by the sample whatever the size of the file. Queries are then
+//! answered exactly over the sample.
+use std::mem;
+
+use crate::types::{NodesDataset, ParsedQuery, ScoredResult};
+
+use super::{Exact, Solver};
+
+/// Exact search over a sample of the nodes, reporting the IDs the nodes
+/// have in the full file. It searches the sampled nodes dataset.
+#[derive(Debug, Clone)]
+pub struct Reservoir {
+    /// ID in the full file of each sampled node.
+    ids: Vec<u32>,
+}
+
+impl Reservoir {
+    /// Searches a sample whose nodes had the given IDs in the full file.
+    pub fn new(ids: Vec<u32>) -> Self {
+        Reservoir { ids }
+    }
+
+    /// Number of sampled nodes.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns true if no node was sampled.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+impl Solver for Reservoir {
+    fn search_scored(
+        &self,
+        sample: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        let mut result = Exact.search_scored(sample, query, k);
+        for neighbor in &mut result {
+            neighbor.id = self.ids[neighbor.id as usize];
+        }
+        result
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.ids.capacity() * mem::size_of::<u32>()
+    }
+}
@@ -0,0 +1,212 @@
+//! Exact search spilling the candidates of each query to disk.
+//!
+//! Scanning keeps every node passing the filters of a query before sorting
+//! them, for unfiltered queries over large datasets that is the whole
+//! dataset once per worker thread. Instead, the candidates are gathered in
+//! batches, each batch is sorted and its `k` closest candidates are written
+//! to a temporary file, then the sorted runs are merged. Memory is bounded
+//! by the batch size whatever the number of matching nodes.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, ParsedQuery, ScoredResult};
+
+use super::plan::{candidates, matching};
+use super::{Neighbor, Solver, top_k};
+
+/// Numbers the spill files of the process.
+static SPILLS: AtomicU64 = AtomicU64::new(0);
+
+/// Exact solver keeping at most `batch` candidates of a query in memory,
+/// spilling sorted runs of the others to files in a directory.
+#[derive(Debug, Clone)]
+pub struct SpillingExact {
+    batch: usize,
+    dir: PathBuf,
+}
+
+impl SpillingExact {
+    /// Returns a solver spilling to `dir`, created if it does not exist,
+    /// once a query has more than `batch` candidates.
+    pub fn new<P: AsRef<Path>>(batch: usize, dir: P) -> error::Result<Self> {
+        if batch == 0 {
+            return Err(GlasshouseError::Config(
+                "The spill batch must hold at least one candidate".to_string(),
+            ));
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(SpillingExact {
+            batch,
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Number of candidates kept in memory per query.
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    /// Directory the candidates are spilled to.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the `k` closest candidates, merging the sorted runs spilled
+    /// for every full batch with the last batch.
+    fn top_k_spilled(
+        &self,
+        candidates: impl Iterator<Item = Neighbor>,
+        k: usize,
+    ) -> io::Result<ScoredResult> {
+        let mut runs = Runs(Vec::new());
+        let mut batch = Vec::with_capacity(self.batch.min(1 << 20));
+        for candidate in candidates {
+            batch.push(candidate);
+            if batch.len() == self.batch {
+                runs.spill(&self.dir, &mut batch, k)?;
+            }
+        }
+        if runs.0.is_empty() {
+            return Ok(top_k(batch, k));
+        }
+        if !batch.is_empty() {
+            runs.spill(&self.dir, &mut batch, k)?;
+        }
+        Ok(top_k(runs.merge(k)?, k))
+    }
+}
+
+impl Solver for SpillingExact {
+    /// # Panics
+    ///
+    /// Panics if the candidates cannot be spilled to or read back from the
+    /// spill directory.
+    fn search_scored(
+        &self,
+        nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        let result = match candidates(nodes_dataset, query) {
+            Some(candidates) if candidates.len <= self.batch => Ok(top_k(
+                matching(nodes_dataset, query, candidates.ids).collect(),
+                k,
+            )),
+            Some(candidates) => {
+                self.top_k_spilled(matching(nodes_dataset, query, candidates.ids), k)
+            }
+            None => self.top_k_spilled(
+                matching(nodes_dataset, query, 0..nodes_dataset.num_vectors),
+                k,
+            ),
+        };
+        result
+            .unwrap_or_else(|e| panic!("Cannot spill candidates to {}: {}", self.dir.display(), e))
+    }
+}
+
+/// Spill files of a query, removed once dropped.
+struct Runs(Vec<PathBuf>);
+
+impl Runs {
+    /// Sorts the batch, writes its `k` closest candidates to a new file and
+    /// empties it.
+    fn spill(&mut self, dir: &Path, batch: &mut Vec<Neighbor>, k: usize) -> io::Result<()> {
+        batch.retain(|candidate| !candidate.distance.is_nan());
+        batch.sort_unstable();
+        let path = dir.join(format!(
+            "glasshouse-spill-{}-{}",
+            process::id(),
+            SPILLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        self.0.push(path.clone());
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for candidate in batch.iter().take(k) {
+            writer.write_all(&candidate.distance.to_le_bytes())?;
+            writer.write_all(&candidate.id.to_le_bytes())?;
+        }
+        writer.flush()?;
+        batch.clear();
+        Ok(())
+    }
+
+    /// Merges the sorted runs, returns their `k` closest candidates.
+    fn merge(&self, k: usize) -> io::Result<Vec<Neighbor>> {
+        let mut readers = self
+            .0
+            .iter()
+            .map(|path| File::open(path).map(BufReader::new))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut heads = BinaryHeap::with_capacity(readers.len());
+        for (run, reader) in readers.iter_mut().enumerate() {
+            if let Some(candidate) = read_candidate(reader)? {
+                heads.push(Reverse((candidate, run)));
+            }
+        }
+        let mut merged = Vec::with_capacity(k.min(1 << 20));
+        while merged.len() < k {
+            let Some(Reverse((candidate, run))) = heads.pop() else {
+                break;
+            };
+            merged.push(candidate);
+            if let Some(next) = read_candidate(&mut readers[run])? {
+                heads.push(Reverse((next, run)));
+            }
+        }
+        Ok(merged)
+    }
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Reads the next candidate of a run, `None` at its end.
+fn read_candidate<R: Read>(reader: &mut R) -> io::Result<Option<Neighbor>> {
+    let mut bytes = [0u8; 8];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => Ok(Some(Neighbor {
+            distance: f32::from_le_bytes(bytes[..4].try_into().expect("4 bytes")),
+            id: u32::from_le_bytes(bytes[4..].try_into().expect("4 bytes")),
+        })),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::solvers::{Exact, run_scored};
+
+    #[test]
+    fn spilled_results_match_the_exact_ones() {
+        let mut rng = StdRng::seed_from_u64(67);
+        let nodes = generate::nodes(&mut rng, 500, 4, 4);
+        let queries = generate::queries(&mut rng, 30, 4, 4);
+        let dir = crate::testing::temp_path("spill-test");
+        let spilling = SpillingExact::new(16, &dir).unwrap();
+
+        assert_eq!(
+            run_scored(&spilling, &nodes, &queries, 10).unwrap(),
+            run_scored(&Exact, &nodes, &queries, 10).unwrap()
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+        assert!(SpillingExact::new(0, &dir).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Streaming execution delivering results as queries complete.
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::thread;
+
+use rayon::prelude::*;
+
+use crate::types::{NodesDataset, ParsedQuery, QueryResult, ScoredResult};
+
+use super::{Solver, ids};
+
+/// Order in which streamed results are delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Results are delivered in the order of the queries, results
+    /// completing early are held back until their predecessors complete.
+    Ordered,
+    /// Results are delivered as soon as they complete.
+    Unordered,
+}
+
+/// Answers the queries in parallel and calls `sink` with the position of
+/// each query in the input and its `k` nearest neighbours as results
+/// become available.
+///
+/// Queries are pulled from the iterator lazily, so the caller can pipeline
+/// query parsing, searching and downstream processing. The sink runs on the
+/// calling thread.
+pub fn stream<'q, S, I, F>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries: I,
+    k: usize,
+    delivery: Delivery,
+    mut sink: F,
+) where
+    S: Solver + ?Sized,
+    I: IntoIterator<Item = ParsedQuery<'q>>,
+    I::IntoIter: Send,
+    F: FnMut(usize, QueryResult),
+{
+    stream_scored(
+        solver,
+        nodes_dataset,
+        queries,
+        k,
+        delivery,
+        |index, result| sink(index, ids(&result, k)),
+    );
+}
+
+/// Same as [`stream`], delivering the neighbours found with their distance
+/// to the query rather than their padded IDs.
+pub fn stream_scored<'q, S, I, F>(
+    solver: &S,
+    nodes_dataset: &NodesDataset,
+    queries: I,
+    k: usize,
+    delivery: Delivery,
+    mut sink: F,
+) where
+    S: Solver + ?Sized,
+    I: IntoIterator<Item = ParsedQuery<'q>>,
+    I::IntoIter: Send,
+    F: FnMut(usize, ScoredResult),
+{
+    let queries = queries.into_iter();
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        scope.spawn(move || {
+            queries
+                .enumerate()
+                .par_bridge()
+                .for_each_with(sender, |sender, (index, query)| {
+                    // The receiver only hangs up once all results are delivered.
+                    let _ = sender.send((index, solver.search_scored(nodes_dataset, &query, k)));
+                });
+        });
+
+        let mut pending = BTreeMap::new();
+        let mut next_index = 0;
+        for (index, result) in receiver {
+            match delivery {
+                Delivery::Unordered => sink(index, result),
+                Delivery::Ordered => {
+                    pending.insert(index, result);
+                    while let Some(result) = pending.remove(&next_index) {
+                        sink(next_index, result);
+                        next_index += 1;
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::constants::K_NEAREST;
+    use crate::generate;
+    use crate::solvers::{DEFAULT_PAD_ID, Exact, run, run_scored};
+
+    #[test]
+    fn streamed_results_match_batch_results() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let nodes = generate::nodes(&mut rng, 300, 8, 4);
+        let queries = generate::queries(&mut rng, 100, 8, 4);
+        let expected = run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+        let parsed = || (0..queries.num_queries as usize).map(|i| queries.get(i).unwrap());
+
+        let mut ordered = Vec::new();
+        stream(
+            &Exact,
+            &nodes,
+            parsed(),
+            K_NEAREST,
+            Delivery::Ordered,
+            |index, result| {
+                assert_eq!(index, ordered.len());
+                ordered.push(result);
+            },
+        );
+        assert_eq!(ordered, expected);
+
+        let mut unordered = vec![None; expected.len()];
+        stream(
+            &Exact,
+            &nodes,
+            parsed(),
+            K_NEAREST,
+            Delivery::Unordered,
+            |index, result| {
+                unordered[index] = Some(result);
+            },
+        );
+        assert_eq!(
+            unordered,
+            expected.into_iter().map(Some).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn streamed_scored_results_are_not_padded() {
+        let mut rng = StdRng::seed_from_u64(12);
+        let nodes = generate::nodes(&mut rng, 300, 8, 4);
+        let queries = generate::queries(&mut rng, 100, 8, 4);
+        let expected = run_scored(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+        assert!(expected.iter().any(|result| result.len() < K_NEAREST));
+
+        let mut streamed = vec![None; expected.len()];
+        stream_scored(
+            &Exact,
+            &nodes,
+            (0..queries.num_queries as usize).map(|i| queries.get(i).unwrap()),
+            K_NEAREST,
+            Delivery::Unordered,
+            |index, result| {
+                assert!(result.iter().all(|neighbor| neighbor.id != DEFAULT_PAD_ID));
+                streamed[index] = Some(result);
+            },
+        );
+        assert_eq!(streamed, expected.into_iter().map(Some).collect::<Vec<_>>());
+    }
+}
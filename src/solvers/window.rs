@@ -0,0 +1,393 @@
+//! Sliding-window index over nodes ingested in timestamp order.
+//!
+//! Nodes are grouped into windows spanning a fixed timestamp range, each
+//! with its own HNSW graph built incrementally as nodes arrive. Once more
+//! windows than retained exist the oldest one is dropped whole, so recent
+//! nodes stay searchable without ever rebuilding a graph.
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::error::{self, GlasshouseError};
+use crate::types::{NodesDataset, ParsedNodeOwned, ParsedQuery, ScoredResult};
+
+use super::plan::{selectivity, time_range};
+use super::{Duplicates, Hnsw, HnswBuilder, Neighbor, Plan, Solver, Strategy, merge_top_k};
+
+/// Builder validating the parameters of a [`SlidingWindow`] index.
+#[derive(Debug, Clone)]
+pub struct SlidingWindowBuilder {
+    span: f32,
+    windows: usize,
+    hnsw: HnswBuilder,
+    duplicates: Duplicates,
+}
+
+impl Default for SlidingWindowBuilder {
+    fn default() -> Self {
+        SlidingWindowBuilder {
+            span: SlidingWindow::DEFAULT_SPAN,
+            windows: SlidingWindow::DEFAULT_WINDOWS,
+            hnsw: HnswBuilder::new(),
+            duplicates: Duplicates::default(),
+        }
+    }
+}
+
+impl SlidingWindowBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Timestamp range covered by each window. Must be positive.
+    pub fn span(mut self, span: f32) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Number of most recent windows kept. Must be at least 1.
+    pub fn windows(mut self, windows: usize) -> Self {
+        self.windows = windows;
+        self
+    }
+
+    /// Parameters of the graph of each window.
+    pub fn hnsw(mut self, hnsw: HnswBuilder) -> Self {
+        self.hnsw = hnsw;
+        self
+    }
+
+    /// How a node inserted in several windows under the same ID is merged,
+    /// distinct IDs before keeping the `k` closest by default.
+    pub fn duplicates(mut self, duplicates: Duplicates) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Checks that the parameters describe a valid index.
+    pub fn validate(&self) -> error::Result<()> {
+        if !(self.span > 0.0 && self.span.is_finite()) {
+            return Err(GlasshouseError::Config(format!(
+                "Window span must be a positive number, got {}",
+                self.span
+            )));
+        }
+        if self.windows == 0 {
+            return Err(GlasshouseError::Config(
+                "At least one window must be kept".to_string(),
+            ));
+        }
+        self.hnsw.validate()
+    }
+
+    /// Validates the parameters and returns an empty index.
+    pub fn build(&self) -> error::Result<SlidingWindow> {
+        self.validate()?;
+        Ok(SlidingWindow {
+            span: self.span,
+            retained: self.windows,
+            hnsw: self.hnsw.clone(),
+            duplicates: self.duplicates,
+            windows: VecDeque::new(),
+        })
+    }
+}
+
+/// Index keeping the nodes of the most recent timestamp windows, each
+/// searched through its own graph. Nodes are identified by the ID given
+/// when inserting them, the nodes dataset passed to searches is unused.
+#[derive(Debug)]
+pub struct SlidingWindow {
+    span: f32,
+    retained: usize,
+    hnsw: HnswBuilder,
+    duplicates: Duplicates,
+    /// Windows from the oldest to the most recent.
+    windows: VecDeque<Window>,
+}
+
+#[derive(Debug)]
+struct Window {
+    /// Position of the window, it covers timestamps from `key * span`
+    /// inclusive to `(key + 1) * span` exclusive.
+    key: i64,
+    nodes: NodesDataset,
+    /// ID given at insertion of each node of the window.
+    ids: Vec<u32>,
+    index: Hnsw,
+}
+
+impl SlidingWindow {
+    pub const DEFAULT_SPAN: f32 = 0.1;
+    pub const DEFAULT_WINDOWS: usize = 4;
+
+    /// Inserts a node, creating its window and expiring the oldest ones
+    /// when it is the first of a new window. Nodes older than every
+    /// retained window are rejected.
+    pub fn insert(&mut self, node_id: u32, node: ParsedNodeOwned) -> error::Result<()> {
+        if !node.t_attr.is_finite() {
+            return Err(GlasshouseError::Malformed(format!(
+                "Node {} has no finite timestamp",
+                node_id
+            )));
+        }
+        let key = (node.t_attr / self.span).floor() as i64;
+        let newest = self
+            .windows
+            .back()
+            .map_or(key, |window| window.key.max(key));
+        if key <= newest - self.retained as i64 {
+            return Err(GlasshouseError::Config(format!(
+                "Node {} at timestamp {} falls in an expired window",
+                node_id, node.t_attr
+            )));
+        }
+
+        let position = self.windows.partition_point(|window| window.key < key);
+        if self
+            .windows
+            .get(position)
+            .is_none_or(|window| window.key != key)
+        {
+            let nodes = NodesDataset::default();
+            let index = self.hnsw.build(&nodes)?;
+            self.windows.insert(
+                position,
+                Window {
+                    key,
+                    nodes,
+                    ids: Vec::new(),
+                    index,
+                },
+            );
+        }
+        let window = &mut self.windows[position];
+        let local_id = window.nodes.push(node)?;
+        window.ids.push(node_id);
+        window.index.insert(&window.nodes, local_id)?;
+
+        while self
+            .windows
+            .front()
+            .is_some_and(|window| window.key <= newest - self.retained as i64)
+        {
+            self.windows.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Drops the windows ending at or before the timestamp, returns the
+    /// number of nodes dropped with them.
+    pub fn expire_before(&mut self, timestamp: f32) -> usize {
+        let mut expired = 0;
+        while self
+            .windows
+            .front()
+            .is_some_and(|window| (window.key + 1) as f32 * self.span <= timestamp)
+        {
+            expired += self
+                .windows
+                .pop_front()
+                .map_or(0, |window| window.ids.len());
+        }
+        expired
+    }
+
+    /// Number of nodes in the retained windows.
+    pub fn len(&self) -> usize {
+        self.windows.iter().map(|window| window.ids.len()).sum()
+    }
+
+    /// Returns true if no node is retained.
+    pub fn is_empty(&self) -> bool {
+        self.windows.iter().all(|window| window.ids.is_empty())
+    }
+
+    /// Number of retained windows.
+    pub fn num_windows(&self) -> usize {
+        self.windows.len()
+    }
+
+    /// Returns true if the window may hold nodes passing the timestamp
+    /// filter of the query.
+    fn overlaps(&self, window: &Window, query: &ParsedQuery<'_>) -> bool {
+        let Some((lower, upper)) = time_range(query) else {
+            return true;
+        };
+        let start = window.key as f32 * self.span;
+        lower < start + self.span && upper >= start
+    }
+}
+
+impl Solver for SlidingWindow {
+    fn search_scored(
+        &self,
+        _nodes_dataset: &NodesDataset,
+        query: &ParsedQuery<'_>,
+        k: usize,
+    ) -> ScoredResult {
+        let mut candidates = Vec::new();
+        for window in self.windows.iter().filter(|w| self.overlaps(w, query)) {
+            let result = window.index.search_scored(&window.nodes, query, k);
+            candidates.extend(result.into_iter().map(|neighbor| Neighbor {
+                distance: neighbor.distance,
+                id: window.ids[neighbor.id as usize],
+            }));
+        }
+        merge_top_k(candidates, k, self.duplicates)
+    }
+
+    /// Reports the windows overlapping the query and the nodes visited in
+    /// all of them, each picking its own strategy.
+    fn explain(&self, _nodes_dataset: &NodesDataset, query: &ParsedQuery<'_>, k: usize) -> Plan {
+        let plans: Vec<Plan> = self
+            .windows
+            .iter()
+            .filter(|w| self.overlaps(w, query))
+            .map(|window| window.index.explain(&window.nodes, query, k))
+            .collect();
+        let len = self.len().max(1) as f64;
+        let index = format!(
+            "sliding window, {} of {} windows",
+            plans.len(),
+            self.windows.len()
+        );
+        Plan {
+            strategy: Strategy::PostFilter { index },
+            selectivity: self
+                .windows
+                .iter()
+                .map(|window| selectivity(&window.nodes, query) * window.ids.len() as f64 / len)
+                .sum(),
+            candidates: plans
+                .iter()
+                .map(|plan| plan.candidates)
+                .sum::<Option<usize>>(),
+            visited: plans.iter().map(|plan| plan.visited).sum(),
+        }
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|window| {
+                window.index.memory_bytes()
+                    + window.ids.capacity() * mem::size_of::<u32>()
+                    + window.nodes.vectors.capacity() * mem::size_of::<f32>()
+                    + window.nodes.c_attrs.capacity() * mem::size_of::<i32>()
+                    + window.nodes.t_attrs.capacity() * mem::size_of::<f32>()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::solvers::{Exact, run_scored};
+    use crate::types::QueryType;
+
+    #[test]
+    fn old_windows_expire_and_recent_nodes_are_found() {
+        let mut rng = StdRng::seed_from_u64(31);
+        let mut nodes = generate::nodes(&mut rng, 300, 4, 3);
+        let queries = generate::queries(&mut rng, 20, 4, 3);
+        let mut window = SlidingWindowBuilder::new()
+            .span(0.25)
+            .windows(2)
+            .hnsw(HnswBuilder::new().ef_search(300))
+            .build()
+            .unwrap();
+
+        let mut order: Vec<u32> = (0..300).collect();
+        order.sort_by(|&a, &b| nodes.t_attrs[a as usize].total_cmp(&nodes.t_attrs[b as usize]));
+        for &id in &order {
+            let node = nodes.get(id as usize).unwrap();
+            window
+                .insert(
+                    id,
+                    ParsedNodeOwned {
+                        c_attr: node.c_attr,
+                        t_attr: node.t_attr,
+                        vector: node.vector.to_vec(),
+                    },
+                )
+                .unwrap();
+        }
+        assert_eq!(window.num_windows(), 2);
+        let node = |t_attr, dimensions| ParsedNodeOwned {
+            c_attr: 0,
+            t_attr,
+            vector: vec![0.0; dimensions],
+        };
+        assert!(matches!(
+            window.insert(300, node(0.1, 4)),
+            Err(GlasshouseError::Config(_))
+        ));
+        assert!(matches!(
+            window.insert(300, node(f32::NAN, 4)),
+            Err(GlasshouseError::Malformed(_))
+        ));
+        assert!(matches!(
+            window.insert(300, node(0.99, 3)),
+            Err(GlasshouseError::Malformed(_))
+        ));
+
+        // Only the nodes of the two most recent windows are retained.
+        for id in 0..300 {
+            if nodes.t_attrs[id as usize] < 0.5 {
+                nodes.delete(id);
+            }
+        }
+        assert_eq!(
+            window.len(),
+            (0..300).filter(|&id| nodes.get(id).is_some()).count()
+        );
+        assert_eq!(
+            run_scored(&window, &nodes, &queries, 10).unwrap(),
+            run_scored(&Exact, &nodes, &queries, 10).unwrap()
+        );
+
+        assert_eq!(window.expire_before(0.7), 0);
+        assert!(window.expire_before(0.75) > 0);
+        assert_eq!(window.num_windows(), 1);
+    }
+
+    #[test]
+    fn nodes_inserted_in_several_windows_are_merged() {
+        let nodes = NodesDataset::default();
+        let query = ParsedQuery {
+            query_type: QueryType::VectorOnly,
+            v_categorical: None,
+            t_lower_bound: None,
+            t_upper_bound: None,
+            query_vector: &[0.0],
+        };
+        let ids = |duplicates| {
+            let mut window = SlidingWindowBuilder::new()
+                .duplicates(duplicates)
+                .build()
+                .unwrap();
+            // Node 7 is updated to a later timestamp and a close vector.
+            for (id, t_attr, v) in [(7, 0.05, 1.0), (8, 0.05, 2.0), (7, 0.15, 1.5)] {
+                let node = ParsedNodeOwned {
+                    c_attr: 0,
+                    t_attr,
+                    vector: vec![v],
+                };
+                window.insert(id, node).unwrap();
+            }
+            window
+                .search_scored(&nodes, &query, 2)
+                .iter()
+                .map(|neighbor| neighbor.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(Duplicates::BeforeTruncation), [7, 8]);
+        assert_eq!(ids(Duplicates::AfterTruncation), [7]);
+        assert_eq!(ids(Duplicates::Keep), [7, 7]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Distributions of the node attributes and query filters, used to choose
+//! an indexing strategy for a dataset.
+use std::collections::HashMap;
+
+use crate::types::{NodesDataset, QueriesDataset, QueryType};
+
+/// Attribute distributions of a nodes dataset, deleted nodes are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodesStats {
+    pub nodes: u32,
+    /// Number of nodes of each category, most frequent first.
+    pub categories: Vec<(i32, u32)>,
+    /// Smallest and largest timestamp, `None` for an empty dataset.
+    pub timestamps: Option<(f32, f32)>,
+    /// Number of nodes in equal width timestamp buckets spanning the
+    /// timestamp range.
+    pub timestamp_histogram: Vec<u32>,
+}
+
+/// Number of queries of a type and the fraction of nodes passing their
+/// filters, averaged over these queries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selectivity {
+    pub queries: u32,
+    pub selectivity: f64,
+}
+
+/// Filter selectivity over all queries and over the queries of each type,
+/// types without any query are omitted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueriesStats {
+    pub overall: Selectivity,
+    pub by_type: Vec<(QueryType, Selectivity)>,
+}
+
+/// Computes the attribute distributions of the nodes, with `buckets`
+/// timestamp histogram buckets.
+pub fn nodes(nodes_dataset: &NodesDataset, buckets: usize) -> NodesStats {
+    let live = || (0..nodes_dataset.num_vectors as usize).filter_map(|id| nodes_dataset.get(id));
+    let mut counts: HashMap<i32, u32> = HashMap::new();
+    let mut timestamps: Option<(f32, f32)> = None;
+    let mut nodes = 0;
+    for node in live() {
+        *counts.entry(node.c_attr).or_default() += 1;
+        timestamps = Some(match timestamps {
+            Some((min, max)) => (min.min(node.t_attr), max.max(node.t_attr)),
+            None => (node.t_attr, node.t_attr),
+        });
+        nodes += 1;
+    }
+    let mut categories: Vec<(i32, u32)> = counts.into_iter().collect();
+    categories.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let mut timestamp_histogram = vec![0; buckets];
+    if let (Some((min, max)), true) = (timestamps, buckets > 0) {
+        let width = (max - min) / buckets as f32;
+        for node in live() {
+            let bucket = if width > 0.0 {
+                ((node.t_attr - min) / width) as usize
+            } else {
+                0
+            };
+            timestamp_histogram[bucket.min(buckets - 1)] += 1;
+        }
+    }
+
+    NodesStats {
+        nodes,
+        categories,
+        timestamps,
+        timestamp_histogram,
+    }
+}
+
+/// Computes the fraction of nodes passing the filters of each query.
+pub fn queries(nodes_dataset: &NodesDataset, queries_dataset: &QueriesDataset) -> QueriesStats {
+    // Timestamps sorted overall and per category count the nodes of a range
+    // with two binary searches instead of a scan per query.
+    let mut all = Vec::new();
+    let mut by_category: HashMap<i32, Vec<f32>> = HashMap::new();
+    for node in (0..nodes_dataset.num_vectors as usize).filter_map(|id| nodes_dataset.get(id)) {
+        all.push(node.t_attr);
+        by_category
+            .entry(node.c_attr)
+            .or_default()
+            .push(node.t_attr);
+    }
+    all.sort_unstable_by(f32::total_cmp);
+    for timestamps in by_category.values_mut() {
+        timestamps.sort_unstable_by(f32::total_cmp);
+    }
+    let in_range = |timestamps: &[f32], lower: f32, upper: f32| {
+        timestamps.partition_point(|&t| t <= upper) - timestamps.partition_point(|&t| t < lower)
+    };
+
+    let mut totals: Vec<(QueryType, u32, f64)> = QueryType::ALL
+        .iter()
+        .map(|&query_type| (query_type, 0, 0.0))
+        .collect();
+    for index in 0..queries_dataset.num_queries as usize {
+        let Some(query) = queries_dataset.get(index) else {
+            continue;
+        };
+        let category = query
+            .v_categorical
+            .and_then(|category| by_category.get(&category))
+            .map_or(&[][..], Vec::as_slice);
+        let (lower, upper) = (
+            query.t_lower_bound.unwrap_or(f32::INFINITY),
+            query.t_upper_bound.unwrap_or(f32::NEG_INFINITY),
+        );
+        let matching = match query.query_type {
+            QueryType::VectorOnly => all.len(),
+            QueryType::CategoricalConstraint => category.len(),
+            QueryType::TimestampConstraint => in_range(&all, lower, upper),
+            QueryType::BothConstraints => in_range(category, lower, upper),
+        };
+        let total = &mut totals[query.query_type.to_f32() as usize];
+        total.1 += 1;
+        total.2 += matching as f64 / all.len().max(1) as f64;
+    }
+
+    let selectivity = |queries: u32, sum: f64| Selectivity {
+        queries,
+        selectivity: if queries == 0 {
+            0.0
+        } else {
+            sum / queries as f64
+        },
+    };
+    let overall = selectivity(
+        totals.iter().map(|total| total.1).sum(),
+        totals.iter().map(|total| total.2).sum(),
+    );
+    QueriesStats {
+        overall,
+        by_type: totals
+            .into_iter()
+            .filter(|total| total.1 > 0)
+            .map(|(query_type, queries, sum)| (query_type, selectivity(queries, sum)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ParsedQuery;
+
+    #[test]
+    fn reports_attribute_distributions_and_selectivity() {
+        let nodes_dataset =
+            NodesDataset::from_parts(1, vec![1, 1, 2, 1], vec![0.0, 0.5, 0.75, 1.0], vec![0.0; 4])
+                .unwrap();
+        let report = nodes(&nodes_dataset, 2);
+        assert_eq!(report.nodes, 4);
+        assert_eq!(report.categories, vec![(1, 3), (2, 1)]);
+        assert_eq!(report.timestamps, Some((0.0, 1.0)));
+        assert_eq!(report.timestamp_histogram, vec![1, 3]);
+
+        let mut queries_dataset = QueriesDataset::default();
+        let filters = [
+            (QueryType::VectorOnly, None, None, None),
+            (QueryType::CategoricalConstraint, Some(1), None, None),
+            (QueryType::BothConstraints, Some(1), Some(0.25), Some(1.0)),
+            (QueryType::TimestampConstraint, None, Some(0.5), Some(0.75)),
+        ];
+        for (query_type, v_categorical, t_lower_bound, t_upper_bound) in filters {
+            queries_dataset
+                .push(&ParsedQuery {
+                    query_type,
+                    v_categorical,
+                    t_lower_bound,
+                    t_upper_bound,
+                    query_vector: &[0.0],
+                })
+                .unwrap();
+        }
+        let report = queries(&nodes_dataset, &queries_dataset);
+        assert_eq!(report.overall.queries, 4);
+        assert_eq!(report.overall.selectivity, (1.0 + 0.75 + 0.5 + 0.5) / 4.0);
+        assert_eq!(report.by_type[2].0, QueryType::TimestampConstraint);
+        assert_eq!(report.by_type[2].1.selectivity, 0.5);
+    }
+}
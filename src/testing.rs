@@ -0,0 +1,223 @@
+//! Proptest strategies generating small datasets with their exact answers.
+//!
+//! Vector entries are small integers and timestamps multiples of `1/8`, so
+//! distances are computed exactly and many nodes tie on their distance or
+//! sit on the bounds of a timestamp range, the edge cases solvers get wrong.
+//! The answers are found by a brute-force scan sharing no code with the
+//! solvers beyond [`ParsedQuery::matches`], which defines the filters.
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn ivf_is_exact_when_probing_every_list(case in testing::cases(64, 8)) {
+//!         let ivf = IvfBuilder::new().nlist(4).nprobe(4).build(&case.nodes).unwrap();
+//!         case.check(&ivf)?;
+//!     }
+//! }
+//! ```
+use proptest::prelude::*;
+
+use crate::solvers::{self, Solver};
+use crate::types::{
+    NodesDataset, ParsedNodeOwned, ParsedQuery, QueriesDataset, QueryType, ScoredNeighbor,
+    ScoredResults,
+};
+
+/// Largest dimensionality of the generated vectors.
+pub const MAX_DIMENSIONS: usize = 4;
+/// Number of categories of the generated nodes and queries.
+pub const NUM_CATEGORIES: i32 = 4;
+/// Number of neighbours of the generated answers.
+pub const K: usize = 5;
+
+/// Datasets and the exact `K` nearest neighbours of every query.
+#[derive(Debug)]
+pub struct Case {
+    pub nodes: NodesDataset,
+    pub queries: QueriesDataset,
+    /// Neighbours of every query passing its filters, closest first and by
+    /// increasing ID at equal distances.
+    pub expected: ScoredResults,
+}
+
+impl Case {
+    /// Fails unless the solver answers every query with the expected
+    /// neighbours.
+    pub fn check<S: Solver + ?Sized>(&self, solver: &S) -> Result<(), TestCaseError> {
+        let results = solvers::run_scored(solver, &self.nodes, &self.queries, K)
+            .map_err(|e| TestCaseError::fail(e.to_string()))?;
+        for (index, (result, expected)) in results.iter().zip(&self.expected).enumerate() {
+            prop_assert_eq!(result, expected, "query {}", index);
+        }
+        Ok(())
+    }
+}
+
+/// Generates up to `max_nodes` nodes, some of them deleted, and up to
+/// `max_queries` queries of every type, along with their answers.
+pub fn cases(max_nodes: usize, max_queries: usize) -> impl Strategy<Value = Case> {
+    (1..=MAX_DIMENSIONS).prop_flat_map(move |dimensions| {
+        (
+            nodes(dimensions, max_nodes),
+            queries(dimensions, max_queries),
+        )
+            .prop_map(|(nodes, queries)| {
+                let expected = answers(&nodes, &queries);
+                Case {
+                    nodes,
+                    queries,
+                    expected,
+                }
+            })
+    })
+}
+
+/// Generates between one and `max_nodes` nodes of the dimensionality, each
+/// deleted with a probability of 1/8.
+pub fn nodes(dimensions: usize, max_nodes: usize) -> impl Strategy<Value = NodesDataset> {
+    let node = (
+        0..NUM_CATEGORIES,
+        timestamp(),
+        vector(dimensions),
+        prop::bool::weighted(0.125),
+    );
+    prop::collection::vec(node, 1..=max_nodes.max(1)).prop_map(move |nodes| {
+        let mut nodes_dataset = NodesDataset {
+            dimensions,
+            ..Default::default()
+        };
+        for (c_attr, t_attr, vector, _) in &nodes {
+            let node = ParsedNodeOwned {
+                c_attr: *c_attr,
+                t_attr: *t_attr,
+                vector: vector.clone(),
+            };
+            nodes_dataset
+                .push(node)
+                .expect("vectors have the dimensionality");
+        }
+        for (node_id, (.., deleted)) in nodes.iter().enumerate() {
+            if *deleted {
+                nodes_dataset.delete(node_id as u32);
+            }
+        }
+        nodes_dataset
+    })
+}
+
+/// Generates up to `max_queries` queries of the dimensionality with
+/// uniformly distributed types.
+pub fn queries(dimensions: usize, max_queries: usize) -> impl Strategy<Value = QueriesDataset> {
+    let query = (
+        prop::sample::select(QueryType::ALL.to_vec()),
+        0..NUM_CATEGORIES,
+        timestamp(),
+        timestamp(),
+        vector(dimensions),
+    );
+    prop::collection::vec(query, 0..=max_queries).prop_map(move |queries| {
+        let mut queries_dataset = QueriesDataset {
+            dimensions,
+            ..Default::default()
+        };
+        for (query_type, category, a, b, vector) in &queries {
+            let by_category = matches!(
+                query_type,
+                QueryType::CategoricalConstraint | QueryType::BothConstraints
+            );
+            let by_time = matches!(
+                query_type,
+                QueryType::TimestampConstraint | QueryType::BothConstraints
+            );
+            let query = ParsedQuery {
+                query_type: *query_type,
+                v_categorical: by_category.then_some(*category),
+                t_lower_bound: by_time.then_some(a.min(*b)),
+                t_upper_bound: by_time.then_some(a.max(*b)),
+                query_vector: vector,
+            };
+            queries_dataset
+                .push(&query)
+                .expect("vectors have the dimensionality");
+        }
+        queries_dataset
+    })
+}
+
+/// Returns the exact `K` nearest neighbours of every query by scanning
+/// all nodes.
+pub fn answers(nodes_dataset: &NodesDataset, queries_dataset: &QueriesDataset) -> ScoredResults {
+    (0..queries_dataset.num_queries as usize)
+        .map(|index| {
+            let query = queries_dataset.get(index).expect("index is in range");
+            let mut neighbors: Vec<ScoredNeighbor> = (0..nodes_dataset.num_vectors as usize)
+                .filter_map(|id| nodes_dataset.get(id).map(|node| (id, node)))
+                .filter(|(_, node)| query.matches(node))
+                .map(|(id, node)| ScoredNeighbor {
+                    id: id as u32,
+                    distance: node
+                        .vector
+                        .iter()
+                        .zip(query.query_vector)
+                        .map(|(a, b)| (a - b) * (a - b))
+                        .sum(),
+                })
+                .collect();
+            neighbors.sort_by(|a, b| a.distance.total_cmp(&b.distance).then(a.id.cmp(&b.id)));
+            neighbors.truncate(K);
+            neighbors
+        })
+        .collect()
+}
+
+/// Timestamps in `[0, 1]` by steps of `1/8`.
+fn timestamp() -> impl Strategy<Value = f32> {
+    (0..=8u8).prop_map(|step| step as f32 / 8.0)
+}
+
+/// Vectors with integer entries in `[-3, 3]`.
+fn vector(dimensions: usize) -> impl Strategy<Value = Vec<f32>> {
+    prop::collection::vec((-3..=3i8).prop_map(f32::from), dimensions)
+}
+
+/// Path named after a test in the temporary directory, prefixed with the
+/// process ID so that concurrent test runs do not clobber each other's files.
+#[cfg(all(test, feature = "fs"))]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("glasshouse-{}-{}", std::process::id(), name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solvers::{BaselineBuilder, Exact, HnswBuilder, IvfBuilder};
+
+    proptest! {
+        #[test]
+        fn exact_solvers_match_the_answers(case in cases(48, 8)) {
+            case.check(&Exact)?;
+            #[cfg(feature = "fs")]
+            {
+                let dir = crate::testing::temp_path("testing-spill");
+                case.check(&solvers::SpillingExact::new(4, dir).unwrap())?;
+            }
+        }
+
+        #[test]
+        fn exhaustive_indexes_match_the_answers(case in cases(32, 8)) {
+            let baseline = BaselineBuilder::new()
+                .sample_proportion(1.0)
+                .build(&case.nodes)
+                .unwrap();
+            case.check(&baseline)?;
+            let ivf = IvfBuilder::new().nlist(4).nprobe(4).build(&case.nodes).unwrap();
+            case.check(&ivf)?;
+            let hnsw = HnswBuilder::new()
+                .m(32)
+                .ef_search(64)
+                .build(&case.nodes)
+                .unwrap();
+            case.check(&hnsw)?;
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Search parameter tuning against a recall target.
+//!
+//! The index is built once with the configured build parameters, then the
+//! search parameter of the solver is swept over a grid on a sample of
+//! queries with known ground truth. The fastest setting reaching the
+//! recall target is kept:
+//!
+//! | Solver   | Swept parameter     |
+//! |----------|---------------------|
+//! | baseline | `sample_proportion` |
+//! | ivf      | `nprobe`            |
+//! | hnsw     | `ef_search`         |
+//!
+//! The exact solver has nothing to tune and always reaches a recall of 1.
+//! [`sweep`] runs arbitrary grids of build and search parameters, to plot
+//! recall against throughput.
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, SolverKind};
+use crate::error::{self, GlasshouseError};
+use crate::eval;
+use crate::progress::{NoProgress, Progress};
+use crate::solvers::{self, Hnsw, Ivf, Solver};
+use crate::types::{NodesDataset, QueriesDataset, QueryResult};
+
+/// Proportions of the nodes scanned by the baseline.
+const SAMPLE_PROPORTIONS: [f32; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Multiples of the number of neighbours `k` used as HNSW search widths.
+const EF_SEARCH_FACTORS: [usize; 7] = [1, 2, 3, 4, 6, 8, 16];
+
+/// Parameters changed on a built index, the others require a rebuild.
+const SEARCH_PARAMETERS: [&str; 3] = ["ivf.nprobe", "ivf.multi_probe", "hnsw.ef_search"];
+
+/// Outcome of searching the sample with one parameter setting.
+#[derive(Debug, Clone)]
+pub struct Trial {
+    /// Swept parameters and their value in this trial.
+    pub parameters: Vec<(String, String)>,
+    /// Configuration the sample was searched with.
+    pub config: Config,
+    pub recall: f64,
+    /// Wall-clock time spent building the index the trial searched.
+    pub build_time: Duration,
+    /// Wall-clock time spent answering the sample.
+    pub elapsed: Duration,
+}
+
+impl Trial {
+    /// Queries answered per second.
+    pub fn qps(&self, num_queries: u32) -> f64 {
+        num_queries as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Every trial of a tuning run, in the order they were run.
+#[derive(Debug, Clone)]
+pub struct Tuning {
+    pub trials: Vec<Trial>,
+}
+
+impl Tuning {
+    /// Returns the fastest trial reaching the recall target.
+    pub fn best(&self, target: f64) -> Option<&Trial> {
+        self.trials
+            .iter()
+            .filter(|trial| trial.recall >= target)
+            .min_by_key(|trial| trial.elapsed)
+    }
+}
+
+/// Sweeps the search parameter of the configured solver over the sample,
+/// `ground_truth` holds the exact results of every query.
+pub fn tune(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    ground_truth: &[QueryResult],
+) -> error::Result<Tuning> {
+    tune_with_progress(
+        config,
+        nodes_dataset,
+        queries_dataset,
+        ground_truth,
+        &NoProgress,
+    )
+}
+
+/// Same as [`tune`], reporting the progress of the index construction.
+pub fn tune_with_progress(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    ground_truth: &[QueryResult],
+    progress: &dyn Progress,
+) -> error::Result<Tuning> {
+    let (name, values): (&str, Vec<String>) = match config.solver {
+        SolverKind::Exact => ("", Vec::new()),
+        SolverKind::Baseline => (
+            "baseline.sample_proportion",
+            SAMPLE_PROPORTIONS.iter().map(f32::to_string).collect(),
+        ),
+        SolverKind::Ivf => (
+            "ivf.nprobe",
+            nprobes(Ivf::built_nlist(config.ivf.nlist, nodes_dataset.num_vectors).max(1))
+                .iter()
+                .map(usize::to_string)
+                .collect(),
+        ),
+        SolverKind::Hnsw => (
+            "hnsw.ef_search",
+            EF_SEARCH_FACTORS
+                .iter()
+                .map(|factor| (factor * config.k()).to_string())
+                .collect(),
+        ),
+    };
+    let grid = if values.is_empty() {
+        Vec::new()
+    } else {
+        vec![(name.to_string(), values)]
+    };
+    let trials = sweep_with_progress(
+        config,
+        nodes_dataset,
+        queries_dataset,
+        ground_truth,
+        &grid,
+        progress,
+    )?;
+    Ok(Tuning { trials })
+}
+
+/// Searches the sample with every combination of the parameter values of
+/// the grid, given as dotted names accepted by [`Config::set`]. The index
+/// is only rebuilt when a build parameter changes, and IVF combinations
+/// probing more lists than the index holds are skipped.
+pub fn sweep(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    ground_truth: &[QueryResult],
+    grid: &[(String, Vec<String>)],
+) -> error::Result<Vec<Trial>> {
+    sweep_with_progress(
+        config,
+        nodes_dataset,
+        queries_dataset,
+        ground_truth,
+        grid,
+        &NoProgress,
+    )
+}
+
+/// Same as [`sweep`], reporting the progress of the index constructions.
+pub fn sweep_with_progress(
+    config: &Config,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    ground_truth: &[QueryResult],
+    grid: &[(String, Vec<String>)],
+    progress: &dyn Progress,
+) -> error::Result<Vec<Trial>> {
+    if ground_truth.len() != queries_dataset.num_queries as usize {
+        return Err(GlasshouseError::Malformed(format!(
+            "Ground truth holds {} queries but the sample holds {}",
+            ground_truth.len(),
+            queries_dataset.num_queries
+        )));
+    }
+
+    let (search_axes, build_axes): (Vec<_>, Vec<_>) = grid
+        .iter()
+        .cloned()
+        .partition(|(name, _)| SEARCH_PARAMETERS.contains(&name.as_str()));
+    let searches = combinations(&search_axes);
+
+    let mut trials = Vec::new();
+    for build in combinations(&build_axes) {
+        // The builders validate the search parameters as well, build with
+        // the first ones probing a single list, which is valid whatever the
+        // number of lists.
+        let build_config = configured(config, &build)?;
+        let mut index_config = configured(&build_config, &searches[0])?;
+        index_config.ivf.nprobe = 1;
+        let start = Instant::now();
+        let mut solver = Swept::build(&index_config, nodes_dataset, progress)?;
+        let build_time = start.elapsed();
+
+        for search in &searches {
+            let config = configured(&build_config, search)?;
+            if !solver.configure(&config)? {
+                continue;
+            }
+            let start = Instant::now();
+            let results = solvers::run(
+                solver.as_solver(),
+                nodes_dataset,
+                queries_dataset,
+                config.k(),
+            )?;
+            let elapsed = start.elapsed();
+            let recall = eval::recall(&results, ground_truth, solvers::DEFAULT_PAD_ID)
+                .expect("ground truth and results answer the same queries");
+            trials.push(Trial {
+                parameters: build.iter().chain(search).cloned().collect(),
+                config,
+                recall,
+                build_time,
+                elapsed,
+            });
+        }
+    }
+    Ok(trials)
+}
+
+/// Solver built by a sweep, keeping the concrete index types whose search
+/// parameters can change without rebuilding.
+enum Swept {
+    Ivf(Ivf),
+    Hnsw(Hnsw),
+    Other(Box<dyn Solver>),
+}
+
+impl Swept {
+    fn build(
+        config: &Config,
+        nodes_dataset: &NodesDataset,
+        progress: &dyn Progress,
+    ) -> error::Result<Self> {
+        Ok(match config.solver {
+            SolverKind::Ivf => Swept::Ivf(
+                config
+                    .ivf_builder()
+                    .build_with_progress(nodes_dataset, progress)?,
+            ),
+            SolverKind::Hnsw => Swept::Hnsw(
+                config
+                    .hnsw_builder()
+                    .build_with_progress(nodes_dataset, progress)?,
+            ),
+            _ => Swept::Other(solvers::build_with_progress(
+                config,
+                nodes_dataset,
+                progress,
+            )?),
+        })
+    }
+
+    /// Validates and applies the search parameters of the configuration.
+    /// Returns false, leaving the solver as is, for IVF settings probing
+    /// more lists than the index holds, which are skipped by the sweep.
+    fn configure(&mut self, config: &Config) -> error::Result<bool> {
+        match self {
+            Swept::Ivf(ivf) => {
+                if config.ivf.nprobe > ivf.nlist().max(1) {
+                    return Ok(false);
+                }
+                config.ivf_builder().validate()?;
+                ivf.set_nprobe(config.ivf.nprobe);
+                ivf.set_multi_probe(config.ivf.multi_probe);
+                ivf.set_adaptive(config.ivf.adaptive);
+            }
+            Swept::Hnsw(hnsw) => {
+                config.hnsw_builder().validate()?;
+                hnsw.set_ef_search(config.hnsw.ef_search);
+                hnsw.set_adaptive(config.hnsw.adaptive);
+            }
+            Swept::Other(_) => {}
+        }
+        Ok(true)
+    }
+
+    fn as_solver(&self) -> &dyn Solver {
+        match self {
+            Swept::Ivf(ivf) => ivf,
+            Swept::Hnsw(hnsw) => hnsw,
+            Swept::Other(solver) => solver.as_ref(),
+        }
+    }
+}
+
+/// Returns a copy of the configuration with the parameters set.
+fn configured(config: &Config, parameters: &[(String, String)]) -> error::Result<Config> {
+    let mut config = config.clone();
+    for (name, value) in parameters {
+        config.set(name, value)?;
+    }
+    Ok(config)
+}
+
+/// Cartesian product of the values of the axes, a single empty combination
+/// without axes.
+fn combinations(axes: &[(String, Vec<String>)]) -> Vec<Vec<(String, String)>> {
+    axes.iter()
+        .fold(vec![Vec::new()], |combinations, (name, values)| {
+            combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.push((name.clone(), value.clone()));
+                        combination
+                    })
+                })
+                .collect()
+        })
+}
+
+/// Powers of two below `nlist`, followed by `nlist` itself.
+fn nprobes(nlist: usize) -> Vec<usize> {
+    let mut nprobes: Vec<usize> = (0..usize::BITS)
+        .map(|shift| 1 << shift)
+        .take_while(|&nprobe| nprobe < nlist)
+        .collect();
+    nprobes.push(nlist);
+    nprobes
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::constants::K_NEAREST;
+    use crate::generate;
+    use crate::solvers::Exact;
+
+    #[test]
+    fn tuned_ivf_reaches_the_target() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let nodes = generate::nodes(&mut rng, 2_000, 8, 4);
+        let queries = generate::queries(&mut rng, 50, 8, 4);
+        let ground_truth = solvers::run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+        let mut config = Config {
+            solver: SolverKind::Ivf,
+            ..Config::default()
+        };
+        config.ivf.nlist = 16;
+
+        let tuning = tune(&config, &nodes, &queries, &ground_truth).unwrap();
+        let nprobes: Vec<usize> = tuning.trials.iter().map(|t| t.config.ivf.nprobe).collect();
+        assert_eq!(nprobes, vec![1, 2, 4, 8, 16]);
+        // Probing every list scans every node.
+        assert_eq!(tuning.trials.last().unwrap().recall, 1.0);
+        assert!(tuning.best(0.9).unwrap().recall >= 0.9);
+        assert!(tuning.best(1.1).is_none());
+        assert!(tune(&config, &nodes, &queries, &ground_truth[1..]).is_err());
+    }
+
+    #[test]
+    fn sweeps_cover_the_grid() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let nodes = generate::nodes(&mut rng, 1_000, 8, 4);
+        let queries = generate::queries(&mut rng, 20, 8, 4);
+        let ground_truth = solvers::run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+        let config = Config {
+            solver: SolverKind::Ivf,
+            ..Config::default()
+        };
+        let grid = vec![
+            (
+                "ivf.nprobe".to_string(),
+                vec!["1".to_string(), "8".to_string()],
+            ),
+            (
+                "ivf.nlist".to_string(),
+                vec!["8".to_string(), "16".to_string()],
+            ),
+        ];
+
+        let trials = sweep(&config, &nodes, &queries, &ground_truth, &grid).unwrap();
+        let settings: Vec<(usize, usize)> = trials
+            .iter()
+            .map(|t| (t.config.ivf.nlist, t.config.ivf.nprobe))
+            .collect();
+        assert_eq!(settings, vec![(8, 1), (8, 8), (16, 1), (16, 8)]);
+        assert_eq!(trials[1].recall, 1.0);
+        assert_eq!(trials[0].build_time, trials[1].build_time);
+        assert_eq!(
+            trials[2].parameters,
+            vec![
+                ("ivf.nlist".to_string(), "16".to_string()),
+                ("ivf.nprobe".to_string(), "1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn sweeps_skip_probing_more_lists_than_built() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let nodes = generate::nodes(&mut rng, 500, 8, 4);
+        let queries = generate::queries(&mut rng, 10, 8, 4);
+        let ground_truth = solvers::run(&Exact, &nodes, &queries, K_NEAREST).unwrap();
+        let mut config = Config {
+            solver: SolverKind::Ivf,
+            ..Config::default()
+        };
+        config.ivf.nprobe = 8;
+        let grid = vec![(
+            "ivf.nlist".to_string(),
+            vec!["4".to_string(), "16".to_string()],
+        )];
+
+        let trials = sweep(&config, &nodes, &queries, &ground_truth, &grid).unwrap();
+        let settings: Vec<(usize, usize)> = trials
+            .iter()
+            .map(|t| (t.config.ivf.nlist, t.config.ivf.nprobe))
+            .collect();
+        assert_eq!(settings, vec![(16, 8)]);
+
+        // Fewer nodes than lists, the probes stop at the lists built.
+        config.ivf.nlist = 1024;
+        let tuning = tune(&config, &nodes, &queries, &ground_truth).unwrap();
+        assert_eq!(tuning.trials.last().unwrap().config.ivf.nprobe, 500);
+        assert_eq!(tuning.trials.last().unwrap().recall, 1.0);
+    }
+}
@@ -1,8 +1,11 @@
 //! Types used to represent data points and queries for the solvers.
-use crate::constants::*;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::error::GlasshouseError;
 
 /// Possible type of queries that can be made against the dataset.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum QueryType {
     VectorOnly,
     CategoricalConstraint,
@@ -11,7 +14,15 @@ pub enum QueryType {
 }
 
 impl QueryType {
-    pub fn from_f32(val: f32) -> Result<Self, String> {
+    /// Every query type, in the order of their binary encoding.
+    pub const ALL: [QueryType; 4] = [
+        QueryType::VectorOnly,
+        QueryType::CategoricalConstraint,
+        QueryType::TimestampConstraint,
+        QueryType::BothConstraints,
+    ];
+
+    pub fn from_f32(val: f32) -> Result<Self, GlasshouseError> {
         // The query type is represented as a float but guaranteed to be one
         // of (0,1,2,3) so this cast is safe.
         let int_val = val as i32;
@@ -20,7 +31,17 @@ impl QueryType {
             1 => Ok(QueryType::CategoricalConstraint),
             2 => Ok(QueryType::TimestampConstraint),
             3 => Ok(QueryType::BothConstraints),
-            _ => Err(format!("Invalid query type value: {}", val)),
+            _ => Err(GlasshouseError::InvalidQueryType(val)),
+        }
+    }
+
+    /// Returns the float encoding of the query type used in the binary format.
+    pub fn to_f32(self) -> f32 {
+        match self {
+            QueryType::VectorOnly => 0.0,
+            QueryType::CategoricalConstraint => 1.0,
+            QueryType::TimestampConstraint => 2.0,
+            QueryType::BothConstraints => 3.0,
         }
     }
 }
@@ -39,6 +60,11 @@ impl OptionalFilterValue {
         if self.0 == -1.0 { None } else { Some(self.0) }
     }
 
+    /// Returns the raw encoded value, `-1.0` when not set.
+    pub fn raw(&self) -> f32 {
+        self.0
+    }
+
     pub fn categorical_value(&self) -> Option<i32> {
         if self.0 == -1.0 {
             None
@@ -48,20 +74,78 @@ impl OptionalFilterValue {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct NodesDataset {
     pub num_vectors: u32,
-    /// Discretized categorical attribute C for each vector.
-    pub c_attrs: Vec<f32>,
+    /// Number of dimensions of each vector.
+    pub dimensions: usize,
+    /// Categorical attribute C for each vector, stored as `f32` in the
+    /// dataset files and converted when loading them.
+    pub c_attrs: Vec<i32>,
     /// Normalized timestamp attribute T for each vector.
     pub t_attrs: Vec<f32>,
-    /// The 100-dimensional vectors.
-    pub vectors: Vec<[f32; VECTOR_DIMENSIONS]>,
+    /// The vectors stored contiguously, `dimensions` entries per node.
+    pub vectors: Vec<f32>,
+    /// Bitmap of deleted nodes, empty until the first deletion.
+    pub tombstones: Vec<u64>,
+    /// Timestamp and ID of every node with a timestamp other than NaN,
+    /// sorted by timestamp then ID, see `NodesDataset::timestamp_range`.
+    /// Rebuilt on first use when pushed nodes leave it unsorted.
+    pub t_index: OnceLock<Vec<(f32, u32)>>,
+    /// Category and ID of every node sorted by category then ID, see
+    /// `NodesDataset::category_nodes`. Rebuilt on first use when pushed
+    /// nodes leave it unsorted.
+    pub c_index: OnceLock<Vec<(i32, u32)>>,
+    /// Statistics of every category sorted by category, see
+    /// `NodesDataset::category_stats`.
+    pub category_stats: Vec<CategoryStats>,
+}
+
+/// Statistics of the nodes of a category, deleted nodes included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryStats {
+    pub category: i32,
+    /// Number of nodes of the category.
+    pub count: usize,
+    /// Lowest and highest timestamps other than NaN of the category, `None`
+    /// if every timestamp is NaN.
+    pub timestamps: Option<(f32, f32)>,
+    /// Mean vector of the nodes of the category.
+    pub centroid: Vec<f32>,
+}
+
+impl CategoryStats {
+    pub(crate) fn new(category: i32, dimensions: usize) -> Self {
+        CategoryStats {
+            category,
+            count: 0,
+            timestamps: None,
+            centroid: vec![0.0; dimensions],
+        }
+    }
+
+    /// Adds a node to the statistics, updating the centroid as a running
+    /// mean.
+    pub(crate) fn add(&mut self, t_attr: f32, vector: &[f32]) {
+        self.count += 1;
+        if !t_attr.is_nan() {
+            self.timestamps = Some(match self.timestamps {
+                Some((min, max)) => (min.min(t_attr), max.max(t_attr)),
+                None => (t_attr, t_attr),
+            });
+        }
+        let weight = 1.0 / self.count as f32;
+        for (mean, &v) in self.centroid.iter_mut().zip(vector) {
+            *mean += (v - *mean) * weight;
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct QueriesDataset {
     pub num_queries: u32,
+    /// Number of dimensions of each query vector.
+    pub dimensions: usize,
     /// Type of each query.
     pub query_types: Vec<QueryType>,
     /// Specific query value v for the categorical attribute.
@@ -70,8 +154,8 @@ pub struct QueriesDataset {
     pub t_lower_bounds: Vec<OptionalFilterValue>,
     /// Specific query value r for the timestamp attribute.
     pub t_upper_bounds: Vec<OptionalFilterValue>,
-    /// The 100-dimensional query vectors.
-    pub query_vectors: Vec<[f32; VECTOR_DIMENSIONS]>,
+    /// The query vectors stored contiguously, `dimensions` entries per query.
+    pub query_vectors: Vec<f32>,
 }
 
 /// Represents a single parsed query with its associated attributes.
@@ -81,18 +165,68 @@ pub struct ParsedQuery<'a> {
     pub v_categorical: Option<i32>,
     pub t_lower_bound: Option<f32>,
     pub t_upper_bound: Option<f32>,
-    pub query_vector: &'a [f32; VECTOR_DIMENSIONS],
+    pub query_vector: &'a [f32],
+}
+
+impl ParsedQuery<'_> {
+    /// Returns true if the node satisfies the attribute constraints of the query.
+    pub fn matches(&self, node: &ParsedNode) -> bool {
+        match self.query_type {
+            QueryType::VectorOnly => true,
+            QueryType::CategoricalConstraint => self.matches_categorical(node.c_attr),
+            QueryType::TimestampConstraint => self.matches_timestamp(node.t_attr),
+            QueryType::BothConstraints => {
+                self.matches_categorical(node.c_attr) && self.matches_timestamp(node.t_attr)
+            }
+        }
+    }
+
+    fn matches_categorical(&self, c_attr: i32) -> bool {
+        self.v_categorical == Some(c_attr)
+    }
+
+    fn matches_timestamp(&self, t_attr: f32) -> bool {
+        match (self.t_lower_bound, self.t_upper_bound) {
+            (Some(l_bound), Some(r_bound)) => t_attr >= l_bound && t_attr <= r_bound,
+            _ => false,
+        }
+    }
 }
 
 /// Represents a single node with it's associated attributes.
 #[derive(Debug)]
 pub struct ParsedNode<'a> {
-    pub c_attr: f32,
+    pub c_attr: i32,
+    pub t_attr: f32,
+    pub vector: &'a [f32],
+}
+
+/// Owned counterpart of [`ParsedNode`], used to add nodes to a dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedNodeOwned {
+    pub c_attr: i32,
     pub t_attr: f32,
-    pub vector: &'a [f32; VECTOR_DIMENSIONS],
+    pub vector: Vec<f32>,
 }
 
-/// Type alias for the KNN results for a single query.
-pub type QueryResult = [u32; K_NEAREST];
+/// Type alias for the KNN results for a single query, `k` node IDs.
+pub type QueryResult = Box<[u32]>;
 /// Type alias for all KNN results.
 pub type QueryResults = Vec<QueryResult>;
+
+/// A node found by a solver and its squared Euclidean distance to the query,
+/// `2 - 2 cos` between the unit vectors under the cosine metric, see
+/// `Metric::distance` for the distance reported to users.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScoredNeighbor {
+    pub id: u32,
+    pub distance: f32,
+}
+
+/// Neighbours of a query ordered by increasing distance, at most `k` of
+/// them. Unlike [`QueryResult`] it is not padded when fewer
+/// nodes match the query.
+pub type ScoredResult = Vec<ScoredNeighbor>;
+
+/// Scored results of each query of a dataset.
+pub type ScoredResults = Vec<ScoredResult>;
@@ -0,0 +1,342 @@
+//! Validation of the datasets of a run without searching them.
+//!
+//! Parsing already rejects truncated files, unknown query types and
+//! non-integer node categories. The checks below find the values that parse
+//! but corrupt distances or answer nothing. Non-finite entries, malformed
+//! filters and trailing bytes are errors. Filters missing from their query
+//! type are rewritten by [`QueriesDataset::normalize`] and filters matching
+//! no node are legal, both are reported as they usually point at attributes
+//! encoded differently from the nodes.
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[cfg(feature = "fs")]
+use crate::constants::{NODE_VECTOR_START_INDEX, QUERY_VECTOR_START_INDEX};
+#[cfg(feature = "fs")]
+use crate::error;
+use crate::io;
+#[cfg(feature = "fs")]
+use crate::io::compression;
+use crate::types::{NodesDataset, QueriesDataset, QueryType};
+
+/// Value of a dataset that parses but is likely wrong.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anomaly {
+    /// The file holds more bytes than the rows announced by its header.
+    TrailingBytes { rows: u32, expected: u64 },
+    /// A node holds a NaN or infinite attribute or vector entry.
+    NonFiniteNode { node: u32 },
+    /// A node has a negative category, no query can filter on it.
+    NegativeCategory { node: u32, category: i32 },
+    /// A query holds a NaN or infinite filter or vector entry.
+    NonFiniteQuery { query: u32 },
+    /// A query lacks a filter its type requires.
+    MissingFilter { query: u32, query_type: QueryType },
+    /// A query filters on a category that is not an integer.
+    NonIntegerCategory { query: u32, value: f32 },
+    /// A query filters on a timestamp range whose lower bound exceeds its
+    /// upper bound.
+    InvertedRange { query: u32, lower: f32, upper: f32 },
+    /// A query filters on a category no node has.
+    UnknownCategory { query: u32, category: i32 },
+    /// A query filters on a timestamp range holding no node timestamp.
+    RangeOutOfBounds { query: u32, lower: f32, upper: f32 },
+}
+
+impl Anomaly {
+    /// Returns whether the anomaly makes results wrong rather than empty.
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            Anomaly::NegativeCategory { .. }
+                | Anomaly::MissingFilter { .. }
+                | Anomaly::UnknownCategory { .. }
+                | Anomaly::RangeOutOfBounds { .. }
+        )
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::TrailingBytes { rows, expected } => write!(
+                f,
+                "File is longer than the {} bytes of the {} rows of its header",
+                expected, rows
+            ),
+            Anomaly::NonFiniteNode { node } => write!(f, "Node {} holds a non-finite value", node),
+            Anomaly::NegativeCategory { node, category } => {
+                write!(f, "Node {} has the negative category {}", node, category)
+            }
+            Anomaly::NonFiniteQuery { query } => {
+                write!(f, "Query {} holds a non-finite value", query)
+            }
+            Anomaly::MissingFilter { query, query_type } => {
+                write!(f, "Query {} of type {:?} lacks a filter", query, query_type)
+            }
+            Anomaly::NonIntegerCategory { query, value } => {
+                write!(f, "Query {} filters on the category {}", query, value)
+            }
+            Anomaly::InvertedRange {
+                query,
+                lower,
+                upper,
+            } => write!(
+                f,
+                "Query {} filters on the inverted range [{}, {}]",
+                query, lower, upper
+            ),
+            Anomaly::UnknownCategory { query, category } => write!(
+                f,
+                "Query {} filters on the category {} no node has",
+                query, category
+            ),
+            Anomaly::RangeOutOfBounds {
+                query,
+                lower,
+                upper,
+            } => write!(
+                f,
+                "Query {} filters on the range [{}, {}] holding no node",
+                query, lower, upper
+            ),
+        }
+    }
+}
+
+/// Returns the anomalies of the nodes, in node order.
+pub fn nodes(nodes_dataset: &NodesDataset) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    for index in 0..nodes_dataset.num_vectors as usize {
+        let node = index as u32;
+        let t_attr = nodes_dataset.t_attrs[index];
+        if !std::iter::once(&t_attr)
+            .chain(nodes_dataset.vector(index))
+            .all(|v| v.is_finite())
+        {
+            anomalies.push(Anomaly::NonFiniteNode { node });
+        }
+        let category = nodes_dataset.c_attrs[index];
+        if category < 0 {
+            anomalies.push(Anomaly::NegativeCategory { node, category });
+        }
+    }
+    anomalies
+}
+
+/// Returns the anomalies of the queries over the nodes, in query order.
+pub fn queries(nodes_dataset: &NodesDataset, queries_dataset: &QueriesDataset) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    for index in 0..queries_dataset.num_queries as usize {
+        let query = index as u32;
+        let query_type = queries_dataset.query_types[index];
+        let v_categorical = queries_dataset.v_categoricals[index];
+        let (lower, upper) = (
+            queries_dataset.t_lower_bounds[index],
+            queries_dataset.t_upper_bounds[index],
+        );
+        if ![v_categorical.raw(), lower.raw(), upper.raw()]
+            .iter()
+            .chain(queries_dataset.query_vector(index))
+            .all(|v| v.is_finite())
+        {
+            anomalies.push(Anomaly::NonFiniteQuery { query });
+            continue;
+        }
+
+        let by_category = matches!(
+            query_type,
+            QueryType::CategoricalConstraint | QueryType::BothConstraints
+        );
+        let by_time = matches!(
+            query_type,
+            QueryType::TimestampConstraint | QueryType::BothConstraints
+        );
+        if (by_category && v_categorical.value().is_none())
+            || (by_time && (lower.value().is_none() || upper.value().is_none()))
+        {
+            anomalies.push(Anomaly::MissingFilter { query, query_type });
+            continue;
+        }
+        if by_category {
+            let value = v_categorical.raw();
+            match io::categorical(value) {
+                Err(_) => anomalies.push(Anomaly::NonIntegerCategory { query, value }),
+                Ok(category) if nodes_dataset.category_stats(category).is_none() => {
+                    anomalies.push(Anomaly::UnknownCategory { query, category });
+                }
+                Ok(_) => {}
+            }
+        }
+        if by_time {
+            let (lower, upper) = (lower.raw(), upper.raw());
+            if lower > upper {
+                anomalies.push(Anomaly::InvertedRange {
+                    query,
+                    lower,
+                    upper,
+                });
+            } else if nodes_dataset.num_vectors > 0
+                && nodes_dataset.timestamp_range_len(lower, upper) == 0
+            {
+                anomalies.push(Anomaly::RangeOutOfBounds {
+                    query,
+                    lower,
+                    upper,
+                });
+            }
+        }
+    }
+    anomalies
+}
+
+/// Returns an anomaly if a nodes file holds more than its rows.
+#[cfg(feature = "fs")]
+pub fn nodes_file<P: AsRef<Path>>(
+    file_path: P,
+    nodes_dataset: &NodesDataset,
+) -> error::Result<Option<Anomaly>> {
+    file_length(
+        file_path.as_ref(),
+        nodes_dataset.num_vectors,
+        NODE_VECTOR_START_INDEX + nodes_dataset.dimensions,
+    )
+}
+
+/// Returns an anomaly if a queries file holds more than its rows.
+#[cfg(feature = "fs")]
+pub fn queries_file<P: AsRef<Path>>(
+    file_path: P,
+    queries_dataset: &QueriesDataset,
+) -> error::Result<Option<Anomaly>> {
+    file_length(
+        file_path.as_ref(),
+        queries_dataset.num_queries,
+        QUERY_VECTOR_START_INDEX + queries_dataset.dimensions,
+    )
+}
+
+/// Compares the uncompressed length of a file with the length of its
+/// header and rows, files of unknown length pass.
+#[cfg(feature = "fs")]
+fn file_length(file_path: &Path, rows: u32, row_width: usize) -> error::Result<Option<Anomaly>> {
+    let expected = 4 + rows as u64 * row_width as u64 * 4;
+    let (_, length) = compression::open(file_path)?;
+    let matches = length
+        .candidates()
+        .is_none_or(|mut candidates| candidates.any(|len| len == expected));
+    Ok((!matches).then_some(Anomaly::TrailingBytes { rows, expected }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{OptionalFilterValue, ParsedNodeOwned};
+
+    #[test]
+    fn anomalies_are_found_and_classified() {
+        let mut nodes_dataset = NodesDataset {
+            dimensions: 2,
+            ..Default::default()
+        };
+        for (c_attr, t_attr, vector) in [(0, 0.25, [0.0, 1.0]), (-2, 0.5, [f32::NAN, 0.0])] {
+            nodes_dataset
+                .push(ParsedNodeOwned {
+                    c_attr,
+                    t_attr,
+                    vector: vector.to_vec(),
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            nodes(&nodes_dataset),
+            [
+                Anomaly::NonFiniteNode { node: 1 },
+                Anomaly::NegativeCategory {
+                    node: 1,
+                    category: -2
+                }
+            ]
+        );
+
+        let filters = [
+            (QueryType::VectorOnly, -1.0, -1.0, -1.0),
+            (QueryType::CategoricalConstraint, -1.0, -1.0, -1.0),
+            (QueryType::CategoricalConstraint, 0.5, -1.0, -1.0),
+            (QueryType::CategoricalConstraint, 7.0, -1.0, -1.0),
+            (QueryType::TimestampConstraint, -1.0, 0.75, 0.25),
+            (QueryType::BothConstraints, 0.0, 0.75, 1.0),
+            (QueryType::TimestampConstraint, -1.0, f32::INFINITY, 1.0),
+        ];
+        let value = OptionalFilterValue::new;
+        let queries_dataset = QueriesDataset {
+            num_queries: filters.len() as u32,
+            dimensions: 2,
+            query_types: filters.iter().map(|f| f.0).collect(),
+            v_categoricals: filters.iter().map(|f| value(f.1)).collect(),
+            t_lower_bounds: filters.iter().map(|f| value(f.2)).collect(),
+            t_upper_bounds: filters.iter().map(|f| value(f.3)).collect(),
+            query_vectors: vec![0.0; filters.len() * 2],
+        };
+        let anomalies = queries(&nodes_dataset, &queries_dataset);
+        assert_eq!(
+            anomalies,
+            [
+                Anomaly::MissingFilter {
+                    query: 1,
+                    query_type: QueryType::CategoricalConstraint
+                },
+                Anomaly::NonIntegerCategory {
+                    query: 2,
+                    value: 0.5
+                },
+                Anomaly::UnknownCategory {
+                    query: 3,
+                    category: 7
+                },
+                Anomaly::InvertedRange {
+                    query: 4,
+                    lower: 0.75,
+                    upper: 0.25
+                },
+                Anomaly::RangeOutOfBounds {
+                    query: 5,
+                    lower: 0.75,
+                    upper: 1.0
+                },
+                Anomaly::NonFiniteQuery { query: 6 },
+            ]
+        );
+        let errors: Vec<bool> = anomalies.iter().map(Anomaly::is_error).collect();
+        assert_eq!(errors, [false, true, false, true, false, true]);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn trailing_bytes_are_reported() {
+        let nodes_dataset = NodesDataset {
+            num_vectors: 1,
+            dimensions: 2,
+            c_attrs: vec![0],
+            t_attrs: vec![0.0],
+            vectors: vec![1.0, 2.0],
+            ..Default::default()
+        };
+        let path = crate::testing::temp_path("validate-nodes.bin");
+        nodes_dataset.write(&path).unwrap();
+        assert_eq!(nodes_file(&path, &nodes_dataset).unwrap(), None);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.extend_from_slice(&[0; 4]);
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(
+            nodes_file(&path, &nodes_dataset).unwrap(),
+            Some(Anomaly::TrailingBytes {
+                rows: 1,
+                expected: 20
+            })
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+//! Warm-up before timing a search.
+//!
+//! The first queries of a run pay for the page faults of the freshly
+//! loaded datasets and for cold CPU caches and index structures. Touching
+//! every page of the datasets and answering a few throwaway queries first
+//! keeps those costs out of the measured latencies.
+use std::hint::black_box;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::error::{self, GlasshouseError};
+use crate::solvers::Solver;
+use crate::types::{NodesDataset, QueriesDataset};
+
+/// Size of the memory pages touched, the smallest page size of common
+/// platforms.
+const PAGE_BYTES: usize = 4096;
+
+/// Work done by [`warm_up`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WarmUp {
+    /// Number of dataset pages touched.
+    pub pages: usize,
+    /// Number of throwaway queries answered.
+    pub queries: usize,
+    pub elapsed: Duration,
+}
+
+/// Touches every page of the datasets, then answers up to `queries` queries
+/// spread over the queries dataset for their `k` nearest neighbours and
+/// discards their results.
+pub fn warm_up(
+    solver: &dyn Solver,
+    nodes_dataset: &NodesDataset,
+    queries_dataset: &QueriesDataset,
+    queries: usize,
+    k: usize,
+) -> error::Result<WarmUp> {
+    if nodes_dataset.dimensions != queries_dataset.dimensions {
+        return Err(GlasshouseError::DimensionMismatch {
+            nodes: nodes_dataset.dimensions,
+            queries: queries_dataset.dimensions,
+        });
+    }
+
+    let start = Instant::now();
+    let pages = touch(&nodes_dataset.vectors)
+        + touch(&nodes_dataset.c_attrs)
+        + touch(&nodes_dataset.t_attrs)
+        + touch(&queries_dataset.query_vectors);
+
+    let num_queries = queries_dataset.num_queries as usize;
+    let step = num_queries.div_ceil(queries.max(1)).max(1);
+    let probes: Vec<usize> = (0..num_queries).step_by(step).take(queries).collect();
+    probes.par_iter().for_each(|&i| {
+        let query = queries_dataset.get(i).expect("probe indices are in range");
+        black_box(solver.search_scored(nodes_dataset, &query, k));
+    });
+    Ok(WarmUp {
+        pages,
+        queries: probes.len(),
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Reads one value of every page of the slice, returns the number of pages.
+fn touch<T: Copy + Send + Sync>(values: &[T]) -> usize {
+    let per_page = (PAGE_BYTES / mem::size_of::<T>().max(1)).max(1);
+    values
+        .par_chunks(per_page)
+        .map(|page| {
+            black_box(page[0]);
+            1
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::generate;
+    use crate::solvers::Exact;
+
+    #[test]
+    fn warm_up_touches_every_page_and_spreads_the_queries() {
+        let mut rng = StdRng::seed_from_u64(53);
+        let nodes = generate::nodes(&mut rng, 1_000, 8, 4);
+        let queries = generate::queries(&mut rng, 100, 8, 4);
+
+        let warm_up = warm_up(&Exact, &nodes, &queries, 8, 10).unwrap();
+        // 8000 vector entries span 8 pages, 1000 categories and timestamps
+        // one page each and 800 query vector entries one page.
+        assert_eq!(warm_up.pages, 11);
+        assert_eq!(warm_up.queries, 8);
+        assert_eq!(
+            super::warm_up(&Exact, &nodes, &queries, 0, 10)
+                .unwrap()
+                .queries,
+            0
+        );
+    }
+
+    #[test]
+    fn warm_up_rejects_mismatched_dimensions() {
+        let mut rng = StdRng::seed_from_u64(54);
+        let nodes = generate::nodes(&mut rng, 100, 8, 4);
+        let queries = generate::queries(&mut rng, 10, 4, 4);
+
+        assert!(matches!(
+            warm_up(&Exact, &nodes, &queries, 8, 10),
+            Err(GlasshouseError::DimensionMismatch {
+                nodes: 8,
+                queries: 4
+            })
+        ));
+    }
+}